@@ -0,0 +1,126 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// ABEND.LOG, as written by the server after an ABEND. It is a text file with
+// the reason, the running process, the registers ('EAX = 00000000'), a stack
+// dump and the list of loaded modules, where every module is followed by
+// lines with its 'Code Address: X Length: Y' and 'Data Address: X Length: Y'.
+// Only these parts are parsed; everything else is kept as-is.
+use crate::resolve::{ModuleRange, Resolver};
+
+const MODULE_EXTENSIONS: [ &str; 8 ] = [ ".NLM", ".DSK", ".LAN", ".NAM", ".CDM", ".HAM", ".MSL", ".NLF" ];
+
+pub struct AbendLog {
+    pub reason: Option<String>,
+    pub process: Option<String>,
+    pub registers: Vec<(String, u32)>,
+    pub modules: Vec<ModuleRange>,
+    pub lines: Vec<String>,
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = s.trim_end_matches(['h', 'H', ',', ')']);
+    if s.is_empty() || s.len() > 8 { return None; }
+    u32::from_str_radix(s, 16).ok()
+}
+
+// Parses 'Address: X Length: Y'
+fn parse_range(line: &str, kind: &str) -> Option<(u32, u32)> {
+    let rest = &line[line.find(kind)? + kind.len()..];
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    let address = parse_hex(words.first()?)?;
+    let length_pos = words.iter().position(|w| w.eq_ignore_ascii_case("length:"))?;
+    Some((address, parse_hex(words.get(length_pos + 1)?)?))
+}
+
+fn is_module_name(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    MODULE_EXTENSIONS.iter().any(|ext| upper.ends_with(ext)) && upper.len() > 4
+}
+
+impl AbendLog {
+    pub fn parse(text: &str) -> Self {
+        let mut log = Self{ reason: None, process: None, registers: Vec::new(), modules: Vec::new(), lines: Vec::new() };
+        let mut current: Option<ModuleRange> = None;
+        for line in text.lines() {
+            log.lines.push(line.to_string());
+            let trimmed = line.trim();
+            if log.reason.is_none() {
+                if let Some(reason) = trimmed.strip_prefix("ABEND:") {
+                    log.reason = Some(reason.trim().to_string());
+                }
+            }
+            if log.process.is_none() {
+                if let Some(process) = trimmed.strip_prefix("Running process:") {
+                    log.process = Some(process.trim().to_string());
+                }
+            }
+
+            // Registers are listed as 'EAX = 00000000 EBX = 00000001 ...'
+            let words: Vec<&str> = trimmed.split_whitespace().collect();
+            for w in words.windows(3) {
+                if w[1] == "=" && w[0].len() <= 6 && w[0].chars().all(|c| c.is_ascii_alphabetic()) {
+                    if let Some(value) = parse_hex(w[2]) {
+                        let name = w[0].to_ascii_uppercase();
+                        if !log.registers.iter().any(|(n, _)| *n == name) {
+                            log.registers.push((name, value));
+                        }
+                    }
+                }
+            }
+
+            if let Some(first) = words.first() {
+                if !line.starts_with([' ', '\t']) && is_module_name(first) {
+                    log.modules.extend(current.take());
+                    current = Some(ModuleRange{ name: first.to_string(), code_address: 0, code_length: 0, data_address: 0, data_length: 0 });
+                    continue;
+                }
+            }
+            if let Some(module) = current.as_mut() {
+                if let Some((address, length)) = parse_range(line, "Code Address:") {
+                    module.code_address = address;
+                    module.code_length = length;
+                }
+                if let Some((address, length)) = parse_range(line, "Data Address:") {
+                    module.data_address = address;
+                    module.data_length = length;
+                }
+            }
+        }
+        log.modules.extend(current);
+        log.modules.retain(|m| m.code_length != 0);
+        log
+    }
+
+    pub fn register(&self, name: &str) -> Option<u32> {
+        self.registers.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+}
+
+// Appends the resolved location to every word that is the address of code;
+// other values are left alone to keep the noise down
+pub fn annotate(line: &str, resolver: &Resolver) -> String {
+    let mut result = line.to_string();
+    let mut annotations: Vec<String> = Vec::new();
+    for word in line.split(|c: char| !c.is_ascii_hexdigit()) {
+        if word.len() < 6 { continue; }
+        if let Some(addr) = parse_hex(word) {
+            if !resolver.is_code(addr) { continue; }
+            if let Some(location) = resolver.resolve(addr) {
+                let annotation = format!("{:08x}={}", addr, location);
+                if !annotations.contains(&annotation) {
+                    annotations.push(annotation);
+                }
+            }
+        }
+    }
+    if !annotations.is_empty() {
+        result.push_str("    ; ");
+        result.push_str(&annotations.join(", "));
+    }
+    result
+}
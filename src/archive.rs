@@ -0,0 +1,354 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Writers for tar and zip archives. Tar archives use the ustar format, with
+// GNU long name records for paths that do not fit; files of a known size
+// are copied into them as they are read. Zip entries are stored
+// uncompressed with their CRC known up front, so files are held in memory
+// in full and no data descriptors are needed; zip64 is not supported, which
+// limits zip archives to 65535 entries and 4 GB.
+use std::io::{Error, ErrorKind, Read, Write};
+
+pub const TAR_BLOCK_SIZE: usize = 512;
+const TAR_NAME_MAX: usize = 100;
+const TAR_TYPE_FILE: u8 = b'0';
+const TAR_TYPE_DIRECTORY: u8 = b'5';
+const TAR_TYPE_LONG_NAME: u8 = b'L';
+
+const ZIP_LOCAL_HEADER: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP_VERSION: u16 = 20;
+const ZIP_MADE_BY_UNIX: u16 = 3 << 8;
+const ZIP_FLAG_UTF8: u16 = 0x0800;
+
+// Paths use / as separator. The mode holds the Unix permissions, and the DOS
+// attributes the read-only, hidden, system, directory and archive bits (which
+// only zip archives store)
+pub trait ArchiveWriter {
+    fn add_directory(&mut self, path: &str, mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error>;
+    fn add_file(&mut self, path: &str, data: &[u8], mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error>;
+    fn finish(&mut self) -> Result<(), Error>;
+
+    // Adds a file of size bytes read from data
+    fn add_file_from(&mut self, path: &str, size: u64, data: &mut dyn Read, mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        data.take(size).read_to_end(&mut buf)?;
+        if buf.len() as u64 != size {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("{} is shorter than {} bytes", path, size)));
+        }
+        self.add_file(path, &buf, mtime, mode, dos_attributes)
+    }
+}
+
+pub struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self{ out }
+    }
+
+    fn write_header(&mut self, path: &str, size: u64, mtime: u64, mode: u32, typeflag: u8) -> Result<(), Error> {
+        let name = path.as_bytes();
+        if name.len() > TAR_NAME_MAX {
+            self.write_header("././@LongLink", name.len() as u64 + 1, 0, 0, TAR_TYPE_LONG_NAME)?;
+            self.write_data(&[ name, &[ 0 ] ].concat())?;
+        }
+        let mut header = [ 0u8; TAR_BLOCK_SIZE ];
+        let len = name.len().min(TAR_NAME_MAX);
+        header[0..len].copy_from_slice(&name[0..len]);
+        write_octal(&mut header[100..108], mode as u64 & 0o7777);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], size);
+        write_octal(&mut header[136..148], mtime);
+        header[148..156].fill(b' ');
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        write_octal(&mut header[148..155], checksum as u64);
+        self.out.write_all(&header)
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.out.write_all(data)?;
+        let padding = data.len().next_multiple_of(TAR_BLOCK_SIZE) - data.len();
+        self.out.write_all(&vec![ 0u8; padding ])
+    }
+}
+
+// Octal number padded with zeroes, followed by a NUL
+fn write_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    let digits = digits.as_bytes();
+    field[0..width].copy_from_slice(&digits[digits.len() - width..]);
+    field[width] = 0;
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add_directory(&mut self, path: &str, mtime: u64, mode: u32, _dos_attributes: u8) -> Result<(), Error> {
+        self.write_header(&format!("{}/", path.trim_end_matches('/')), 0, mtime, mode, TAR_TYPE_DIRECTORY)
+    }
+
+    fn add_file(&mut self, path: &str, data: &[u8], mtime: u64, mode: u32, _dos_attributes: u8) -> Result<(), Error> {
+        self.write_header(path, data.len() as u64, mtime, mode, TAR_TYPE_FILE)?;
+        self.write_data(data)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.write_all(&[ 0u8; TAR_BLOCK_SIZE * 2 ])?;
+        self.out.flush()
+    }
+
+    fn add_file_from(&mut self, path: &str, size: u64, data: &mut dyn Read, mtime: u64, mode: u32, _dos_attributes: u8) -> Result<(), Error> {
+        self.write_header(path, size, mtime, mode, TAR_TYPE_FILE)?;
+        if std::io::copy(&mut data.take(size), &mut self.out)? != size {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("{} is shorter than {} bytes", path, size)));
+        }
+        let padding = size.next_multiple_of(TAR_BLOCK_SIZE as u64) - size;
+        self.out.write_all(&vec![ 0u8; padding as usize ])
+    }
+}
+
+pub struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self{ out, offset: 0, central_directory: Vec::new(), entries: 0 }
+    }
+
+    fn add(&mut self, path: &str, data: &[u8], mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error> {
+        if self.entries >= u16::MAX as usize || self.offset + data.len() as u64 > u32::MAX as u64 {
+            return Err(Error::other("zip archive too large, use tar instead"));
+        }
+        let (time, date) = dos_date_time(mtime);
+        let crc = crc32(data);
+        let name = path.as_bytes();
+
+        // Fields shared by the local and central headers
+        let mut common: Vec<u8> = Vec::new();
+        common.extend(ZIP_VERSION.to_le_bytes());
+        common.extend(ZIP_FLAG_UTF8.to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+        common.extend(time.to_le_bytes());
+        common.extend(date.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+
+        let mut local: Vec<u8> = Vec::new();
+        local.extend(ZIP_LOCAL_HEADER.to_le_bytes());
+        local.extend(&common);
+        local.extend(name);
+        self.out.write_all(&local)?;
+        self.out.write_all(data)?;
+
+        self.central_directory.extend(ZIP_CENTRAL_HEADER.to_le_bytes());
+        self.central_directory.extend((ZIP_MADE_BY_UNIX | ZIP_VERSION).to_le_bytes());
+        self.central_directory.extend(&common);
+        self.central_directory.extend(0u16.to_le_bytes());
+        self.central_directory.extend(0u16.to_le_bytes());
+        self.central_directory.extend(0u16.to_le_bytes());
+        self.central_directory.extend(((mode << 16) | dos_attributes as u32).to_le_bytes());
+        self.central_directory.extend((self.offset as u32).to_le_bytes());
+        self.central_directory.extend(name);
+        self.offset += (local.len() + data.len()) as u64;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn add_directory(&mut self, path: &str, mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error> {
+        self.add(&format!("{}/", path.trim_end_matches('/')), &[], mtime, mode | 0o040000, dos_attributes)
+    }
+
+    fn add_file(&mut self, path: &str, data: &[u8], mtime: u64, mode: u32, dos_attributes: u8) -> Result<(), Error> {
+        self.add(path, data, mtime, mode | 0o100000, dos_attributes)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let mut end: Vec<u8> = Vec::new();
+        end.extend(ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend((self.entries as u16).to_le_bytes());
+        end.extend((self.entries as u16).to_le_bytes());
+        end.extend((self.central_directory.len() as u32).to_le_bytes());
+        end.extend((self.offset as u32).to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        self.out.write_all(&self.central_directory)?;
+        self.out.write_all(&end)?;
+        self.out.flush()
+    }
+}
+
+// Zip archives store local DOS times, which run from 1980 up to 2107; times
+// outside of that are clamped
+fn dos_date_time(mtime: u64) -> (u16, u16) {
+    let days = (mtime / 86400) as i64;
+    let seconds = mtime % 86400;
+
+    // Civil date from the days since 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+    let time = ((seconds / 3600) << 11) | (((seconds / 60) % 60) << 5) | ((seconds % 60) / 2);
+    let date = (((year - 1980) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+const CRC32_TABLE: [ u32; 256 ] = {
+    let mut table = [ 0u32; 256 ];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xffffffff, |crc, b| CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal(field: &[u8]) -> u64 {
+        let digits = std::str::from_utf8(field).unwrap().trim_end_matches('\0');
+        u64::from_str_radix(digits, 8).unwrap()
+    }
+
+    #[test]
+    fn crc32_vector() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn dos_dates() {
+        // 2023-02-03 12:34:56
+        assert_eq!(dos_date_time(1675427696), ((12 << 11) | (34 << 5) | 28, (43 << 9) | (2 << 5) | 3));
+        assert_eq!(dos_date_time(0), (0, (1 << 5) | 1));
+        // 2108-01-01 and far beyond
+        assert_eq!(dos_date_time(4354819200), ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31));
+        assert_eq!(dos_date_time(u32::MAX as u64 * 4), dos_date_time(4354819200));
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let long_name = format!("{}/FILE.TXT", "DIRECTORY".repeat(12));
+        let mut out: Vec<u8> = Vec::new();
+        let mut tar = TarWriter::new(&mut out);
+        tar.add_directory("SYS/PUBLIC", 1675427696, 0o755, 0).unwrap();
+        tar.add_file("SYS/PUBLIC/LOGIN.EXE", b"hello", 1675427696, 0o644, 0).unwrap();
+        tar.add_file_from(&long_name, 3, &mut &b"abcdef"[..], 1675427697, 0o600, 0).unwrap();
+        tar.finish().unwrap();
+        assert_eq!(out.len() % TAR_BLOCK_SIZE, 0);
+
+        let mut entries: Vec<(String, u64, u64, u8, Vec<u8>)> = Vec::new();
+        let mut offset = 0;
+        let mut long_link: Option<String> = None;
+        loop {
+            let header = &out[offset..offset + TAR_BLOCK_SIZE];
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            let mut sum = header.to_vec();
+            sum[148..156].fill(b' ');
+            assert_eq!(octal(&header[148..155]), sum.iter().map(|b| *b as u64).sum::<u64>());
+            assert_eq!(&header[257..263], b"ustar\0");
+            let size = octal(&header[124..136]);
+            let data = out[offset + TAR_BLOCK_SIZE..offset + TAR_BLOCK_SIZE + size as usize].to_vec();
+            offset += TAR_BLOCK_SIZE + (size as usize).next_multiple_of(TAR_BLOCK_SIZE);
+            if header[156] == TAR_TYPE_LONG_NAME {
+                long_link = Some(String::from_utf8(data[..data.len() - 1].to_vec()).unwrap());
+                continue;
+            }
+            let name_len = header[..TAR_NAME_MAX].iter().position(|b| *b == 0).unwrap_or(TAR_NAME_MAX);
+            let name = long_link.take().unwrap_or_else(|| String::from_utf8(header[..name_len].to_vec()).unwrap());
+            entries.push((name, octal(&header[100..108]), octal(&header[136..148]), header[156], data));
+        }
+        assert_eq!(offset + 2 * TAR_BLOCK_SIZE, out.len());
+        assert_eq!(entries, vec![
+            ("SYS/PUBLIC/".to_string(), 0o755, 1675427696, TAR_TYPE_DIRECTORY, Vec::new()),
+            ("SYS/PUBLIC/LOGIN.EXE".to_string(), 0o644, 1675427696, TAR_TYPE_FILE, b"hello".to_vec()),
+            (long_name, 0o600, 1675427697, TAR_TYPE_FILE, b"abc".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let mut out: Vec<u8> = Vec::new();
+        let mut zip = ZipWriter::new(&mut out);
+        zip.add_directory("SYS/PUBLIC", 1675427696, 0o755, 0x10).unwrap();
+        zip.add_file("SYS/PUBLIC/LOGIN.EXE", b"123456789", 1675427696, 0o644, 0x21).unwrap();
+        zip.finish().unwrap();
+
+        let u16_at = |o: usize| u16::from_le_bytes([ out[o], out[o + 1] ]);
+        let u32_at = |o: usize| u32::from_le_bytes([ out[o], out[o + 1], out[o + 2], out[o + 3] ]);
+        let end = out.len() - 22;
+        assert_eq!(u32_at(end), ZIP_END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(u16_at(end + 10), 2);
+        let mut central = u32_at(end + 16) as usize;
+        assert_eq!(central + u32_at(end + 12) as usize, end);
+
+        let mut entries: Vec<(String, u32, Vec<u8>)> = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(u32_at(central), ZIP_CENTRAL_HEADER);
+            let name_len = u16_at(central + 28) as usize;
+            let attributes = u32_at(central + 38);
+            let local = u32_at(central + 42) as usize;
+            assert_eq!(u32_at(local), ZIP_LOCAL_HEADER);
+            // The local header repeats the central one from the version on
+            assert_eq!(out[local + 4..local + 30], out[central + 6..central + 32]);
+            let (time, date) = (u16_at(local + 10), u16_at(local + 12));
+            assert_eq!((time, date), dos_date_time(1675427696));
+            let (crc, size) = (u32_at(local + 14), u32_at(local + 18) as usize);
+            let name = String::from_utf8(out[local + 30..local + 30 + name_len].to_vec()).unwrap();
+            let data = out[local + 30 + name_len..local + 30 + name_len + size].to_vec();
+            assert_eq!(crc, crc32(&data));
+            entries.push((name, attributes, data));
+            central += 46 + name_len;
+        }
+        assert_eq!(entries, vec![
+            ("SYS/PUBLIC/".to_string(), (0o040755 << 16) | 0x10, Vec::new()),
+            ("SYS/PUBLIC/LOGIN.EXE".to_string(), (0o100644 << 16) | 0x21, b"123456789".to_vec()),
+        ]);
+    }
+}
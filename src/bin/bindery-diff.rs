@@ -0,0 +1,166 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::BTreeSet;
+use std::env;
+use std::path::Path;
+use nlm_tools::bindery::{self, Bindery, Object};
+use nlm_tools::nwfs;
+
+fn usage(prog: &str) {
+    println!("usage: {} OLD NEW", prog);
+    println!();
+    println!("OLD and NEW are either directories containing the bindery files (or the");
+    println!("NetWare 4.x NDS database), or disk images from which the bindery is read");
+}
+
+fn load_bindery(path: &str) -> Result<Bindery, String> {
+    let p = Path::new(path);
+    if p.is_dir() {
+        let (format, files) = bindery::find_dir_files(p).ok_or(format!("no bindery files found in '{}'", path))?;
+        return Bindery::from_files(format, &files).map_err(|e| format!("cannot read bindery from '{}': {}", path, e));
+    }
+    let (format, file_data) = nwfs::volume::open_volume(path, "SYS")
+        .and_then(|mut v| bindery::read_volume_files(&mut v))
+        .map_err(|e| format!("cannot read bindery from '{}': {}", path, e))?;
+    let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+    Bindery::new(format, &file_slices).map_err(|e| format!("cannot parse bindery from '{}': {}", path, e))
+}
+
+fn describe_object(o: &Object) -> String {
+    let type_name = bindery::object_type_name(o.objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", o.objtype));
+    format!("'{}' ({}, id {:x})", o.name, type_name, o.objid)
+}
+
+// Objects are matched by type and name, as the object ID may differ when an
+// object is re-created
+fn find_matching<'a>(bindery: &'a Bindery, o: &Object) -> Option<&'a Object> {
+    bindery.find_object_by_name(&o.name, Some(o.objtype))
+}
+
+fn property_names(bindery: &Bindery, o: &Object) -> Vec<String> {
+    bindery.object_properties(o).iter()
+        .filter_map(|propid| bindery.find_property_by_id(*propid))
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+// Returns the lines describing the differences between the objects; set
+// members are compared by name, other values by their decoded form
+fn diff_object(old: &Bindery, old_o: &Object, new: &Bindery, new_o: &Object) -> Vec<String> {
+    let mut result = Vec::new();
+    if old_o.objid != new_o.objid {
+        result.push(format!("  id {:x} -> {:x}", old_o.objid, new_o.objid));
+    }
+    if old_o.security != new_o.security {
+        result.push(format!("  security {} -> {}", bindery::security_string(old_o.security), bindery::security_string(new_o.security)));
+    }
+    if old_o.flags != new_o.flags {
+        result.push(format!("  flags {:x} -> {:x}", old_o.flags, new_o.flags));
+    }
+
+    let old_names = property_names(old, old_o);
+    let new_names = property_names(new, new_o);
+    for name in &old_names {
+        if !new_names.contains(name) {
+            result.push(format!("  - property {}", name));
+            let p = old.find_property(old_o, name).unwrap();
+            for line in bindery::decode_property(old, p) {
+                result.push(format!("      - {}", line));
+            }
+        }
+    }
+    for name in &new_names {
+        let new_p = new.find_property(new_o, name).unwrap();
+        let old_p = match old.find_property(old_o, name) {
+            Some(p) => p,
+            None => {
+                result.push(format!("  + property {}", name));
+                for line in bindery::decode_property(new, new_p) {
+                    result.push(format!("      + {}", line));
+                }
+                continue;
+            }
+        };
+
+        let mut changes = Vec::new();
+        if old_p.flags != new_p.flags {
+            changes.push(format!("      flags {:x} -> {:x}", old_p.flags, new_p.flags));
+        }
+        if old_p.security != new_p.security {
+            changes.push(format!("      security {} -> {}", bindery::security_string(old_p.security), bindery::security_string(new_p.security)));
+        }
+        let old_lines = bindery::decode_property(old, old_p);
+        let new_lines = bindery::decode_property(new, new_p);
+        if old_p.is_set() && new_p.is_set() {
+            let old_members: BTreeSet<&String> = old_lines.iter().collect();
+            let new_members: BTreeSet<&String> = new_lines.iter().collect();
+            for member in old_members.difference(&new_members) {
+                changes.push(format!("      - {}", member));
+            }
+            for member in new_members.difference(&old_members) {
+                changes.push(format!("      + {}", member));
+            }
+        } else if old_lines != new_lines {
+            for line in &old_lines {
+                changes.push(format!("      - {}", line));
+            }
+            for line in &new_lines {
+                changes.push(format!("      + {}", line));
+            }
+        }
+        if !changes.is_empty() {
+            result.push(format!("  ~ property {}", name));
+            result.append(&mut changes);
+        }
+    }
+    result
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        usage(&args[0]);
+        return;
+    }
+    let (old, new) = match (load_bindery(&args[1]), load_bindery(&args[2])) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for o in &old.objects {
+        if find_matching(&new, o).is_none() {
+            println!("- object {}", describe_object(o));
+            removed += 1;
+        }
+    }
+    for o in &new.objects {
+        match find_matching(&old, o) {
+            Some(old_o) => {
+                let lines = diff_object(&old, old_o, &new, o);
+                if !lines.is_empty() {
+                    println!("~ object {}", describe_object(o));
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                    changed += 1;
+                }
+            },
+            None => {
+                println!("+ object {}", describe_object(o));
+                for name in property_names(&new, o) {
+                    println!("  + property {}", name);
+                }
+                added += 1;
+            }
+        }
+    }
+    println!("{} object(s) added, {} removed, {} changed", added, removed, changed);
+}
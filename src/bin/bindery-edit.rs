@@ -0,0 +1,209 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::io::{Seek, SeekFrom, Write};
+use nlm_tools::bindery::{self, Bindery, Format, BF_SET, OT_GROUP, OT_USER};
+use nlm_tools::codepage::Codepage;
+use nlm_tools::crypt;
+
+// Security bytes as assigned by SYSCON; the low nibble is the read access
+// and the high nibble the write access
+const SECURITY_USER: u8 = 0x31;
+const SECURITY_PASSWORD: u8 = 0x44;
+const SECURITY_GROUPS_IM_IN: u8 = 0x31;
+const SECURITY_SECURITY_EQUALS: u8 = 0x32;
+const SECURITY_GROUP_MEMBERS: u8 = 0x31;
+const SECURITY_DEFAULT: u8 = 0x31;
+
+const COMMANDS: [ (&str, usize, usize); 6 ] = [
+    ("add-user", 1, 2),
+    ("delete-user", 1, 1),
+    ("delete-object", 1, 2),
+    ("set-password", 2, 3),
+    ("add-to-group", 2, 2),
+    ("set-property-value", 3, 4),
+];
+
+fn usage(prog: &str) {
+    println!("usage: {} net$obj.sys net$prop.sys net$val.sys command [args]", prog);
+    println!("       {} net$bind.sys net$bval.sys command [args]", prog);
+    println!();
+    println!("commands:");
+    println!("  add-user NAME [PASSWORD]");
+    println!("  delete-user NAME");
+    println!("  delete-object NAME [TYPE]");
+    println!("  set-password NAME PASSWORD [TYPE]");
+    println!("  add-to-group USER GROUP");
+    println!("  set-property-value OBJECT PROPERTY VALUE [TYPE]");
+    println!();
+    println!("VALUE is stored as zero-terminated string, unless it is prefixed by hex:");
+    println!("TYPE is needed when several objects share the name.");
+    println!("The files are modified in place.");
+}
+
+fn find_or_add_property(bindery: &mut Bindery, objid: u32, name: &str, flags: u8, security: u8) -> u32 {
+    let o = bindery.find_object(objid).expect("object not found");
+    match bindery.find_property(o, name) {
+        Some(p) => p.propid,
+        None => bindery.add_property(objid, name, flags, security),
+    }
+}
+
+fn set_password(bindery: &mut Bindery, objid: u32, password: &str) {
+    // Clients always convert the password to uppercase
+    let hash = crypt::hash_object_password(objid, &bindery.codepage.encode(&password.to_ascii_uppercase()));
+    let propid = find_or_add_property(bindery, objid, "PASSWORD", 0, SECURITY_PASSWORD);
+    bindery.set_property_data(propid, &hash);
+}
+
+// Adds the user to the group in the same way SYSCON does: the group is added
+// to GROUPS_I'M_IN and SECURITY_EQUALS of the user, and the user to
+// GROUP_MEMBERS of the group
+fn add_to_group(bindery: &mut Bindery, user_id: u32, group_id: u32) -> bool {
+    let propid = find_or_add_property(bindery, group_id, "GROUP_MEMBERS", BF_SET, SECURITY_GROUP_MEMBERS);
+    let added = bindery.add_to_set(propid, user_id);
+    let propid = find_or_add_property(bindery, user_id, "GROUPS_I'M_IN", BF_SET, SECURITY_GROUPS_IM_IN);
+    bindery.add_to_set(propid, group_id);
+    let propid = find_or_add_property(bindery, user_id, "SECURITY_EQUALS", BF_SET, SECURITY_SECURITY_EQUALS);
+    bindery.add_to_set(propid, group_id);
+    added
+}
+
+fn parse_type(objtype: Option<&String>) -> Result<Option<u16>, String> {
+    match objtype {
+        Some(objtype) => Ok(Some(bindery::parse_object_type(objtype).ok_or(format!("invalid object type '{}'", objtype))?)),
+        None => Ok(None)
+    }
+}
+
+// Looks up an object by name, optionally of the given type. A name which is
+// used by several objects is refused, rather than picking one of them
+fn find_object(bindery: &Bindery, name: &str, objtype: Option<u16>) -> Result<u32, String> {
+    let matches = bindery.objects.iter().filter(|x| x.name.eq_ignore_ascii_case(name) && objtype.is_none_or(|t| t == x.objtype)).count();
+    if matches > 1 {
+        return Err(format!("multiple objects named '{}' exist, please specify the type", name));
+    }
+    bindery.find_object_by_name(name, objtype).map(|o| o.objid)
+        .ok_or(format!("object '{}' not found", name))
+}
+
+fn parse_value(value: &str, codepage: &Codepage) -> Option<Vec<u8>> {
+    if let Some(hex) = value.strip_prefix("hex:") {
+        if hex.len() % 2 != 0 { return None; }
+        return (0..hex.len()).step_by(2).map(|n| u8::from_str_radix(&hex[n..n + 2], 16).ok()).collect();
+    }
+    let mut result = codepage.encode(value);
+    result.push(0);
+    Some(result)
+}
+
+fn run_command(bindery: &mut Bindery, command: &str, args: &[String]) -> Result<String, String> {
+    match command {
+        "add-user" => {
+            let name = &args[0];
+            if bindery.codepage.encode(name).len() > bindery::OBJECT_NAME_MAX {
+                return Err(format!("name '{}' is too long", name));
+            }
+            if bindery.find_object_by_name(name, Some(OT_USER)).is_some() {
+                return Err(format!("user '{}' already exists", name));
+            }
+            let objid = bindery.add_object(name, OT_USER, SECURITY_USER);
+            bindery.add_property(objid, "GROUPS_I'M_IN", BF_SET, SECURITY_GROUPS_IM_IN);
+            bindery.add_property(objid, "SECURITY_EQUALS", BF_SET, SECURITY_SECURITY_EQUALS);
+            if let Some(password) = args.get(1) {
+                set_password(bindery, objid, password);
+            }
+            if let Some(everyone) = bindery.find_object_by_name("EVERYONE", Some(OT_GROUP)).map(|o| o.objid) {
+                add_to_group(bindery, objid, everyone);
+            }
+            Ok(format!("user '{}' added with object id {:x}", name.to_ascii_uppercase(), objid))
+        },
+        "delete-user" | "delete-object" => {
+            let objtype = if command == "delete-user" { Some(OT_USER) } else { parse_type(args.get(1))? };
+            let objid = find_object(bindery, &args[0], objtype)?;
+            let sets = bindery.delete_object(objid);
+            Ok(format!("object '{}' deleted, {} reference(s) removed", args[0], sets))
+        },
+        "set-password" => {
+            let objid = find_object(bindery, &args[0], parse_type(args.get(2))?)?;
+            set_password(bindery, objid, &args[1]);
+            Ok(format!("password of '{}' updated", args[0]))
+        },
+        "add-to-group" => {
+            let user_id = bindery.find_object_by_name(&args[0], Some(OT_USER)).map(|o| o.objid)
+                .ok_or(format!("user '{}' not found", args[0]))?;
+            let group_id = bindery.find_object_by_name(&args[1], Some(OT_GROUP)).map(|o| o.objid)
+                .ok_or(format!("group '{}' not found", args[1]))?;
+            if !add_to_group(bindery, user_id, group_id) {
+                return Err(format!("user '{}' is already a member of '{}'", args[0], args[1]));
+            }
+            Ok(format!("user '{}' added to group '{}'", args[0], args[1]))
+        },
+        "set-property-value" => {
+            let objid = find_object(bindery, &args[0], parse_type(args.get(3))?)?;
+            if args[1].len() > bindery::PROPERTY_NAME_MAX {
+                return Err(format!("property name '{}' is too long", args[1]));
+            }
+            let data = parse_value(&args[2], bindery.codepage).ok_or(format!("invalid value '{}'", args[2]))?;
+            let propid = find_or_add_property(bindery, objid, &args[1].to_ascii_uppercase(), 0, SECURITY_DEFAULT);
+            bindery.set_property_data(propid, &data);
+            Ok(format!("property '{}' of '{}' updated", args[1].to_ascii_uppercase(), args[0]))
+        },
+        _ => unreachable!()
+    }
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let command_index = args.iter().skip(1).position(|a| COMMANDS.iter().any(|(c, _, _)| c == a)).map(|n| n + 1);
+    if command_index.is_none() {
+        usage(&args[0]);
+        return Ok(())
+    }
+    let command_index = command_index.unwrap();
+    let files = &args[1..command_index];
+    let command = &args[command_index];
+    let command_args = &args[command_index + 1..];
+
+    let format = Format::detect(files.len());
+    let (_, min_args, max_args) = COMMANDS.iter().find(|(c, _, _)| c == command).unwrap();
+    if format.is_none() || command_args.len() < *min_args || command_args.len() > *max_args {
+        usage(&args[0]);
+        return Ok(())
+    }
+    let format = format.unwrap();
+    if format == Format::NetWare4 {
+        println!("the NetWare 4.x bindery emulation is read-only, use NetWare Administrator instead");
+        return Ok(())
+    }
+
+    let mut file_data: Vec<Vec<u8>> = Vec::new();
+    for fname in files {
+        file_data.push(std::fs::read(fname)?);
+    }
+    let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+    let mut bindery = Bindery::new(format, &file_slices)?;
+
+    match run_command(&mut bindery, command, command_args) {
+        Ok(msg) => {
+            // Only the modified records are written, at their own offset
+            let mut outputs = Vec::new();
+            for fname in files {
+                outputs.push(std::fs::OpenOptions::new().write(true).open(fname)?);
+            }
+            for (file, offset, record) in bindery.changed_records()? {
+                outputs[file].seek(SeekFrom::Start(offset as u64))?;
+                outputs[file].write_all(&record)?;
+            }
+            println!("{}", msg);
+        },
+        Err(msg) => {
+            println!("{}", msg);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::Path;
+use nlm_tools::nlm::{NLM, NLMExport};
+use nlm_tools::resolve;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.sym NLM|DIR ...", prog);
+    println!();
+    println!("Checks whether the symbols imported by every NLM (or every NLM in DIR) are");
+    println!("exported by the server whose symbols are in server.sym, as written by");
+    println!("extract-server-symbols, or by one of the other NLMs given");
+    println!();
+    println!("options:");
+    println!("  --verbose            list every import along with where it is resolved,");
+    println!("                       instead of only the missing ones");
+}
+
+struct Module {
+    name: String,
+    imports: Vec<String>,
+    exports: Vec<String>,
+}
+
+fn read_module(path: &Path) -> Option<Module> {
+    let data = std::fs::read(path).ok()?;
+    let nlm = match NLM::new(&data) {
+        Ok(nlm) => nlm,
+        Err(_) => return None,
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let imports = match nlm.get_externals() {
+        Ok(externals) => externals.into_iter().map(|e| e.name).collect(),
+        Err(e) => {
+            println!("WARNING: cannot read the imports of {}: {}", name, e);
+            return None;
+        }
+    };
+    let exports = nlm.get_exports().unwrap_or_default().into_iter()
+        .map(|e| match e { NLMExport::Code(name, _) | NLMExport::Data(name, _) => name })
+        .collect();
+    Some(Module{ name, imports, exports })
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut verbose = false;
+    let mut files: Vec<String> = Vec::new();
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
+            "--verbose" => { verbose = true; },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() < 2 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let server_symbols: HashSet<String> = resolve::parse_symbol_file(&std::fs::read_to_string(&files[0])?)
+        .into_iter().map(|s| s.name).collect();
+    if server_symbols.is_empty() {
+        println!("no symbols found in '{}'", files[0]);
+        return Ok(())
+    }
+
+    // Files in directories which are not NLMs are skipped
+    let mut modules: Vec<Module> = Vec::new();
+    for fname in &files[1..] {
+        let path = Path::new(fname);
+        if path.is_dir() {
+            let mut entries: Vec<_> = std::fs::read_dir(path)?.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            entries.sort();
+            modules.extend(entries.iter().filter(|p| p.is_file()).filter_map(|p| read_module(p)));
+        } else {
+            match read_module(path) {
+                Some(module) => modules.push(module),
+                None => println!("WARNING: '{}' is not an NLM, skipped", fname),
+            }
+        }
+    }
+
+    let mut exported_by: HashMap<&str, &str> = HashMap::new();
+    for module in &modules {
+        for name in &module.exports {
+            exported_by.entry(name).or_insert(&module.name);
+        }
+    }
+
+    let mut complete = 0;
+    for module in &modules {
+        let mut from_server = 0;
+        let mut from_modules = 0;
+        let mut lines: Vec<String> = Vec::new();
+        let mut missing: Vec<&str> = Vec::new();
+        for name in &module.imports {
+            let provider = if server_symbols.contains(name) {
+                from_server += 1;
+                "server"
+            } else if let Some(other) = exported_by.get(name.as_str()).filter(|m| **m != module.name) {
+                from_modules += 1;
+                other
+            } else {
+                missing.push(name);
+                "MISSING"
+            };
+            lines.push(format!("  {:<32} {}", name, provider));
+        }
+        println!("{}: {} imports, {} from the server, {} from other modules, {} missing",
+            module.name, module.imports.len(), from_server, from_modules, missing.len());
+        if verbose {
+            for line in &lines {
+                println!("{}", line);
+            }
+        } else {
+            for name in &missing {
+                println!("  missing {}", name);
+            }
+        }
+        if missing.is_empty() { complete += 1; }
+    }
+    println!("{} of {} modules resolve all their imports", complete, modules.len());
+    Ok(())
+}
@@ -0,0 +1,95 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::coredump::{CoreDump, REGISTER_NAMES};
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::modules;
+use nlm_tools::symbols::Image;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] core.img", prog);
+    println!();
+    println!("Prints the ABEND information and register state of a core dump written by the");
+    println!("NetWare debugger. The core dump itself is accepted by all tools that take a");
+    println!("memory dump");
+    println!();
+    println!("options:");
+    println!("  --memory out.bin     write the memory contents as flat dump of physical memory");
+}
+
+fn print_core(core: &CoreDump, data: &[u8]) {
+    println!("abend: {}", core.reason);
+    println!("running process: {}", core.process);
+    println!();
+    for (n, (name, value)) in REGISTER_NAMES.iter().zip(core.registers.iter()).enumerate() {
+        print!("{:>6} = {:08x}", name, value);
+        if n % 4 == 3 || n == REGISTER_NAMES.len() - 1 { println!(); } else { print!("  "); }
+    }
+
+    // Only useful if the code that failed is part of a loaded module
+    let eip = core.register("eip").unwrap_or(0);
+    let image = Image::new(data, 0);
+    if let Some(module) = modules::find_modules(&image).iter().find(|m| m.is_code(eip)) {
+        println!();
+        println!("eip is within {}+{:x} ({})", module.file_name, eip - module.code_address, module.description);
+    }
+
+    println!();
+    println!("{} memory ranges", core.ranges.len());
+    for range in &core.ranges {
+        println!("  {:08x}-{:08x} at file offset {:x}", range.address, range.address as u64 + range.length as u64, range.offset);
+    }
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut memory_fname: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--memory" => {
+                memory_fname = arg_iter.next().cloned();
+                if memory_fname.is_none() {
+                    println!("--memory requires a file name");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read core dump '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let core = match &dump.core {
+        Some(core) => core,
+        None => {
+            println!("'{}' is not a NetWare core dump ({} memory dump)", files[0], dump.format.name());
+            return Ok(())
+        }
+    };
+    print_core(core, &dump.data);
+    if let Some(fname) = memory_fname {
+        std::fs::write(&fname, &dump.data)?;
+    }
+    Ok(())
+}
@@ -4,189 +4,1292 @@
  * Copyright (c) 2023 Rink Springer <rink@rink.nu>
  * For conditions of distribution and use, see LICENSE file
  */
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
-use std::io::{Cursor, Read};
-use byteorder::{LittleEndian, ReadBytesExt};
-
-#[derive(Debug)]
-pub struct Object {
-    pub objid: u32,
-    pub objtype: u16,
-    pub name: String,
-    pub security: u8,
-    pub property: u32,
-    pub unk1: u32,
-}
-
-fn read_objects(data: &[ u8 ]) -> Result<Vec<Object>, std::io::Error> {
-    let mut rdr = Cursor::new(data);
-
-    let mut result: Vec<Object> = Vec::new();
-    loop {
-        let objid = rdr.read_u32::<LittleEndian>();
-        if objid.is_err() { break; }
-        let objid = objid.unwrap();
-        let objtype = rdr.read_u16::<LittleEndian>()?;
-        let namelen = rdr.read_u8()?;
-        let mut nameval = [ 0u8; 48 ];
-        rdr.read_exact(&mut nameval)?;
-        let security = rdr.read_u8()?;
-        let property = rdr.read_u32::<LittleEndian>()?;
-        let unk1 = rdr.read_u32::<LittleEndian>()?;
-
-        let name = std::str::from_utf8(&nameval[0..namelen as usize]).unwrap().to_string();
-
-        let object = Object{
-            objid,
-            objtype,
-            name,
-            security,
-            property,
-            unk1
-        };
-        result.push(object);
-    }
-
-    Ok(result)
-}
-
-#[derive(Debug)]
-pub struct Property {
-    pub propid: u32,
-    pub name: String,
-    pub flags: u8,
-    pub security: u8,
-    pub owner: u32,
-    pub next: u32,
-    pub value: u32,
-}
-
-fn read_properties(data: &[ u8 ]) -> Result<Vec<Property>, std::io::Error> {
-    let mut rdr = Cursor::new(data);
-
-    let mut result: Vec<Property> = Vec::new();
-    loop {
-        let propid = rdr.read_u32::<LittleEndian>();
-        if propid.is_err() { break; }
-        let propid = propid.unwrap();
-        let namelen = rdr.read_u8()?;
-        let mut nameval = [ 0u8; 15 ];
-        rdr.read_exact(&mut nameval)?;
-        let flags = rdr.read_u8()?;
-        let security = rdr.read_u8()?;
-        let owner = rdr.read_u32::<LittleEndian>()?;
-        let next = rdr.read_u32::<LittleEndian>()?;
-        let value = rdr.read_u32::<LittleEndian>()?;
-        let name = std::str::from_utf8(&nameval[0..namelen as usize]).unwrap().to_string();
-
-        let property = Property{
-            propid,
-            name,
-            flags,
-            security,
-            owner,
-            next,
-            value
-        };
-        result.push(property);
-    }
-
-    Ok(result)
-}
-
-#[derive(Debug)]
-pub struct Value {
-    pub valueid: u32,
-    pub owner: u32,
-    pub next: u32,
-    pub sequence: u16,
-    pub data: [ u8; 128 ],
-}
-
-fn read_values(data: &[ u8 ]) -> Result<Vec<Value>, std::io::Error> {
-    let mut rdr = Cursor::new(data);
-
-    let mut result: Vec<Value> = Vec::new();
-    loop {
-        let valueid = rdr.read_u32::<LittleEndian>();
-        if valueid.is_err() { break; }
-        let valueid = valueid.unwrap();
-        let owner = rdr.read_u32::<LittleEndian>()?;
-        let next = rdr.read_u32::<LittleEndian>()?;
-        let sequence = rdr.read_u16::<LittleEndian>()?;
+use std::io::{IsTerminal, Read};
+use nlm_tools::bindery::{self, Bindery, Format, Object, Property, OT_FILE_SERVER, OT_GROUP, OT_PRINT_QUEUE, OT_PRINT_SERVER, OT_USER};
+use nlm_tools::codepage::{self, Codepage};
+use nlm_tools::crypt::{self, PASSWORD_HASH_LEN};
+use nlm_tools::ncp::{self, NCPError};
+use nlm_tools::ncp::connection::Connection;
+use nlm_tools::nds::Dib;
+use nlm_tools::nwfs::{self, volume::Volume};
+use byteorder::{BigEndian, ByteOrder};
+use serde_json::{json, Value};
 
-        let mut data = [ 0u8; 128 ];
-        rdr.read_exact(&mut data)?;
+// Sockets commonly found in IPX addresses stored in the bindery
+const WELL_KNOWN_SOCKETS: [ u16; 6 ] = [ 0x0451, 0x0452, 0x0453, 0x0455, 0x0456, 0x8060 ];
 
-        let value = Value{
-            valueid,
-            owner,
-            next,
-            sequence,
-            data
-        };
-        result.push(value);
+// Returns descriptions of recognizable values starting at the given range of
+// positions: object ID's (hi-lo) that exist in the bindery, IPX addresses
+// with a well-known socket and date/time stamps. Positions are shown
+// relative to offset
+fn annotate(data: &[u8], offset: usize, range: std::ops::Range<usize>, bindery: &Bindery) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut pos = range.start;
+    while pos < range.end {
+        let rest = &data[pos..];
+        if pos.is_multiple_of(4) && rest.len() >= 4 {
+            if let Some(o) = bindery.find_object(BigEndian::read_u32(rest)) {
+                result.push(format!("{:x}: object '{}'", offset + pos, o.name));
+                pos += 4;
+                continue;
+            }
+        }
+        if rest.len() >= 12 && rest[0..4].iter().any(|b| *b != 0) && WELL_KNOWN_SOCKETS.contains(&BigEndian::read_u16(&rest[10..])) {
+            result.push(format!("{:x}: ipx {}", offset + pos, bindery::format_net_address(rest)));
+            pos += 12;
+            continue;
+        }
+        if rest.len() >= 6 && (80..=130).contains(&rest[0]) && (1..=12).contains(&rest[1]) && (1..=31).contains(&rest[2]) && rest[3] < 24 && rest[4] < 60 && rest[5] < 60 {
+            result.push(format!("{:x}: date {}", offset + pos, bindery::format_date(&rest[0..6])));
+            pos += 6;
+            continue;
+        }
+        pos += 1;
     }
-
-    Ok(result)
+    result
 }
 
-fn dump_data(data: &[u8], offset: usize, prefix: &str) {
+fn dump_data(data: &[u8], offset: usize, prefix: &str, bindery: &Bindery) {
     const BYTES_PER_LINE: usize  = 16;
     for index in (0..data.len()).step_by(BYTES_PER_LINE) {
+        let line = &data[index..std::cmp::min(index + BYTES_PER_LINE, data.len())];
         print!("{}{:08x}  ", prefix, offset + index);
         for n in 0..BYTES_PER_LINE {
-            let b = data[index + n];
-            print!(" {:02x}", b);
+            match line.get(n) {
+                Some(b) => print!(" {:02x}", b),
+                None => print!("   "),
+            }
         }
         print!("  |");
-        for n in 0..BYTES_PER_LINE {
-            let b = data[index + n] as char;
+        for b in line {
+            let b = *b as char;
             if b.is_ascii_alphanumeric() {
                 print!("{}", b);
             } else {
                 print!(".");
             }
         }
-        println!("|");
+        print!("{}|", " ".repeat(BYTES_PER_LINE - line.len()));
+        let annotations = annotate(data, offset, index..index + line.len(), bindery);
+        if !annotations.is_empty() {
+            print!("  {}", annotations.join(", "));
+        }
+        println!();
     }
 }
 
-fn main() -> Result<(), std::io::Error> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
-        println!("usage: {} net$obj.sys net$prop.sys net$val.sys", args[0]);
-        return Ok(())
+fn dump_set(data: &[u8], bindery: &Bindery, prefix: &str) {
+    for objid in bindery::read_set(data) {
+        match bindery.find_object(objid) {
+            Some(o) => println!("{}object id {:x} type {:x} name '{}'", prefix, objid, o.objtype, o.name),
+            None => println!("{}object id {:x} <unknown object>", prefix, objid),
+        }
     }
-    let obj_fname = &args[1];
-    let prop_fname = &args[2];
-    let val_fname = &args[3];
-
-    let obj_data = std::fs::read(obj_fname)?;
-    let prop_data = std::fs::read(prop_fname)?;
-    let val_data = std::fs::read(val_fname)?;
-
-    let objects = read_objects(&obj_data)?;
-    let properties = read_properties(&prop_data)?;
-    let values = read_values(&val_data)?;
+}
 
-    for o in objects {
-        println!("object id {:x} type {:x} security {:x} name '{}'", o.objid, o.objtype, o.security, o.name);
+// Broken chains are reported along with the offset of the record containing
+// the reference, after which the remainder of the bindery is dumped
+fn dump_bindery(bindery: &Bindery, filter: &Filter) {
+    let file_names = bindery.format.file_names();
+    let (obj_file, prop_file, val_file) = bindery.format.file_indices();
+    for o in &bindery.objects {
+        if !filter.matches(o, bindery) { continue; }
+        let persistence = if o.is_dynamic() { "dynamic" } else { "static" };
+        println!("object id {:x} type {:x} flags {:x} ({}) security {:x} ({}) name '{}'", o.objid, o.objtype, o.flags, persistence, o.security, bindery::security_string(o.security), o.name);
+        let mut referrer = format!("object id {:x} at offset {:x} of {}", o.objid, o.offset, file_names[obj_file]);
+        let mut seen_properties: HashSet<u32> = HashSet::new();
         let mut propertyid = o.property;
         while propertyid != 0xffffffff {
-            let p = properties.iter().filter(|x| x.propid == propertyid).next().expect("property not found");
-            println!("  property id {:x} flags {:x} security {:x} owner {:x} name '{}'", p.propid, p.flags, p.security, p.owner, p.name);
+            if !seen_properties.insert(propertyid) {
+                println!("  <broken chain: {} loops back to property id {:x}>", referrer, propertyid);
+                break;
+            }
+            let p = match bindery.find_property_by_id(propertyid) {
+                Some(p) => p,
+                None => {
+                    println!("  <broken chain: {} refers to missing property id {:x}>", referrer, propertyid);
+                    break;
+                }
+            };
+            referrer = format!("property id {:x} at offset {:x} of {}", p.propid, p.offset, file_names[prop_file]);
+            propertyid = p.next;
+            if filter.static_only && p.is_dynamic() { continue; }
+            let persistence = if p.is_dynamic() { "dynamic" } else { "static" };
+            let kind = if p.is_set() { "set" } else { "item" };
+            println!("  property id {:x} flags {:x} ({} {}) security {:x} ({}) owner {:x} name '{}'", p.propid, p.flags, persistence, kind, p.security, bindery::security_string(p.security), p.owner, p.name);
+            let mut value_referrer = referrer.clone();
+            let mut seen_values: HashSet<u32> = HashSet::new();
             let mut valueid = p.value;
             let mut offset = 0;
             while valueid != 0xffffffff {
-                let v = values.iter().filter(|x| x.valueid == valueid).next().expect("value not found");
+                if !seen_values.insert(valueid) {
+                    println!("    <broken chain: {} loops back to value id {:x}>", value_referrer, valueid);
+                    break;
+                }
+                let v = match bindery.find_value(valueid) {
+                    Some(v) => v,
+                    None => {
+                        println!("    <broken chain: {} refers to missing value id {:x}>", value_referrer, valueid);
+                        break;
+                    }
+                };
+                value_referrer = format!("value id {:x} at offset {:x} of {}", v.valueid, v.offset, file_names[val_file]);
                 println!("    value owner {:x} sequence {:x}", v.owner, v.sequence);
-                dump_data(&v.data, offset, "      ");
+                if p.is_set() {
+                    dump_set(&v.data, bindery, "      ");
+                } else {
+                    dump_data(&v.data, offset, "      ", bindery);
+                }
                 offset += v.data.len();
                 valueid = v.next;
             }
+        }
+    }
+}
+
+// ANSI colours are only used when writing to a terminal
+struct Colour {
+    enabled: bool,
+}
+
+impl Colour {
+    fn new() -> Self {
+        let enabled = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+        Self{ enabled }
+    }
+
+    fn paint(&self, code: &str, s: &str) -> String {
+        if self.enabled { format!("\x1b[{}m{}\x1b[0m", code, s) } else { s.to_string() }
+    }
+}
+
+// Compact view: objects, their properties and the decoded values, one per
+// line
+fn dump_tree(bindery: &Bindery, filter: &Filter) {
+    let colour = Colour::new();
+    for o in &bindery.objects {
+        if !filter.matches(o, bindery) { continue; }
+        let type_name = bindery::object_type_name(o.objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", o.objtype));
+        let dynamic = if o.is_dynamic() { ", dynamic" } else { "" };
+        println!("{} ({}, id {:x}{})", colour.paint("1", &o.name), type_name, o.objid, dynamic);
+        let properties: Vec<&Property> = bindery.object_properties(o).iter()
+            .filter_map(|propid| bindery.find_property_by_id(*propid))
+            .filter(|p| !filter.static_only || !p.is_dynamic())
+            .collect();
+        for (n, p) in properties.iter().enumerate() {
+            let last = n == properties.len() - 1;
+            println!("{} {}", if last { "└─" } else { "├─" }, colour.paint("36", &p.name));
+            for line in bindery::decode_property(bindery, p) {
+                println!("{}   {}", if last { " " } else { "│" }, line);
+            }
+        }
+    }
+}
+
+// Writes the password hashes as objid:hash, one line per object. The object
+// ID is the salt used by the hash algorithm
+fn dump_hashes(bindery: &Bindery, filter: &Filter) {
+    for o in &bindery.objects {
+        if !filter.matches(o, bindery) { continue; }
+        let p = match bindery.find_property(o, "PASSWORD") {
+            Some(p) => p,
+            None => continue,
+        };
+        let data = bindery.property_data(p);
+        if data.len() < PASSWORD_HASH_LEN {
+            eprintln!("warning: object '{}' has a truncated PASSWORD property", o.name);
+            continue;
+        }
+        let hash: String = data[0..PASSWORD_HASH_LEN].iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{:08x}:{}", o.objid, hash);
+    }
+}
+
+fn check_password(bindery: &Bindery, user: &str, password: &str) {
+    let o = match bindery.find_object_by_name(user, None) {
+        Some(o) => o,
+        None => {
+            println!("object '{}' not found", user);
+            return;
+        }
+    };
+    let data = match bindery.find_property(o, "PASSWORD") {
+        Some(p) => bindery.property_data(p),
+        None => {
+            println!("object '{}' has no password", o.name);
+            return;
+        }
+    };
+    if data.len() < PASSWORD_HASH_LEN {
+        println!("object '{}' has a truncated PASSWORD property", o.name);
+        return;
+    }
+
+    // Clients always convert the password to uppercase
+    let hash = crypt::hash_object_password(o.objid, &bindery.codepage.encode(&password.to_ascii_uppercase()));
+    if hash[..] == data[0..PASSWORD_HASH_LEN] {
+        println!("password for '{}' is correct", o.name);
+    } else {
+        println!("password for '{}' is incorrect", o.name);
+    }
+}
+
+struct Checker {
+    issues: usize,
+}
+
+impl Checker {
+    fn report(&mut self, file: &str, offset: usize, msg: String) {
+        println!("{} offset {:x}: {}", file, offset, msg);
+        self.issues += 1;
+    }
+
+    // Verifies the length-prefixed name at the given record, which must not be
+    // empty, fit the field and be zero padded
+    fn check_name(&mut self, file: &str, record: &[u8], name_offset: usize, name_size: usize, offset: usize) {
+        let namelen = record[name_offset] as usize;
+        let field = &record[name_offset + 1..name_offset + 1 + name_size];
+        if namelen == 0 || namelen > name_size {
+            self.report(file, offset, format!("invalid name length {}", namelen));
+            return;
+        }
+        if field[0..namelen].iter().any(|b| *b < 0x20 || *b == 0x7f) {
+            self.report(file, offset, "name contains control characters".to_string());
+        }
+        if field[namelen..].iter().any(|b| *b != 0) {
+            self.report(file, offset, "name padding is not zero".to_string());
+        }
+    }
+}
+
+fn check_bindery(files: &[ Vec<u8> ], bindery: &Bindery) {
+    let format = bindery.format;
+    let file_names = format.file_names();
+    let (obj_file, prop_file, val_file) = format.file_indices();
+    let (obj_file, prop_file, val_file) = (file_names[obj_file], file_names[prop_file], file_names[val_file]);
+    let (objects, properties, values) = (&bindery.objects, &bindery.properties, &bindery.values);
+    let mut checker = Checker{ issues: 0 };
+
+    for ((file, data), size) in file_names.iter().zip(files).zip(format.record_sizes()) {
+        if data.len() % size != 0 {
+            checker.report(file, data.len() - data.len() % size, format!("trailing {} bytes do not form a complete record", data.len() % size));
+        }
+    }
+    if let Some((name_offset, name_size)) = format.object_name_field() {
+        for o in objects {
+            checker.check_name(obj_file, &files[format.file_indices().0][o.offset..], name_offset, name_size, o.offset);
+        }
+    }
+    if let Some((name_offset, name_size)) = format.property_name_field() {
+        for p in properties {
+            checker.check_name(prop_file, &files[format.file_indices().1][p.offset..], name_offset, name_size, p.offset);
+        }
+    }
+
+    let mut object_index: HashMap<u32, usize> = HashMap::new();
+    for (n, o) in objects.iter().enumerate() {
+        if object_index.insert(o.objid, n).is_some() {
+            checker.report(obj_file, o.offset, format!("duplicate object id {:x}", o.objid));
+        }
+    }
+    let mut property_index: HashMap<u32, usize> = HashMap::new();
+    for (n, p) in properties.iter().enumerate() {
+        if property_index.insert(p.propid, n).is_some() {
+            checker.report(prop_file, p.offset, format!("duplicate property id {:x}", p.propid));
+        }
+    }
+    let mut value_index: HashMap<u32, usize> = HashMap::new();
+    for (n, v) in values.iter().enumerate() {
+        if value_index.insert(v.valueid, n).is_some() {
+            checker.report(val_file, v.offset, format!("duplicate value id {:x}", v.valueid));
+        }
+    }
+
+    // Walk object -> property -> value chains; every record must be reached
+    // exactly once
+    let mut seen_properties: HashSet<usize> = HashSet::new();
+    let mut seen_values: HashSet<usize> = HashSet::new();
+    for o in objects {
+        let mut propertyid = o.property;
+        let mut chain: HashSet<u32> = HashSet::new();
+        while propertyid != 0xffffffff {
+            if !chain.insert(propertyid) {
+                checker.report(obj_file, o.offset, format!("object '{}' property chain contains a cycle at property id {:x}", o.name, propertyid));
+                break;
+            }
+            let p_index = match property_index.get(&propertyid) {
+                Some(n) => *n,
+                None => {
+                    checker.report(obj_file, o.offset, format!("object '{}' refers to nonexistent property id {:x}", o.name, propertyid));
+                    break;
+                }
+            };
+            let p = &properties[p_index];
+            let p_offset = p.offset;
+            if !seen_properties.insert(p_index) {
+                checker.report(prop_file, p_offset, format!("property id {:x} is linked from multiple chains", p.propid));
+                break;
+            }
+            if p.owner != o.objid {
+                checker.report(prop_file, p_offset, format!("property '{}' has owner {:x}, but is linked from object {:x}", p.name, p.owner, o.objid));
+            }
+
+            let mut valueid = p.value;
+            let mut value_chain: HashSet<u32> = HashSet::new();
+            let mut first_sequence: Option<u16> = None;
+            while valueid != 0xffffffff {
+                if !value_chain.insert(valueid) {
+                    checker.report(prop_file, p_offset, format!("property '{}' value chain contains a cycle at value id {:x}", p.name, valueid));
+                    break;
+                }
+                let v_index = match value_index.get(&valueid) {
+                    Some(n) => *n,
+                    None => {
+                        checker.report(prop_file, p_offset, format!("property '{}' refers to nonexistent value id {:x}", p.name, valueid));
+                        break;
+                    }
+                };
+                let v = &values[v_index];
+                let v_offset = v.offset;
+                if !seen_values.insert(v_index) {
+                    checker.report(val_file, v_offset, format!("value id {:x} is linked from multiple chains", v.valueid));
+                    break;
+                }
+                if v.owner != p.propid {
+                    checker.report(val_file, v_offset, format!("value id {:x} has owner {:x}, but is linked from property {:x}", v.valueid, v.owner, p.propid));
+                }
+                let expected = first_sequence.get_or_insert(v.sequence).wrapping_add(value_chain.len() as u16 - 1);
+                if v.sequence != expected {
+                    checker.report(val_file, v_offset, format!("value id {:x} has sequence {:x}, expected {:x}", v.valueid, v.sequence, expected));
+                }
+                valueid = v.next;
+            }
             propertyid = p.next;
         }
     }
+
+    for (n, p) in properties.iter().enumerate() {
+        if !seen_properties.contains(&n) {
+            checker.report(prop_file, p.offset, format!("orphaned property id {:x} '{}' (owner {:x})", p.propid, p.name, p.owner));
+        }
+    }
+    for (n, v) in values.iter().enumerate() {
+        if !seen_values.contains(&n) {
+            checker.report(val_file, v.offset, format!("orphaned value id {:x} (owner {:x})", v.valueid, v.owner));
+        }
+    }
+
+    println!("{} objects, {} properties, {} values checked: {} issue(s) found", objects.len(), properties.len(), values.len(), checker.issues);
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::new();
+    for chunk in data.chunks(3) {
+        let b = [ chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0) ];
+        let v = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for n in 0..4 {
+            if n <= chunk.len() {
+                result.push(ALPHABET[((v >> (18 - 6 * n)) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+// Escapes a value for use in a distinguished name (RFC 4514)
+fn ldap_escape_dn(s: &str) -> String {
+    let mut result = String::new();
+    let last = s.chars().count().saturating_sub(1);
+    for (n, c) in s.chars().enumerate() {
+        if ",+\"\\<>;=".contains(c) || (n == 0 && (c == ' ' || c == '#')) || (n == last && c == ' ') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+// Writes an LDIF attribute, using base64 if the value isn't a safe string
+// (RFC 2849)
+fn ldif_attr(attr: &str, value: &str) {
+    let safe = value.bytes().all(|b| (0x20..0x7f).contains(&b)) &&
+        !value.starts_with([ ' ', ':', '<' ]) && !value.ends_with(' ');
+    if safe {
+        println!("{}: {}", attr, value);
+    } else {
+        println!("{}:: {}", attr, base64_encode(value.as_bytes()));
+    }
+}
+
+fn dump_ldif(bindery: &Bindery, filter: &Filter, base_dn: &str) {
+    let dn_of = |o: &Object| -> String {
+        if o.objtype == OT_USER {
+            format!("uid={},ou=Users,{}", ldap_escape_dn(&o.name.to_ascii_lowercase()), base_dn)
+        } else {
+            format!("cn={},ou=Groups,{}", ldap_escape_dn(&o.name.to_ascii_lowercase()), base_dn)
+        }
+    };
+
+    for ou in [ "Users", "Groups" ] {
+        ldif_attr("dn", &format!("ou={},{}", ou, base_dn));
+        println!("objectClass: organizationalUnit");
+        println!("ou: {}", ou);
+        println!();
+    }
+
+    for o in &bindery.objects {
+        if o.objtype != OT_USER && o.objtype != OT_GROUP { continue; }
+        if !filter.matches(o, bindery) { continue; }
+
+        let full_name = bindery.find_property(o, "IDENTIFICATION")
+            .map(|p| bindery::read_string_property(&bindery.property_data(p), bindery.codepage))
+            .filter(|s| !s.is_empty());
+
+        println!("# NetWare object id {:x}", o.objid);
+        ldif_attr("dn", &dn_of(o));
+        if o.objtype == OT_USER {
+            let full_name = full_name.unwrap_or_else(|| o.name.clone());
+            let surname = full_name.split_whitespace().last().unwrap_or(&o.name).to_string();
+            println!("objectClass: inetOrgPerson");
+            ldif_attr("uid", &o.name.to_ascii_lowercase());
+            ldif_attr("cn", &full_name);
+            ldif_attr("sn", &surname);
+            if let Some(given_name) = full_name.split_whitespace().next().filter(|s| *s != surname) {
+                ldif_attr("givenName", given_name);
+            }
+        } else {
+            println!("objectClass: groupOfNames");
+            ldif_attr("cn", &o.name.to_ascii_lowercase());
+            if let Some(description) = full_name {
+                ldif_attr("description", &description);
+            }
+            let members = bindery.find_property(o, "GROUP_MEMBERS")
+                .map(|p| bindery::read_set(&bindery.property_data(p)))
+                .unwrap_or_default();
+            let mut num_members = 0;
+            for objid in members {
+                if let Some(m) = bindery.find_object(objid).filter(|x| x.objtype == OT_USER || x.objtype == OT_GROUP) {
+                    ldif_attr("member", &dn_of(m));
+                    num_members += 1;
+                }
+            }
+            if num_members == 0 {
+                // groupOfNames requires at least one member
+                ldif_attr("member", &format!("ou=Groups,{}", base_dn));
+            }
+        }
+        println!();
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Unix account names are lowercase and may only contain letters, digits, -
+// and _
+fn unix_name(name: &str) -> String {
+    name.to_ascii_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+// UID's and GID's are derived from the object ID by adding its lower 24 bits
+// to the base; the upper bits are assigned by the server sequentially
+fn unix_id(base: u32, objid: u32) -> u32 {
+    base + (objid & 0x00ffffff)
+}
+
+fn dump_unix(bindery: &Bindery, filter: &Filter, format: &str, uid_base: u32, gid_base: u32) {
+    let users: Vec<&Object> = bindery.objects.iter().filter(|o| o.objtype == OT_USER && filter.matches(o, bindery)).collect();
+    let groups: Vec<&Object> = bindery.objects.iter().filter(|o| o.objtype == OT_GROUP && filter.matches(o, bindery)).collect();
+    let mut seen_ids: HashMap<u32, &str> = HashMap::new();
+    for o in if format == "group" { &groups } else { &users } {
+        let id = unix_id(if format == "group" { gid_base } else { uid_base }, o.objid);
+        if let Some(other) = seen_ids.insert(id, &o.name) {
+            eprintln!("warning: '{}' and '{}' both map to id {}", other, o.name, id);
+        }
+    }
+
+    match format {
+        "passwd" => {
+            let everyone = bindery.find_object_by_name("EVERYONE", Some(OT_GROUP));
+            for o in &users {
+                let full_name = bindery.find_property(o, "IDENTIFICATION")
+                    .map(|p| bindery::read_string_property(&bindery.property_data(p), bindery.codepage))
+                    .unwrap_or_default()
+                    .replace([ ':', '\n' ], " ");
+                // The primary group is the first group the user is a member of
+                let primary_group = bindery.find_property(o, "GROUPS_I'M_IN")
+                    .and_then(|p| bindery::read_set(&bindery.property_data(p)).first().copied())
+                    .or(everyone.map(|e| e.objid));
+                let gid = primary_group.map(|g| unix_id(gid_base, g)).unwrap_or(gid_base);
+                let name = unix_name(&o.name);
+                println!("{}:x:{}:{}:{}:/home/{}:/bin/sh", name, unix_id(uid_base, o.objid), gid, full_name, name);
+            }
+        },
+        "group" => {
+            for o in &groups {
+                let members: Vec<String> = bindery.find_property(o, "GROUP_MEMBERS")
+                    .map(|p| bindery::read_set(&bindery.property_data(p)))
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|objid| bindery.find_object(*objid))
+                    .filter(|m| m.objtype == OT_USER)
+                    .map(|m| unix_name(&m.name))
+                    .collect();
+                println!("{}:x:{}:{}", unix_name(&o.name), unix_id(gid_base, o.objid), members.join(","));
+            }
+        },
+        _ => {
+            // NetWare password hashes cannot be converted, so the accounts are
+            // created without password; disabled accounts remain disabled
+            for o in &users {
+                let disabled = bindery.find_property(o, "LOGIN_CONTROL")
+                    .and_then(|p| bindery::LoginControl::from(&bindery.property_data(p)).ok())
+                    .is_some_and(|lc| lc.account_disabled);
+                let flags = if disabled { "[UD         ]" } else { "[U          ]" };
+                println!("{}:{}:{}:{}:{}:LCT-00000000:", unix_name(&o.name), unix_id(uid_base, o.objid), "X".repeat(32), "X".repeat(32), flags);
+            }
+        }
+    }
+}
+
+// Writes a Graphviz graph with group membership and security equivalence
+// edges. Membership is recorded in both GROUP_MEMBERS of the group and
+// GROUPS_I'M_IN of the member; either one results in a single edge
+fn dump_dot(bindery: &Bindery, filter: &Filter) {
+    let mut members: BTreeSet<(u32, u32)> = BTreeSet::new();
+    let mut equals: BTreeSet<(u32, u32)> = BTreeSet::new();
+    for o in &bindery.objects {
+        if let Some(p) = bindery.find_property(o, "GROUP_MEMBERS") {
+            for objid in bindery::read_set(&bindery.property_data(p)) {
+                members.insert((objid, o.objid));
+            }
+        }
+        if let Some(p) = bindery.find_property(o, "GROUPS_I'M_IN") {
+            for objid in bindery::read_set(&bindery.property_data(p)) {
+                members.insert((o.objid, objid));
+            }
+        }
+        if let Some(p) = bindery.find_property(o, "SECURITY_EQUALS") {
+            for objid in bindery::read_set(&bindery.property_data(p)) {
+                // Every group a user is in is listed here as well
+                if !members.contains(&(o.objid, objid)) {
+                    equals.insert((o.objid, objid));
+                }
+            }
+        }
+    }
+    members.retain(|(member, group)| filter_either(bindery, filter, *member, *group));
+    equals.retain(|(from, to)| filter_either(bindery, filter, *from, *to));
+
+    let mut nodes: BTreeSet<u32> = BTreeSet::new();
+    for (from, to) in members.iter().chain(equals.iter()) {
+        nodes.insert(*from);
+        nodes.insert(*to);
+    }
+
+    println!("digraph bindery {{");
+    println!("  rankdir=LR;");
+    for objid in &nodes {
+        match bindery.find_object(*objid) {
+            Some(o) => {
+                let shape = match o.objtype {
+                    OT_USER => "ellipse",
+                    OT_GROUP => "box",
+                    _ => "diamond",
+                };
+                println!("  o{:x} [label=\"{}\", shape={}];", objid, dot_escape(&o.name), shape);
+            },
+            None => {
+                println!("  o{:x} [label=\"unknown {:x}\", shape=plaintext];", objid, objid);
+            }
+        }
+    }
+    for (member, group) in &members {
+        println!("  o{:x} -> o{:x};", member, group);
+    }
+    for (from, to) in &equals {
+        println!("  o{:x} -> o{:x} [style=dashed, label=\"equals\"];", from, to);
+    }
+    println!("}}");
+}
+
+// Edges are kept if either end matches the filter
+fn filter_either(bindery: &Bindery, filter: &Filter, a: u32, b: u32) -> bool {
+    [ a, b ].iter().any(|objid| bindery.find_object(*objid).is_some_and(|o| filter.matches(o, bindery)))
+}
+
+fn dump_stats(bindery: &Bindery, filter: &Filter) {
+    let mut objects_per_type: BTreeMap<u16, usize> = BTreeMap::new();
+    let mut property_usage: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut num_properties = 0;
+    let mut num_segments = 0;
+    let mut users_with_password = 0;
+    let mut users_without_password = 0;
+    let mut disabled_accounts: Vec<&str> = Vec::new();
+    let mut chains: Vec<(usize, &str, &str)> = Vec::new();
+    for o in &bindery.objects {
+        if !filter.matches(o, bindery) { continue; }
+        *objects_per_type.entry(o.objtype).or_default() += 1;
+
+        for propid in bindery.object_properties(o) {
+            let p = match bindery.find_property_by_id(propid) {
+                Some(p) => p,
+                None => break,
+            };
+            num_properties += 1;
+            *property_usage.entry(&p.name).or_default() += 1;
+
+            let segments = bindery.property_values(p).iter().filter(|valueid| bindery.find_value(**valueid).is_some()).count();
+            num_segments += segments;
+            chains.push((segments, &o.name, &p.name));
+        }
+
+        if o.objtype == OT_USER {
+            if bindery.find_property(o, "PASSWORD").is_some() {
+                users_with_password += 1;
+            } else {
+                users_without_password += 1;
+            }
+            let login_control = bindery.find_property(o, "LOGIN_CONTROL")
+                .and_then(|p| bindery::LoginControl::from(&bindery.property_data(p)).ok());
+            if login_control.is_some_and(|lc| lc.account_disabled) {
+                disabled_accounts.push(&o.name);
+            }
+        }
+    }
+
+    println!("objects per type:");
+    for (objtype, count) in &objects_per_type {
+        println!("  {:6} {:04x} {}", count, objtype, bindery::object_type_name(*objtype).unwrap_or(""));
+    }
+    println!("property usage:");
+    let mut property_usage: Vec<(&str, usize)> = property_usage.into_iter().collect();
+    property_usage.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (name, count) in &property_usage {
+        println!("  {:6} {}", count, name);
+    }
+    println!("totals:");
+    println!("  {:6} objects", objects_per_type.values().sum::<usize>());
+    println!("  {:6} properties", num_properties);
+    println!("  {:6} value segments", num_segments);
+    println!("users:");
+    println!("  {:6} with password", users_with_password);
+    println!("  {:6} without password", users_without_password);
+    println!("  {:6} disabled{}{}", disabled_accounts.len(), if disabled_accounts.is_empty() { "" } else { ": " }, disabled_accounts.join(", "));
+    println!("largest value chains:");
+    chains.sort_by_key(|c| std::cmp::Reverse(c.0));
+    for (segments, object, property) in chains.iter().take(10) {
+        println!("  {:6} segments in '{}' of '{}'", segments, property, object);
+    }
+}
+
+// Case-insensitive glob match supporting '*' and '?'
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|n| glob_match(rest, &s[n..])),
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((c, rest)) => !s.is_empty() && c.eq_ignore_ascii_case(&s[0]) && glob_match(rest, &s[1..]),
+    }
+}
+
+#[derive(Default)]
+struct Filter {
+    objtype: Option<u16>,
+    name: Option<String>,
+    property: Option<String>,
+    static_only: bool,
+}
+
+impl Filter {
+    fn matches(&self, o: &Object, bindery: &Bindery) -> bool {
+        if self.static_only && o.is_dynamic() { return false; }
+        if let Some(objtype) = self.objtype {
+            if o.objtype != objtype { return false; }
+        }
+        if let Some(name) = &self.name {
+            if !glob_match(name.as_bytes(), o.name.as_bytes()) { return false; }
+        }
+        if let Some(property) = &self.property {
+            if bindery.find_property(o, &property.to_ascii_uppercase()).is_none() { return false; }
+        }
+        true
+    }
+}
+
+enum Mode {
+    Dump,
+    Tree,
+    Hashes,
+    Check,
+    CheckPassword(String, String),
+    Ldif(String),
+    Dot,
+    Stats,
+    Queues,
+    Html(String),
+    PrintConfig(String),
+    Unix(String),
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html_object_link(bindery: &Bindery, objid: u32) -> String {
+    match bindery.find_object(objid) {
+        Some(o) => format!("<a href=\"#obj-{:x}\">{}</a>", objid, html_escape(&o.name)),
+        None => format!("&lt;unknown object {:x}&gt;", objid)
+    }
+}
+
+// Writes a standalone HTML report: an index of all objects per type,
+// followed by the details of every object. Set members and the sets
+// referring to an object are cross-linked
+fn dump_html(bindery: &Bindery, filter: &Filter, fname: &str) -> Result<(), std::io::Error> {
+    let mut referenced_by: HashMap<u32, Vec<(u32, &str)>> = HashMap::new();
+    for o in &bindery.objects {
+        for propid in bindery.object_properties(o) {
+            let p = match bindery.find_property_by_id(propid) {
+                Some(p) => p,
+                None => continue
+            };
+            if !p.is_set() { continue; }
+            for objid in bindery::read_set(&bindery.property_data(p)) {
+                referenced_by.entry(objid).or_default().push((o.objid, &p.name));
+            }
+        }
+    }
+
+    let objects: Vec<&Object> = bindery.objects.iter().filter(|o| filter.matches(o, bindery)).collect();
+    let mut by_type: BTreeMap<u16, Vec<&Object>> = BTreeMap::new();
+    for o in &objects {
+        by_type.entry(o.objtype).or_default().push(o);
+    }
+
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Bindery report</title>\n";
+    out += "<style>\nbody { font-family: sans-serif; }\ntable { border-collapse: collapse; }\n";
+    out += "td, th { border: 1px solid #ccc; padding: 2px 6px; text-align: left; vertical-align: top; }\n";
+    out += ".value { font-family: monospace; }\n</style>\n</head>\n<body>\n";
+    out += &format!("<h1>Bindery report</h1>\n<p>{} objects, {} properties, {} value segments</p>\n", bindery.objects.len(), bindery.properties.len(), bindery.values.len());
+
+    out += "<h2>Objects</h2>\n";
+    for (objtype, objects) in &by_type {
+        let type_name = bindery::object_type_name(*objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", objtype));
+        out += &format!("<h3>{} ({})</h3>\n<ul>\n", html_escape(&type_name), objects.len());
+        for o in objects {
+            out += &format!("<li>{}</li>\n", html_object_link(bindery, o.objid));
+        }
+        out += "</ul>\n";
+    }
+
+    out += "<h2>Details</h2>\n";
+    for o in &objects {
+        let type_name = bindery::object_type_name(o.objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", o.objtype));
+        out += &format!("<h3 id=\"obj-{:x}\">{}</h3>\n", o.objid, html_escape(&o.name));
+        out += &format!("<p>id {:x}, {}, {}, security {}</p>\n", o.objid, html_escape(&type_name), if o.is_dynamic() { "dynamic" } else { "static" }, bindery::security_string(o.security));
+        out += "<table>\n<tr><th>property</th><th>security</th><th>value</th></tr>\n";
+        for propid in bindery.object_properties(o) {
+            let p = match bindery.find_property_by_id(propid) {
+                Some(p) => p,
+                None => continue
+            };
+            if filter.static_only && p.is_dynamic() { continue; }
+            let value = if p.is_set() {
+                bindery::read_set(&bindery.property_data(p)).iter().map(|objid| html_object_link(bindery, *objid)).collect::<Vec<_>>()
+            } else {
+                bindery::decode_property(bindery, p).iter().map(|s| html_escape(s)).collect()
+            };
+            out += &format!("<tr><td>{}</td><td>{}</td><td class=\"value\">{}</td></tr>\n", html_escape(&p.name), bindery::security_string(p.security), value.join("<br>"));
+        }
+        out += "</table>\n";
+        if let Some(refs) = referenced_by.get(&o.objid) {
+            let refs: Vec<String> = refs.iter().map(|(objid, name)| format!("{} ({})", html_object_link(bindery, *objid), html_escape(name))).collect();
+            out += &format!("<p>Referenced by: {}</p>\n", refs.join(", "));
+        }
+    }
+    out += "</body>\n</html>\n";
+    std::fs::write(fname, out)
+}
+
+// Object types of queues: print, archive and job queues
+const QUEUE_TYPES: [ u16; 3 ] = [ 0x0003, 0x0008, 0x000a ];
+
+fn dump_set_names(bindery: &Bindery, o: &Object, property: &str) -> String {
+    let objids = match bindery.find_property(o, property) {
+        Some(p) => bindery::read_set(&bindery.property_data(p)),
+        None => return "-".to_string()
+    };
+    let names: Vec<String> = objids.iter().map(|objid| match bindery.find_object(*objid) {
+        Some(o) => o.name.clone(),
+        None => format!("<unknown object {:x}>", objid)
+    }).collect();
+    if names.is_empty() { "-".to_string() } else { names.join(", ") }
+}
+
+// Locates the queue directory on the volume; this is usually
+// SYS:SYSTEM/<queue id>.QDR, but Q_DIRECTORY is authoritative
+fn find_queue_directory(volume: &Volume, o: &Object, q_directory: Option<&str>) -> Option<u32> {
+    let mut candidates = Vec::new();
+    if let Some(path) = q_directory {
+        candidates.push(path.to_string());
+        candidates.push(format!("{}.QDR", path));
+    }
+    candidates.push(format!("SYSTEM/{:08X}.QDR", o.objid));
+    candidates.iter()
+        .filter_map(|path| volume.lookup(path))
+        .find(|e| e.is_directory())
+        .map(|e| e.entry)
+}
+
+fn dump_queues(bindery: &Bindery, filter: &Filter, volume: Option<&Volume>) {
+    for o in &bindery.objects {
+        if !QUEUE_TYPES.contains(&o.objtype) || !filter.matches(o, bindery) { continue; }
+        let type_name = bindery::object_type_name(o.objtype).unwrap_or("queue");
+        println!("queue '{}' id {:x} type {}", o.name, o.objid, type_name);
+        let q_directory = bindery.find_property(o, "Q_DIRECTORY")
+            .map(|p| bindery::read_string_property(&bindery.property_data(p), bindery.codepage));
+        println!("  directory: {}", q_directory.as_deref().unwrap_or("-"));
+        println!("  users: {}", dump_set_names(bindery, o, "Q_USERS"));
+        println!("  operators: {}", dump_set_names(bindery, o, "Q_OPERATORS"));
+        println!("  servers: {}", dump_set_names(bindery, o, "Q_SERVERS"));
+
+        let volume = match volume {
+            Some(volume) => volume,
+            None => continue
+        };
+        if let Some((vol_name, _)) = q_directory.as_deref().and_then(|d| d.split_once(':')) {
+            if !vol_name.eq_ignore_ascii_case(volume.name()) {
+                println!("  queue directory is not on volume {}", volume.name());
+                continue;
+            }
+        }
+        match find_queue_directory(volume, o, q_directory.as_deref()) {
+            Some(dir) => {
+                println!("  files:");
+                for e in volume.children(dir) {
+                    println!("    {:12} {:>10}", e.name, e.size);
+                }
+            },
+            None => println!("  queue directory not found on volume")
+        }
+    }
+}
+
+// Returns the members of the first set property present out of the names
+// given; PCONSOLE versions differ in the property names used
+fn set_members<'a>(bindery: &'a Bindery, o: &Object, properties: &[ &str ]) -> Vec<&'a Object> {
+    let p = match properties.iter().find_map(|name| bindery.find_property(o, name)) {
+        Some(p) => p,
+        None => return Vec::new()
+    };
+    bindery::read_set(&bindery.property_data(p)).iter().filter_map(|objid| bindery.find_object(*objid)).collect()
+}
+
+fn object_names(objects: &[ &Object ]) -> Vec<String> {
+    objects.iter().map(|o| o.name.clone()).collect()
+}
+
+fn dump_print_config_json(bindery: &Bindery, filter: &Filter) {
+    let file_server = bindery.objects.iter().find(|o| o.objtype == OT_FILE_SERVER).map(|o| o.name.clone());
+    let queues: Vec<&Object> = bindery.objects.iter().filter(|o| QUEUE_TYPES.contains(&o.objtype) && filter.matches(o, bindery)).collect();
+
+    let mut print_servers = Vec::new();
+    for ps in bindery.objects.iter().filter(|o| o.objtype == OT_PRINT_SERVER && filter.matches(o, bindery)) {
+        let serviced: Vec<String> = queues.iter()
+            .filter(|q| set_members(bindery, q, &[ "Q_SERVERS" ]).iter().any(|s| s.objid == ps.objid))
+            .map(|q| q.name.clone())
+            .collect();
+        print_servers.push(json!({
+            "name": ps.name,
+            "id": format!("{:08x}", ps.objid),
+            "operators": object_names(&set_members(bindery, ps, &[ "PS_OPERATORS", "OPERATORS" ])),
+            "users": object_names(&set_members(bindery, ps, &[ "PS_USERS", "USERS" ])),
+            "queues": serviced,
+        }));
+    }
+
+    let queues: Vec<Value> = queues.iter().map(|q| {
+        let directory = bindery.find_property(q, "Q_DIRECTORY").map(|p| bindery::read_string_property(&bindery.property_data(p), bindery.codepage));
+        json!({
+            "name": q.name,
+            "id": format!("{:08x}", q.objid),
+            "type": bindery::object_type_name(q.objtype),
+            "directory": directory,
+            "users": object_names(&set_members(bindery, q, &[ "Q_USERS" ])),
+            "operators": object_names(&set_members(bindery, q, &[ "Q_OPERATORS" ])),
+            "servers": object_names(&set_members(bindery, q, &[ "Q_SERVERS" ])),
+        })
+    }).collect();
+
+    let config = json!({
+        "file_server": file_server,
+        "print_servers": print_servers,
+        "queues": queues,
+    });
+    println!("{}", serde_json::to_string_pretty(&config).unwrap());
+}
+
+// Writes print queues as CUPS printers.conf entries; groups are prefixed with
+// @ in AllowUser, as CUPS expects
+fn dump_print_config_cups(bindery: &Bindery, filter: &Filter) {
+    let file_server = bindery.objects.iter().find(|o| o.objtype == OT_FILE_SERVER).map(|o| o.name.as_str()).unwrap_or("SERVER");
+    for q in bindery.objects.iter().filter(|o| o.objtype == OT_PRINT_QUEUE && filter.matches(o, bindery)) {
+        let directory = bindery.find_property(q, "Q_DIRECTORY").map(|p| bindery::read_string_property(&bindery.property_data(p), bindery.codepage));
+        println!("# NetWare print queue {} (id {:x}), directory {}", q.name, q.objid, directory.as_deref().unwrap_or("-"));
+        let operators = set_members(bindery, q, &[ "Q_OPERATORS" ]);
+        if !operators.is_empty() {
+            println!("# operators: {}", object_names(&operators).join(" "));
+        }
+        let servers = set_members(bindery, q, &[ "Q_SERVERS" ]);
+        if !servers.is_empty() {
+            println!("# serviced by: {}", object_names(&servers).join(" "));
+        }
+        println!("<Printer {}>", q.name);
+        println!("Info NetWare print queue {}", q.name);
+        println!("DeviceURI ncp://{}/{}", file_server, q.name);
+        println!("State Idle");
+        println!("Accepting Yes");
+        println!("Shared Yes");
+        let users: Vec<String> = set_members(bindery, q, &[ "Q_USERS" ]).iter()
+            .map(|o| if o.objtype == OT_GROUP { format!("@{}", o.name) } else { o.name.clone() })
+            .collect();
+        if !users.is_empty() {
+            println!("AllowUser {}", users.join(" "));
+        }
+        println!("</Printer>");
+    }
+}
+
+// The bindery context only applies to NetWare 4.x, where it determines which
+// containers are presented as bindery
+fn load_bindery<R: Read>(format: Format, readers: Vec<R>, contexts: &[String], codepage: &'static Codepage) -> Result<Bindery, std::io::Error> {
+    if format == Format::NetWare4 && !contexts.is_empty() {
+        return Ok(Bindery::from_dib(&Dib::from_readers(readers)?, contexts, codepage));
+    }
+    Bindery::from_readers_with_codepage(format, readers, codepage)
+}
+
+// Server to read the bindery from over NCP
+#[derive(Default)]
+struct Remote {
+    tunnel: Option<String>,
+    tcp: Option<String>,
+    server: Option<String>,
+    user: Option<String>,
+    password: String,
+    unencrypted: bool,
+}
+
+impl Remote {
+    fn is_given(&self) -> bool {
+        self.tunnel.is_some() || self.tcp.is_some()
+    }
+}
+
+// Reads what the user logged in as may see of the bindery of a live server;
+// properties which cannot be read are reported and left out
+fn read_remote_bindery(remote: &Remote, codepage: &'static Codepage) -> Result<Bindery, NCPError> {
+    let transport = ncp::transport::open(remote.tcp.as_deref(), remote.tunnel.as_deref(), remote.server.as_deref())?;
+    let mut conn = Connection::create(transport)?;
+    conn.negotiate_buffer_size()?;
+    let user = remote.user.as_deref().unwrap_or("SUPERVISOR");
+    let login = if remote.unencrypted {
+        conn.login_unencrypted(OT_USER, user, &remote.password)
+    } else {
+        conn.login_encrypted(OT_USER, user, &remote.password)
+    };
+    match login {
+        Err(NCPError::Completion(ncp::COMPLETION_PASSWORD_EXPIRED)) => eprintln!("warning: password has expired; logged in using a grace login"),
+        result => result?,
+    }
+    let format = if conn.server_info()?.version.0 < 3 { Format::NetWare2 } else { Format::NetWare3 };
+    let (mut bindery, warnings) = ncp::bindery::read_bindery(&mut conn, format)?;
+    for w in &warnings {
+        eprintln!("warning: {}", w);
+    }
+    conn.logout()?;
+    conn.destroy()?;
+    bindery.codepage = codepage;
+    Ok(bindery)
+}
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] net$obj.sys net$prop.sys net$val.sys", prog);
+    println!("       {} [options] net$bind.sys net$bval.sys", prog);
+    println!("       {} [options] 0.dsd 1.dsd 2.dsd 3.dsd", prog);
+    println!("       {} [options] --volume IMAGE", prog);
+    println!("       {} [options] --tunnel HOST[:PORT] | --tcp HOST[:PORT]", prog);
+    println!();
+    println!("  --tree                      compact view with decoded property values");
+    println!("  --hashes                    only write password hashes as objid:hash");
+    println!("  --check-password USER PASS  verify PASS against the password of USER");
+    println!("  --check                     check the consistency of the bindery");
+    println!("  --ldif BASEDN               export users and groups as LDIF below BASEDN");
+    println!("  --html FILE                 write a standalone HTML report to FILE");
+    println!("  --export FORMAT             export users/groups as passwd, group or smbpasswd file");
+    println!("  --uid-base N, --gid-base N  base of the UID's/GID's used by --export (default 10000)");
+    println!("  --dot                       write group membership/security equivalence as Graphviz graph");
+    println!("  --stats                     show statistics on the bindery contents");
+    println!("  --print-config FORMAT       export print servers and queues as json or cups (printers.conf)");
+    println!("  --queues                    show queues along with their users, operators and job files");
+    println!("  --volume IMAGE              read the bindery from SYS:SYSTEM of a disk or partition image");
+    println!("  --tunnel HOST[:PORT]        read the bindery from a live server using NCP over an IPX tunnel");
+    println!("  --tcp HOST[:PORT]           read the bindery from a live server using NCP over TCP");
+    println!("  --server NAME               server to use over the IPX tunnel (default the first found)");
+    println!("  --user NAME, --password PW  user to log in as (default SUPERVISOR) and its password");
+    println!("  --unencrypted               send the password as is rather than encrypted");
+    println!("  --codepage CODEPAGE         codepage of names and strings (default 437)");
+    println!("  --context CONTAINER         NetWare 4.x bindery context (may be repeated, default all)");
+    println!("  --type TYPE                 only objects of TYPE (number or name, i.e. 0x1 or user)");
+    println!("  --name PATTERN              only objects whose name matches PATTERN (* and ? allowed)");
+    println!("  --property NAME             only objects having property NAME");
+    println!("  --static-only               skip dynamic objects and properties");
+    println!("  --redact                    pseudonymize names and clear all item values, except for");
+    println!("                              LOGIN_CONTROL, USER_DEFAULTS and ACCOUNT_BALANCE");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut files: Vec<&String> = Vec::new();
+    let mut mode = Mode::Dump;
+    let mut filter = Filter::default();
+    let mut image: Option<&String> = None;
+    let mut volume: Option<Volume> = None;
+    let mut redact = false;
+    let mut contexts: Vec<String> = Vec::new();
+    let mut codepage = codepage::DEFAULT;
+    let mut uid_base = 10000;
+    let mut gid_base = 10000;
+    let mut remote = Remote::default();
+    let mut arg_iter = args[1..].iter();
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--hashes" => { mode = Mode::Hashes; },
+            "--check" => { mode = Mode::Check; },
+            "--dot" => { mode = Mode::Dot; },
+            "--stats" => { mode = Mode::Stats; },
+            "--queues" => { mode = Mode::Queues; },
+            "--tree" => { mode = Mode::Tree; },
+            "--static-only" => { filter.static_only = true; },
+            "--redact" => { redact = true; },
+            "--unencrypted" => { remote.unencrypted = true; },
+            "--check-password" => {
+                let user = arg_iter.next();
+                let password = arg_iter.next();
+                if user.is_none() || password.is_none() {
+                    usage(&args[0]);
+                    return Ok(())
+                }
+                mode = Mode::CheckPassword(user.unwrap().to_string(), password.unwrap().to_string());
+            },
+            "--export" => {
+                let format = arg_iter.next();
+                if !format.is_some_and(|f| f == "passwd" || f == "group" || f == "smbpasswd") {
+                    println!("--export requires a format (passwd, group or smbpasswd)");
+                    return Ok(())
+                }
+                mode = Mode::Unix(format.unwrap().to_string());
+            },
+            "--uid-base" | "--gid-base" => {
+                let base = arg_iter.next().and_then(|s| s.parse::<u32>().ok());
+                if base.is_none() {
+                    println!("{} requires a number", arg);
+                    return Ok(())
+                }
+                if arg == "--uid-base" { uid_base = base.unwrap(); } else { gid_base = base.unwrap(); }
+            },
+            "--print-config" => {
+                let format = arg_iter.next();
+                if !format.is_some_and(|f| f == "json" || f == "cups") {
+                    println!("--print-config requires a format (json or cups)");
+                    return Ok(())
+                }
+                mode = Mode::PrintConfig(format.unwrap().to_string());
+            },
+            "--html" => {
+                let fname = arg_iter.next();
+                if fname.is_none() {
+                    println!("--html requires an output file");
+                    return Ok(())
+                }
+                mode = Mode::Html(fname.unwrap().to_string());
+            },
+            "--ldif" => {
+                let base_dn = arg_iter.next();
+                if base_dn.is_none() {
+                    println!("--ldif requires a base DN");
+                    return Ok(())
+                }
+                mode = Mode::Ldif(base_dn.unwrap().to_string());
+            },
+            "--codepage" => {
+                match arg_iter.next().and_then(|name| codepage::find(name)) {
+                    Some(c) => { codepage = c; },
+                    None => {
+                        let supported: Vec<String> = codepage::CODEPAGES.iter().map(|c| format!("{} ({})", c.number, c.description)).collect();
+                        println!("--codepage requires a supported codepage: {}", supported.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            "--context" => {
+                let context = arg_iter.next();
+                if context.is_none() {
+                    println!("--context requires a container, i.e. OU=Sales.O=ACME");
+                    return Ok(())
+                }
+                contexts.push(context.unwrap().to_string());
+            },
+            "--tunnel" | "--tcp" | "--server" | "--user" | "--password" => {
+                let Some(value) = arg_iter.next() else {
+                    println!("{} requires an argument", arg);
+                    return Ok(())
+                };
+                let value = value.to_string();
+                match arg.as_str() {
+                    "--tunnel" => { remote.tunnel = Some(value); },
+                    "--tcp" => { remote.tcp = Some(value); },
+                    "--server" => { remote.server = Some(value); },
+                    "--user" => { remote.user = Some(value); },
+                    _ => { remote.password = value; },
+                }
+            },
+            "--volume" => {
+                image = arg_iter.next();
+                if image.is_none() {
+                    println!("--volume requires an image file");
+                    return Ok(())
+                }
+            },
+            "--type" => {
+                let objtype = arg_iter.next().and_then(|s| bindery::parse_object_type(s));
+                if objtype.is_none() {
+                    println!("--type requires a valid object type");
+                    return Ok(())
+                }
+                filter.objtype = objtype;
+            },
+            "--name" | "--property" => {
+                let value = arg_iter.next();
+                if value.is_none() {
+                    println!("{} requires an argument", arg);
+                    return Ok(())
+                }
+                let value = Some(value.unwrap().to_string());
+                if arg == "--name" { filter.name = value; } else { filter.property = value; }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg);
+            }
+        }
+    }
+    let (mut bindery, file_data) = if remote.is_given() {
+        if !files.is_empty() || image.is_some() {
+            usage(&args[0]);
+            return Ok(())
+        }
+        if matches!(mode, Mode::Check) {
+            println!("--check needs the bindery files, which a live server does not give");
+            return Ok(())
+        }
+        if matches!(mode, Mode::Hashes | Mode::CheckPassword(..)) {
+            println!("password hashes cannot be read from a live server");
+            return Ok(())
+        }
+        match read_remote_bindery(&remote, codepage) {
+            Ok(bindery) => (bindery, Vec::new()),
+            Err(e) => {
+                println!("cannot read bindery from server: {}", e);
+                return Ok(())
+            }
+        }
+    } else if let Some(image) = image {
+        if !files.is_empty() {
+            usage(&args[0]);
+            return Ok(())
+        }
+        let (format, file_data) = match nwfs::volume::open_volume(image, "SYS").and_then(|mut v| bindery::read_volume_files(&mut v).map(|r| (r, v))) {
+            Ok((result, v)) => {
+                volume = Some(v);
+                result
+            },
+            Err(e) => {
+                println!("cannot read bindery from '{}': {}", image, e);
+                return Ok(())
+            }
+        };
+        let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+        (load_bindery(format, file_slices, &contexts, codepage)?, file_data)
+    } else {
+        let format = match Format::detect(files.len()) {
+            Some(format) => format,
+            None => {
+                usage(&args[0]);
+                return Ok(())
+            }
+        };
+        if matches!(mode, Mode::Check) {
+            // The consistency check needs the raw records
+            let mut file_data: Vec<Vec<u8>> = Vec::new();
+            for fname in &files {
+                file_data.push(std::fs::read(fname)?);
+            }
+            let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+            (load_bindery(format, file_slices, &contexts, codepage)?, file_data)
+        } else {
+            let mut readers = Vec::new();
+            for fname in &files {
+                readers.push(std::io::BufReader::new(std::fs::File::open(fname)?));
+            }
+            (load_bindery(format, readers, &contexts, codepage)?, Vec::new())
+        }
+    };
+
+    if !contexts.is_empty() && bindery.format != Format::NetWare4 {
+        println!("--context is only supported for NetWare 4.x");
+        return Ok(())
+    }
+    if bindery.format == Format::NetWare2 {
+        eprintln!("warning: the NetWare 2.x bindery layout is unverified; the output may be wrong");
+    }
+    if bindery.format == Format::NetWare4 {
+        eprintln!("warning: the NDS database layout used for the NetWare 4.x bindery emulation is unverified; the output may be wrong");
+    }
+    if redact {
+        bindery.redact();
+    }
+
+    match mode {
+        Mode::Dump => dump_bindery(&bindery, &filter),
+        Mode::Tree => dump_tree(&bindery, &filter),
+        Mode::Hashes => dump_hashes(&bindery, &filter),
+        Mode::Check => check_bindery(&file_data, &bindery),
+        Mode::CheckPassword(user, password) => check_password(&bindery, &user, &password),
+        Mode::Ldif(base_dn) => dump_ldif(&bindery, &filter, &base_dn),
+        Mode::Dot => dump_dot(&bindery, &filter),
+        Mode::Stats => dump_stats(&bindery, &filter),
+        Mode::Queues => dump_queues(&bindery, &filter, volume.as_ref()),
+        Mode::Html(fname) => dump_html(&bindery, &filter, &fname)?,
+        Mode::Unix(format) => dump_unix(&bindery, &filter, &format, uid_base, gid_base),
+        Mode::PrintConfig(format) => {
+            if format == "json" {
+                dump_print_config_json(&bindery, &filter);
+            } else {
+                dump_print_config_cups(&bindery, &filter);
+            }
+        },
+    }
     Ok(())
 }
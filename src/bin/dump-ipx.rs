@@ -0,0 +1,204 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use serde_json::{json, Value};
+use nlm_tools::ipx::{self, Address, Encapsulation, Frame};
+use nlm_tools::nwfs::format_unix_time;
+use nlm_tools::pcap::Capture;
+use nlm_tools::sap::{self, SapPacket};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] capture.pcap", prog);
+    println!();
+    println!("Lists the IPX packets in a packet capture (pcap or pcapng, of Ethernet or");
+    println!("Linux cooked frames), along with the frame type used, and summarizes the");
+    println!("frame types and network numbers seen");
+    println!();
+    println!("options:");
+    println!("  --socket N           only show packets from or to socket N (hex)");
+    println!("  --frame-type TYPE    only show packets of frame type TYPE (802.3, 802.2, II or");
+    println!("                       SNAP)");
+    println!("  --hex                dump the data of every packet");
+    println!("  --json               print the packets as json");
+}
+
+fn hex_dump(data: &[u8]) {
+    for (n, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        println!("    {:04x}  {:<47}  {}", n * 16, hex.join(" "), text);
+    }
+}
+
+fn type_string(packet_type: u8) -> String {
+    match ipx::packet_type_name(packet_type) {
+        Some(name) => name.to_string(),
+        None => format!("type {}", packet_type),
+    }
+}
+
+fn address_string(address: &Address) -> String {
+    match ipx::socket_name(address.socket) {
+        Some(name) => format!("{} ({})", address, name),
+        None => address.to_string(),
+    }
+}
+
+fn frame_json(number: usize, time: u64, frame: &Frame, hex: bool) -> Value {
+    let p = &frame.packet;
+    let mut result = json!({
+        "number": number,
+        "time": format!("{}.{:09}", format_unix_time(time / 1_000_000_000).trim_end_matches('Z'), time % 1_000_000_000) + "Z",
+        "frame_type": frame.encapsulation.name(),
+        "source_mac": ipx::node_string(&frame.source),
+        "destination_mac": ipx::node_string(&frame.destination),
+        "checksum": p.checksum,
+        "checksum_valid": p.checksum_valid(),
+        "hops": p.transport_control,
+        "packet_type": p.packet_type,
+        "packet_type_name": ipx::packet_type_name(p.packet_type),
+        "source": p.source.to_string(),
+        "source_socket_name": ipx::socket_name(p.source.socket),
+        "destination": p.destination.to_string(),
+        "destination_socket_name": ipx::socket_name(p.destination.socket),
+        "length": p.len(),
+    });
+    if hex {
+        result["data"] = json!(p.data.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    }
+    result
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut socket: Option<u16> = None;
+    let mut frame_type: Option<Encapsulation> = None;
+    let mut hex = false;
+    let mut json = false;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--hex" => { hex = true; },
+            "--json" => { json = true; },
+            "--socket" => {
+                match arg_iter.next().and_then(|s| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok()) {
+                    Some(s) => { socket = Some(s); },
+                    None => {
+                        println!("--socket requires a socket number in hex");
+                        return Ok(())
+                    }
+                }
+            },
+            "--frame-type" => {
+                match arg_iter.next().and_then(|s| Encapsulation::find(s)) {
+                    Some(e) => { frame_type = Some(e); },
+                    None => {
+                        let names: Vec<&str> = Encapsulation::ALL.iter().map(|e| e.name()).collect();
+                        println!("--frame-type requires one of {}", names.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let mut capture = match Capture::open(BufReader::new(File::open(&files[0])?)) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("cannot read '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let mut first_time: Option<u64> = None;
+    let mut total = 0;
+    let mut skipped = 0;
+    let mut frames: Vec<Value> = Vec::new();
+    // Packets and network numbers of every frame type; packets which were not
+    // routed carry the network number of the segment they were sent on
+    let mut frame_types: BTreeMap<&str, (usize, Vec<u32>)> = BTreeMap::new();
+    while let Some(packet) = capture.next_packet()? {
+        total += 1;
+        let time = packet.timestamp.saturating_sub(*first_time.get_or_insert(packet.timestamp));
+        let frame = match ipx::parse_captured(packet.link_type, &packet.data) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => continue,
+            Err(e) => {
+                if !json {
+                    println!("packet {}: {}", total, e);
+                }
+                skipped += 1;
+                continue
+            }
+        };
+        let p = &frame.packet;
+        let (count, networks) = frame_types.entry(frame.encapsulation.name()).or_default();
+        *count += 1;
+        if p.transport_control == 0 && p.source.network != 0 && !networks.contains(&p.source.network) {
+            networks.push(p.source.network);
+        }
+        if socket.is_some_and(|s| s != p.source.socket && s != p.destination.socket) || frame_type.is_some_and(|e| e != frame.encapsulation) {
+            continue;
+        }
+
+        if json {
+            frames.push(frame_json(total, packet.timestamp, &frame, hex));
+            continue;
+        }
+        let mut notes = Vec::new();
+        if p.destination.socket == ipx::SOCKET_SAP || p.source.socket == ipx::SOCKET_SAP {
+            if let Ok(sap) = SapPacket::parse(&p.data) {
+                notes.push(format!("SAP {}", sap::operation_name(sap.operation())));
+            }
+        }
+        if p.transport_control != 0 {
+            notes.push(format!("{} hops", p.transport_control));
+        }
+        if p.checksum_valid() == Some(false) {
+            notes.push("bad checksum".to_string());
+        }
+        println!("{:>5} {:>4}.{:06} {:<14} {} -> {} {} {} bytes{}", total,
+            time / 1_000_000_000, time % 1_000_000_000 / 1000, frame.encapsulation.name(),
+            address_string(&p.source), address_string(&p.destination), type_string(p.packet_type), p.len(),
+            notes.iter().map(|n| format!(", {}", n)).collect::<String>());
+        if hex {
+            hex_dump(&p.data);
+        }
+    }
+
+    if json {
+        let summary: Vec<Value> = frame_types.iter().map(|(name, (count, networks))| json!({
+            "frame_type": name,
+            "packets": count,
+            "networks": networks.iter().map(|n| format!("{:08x}", n)).collect::<Vec<_>>(),
+        })).collect();
+        let output = json!({ "packets": total, "unreadable": skipped, "frame_types": summary, "ipx": frames });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return Ok(())
+    }
+    let ipx_packets: usize = frame_types.values().map(|(count, _)| count).sum();
+    println!("{} IPX packets out of {}{}", ipx_packets, total, if skipped > 0 { format!(", {} unreadable", skipped) } else { String::new() });
+    for (name, (count, networks)) in &frame_types {
+        let networks: Vec<String> = networks.iter().map(|n| format!("{:08x}", n)).collect();
+        println!("  {:<14} {:>7} packets, network {}", name, count, if networks.is_empty() { "unknown".to_string() } else { networks.join(", ") });
+    }
+    Ok(())
+}
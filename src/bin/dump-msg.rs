@@ -0,0 +1,120 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use serde_json::{json, Value};
+use nlm_tools::codepage::{self, Codepage};
+use nlm_tools::msgfile::{self, MessageFile};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] file.msg", prog);
+    println!("       {} [options] --update table.txt file.msg out.msg", prog);
+    println!();
+    println!("Prints the messages of a NetWare message file as 'number: text', with control");
+    println!("characters escaped (\\n, \\r, \\t, \\\\ and \\xNN). Using --update, the messages");
+    println!("listed in table.txt (in the same format) are replaced and the result is written");
+    println!("to out.msg; messages numbered one past the last are appended");
+    println!();
+    println!("options:");
+    println!("  --codepage CP        codepage of the messages (default {})", codepage::DEFAULT.number);
+    println!("  --json               print the messages as json");
+}
+
+// Applies the table to msg; returns the number of messages changed
+fn update(msg: &mut MessageFile, table: &str, codepage: &Codepage) -> Result<usize, String> {
+    let mut changed = 0;
+    for (n, line) in table.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') { continue; }
+        let (number, text) = line.split_once(": ").or(line.split_once(':')).ok_or(format!("line {}: expected 'number: text'", n + 1))?;
+        let number: usize = number.trim().parse().map_err(|_| format!("line {}: invalid message number '{}'", n + 1, number))?;
+        let text = msgfile::unescape(text, codepage).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        match number.cmp(&msg.messages.len()) {
+            std::cmp::Ordering::Less => {
+                if msg.messages[number] == text { continue; }
+                msg.messages[number] = text;
+            },
+            std::cmp::Ordering::Equal => msg.messages.push(text),
+            std::cmp::Ordering::Greater => return Err(format!("line {}: message {} does not exist", n + 1, number)),
+        }
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut codepage: &Codepage = codepage::DEFAULT;
+    let mut json = false;
+    let mut table_fname: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--json" => { json = true; },
+            "--update" => {
+                table_fname = arg_iter.next().cloned();
+                if table_fname.is_none() {
+                    println!("--update requires a file name");
+                    return Ok(())
+                }
+            },
+            "--codepage" => {
+                match arg_iter.next().and_then(|name| codepage::find(name)) {
+                    Some(c) => { codepage = c; },
+                    None => {
+                        let supported: Vec<String> = codepage::CODEPAGES.iter().map(|c| format!("{} ({})", c.number, c.description)).collect();
+                        println!("--codepage requires a supported codepage: {}", supported.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != if table_fname.is_some() { 2 } else { 1 } {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let mut msg = match MessageFile::parse(&std::fs::read(&files[0])?) {
+        Ok(msg) => msg,
+        Err(e) => {
+            println!("cannot parse '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+
+    if let Some(table_fname) = table_fname {
+        let table = std::fs::read_to_string(&table_fname)?;
+        match update(&mut msg, &table, codepage) {
+            Ok(changed) => println!("{} messages changed, {} messages total", changed, msg.messages.len()),
+            Err(e) => {
+                println!("{}: {}", table_fname, e);
+                return Ok(())
+            }
+        }
+        std::fs::write(&files[1], msg.to_bytes())?;
+        return Ok(())
+    }
+
+    if json {
+        let messages: Vec<Value> = msg.messages.iter().map(|m| json!(codepage.decode(m))).collect();
+        let output = json!({ "language": msg.language, "messages": messages });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return Ok(())
+    }
+    println!("# language {}, {} messages", msg.language, msg.messages.len());
+    for (n, m) in msg.messages.iter().enumerate() {
+        println!("{}: {}", n, msgfile::escape(m, codepage));
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::HashSet;
+use std::env;
+use nlm_tools::nds::{self, Dib, Entry};
+use nlm_tools::nwfs;
+
+struct Options {
+    schema: bool,
+    deleted: bool,
+    class: Option<String>,
+}
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] 0.dsd 1.dsd 2.dsd 3.dsd", prog);
+    println!("       {} [options] --volume IMAGE", prog);
+    println!();
+    println!("options:");
+    println!("  --partitions    list the partitions and replicas only");
+    println!("  --base DN       only dump the subtree starting at DN, i.e. OU=Sales.O=ACME");
+    println!("  --class NAME    only dump entries of the given class, i.e. User");
+    println!("  --schema        include the class and attribute definitions");
+    println!("  --deleted       include deleted entries and values that were not yet purged");
+}
+
+fn dump_partitions(dib: &Dib) {
+    for p in &dib.partitions {
+        let root = match dib.find_entry(p.root_entry) {
+            Some(e) => dib.dn(e),
+            None if p.root_entry == nds::ID_NONE => "none".to_string(),
+            None => format!("<unknown entry {:x}>", p.root_entry),
+        };
+        println!("partition id {:x} '{}' root {} replica {} state {:x}", p.id, p.name, root, nds::format_replica_type(p.replica_type), p.state);
+    }
+}
+
+fn dump_entry(dib: &Dib, e: &Entry, options: &Options) {
+    let deleted = if e.is_present() { "" } else { ", deleted" };
+    let tree = if e.parent == nds::ID_NONE { format!(" {}", e.name) } else { String::new() };
+    println!("{}{} ({}, id {:x}{})", dib.dn(e), tree, dib.class_name(e), e.id, deleted);
+
+    // Values are grouped by attribute, in the order they first appear
+    let mut attributes: Vec<(String, Vec<String>)> = Vec::new();
+    for valueid in dib.entry_values(e) {
+        let v = match dib.find_value(valueid) {
+            Some(v) => v,
+            None => {
+                attributes.push(("<broken chain>".to_string(), vec![ format!("missing value id {:x}", valueid) ]));
+                break;
+            }
+        };
+        if !v.is_present() && !options.deleted { continue; }
+        let attribute = dib.attribute_name(v);
+        let mut lines = nds::decode_value(dib, v);
+        if !v.is_present() {
+            lines = lines.into_iter().map(|line| line + " (deleted)").collect();
+        }
+        match attributes.iter_mut().find(|(a, _)| *a == attribute) {
+            Some((_, values)) => values.append(&mut lines),
+            None => attributes.push((attribute, lines)),
+        }
+    }
+    for (n, (attribute, values)) in attributes.iter().enumerate() {
+        let last = n == attributes.len() - 1;
+        println!("{} {}", if last { "└─" } else { "├─" }, attribute);
+        for value in values {
+            println!("{}   {}", if last { " " } else { "│" }, value);
+        }
+    }
+}
+
+fn dump_subtree(dib: &Dib, e: &Entry, options: &Options, seen: &mut HashSet<u32>) {
+    if !seen.insert(e.id) { return; }
+    if !options.schema && dib.is_schema(e) { return; }
+    if !e.is_present() && !options.deleted { return; }
+    if options.class.as_ref().is_none_or(|c| c.eq_ignore_ascii_case(&dib.class_name(e))) {
+        dump_entry(dib, e, options);
+    }
+    for child in dib.children(e.id) {
+        dump_subtree(dib, child, options, seen);
+    }
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options{ schema: false, deleted: false, class: None };
+    let mut partitions = false;
+    let mut base: Option<String> = None;
+    let mut image: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--partitions" => { partitions = true; },
+            "--schema" => { options.schema = true; },
+            "--deleted" => { options.deleted = true; },
+            "--base" | "--class" | "--volume" => {
+                let value = arg_iter.next();
+                if value.is_none() {
+                    println!("{} requires an argument", arg);
+                    return Ok(())
+                }
+                let value = value.cloned();
+                match arg.as_str() {
+                    "--base" => { base = value; },
+                    "--class" => { options.class = value; },
+                    _ => { image = value; }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+
+    let dib = if let Some(image) = image {
+        if !files.is_empty() {
+            usage(&args[0]);
+            return Ok(())
+        }
+        let file_data = match nwfs::volume::open_volume(&image, "SYS").and_then(|mut v| nds::read_volume_files(&mut v)) {
+            Ok(file_data) => file_data,
+            Err(e) => {
+                println!("cannot read DIB from '{}': {}", image, e);
+                return Ok(())
+            }
+        };
+        let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+        Dib::new(&file_slices)?
+    } else {
+        if files.len() != nds::FILE_NAMES.len() {
+            usage(&args[0]);
+            return Ok(())
+        }
+        Dib::from_files(&files)?
+    };
+
+    if partitions {
+        dump_partitions(&dib);
+        return Ok(())
+    }
+
+    let start = match &base {
+        Some(base) => dib.entries.iter().find(|e| dib.dn(e).eq_ignore_ascii_case(base)),
+        None => dib.root(),
+    };
+    let start = match start {
+        Some(e) => e,
+        None => {
+            println!("entry '{}' not found", base.as_deref().unwrap_or("[Root]"));
+            return Ok(())
+        }
+    };
+    let mut seen: HashSet<u32> = HashSet::new();
+    dump_subtree(&dib, start, &options, &mut seen);
+
+    // Entries that cannot be reached from [Root] because their parent is
+    // missing are dumped as well
+    if base.is_none() {
+        for e in &dib.entries {
+            if !seen.contains(&e.id) && dib.find_entry(e.parent).is_none() {
+                dump_subtree(&dib, e, &options, &mut seen);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::bindery;
+use nlm_tools::connections;
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::symbols::{self, Image};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] memory.bin", prog);
+    println!();
+    println!("Locates the connection table and the open files in a memory dump, and reports");
+    println!("who was connected and which files were open at the time it was taken");
+    println!();
+    println!("options:");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --netware-version V  use the table layout of NetWare version V, i.e. 3.12;");
+    println!("                       this is detected from the version string if omitted");
+    println!("  --ptr ADDR           address of the connection table; this is located by");
+    println!("                       scanning the memory dump if omitted");
+    println!("  --unverified         use a table layout which has not been confirmed against a");
+    println!("                       real memory dump");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut base: u32 = 0;
+    let mut ptr: Option<u32> = None;
+    let mut version: Option<String> = None;
+    let mut unverified = false;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--base" | "--ptr" => {
+                let value = match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(value) => value,
+                    None => {
+                        println!("{} requires an address", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--base" { base = value; } else { ptr = Some(value); }
+            },
+            "--unverified" => { unverified = true; },
+            "--netware-version" => {
+                version = arg_iter.next().cloned();
+                if version.is_none() {
+                    println!("--netware-version requires an argument");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read memory dump '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let layout = match connections::select_layout(version.as_deref(), &dump.data) {
+        Ok(layout) => layout,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    if !layout.verified && !unverified {
+        println!("the {} table layout has not been confirmed against a real memory dump, so the", layout.description);
+        println!("results may be wrong; use --unverified to use it anyway");
+        return Ok(())
+    }
+    let image = Image::new(&dump.data, base);
+    let table = match ptr {
+        Some(ptr) => connections::read_connection_table(&image, layout, ptr),
+        None => match connections::find_connection_table(&image, layout) {
+            Some(table) => table,
+            None => {
+                println!("connection table not found, use --ptr to specify it");
+                return Ok(())
+            }
+        }
+    };
+
+    println!("connection table at {:08x} ({} layout), {} connections in {} slots", table.address, layout.description, table.connections.len(), table.slots);
+    println!();
+    println!("conn  object id  type                 address                    login time");
+    for conn in &table.connections {
+        let type_name = bindery::object_type_name(conn.object_type).map(|s| s.to_string()).unwrap_or(format!("{:04x}", conn.object_type));
+        println!("{:4}  {:08x}   {:<20} {}  {}", conn.number, conn.object_id, type_name, conn.address_text(), conn.login_time.to_text());
+    }
+
+    let open_files = connections::find_open_files(&image, layout, &table);
+    println!();
+    println!("{} open files", open_files.len());
+    if !open_files.is_empty() {
+        println!();
+        println!("conn  task  volume  entry     access                     name");
+        for file in &open_files {
+            println!("{:4}  {:4}  {:6}  {:08x}  {:<26} {}", file.connection, file.task, file.volume, file.entry, file.access_text(), file.name);
+        }
+    }
+    Ok(())
+}
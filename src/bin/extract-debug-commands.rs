@@ -0,0 +1,130 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::io::Write;
+use nlm_tools::debugcmds;
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::nlm::{NLM, NLM_DATA_VADDR};
+use nlm_tools::serverexe::{self, HereBlock};
+use nlm_tools::symbols::{self, Image, Symbol, SymbolFormat};
+use nlm_tools::unpack;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.exe|memory.bin [out.txt]", prog);
+    println!();
+    println!("Locates the command list of the internal debugger, and writes the name, handler");
+    println!("address and help text of every command to out.txt (or the console)");
+    println!();
+    println!("options:");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --format FORMAT      output format: text (the default), nm, map (IDA), ghidra");
+    println!("                       or json; these contain a debugcmd_NAME symbol per handler");
+    println!("  --ptr ADDR           address of the command list pointer; this is located by");
+    println!("                       scanning if omitted");
+}
+
+// SERVER.NLM is packed within SERVER.EXE, so its data segment is used; the
+// addresses match those of the ELF file written by nlm2elf. SERVER.EXE itself
+// may have been processed by a DOS executable packer as well.
+fn server_nlm_data(data: &[u8]) -> Option<Vec<u8>> {
+    let unpacked = unpack::unpack(data).ok().flatten().map(|(_, unpacked)| unpacked);
+    let data = unpacked.as_deref().unwrap_or(data);
+    let here = HereBlock::find(data).ok()?;
+    let offset = serverexe::first_nlm(data, &here)?;
+    let embedded = serverexe::find_nlms(data, offset).into_iter().next()?;
+    NLM::new(embedded.data(data)).ok()?.data_segment().ok()
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut base: u32 = 0;
+    let mut ptr: Option<u32> = None;
+    let mut format = SymbolFormat::Text;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--base" | "--ptr" => {
+                let value = match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(value) => value,
+                    None => {
+                        println!("{} requires an address", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--base" { base = value; } else { ptr = Some(value); }
+            },
+            "--format" => {
+                match arg_iter.next().and_then(|s| SymbolFormat::find(s)) {
+                    Some(f) => { format = f; },
+                    None => {
+                        let names: Vec<&str> = symbols::SYMBOL_FORMATS.iter().map(|(n, _)| *n).collect();
+                        println!("--format requires a format ({})", names.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.is_empty() || files.len() > 2 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let data = std::fs::read(&files[0])?;
+    let (data, base) = match server_nlm_data(&data) {
+        Some(nlm_data) => {
+            println!("using the data segment of SERVER.NLM");
+            (nlm_data, NLM_DATA_VADDR)
+        },
+        None => match MemoryDump::from_bytes(data) {
+            Ok(dump) => (dump.data, base),
+            Err(e) => {
+                println!("cannot read memory dump '{}': {}", files[0], e);
+                return Ok(())
+            }
+        }
+    };
+    let image = Image::new(&data, base);
+    let head = match ptr.or(debugcmds::find_list_head(&image).map(|(ptr, _)| ptr)) {
+        Some(head) => head,
+        None => {
+            println!("unable to locate the debugger command list, use --ptr to specify it");
+            return Ok(())
+        }
+    };
+    let commands = match debugcmds::read_list(&image, head) {
+        Ok(commands) => commands,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    println!("command list pointer at {:x}, {} commands", head, commands.len());
+
+    let mut out: Box<dyn Write> = match files.get(1) {
+        Some(fname) => Box::new(std::fs::File::create(fname)?),
+        None => Box::new(std::io::stdout()),
+    };
+    if format == SymbolFormat::Text {
+        for command in &commands {
+            writeln!(out, "{:<12} 0x{:08x} {}", command.name, command.handler, command.help)?;
+        }
+    } else {
+        let symbols: Vec<Symbol> = commands.iter().map(|c| c.to_symbol()).collect();
+        symbols::write_symbols(&mut out, &symbols, &[], format)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::path::Path;
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::modules;
+use nlm_tools::symbols::{self, Image};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] memory.bin outdir", prog);
+    println!();
+    println!("Locates the modules loaded by the server in a memory dump, and writes the code");
+    println!("and data of each of them as ELF file to outdir, using the addresses at which");
+    println!("they were loaded");
+    println!();
+    println!("options:");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --ptr ADDR           address of the public symbol list pointer; this is");
+    println!("                       located by scanning the memory dump if omitted");
+    println!("  --list               only list the modules, do not write anything");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut base: u32 = 0;
+    let mut ptr: Option<u32> = None;
+    let mut list = false;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--list" => { list = true; },
+            "--base" | "--ptr" => {
+                let value = match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(value) => value,
+                    None => {
+                        println!("{} requires an address", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--base" { base = value; } else { ptr = Some(value); }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != if list { 1 } else { 2 } {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read memory dump '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let image = Image::new(&dump.data, base);
+    let loaded = modules::find_modules(&image);
+    if loaded.is_empty() {
+        println!("no loaded modules found");
+        return Ok(())
+    }
+
+    // Public symbols of all modules are kept in a single list
+    let public_symbols = match ptr.or(image.find_list_head().map(|(ptr, _)| ptr)) {
+        Some(ptr) => image.read_list(ptr).unwrap_or_else(|e| {
+            println!("WARNING: {}", e);
+            Vec::new()
+        }),
+        None => {
+            println!("WARNING: public symbol list not found, use --ptr to specify it");
+            Vec::new()
+        }
+    };
+
+    if !list {
+        std::fs::create_dir_all(&files[1])?;
+    }
+    let mut names: Vec<String> = Vec::new();
+    for module in &loaded {
+        let count = public_symbols.iter().filter(|s| module.contains(s.address)).count();
+        println!("{:08x} {:<12} code {:08x}+{:x} data {:08x}+{:x} {} symbols, {}",
+            module.id, module.file_name, module.code_address, module.code_length,
+            module.data_address, module.data_length + module.uninitialized_length, count, module.description);
+        if list { continue; }
+
+        let stem = module.file_name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&module.file_name);
+        let mut name = format!("{}.elf", stem);
+        if names.contains(&name) {
+            name = format!("{}-{:x}.elf", stem, module.id);
+        }
+        modules::write_module_elf(&Path::new(&files[1]).join(&name).to_string_lossy(), &image, module, &public_symbols)?;
+        names.push(name);
+    }
+    Ok(())
+}
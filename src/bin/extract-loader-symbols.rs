@@ -4,48 +4,162 @@
  * Copyright (c) 2022 Rink Springer <rink@rink.nu>
  * For conditions of distribution and use, see LICENSE file
  */
-use byteorder::{ByteOrder, LittleEndian};
 use std::env;
 use std::fs::File;
-use std::io::Write;
+use nlm_tools::codepage::{self, Codepage};
+use nlm_tools::memdump::{DumpFormat, MemoryDump};
+use nlm_tools::symbols::{self, Image, SymbolFormat};
+use nlm_tools::versions;
 
-const LOADER_SYM_PTR: usize = 0x1c564;
-
-fn read_string(data: &[u8]) -> String {
-    let len = data[0] as usize;
-    let s = &data[1..len + 1];
-    return if let Ok(s) = std::str::from_utf8(&s) {
-        s.to_string()
-    } else {
-        "?".to_string()
-    }
+fn usage(prog: &str) {
+    println!("usage: {} [options] memory.bin out.txt", prog);
+    println!();
+    println!("memory.bin is a flat dump of physical memory (i.e. a QEMU pmemsave or VMware");
+    println!(".vmem file), or an ELF core file from QEMU dump-guest-memory or VirtualBox");
+    println!();
+    println!("options:");
+    println!("  --codepage CP        codepage used to decode symbol names (default {})", codepage::DEFAULT.number);
+    println!("  --netware-version V  use the known offsets of NetWare version V, i.e. 3.12;");
+    println!("                       this is detected from the version string if omitted");
+    println!("  --format FORMAT      output format: text (name 0xaddr, the default), nm,");
+    println!("                       map (IDA), ghidra (ImportSymbolsScript.py), ghidra-script");
+    println!("                       (a script creating the labels and functions) or json");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --ptr ADDR           offset of the symbol list pointer, i.e. 0x1c564; this");
+    println!("                       is located by scanning the memory dump if it is not");
+    println!("                       known for the version");
 }
 
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("usage: {} memory.bin out.txt", args[0]);
+    let mut version: Option<String> = None;
+    let mut codepage: &Codepage = codepage::DEFAULT;
+    let mut ptr: Option<u32> = None;
+    let mut base: u32 = 0;
+    let mut format = SymbolFormat::Text;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--ptr" => {
+                ptr = arg_iter.next().and_then(|s| symbols::parse_address(s));
+                if ptr.is_none() {
+                    println!("--ptr requires an address");
+                    return Ok(())
+                }
+            },
+            "--base" => {
+                match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(b) => { base = b; },
+                    None => {
+                        println!("--base requires an address");
+                        return Ok(())
+                    }
+                }
+            },
+            "--format" => {
+                match arg_iter.next().and_then(|s| SymbolFormat::find(s)) {
+                    Some(f) => { format = f; },
+                    None => {
+                        let names: Vec<&str> = symbols::SYMBOL_FORMATS.iter().map(|(n, _)| *n).collect();
+                        println!("--format requires a format ({})", names.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            "--codepage" => {
+                match arg_iter.next().and_then(|name| codepage::find(name)) {
+                    Some(c) => { codepage = c; },
+                    None => {
+                        let supported: Vec<String> = codepage::CODEPAGES.iter().map(|c| format!("{} ({})", c.number, c.description)).collect();
+                        println!("--codepage requires a supported codepage: {}", supported.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            "--netware-version" => {
+                version = arg_iter.next().cloned();
+                if version.is_none() {
+                    println!("--netware-version requires an argument");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 2 {
+        usage(&args[0]);
         return Ok(())
     }
 
-    let memory_fname = &args[1];
-    let out_fname = &args[2];
+    let memory_fname = &files[0];
+    let out_fname = &files[1];
 
-    let memory_data = std::fs::read(memory_fname)?;
-
-    // Look up the pointer
-    let mut sym_ptr = LittleEndian::read_u32(&memory_data[LOADER_SYM_PTR..LOADER_SYM_PTR + 4]) as usize;
+    let dump = match MemoryDump::read(memory_fname) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read memory dump '{}': {}", memory_fname, e);
+            return Ok(())
+        }
+    };
+    if dump.format != DumpFormat::Raw {
+        println!("{} memory dump, {} bytes of physical memory", dump.format.name(), dump.data.len());
+    }
+    let memory_data = dump.data;
+    let known = match versions::select(version.as_deref(), &memory_data) {
+        Ok(known) => known,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    if let Some(known) = known {
+        println!("using known offsets of {}", known.description);
+    }
 
-    let mut f = File::create(out_fname)?;
-    while sym_ptr != 0 {
-        let next_ptr = LittleEndian::read_u32(&memory_data[sym_ptr+0..sym_ptr+4]) as usize;
-        let func_ptr = LittleEndian::read_u32(&memory_data[sym_ptr+4..sym_ptr+8]) as usize;
-        let name_ptr = LittleEndian::read_u32(&memory_data[sym_ptr+8..sym_ptr+12]) as usize;
+    // The known pointer is only used if it leads to a plausible list, as
+    // the version string does not identify the exact loader build
+    let image = Image::with_codepage(&memory_data, base, codepage);
+    let known_ptr = known.and_then(|v| v.loader_sym_ptr)
+        .filter(|p| image.read_list(*p).is_ok_and(|l| l.len() >= symbols::MIN_SYMBOLS));
+    let sym_ptr = match ptr.or(known_ptr) {
+        Some(ptr) => ptr,
+        None => {
+            match image.find_list_head() {
+                Some((ptr, count)) => {
+                    println!("symbol list pointer found at {:x} ({} symbols)", ptr, count);
+                    ptr
+                },
+                None => {
+                    println!("unable to locate the symbol list, use --ptr to specify it");
+                    return Ok(())
+                }
+            }
+        }
+    };
 
-        let name = read_string(&memory_data[name_ptr..]);
-        writeln!(f, "{} 0x{:x}", name, func_ptr)?;
-        sym_ptr = next_ptr;
+    let (symbols, warnings) = match image.read_list_with_warnings(sym_ptr) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    for warning in warnings.iter().take(symbols::MAX_WARNINGS) {
+        println!("WARNING: {}", warning);
     }
+    if warnings.len() > symbols::MAX_WARNINGS {
+        println!("WARNING: {} more damaged symbol names", warnings.len() - symbols::MAX_WARNINGS);
+    }
+    let mut f = File::create(out_fname)?;
+    symbols::write_symbols(&mut f, &symbols, &[], format)?;
 
     Ok(())
 }
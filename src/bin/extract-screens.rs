@@ -0,0 +1,128 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::path::Path;
+use nlm_tools::codepage::{self, Codepage};
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::screens::{self, Screen};
+use nlm_tools::symbols::{self, Image};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] memory.bin [outdir]", prog);
+    println!();
+    println!("Locates the console screens in a memory dump, and prints the text contents of");
+    println!("each of them; if outdir is given, every screen is written to a separate file");
+    println!();
+    println!("options:");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --codepage CP        codepage of the server (default {})", codepage::DEFAULT.number);
+    println!("  --list               only list the screens");
+}
+
+// Turns the screen name into something usable as file name
+fn file_name(screen: &Screen, n: usize) -> String {
+    let stem: String = screen.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect();
+    format!("{:02}-{}.txt", n, stem)
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut base: u32 = 0;
+    let mut codepage: &Codepage = codepage::DEFAULT;
+    let mut list = false;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--list" => { list = true; },
+            "--base" => {
+                match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(value) => { base = value; },
+                    None => {
+                        println!("--base requires an address");
+                        return Ok(())
+                    }
+                }
+            },
+            "--codepage" => {
+                match arg_iter.next().and_then(|name| codepage::find(name)) {
+                    Some(c) => { codepage = c; },
+                    None => {
+                        let supported: Vec<String> = codepage::CODEPAGES.iter().map(|c| format!("{} ({})", c.number, c.description)).collect();
+                        println!("--codepage requires a supported codepage: {}", supported.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.is_empty() || files.len() > 2 || (list && files.len() != 1) {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read memory dump '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let image = Image::new(&dump.data, base);
+    let mut found = screens::find_screens(&image);
+    if let Some(video) = Screen::video_memory(&dump.data) {
+        found.push(video);
+    }
+    if found.is_empty() {
+        println!("no screens found");
+        return Ok(())
+    }
+
+    let outdir = files.get(1).map(Path::new);
+    if let Some(outdir) = outdir {
+        std::fs::create_dir_all(outdir)?;
+    }
+    for (n, screen) in found.iter().enumerate() {
+        let location = screen.address.map(|a| format!("{:08x}", a)).unwrap_or("physical".to_string());
+        let header = format!("{} {}x{} buffer {:08x} cursor {},{}: {}",
+            location, screen.columns, screen.rows, screen.buffer, screen.cursor.0, screen.cursor.1, screen.name);
+        if list {
+            println!("{}", header);
+            continue;
+        }
+        let lines = match screen.cells(&image) {
+            Some(cells) => screen.text(cells, codepage),
+            None => continue,
+        };
+        match outdir {
+            Some(outdir) => {
+                let name = file_name(screen, n);
+                std::fs::write(outdir.join(&name), lines.join("\n") + "\n")?;
+                println!("{} -> {}", header, name);
+            },
+            None => {
+                println!("{}", header);
+                println!("{}", "-".repeat(screen.columns as usize));
+                for line in &lines {
+                    println!("{}", line);
+                }
+                println!();
+            }
+        }
+    }
+    Ok(())
+}
@@ -4,54 +4,186 @@
  * Copyright (c) 2022 Rink Springer <rink@rink.nu>
  * For conditions of distribution and use, see LICENSE file
  */
-use byteorder::{ByteOrder, LittleEndian};
 use std::env;
+use std::path::Path;
+use nlm_tools::nlm::{self, NLM};
+use nlm_tools::serverexe::{self, HereBlock, ServerVariant};
+use nlm_tools::unpack;
+use nlm_tools::versions;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.exe out.nlm", prog);
+    println!("       {} [options] --all server.exe outdir", prog);
+    println!("       {} [options] --here FORMAT server.exe", prog);
+    println!("       {} [options] --list server.exe", prog);
+    println!();
+    println!("options:");
+    println!("  --all                extract all embedded NLMs into outdir, instead of only");
+    println!("                       the first one (SERVER.NLM)");
+    println!("  --here FORMAT        show the HERE block describing the layout as text or");
+    println!("                       json, instead of extracting");
+    println!("  --list               list the embedded NLMs along with their module type,");
+    println!("                       load flags and description, instead of extracting");
+    println!("  --netware-version V  use the known offsets of NetWare version V, i.e. 3.12;");
+    println!("                       this is detected from the version string if omitted");
+}
 
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("usage: {} file.nlm server.exe out.nlm", args[0]);
+    let mut version: Option<String> = None;
+    let mut all = false;
+    let mut list = false;
+    let mut here_format: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--all" => { all = true; },
+            "--list" => { list = true; },
+            "--here" => {
+                here_format = arg_iter.next().cloned();
+                if !here_format.as_ref().is_some_and(|f| f == "text" || f == "json") {
+                    println!("--here requires a format (text or json)");
+                    return Ok(())
+                }
+            },
+            "--netware-version" => {
+                version = arg_iter.next().cloned();
+                if version.is_none() {
+                    println!("--netware-version requires an argument");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != if here_format.is_some() || list { 1 } else { 2 } {
+        usage(&args[0]);
         return Ok(())
     }
 
-    let server_fname = &args[1];
-    let nlm_fname = &args[2];
+    let server_fname = &files[0];
+
+    let server_data = match unpack::unpack_exe(std::fs::read(server_fname)?) {
+        Ok((server_data, packer)) => {
+            if let Some(packer) = packer {
+                println!("unpacked {} compressed executable", packer.name());
+            }
+            server_data
+        },
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    let known = match versions::select(version.as_deref(), &server_data) {
+        Ok(known) => known,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
 
-    let server_data = std::fs::read(server_fname)?;
+    let here = match HereBlock::find(&server_data) {
+        Ok(here) => here,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    let here_nlm = serverexe::here_nlm(&server_data, &here);
+    match here_format.as_deref() {
+        Some("json") => {
+            let mut json = here.to_json();
+            json["nlm_file_offset"] = serde_json::json!(here_nlm.map(|(offset, _)| offset));
+            json["nlm_pointer_kind"] = serde_json::json!(here_nlm.map(|(_, kind)| kind.name()));
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            return Ok(())
+        },
+        Some(_) => {
+            for line in here.to_text() {
+                println!("{}", line);
+            }
+            match here_nlm {
+                Some((offset, kind)) => println!("  nlm file offset {:x} ({})", offset, kind.name()),
+                None => println!("  nlm file offset unknown"),
+            }
+            return Ok(())
+        },
+        None => { }
+    }
 
-    // Locate HERE signature
-    let mut here_offset: Option<usize> = None;
-    for n in 0..0x1000 {
-        let piece = &server_data[n..n+4];
-        if piece == b"HERE" {
-            here_offset = Some(n);
-            break;
+    let nlm_offset = match serverexe::first_nlm(&server_data, &here) {
+        Some(offset) => offset,
+        None => {
+            println!("Signature found, but no NLM present");
+            return Ok(())
         }
+    };
+    if here_nlm.is_none() {
+        println!("WARNING: no NLM at offset {:x} given by the HERE block, using the one at {:x}", here.nlm_offset, nlm_offset);
     }
-    if here_offset.is_none() {
-        println!("HERE signature not found");
-        return Ok(())
+    let nlms = serverexe::find_nlms(&server_data, nlm_offset);
+    let variant = ServerVariant::detect(&server_data, &nlms);
+    if variant != ServerVariant::Standard {
+        println!("{} server, {} embedded NLMs", variant.name(), nlms.len());
     }
-    let here_offset = here_offset.unwrap() as usize;
 
-    let x = LittleEndian::read_u16(&server_data[here_offset + 0x2a..here_offset + 0x2c]);
-    let y = LittleEndian::read_u16(&server_data[here_offset + 0x2c..here_offset + 0x2e]);
-    if x != 0 || y != 0x110 {
-        println!("WARNING: unexpected version (?) words read, got {:x} {:x}", x, y);
+    // The version words identify the build when there is no version string;
+    // those of SFT III and SMP servers are not known
+    let words = here.version_words;
+    match known.or(versions::find_by_here_words(words)) {
+        _ if variant != ServerVariant::Standard => { },
+        Some(known) => {
+            if known.here_words.is_some_and(|w| w != words) {
+                println!("WARNING: unexpected version (?) words read for {}, got {:x} {:x}", known.description, words.0, words.1);
+            }
+        },
+        None => {
+            println!("WARNING: unknown version (?) words read, got {:x} {:x}", words.0, words.1);
+        }
     }
 
-    let nlm_offset = LittleEndian::read_u32(&server_data[here_offset + 0x18..here_offset + 0x1c]) as usize;
+    if list {
+        println!("offset   length   name         type                 flags                    description");
+        for nlm in &nlms {
+            let parsed = NLM::new(nlm.data(&server_data)).ok();
+            let module_type = parsed.as_ref().and_then(|n| n.module_type());
+            let flags = parsed.as_ref().and_then(|n| n.flags()).map(nlm::flag_names).unwrap_or_default();
+            println!("{:08x} {:8} {:<12} {:<20} {:<24} {}", nlm.offset, nlm.length,
+                nlm.name.as_deref().unwrap_or("?"),
+                module_type.map(nlm::module_type_name).unwrap_or("?".to_string()),
+                if flags.is_empty() { "-".to_string() } else { flags.join(" ") },
+                parsed.as_ref().and_then(|n| n.description()).unwrap_or_default());
+        }
+        return Ok(())
+    }
 
-    let nlm_magic = [
-        'N' as u8, 'e' as u8, 't' as u8, 'W' as u8, 'a' as u8, 'r' as u8,
-        'e' as u8, ' ' as u8, 'L' as u8, 'o' as u8, 'a' as u8, 'd' as u8,
-        'a' as u8, 'b' as u8, 'l' as u8, 'e' as u8, ' ' as u8, 'M' as u8,
-        'o' as u8, 'd' as u8, 'u' as u8, 'l' as u8, 'e' as u8, 0x1a as u8 ];
-    if &server_data[nlm_offset..nlm_offset + nlm_magic.len()] != nlm_magic {
-        println!("Signature found, but NLM at that offset has invalid magic");
-        return Ok(());
+    let out_fname = &files[1];
+    for nlm in &nlms {
+        if let Some(e) = &nlm.error {
+            println!("WARNING: cannot parse NLM at offset {:x}: {}", nlm.offset, e);
+        }
+    }
+    if !all {
+        std::fs::write(out_fname, nlms[0].data(&server_data))?;
+        return Ok(())
     }
 
-    std::fs::write(nlm_fname, &server_data[nlm_offset..])?;
+    std::fs::create_dir_all(out_fname)?;
+    let mut names: Vec<String> = Vec::new();
+    for (n, nlm) in nlms.iter().enumerate() {
+        let name = nlm.file_name(n, &names);
+        println!("{:08x} {:8} {}", nlm.offset, nlm.length, name);
+        std::fs::write(Path::new(out_fname).join(&name), nlm.data(&server_data))?;
+        names.push(name);
+    }
     Ok(())
 }
+
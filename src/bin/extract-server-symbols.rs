@@ -4,73 +4,218 @@
  * Copyright (c) 2022 Rink Springer <rink@rink.nu>
  * For conditions of distribution and use, see LICENSE file
  */
-use byteorder::{ByteOrder, LittleEndian};
 use std::env;
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
 use object::{Object, ObjectSection};
+use nlm_tools::codepage::{self, Codepage};
+use nlm_tools::components;
+use nlm_tools::serverexe::ServerVariant;
+use nlm_tools::symbols::{self, Image, Segment, Symbol, SymbolFormat};
+use nlm_tools::versions;
 
-const SERVER_SYM_PTR: usize = 0x40021628;
-
-fn read_string(data: &[u8]) -> String {
-    let len = data[0] as usize;
-    let s = &data[1..len + 1];
-    return if let Ok(s) = std::str::from_utf8(&s) {
-        s.to_string()
-    } else {
-        "?".to_string()
-    }
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.elf out.txt", prog);
+    println!();
+    println!("options:");
+    println!("  --codepage CP        codepage used to decode symbol names (default {})", codepage::DEFAULT.number);
+    println!("  --netware-version V  use the known offsets of NetWare version V, i.e. 3.12;");
+    println!("                       this is detected from the version string if omitted");
+    println!("  --format FORMAT      output format: text (name 0xaddr, the default), nm,");
+    println!("                       map (IDA), ghidra (ImportSymbolsScript.py), ghidra-script");
+    println!("                       (a script creating the labels and functions) or json");
+    println!("  --ptr ADDR           address of the symbol list pointer, i.e. 0x40021628;");
+    println!("                       this is located by scanning the data section if it is");
+    println!("                       not known for the version");
+    println!("  --components FILE    also write the estimated component regions of the code");
+    println!("                       (file system, memory manager, LAN layer, ...) to FILE,");
+    println!("                       in the same format");
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("usage: {} server.elf out.txt", args[0]);
+    let mut ptr: Option<u32> = None;
+    let mut version: Option<String> = None;
+    let mut codepage: &Codepage = codepage::DEFAULT;
+    let mut format = SymbolFormat::Text;
+    let mut components_fname: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--ptr" => {
+                ptr = arg_iter.next().and_then(|s| symbols::parse_address(s));
+                if ptr.is_none() {
+                    println!("--ptr requires an address");
+                    return Ok(())
+                }
+            },
+            "--format" => {
+                match arg_iter.next().and_then(|s| SymbolFormat::find(s)) {
+                    Some(f) => { format = f; },
+                    None => {
+                        let names: Vec<&str> = symbols::SYMBOL_FORMATS.iter().map(|(n, _)| *n).collect();
+                        println!("--format requires a format ({})", names.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            "--components" => {
+                components_fname = arg_iter.next().cloned();
+                if components_fname.is_none() {
+                    println!("--components requires a file name");
+                    return Ok(())
+                }
+            },
+            "--codepage" => {
+                match arg_iter.next().and_then(|name| codepage::find(name)) {
+                    Some(c) => { codepage = c; },
+                    None => {
+                        let supported: Vec<String> = codepage::CODEPAGES.iter().map(|c| format!("{} ({})", c.number, c.description)).collect();
+                        println!("--codepage requires a supported codepage: {}", supported.join(", "));
+                        return Ok(())
+                    }
+                }
+            },
+            "--netware-version" => {
+                version = arg_iter.next().cloned();
+                if version.is_none() {
+                    println!("--netware-version requires an argument");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 2 {
+        usage(&args[0]);
         return Ok(())
     }
 
-    let server_fname = &args[1];
-    let out_fname = &args[2];
+    let server_fname = &files[0];
+    let out_fname = &files[1];
 
     let server_data = std::fs::read(server_fname)?;
+    let known = match versions::select(version.as_deref(), &server_data) {
+        Ok(known) => known,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    if let Some(known) = known {
+        println!("using known offsets of {}", known.description);
+    }
 
     let elf = object::File::parse(&*server_data)?;
-    let mut base: usize = 0;
+    let mut base: u32 = 0;
     let mut data: Option<Vec<u8>> = None;
+    let mut code: Option<(u32, Vec<u8>)> = None;
+    let mut segments: Vec<Segment> = Vec::new();
     for s in elf.sections() {
-        if s.kind() != object::SectionKind::Data { continue; }
-        if let Ok(c) = s.uncompressed_data() {
-            if data.is_some() {
-                println!("multiple data sections found");
-                return Ok(())
+        if matches!(s.kind(), object::SectionKind::Text | object::SectionKind::Data) {
+            let name = s.name().unwrap_or("?").to_string();
+            segments.push(Segment{ name, address: s.address() as u32, length: s.size() as u32, is_code: s.kind() == object::SectionKind::Text });
+        }
+    }
+    for s in elf.sections() {
+        if s.kind() == object::SectionKind::Text && code.is_none() {
+            if let Ok(c) = s.uncompressed_data() {
+                code = Some((s.address() as u32, c.to_vec()));
             }
-            base = s.address() as usize;
+        }
+        if s.kind() != object::SectionKind::Data || data.is_some() { continue; }
+        if let Ok(c) = s.uncompressed_data() {
+            base = s.address() as u32;
             data = Some(c.to_vec());
-            break;
         }
     }
-    if data.is_none() {
-        println!("unable to find data section");
-        return Ok(())
-    }
-    let data = data.unwrap();
-    println!("{:x}", base);
+    let data = match data {
+        Some(data) => data,
+        None => {
+            println!("unable to find data section");
+            return Ok(())
+        }
+    };
+    println!("data section at {:x}", base);
 
     // Look up the pointer
-    let sym_addr = SERVER_SYM_PTR - base;
-    let mut sym_ptr = LittleEndian::read_u32(&data[sym_addr..sym_addr + 4]) as usize;
+    let image = Image::with_codepage(&data, base, codepage);
+    // The known pointer is only used if it leads to a plausible list, as
+    // the version string does not identify the exact build
+    let known_ptr = known.and_then(|v| v.server_sym_ptr)
+        .filter(|p| image.read_list(*p).is_ok_and(|l| l.len() >= symbols::MIN_SYMBOLS));
+    // SFT III and SMP servers have more than one symbol list, which are all
+    // used unless a pointer is given
+    let variant = ServerVariant::detect(&server_data, &[]);
+    let sym_ptrs: Vec<u32> = match ptr.or(known_ptr) {
+        Some(ptr) => vec![ ptr ],
+        None => {
+            let heads = match variant {
+                ServerVariant::Standard => image.find_list_head().into_iter().collect(),
+                _ => {
+                    println!("{} server, using all symbol lists", variant.name());
+                    image.find_list_heads()
+                }
+            };
+            if heads.is_empty() {
+                println!("unable to locate the symbol list, use --ptr to specify it");
+                return Ok(())
+            }
+            for (ptr, count) in &heads {
+                println!("symbol list pointer found at {:x} ({} symbols)", ptr, count);
+            }
+            heads.iter().map(|(ptr, _)| *ptr).collect()
+        }
+    };
 
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+    for sym_ptr in sym_ptrs {
+        match image.read_list_with_warnings(sym_ptr) {
+            Ok((list, list_warnings)) => {
+                warnings.extend(list_warnings);
+                for sym in list {
+                    if !symbols.iter().any(|s| s.name == sym.name && s.address == sym.address) {
+                        symbols.push(sym);
+                    }
+                }
+            },
+            Err(e) => {
+                println!("{}", e);
+                return Ok(())
+            }
+        }
+    }
+    for warning in warnings.iter().take(symbols::MAX_WARNINGS) {
+        println!("WARNING: {}", warning);
+    }
+    if warnings.len() > symbols::MAX_WARNINGS {
+        println!("WARNING: {} more damaged symbol names", warnings.len() - symbols::MAX_WARNINGS);
+    }
     let mut f = File::create(out_fname)?;
-    while sym_ptr != 0 {
-        let sym_addr = sym_ptr - base;
-        let next_ptr = LittleEndian::read_u32(&data[sym_addr+0..sym_addr+4]) as usize;
-        let func_ptr = LittleEndian::read_u32(&data[sym_addr+4..sym_addr+8]) as usize;
-        let name_ptr = LittleEndian::read_u32(&data[sym_addr+8..sym_addr+12]) as usize;
+    symbols::write_symbols(&mut f, &symbols, &segments, format)?;
 
-        let name = read_string(&data[name_ptr - base..]);
-        writeln!(f, "{} 0x{:x}", name, func_ptr)?;
-        sym_ptr = next_ptr;
+    if let Some(fname) = components_fname {
+        let (code_address, code) = match &code {
+            Some(code) => code,
+            None => {
+                println!("unable to find code section");
+                return Ok(())
+            }
+        };
+        let regions = components::find_components(code, *code_address, &data, base, &symbols);
+        for c in &regions {
+            println!("{:08x}-{:08x} {} ({} symbols, {} strings)", c.address, c.address + c.length, c.name, c.symbols, c.strings);
+        }
+        let mut f = File::create(fname)?;
+        components::write_components(&mut f, &regions, format)?;
     }
 
     Ok(())
@@ -0,0 +1,75 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::loader::{self, Loader};
+use nlm_tools::unpack;
+use nlm_tools::versions;
+
+fn usage(prog: &str) {
+    println!("usage: {} dump server.exe|loader.exe", prog);
+    println!("       {} extract server.exe loader.exe", prog);
+    println!("       {} apply server.exe loader.exe out.exe", prog);
+    println!();
+    println!("commands:");
+    println!("  dump                 show the layout of the DOS loader");
+    println!("  extract              write the loader of server.exe to loader.exe");
+    println!("  apply                replace the loader of server.exe by loader.exe, as");
+    println!("                       LSWAP.EXE does, and write the result to out.exe");
+}
+
+fn run(command: &str, files: &[String]) -> Result<(), String> {
+    let read = |fname: &str| {
+        let data = std::fs::read(fname).map_err(|e| format!("cannot read '{}': {}", fname, e))?;
+        let (data, packer) = unpack::unpack_exe(data)?;
+        if let Some(packer) = packer {
+            println!("unpacked {} compressed executable '{}'", packer.name(), fname);
+        }
+        Ok::<Vec<u8>, String>(data)
+    };
+    let write = |fname: &str, data: &[u8]| std::fs::write(fname, data).map_err(|e| format!("cannot write '{}': {}", fname, e));
+    match command {
+        "dump" => {
+            let data = read(&files[0])?;
+            let loader = Loader::parse(&data)?;
+            if let Some(version) = versions::detect(&data[0..loader.length]) {
+                println!("loader version       {}", version);
+            }
+            for line in loader.to_text(&data) {
+                println!("{}", line);
+            }
+        },
+        "extract" => {
+            let data = loader::extract_loader(&read(&files[0])?)?;
+            write(&files[1], &data)?;
+            println!("loader is {:x} bytes", data.len());
+        },
+        "apply" => {
+            let data = loader::replace_loader(&read(&files[0])?, &read(&files[1])?)?;
+            write(&files[2], &data)?;
+        },
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let expected = match args.get(1).map(|s| s.as_str()) {
+        Some("dump") => 1,
+        Some("extract") => 2,
+        Some("apply") => 3,
+        _ => 0,
+    };
+    if expected == 0 || args.len() != expected + 2 {
+        usage(&args[0]);
+        return Ok(())
+    }
+    if let Err(e) = run(&args[1], &args[2..]) {
+        println!("{}", e);
+    }
+    Ok(())
+}
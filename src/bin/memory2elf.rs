@@ -0,0 +1,134 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::HashSet;
+use std::env;
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::modules;
+use nlm_tools::resolve::{self, Resolver};
+use nlm_tools::symbols::{self, Image, Symbol};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] memory.bin out.elf", prog);
+    println!();
+    println!("Writes the entire memory of a running server to a single ELF file, with a");
+    println!("segment for the code and data of every loaded module and for the memory in");
+    println!("between, and a symbol table containing every symbol found");
+    println!();
+    println!("options:");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+    println!("  --ptr ADDR           address of the public symbol list pointer; this is");
+    println!("                       located by scanning the memory dump if omitted");
+    println!("  --modules FILE       module map, as with resolve-address; the loaded modules");
+    println!("                       are located by scanning the memory dump if omitted");
+    println!("  --symbols FILE       additional symbol file with absolute addresses");
+    println!("  --symbols FILE@NAME  additional symbol file of module NAME, at the addresses");
+    println!("                       nlm2elf uses");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut base: u32 = 0;
+    let mut ptr: Option<u32> = None;
+    let mut modules_fname: Option<String> = None;
+    let mut symbol_files: Vec<String> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--base" | "--ptr" => {
+                let value = match arg_iter.next().and_then(|s| symbols::parse_address(s)) {
+                    Some(value) => value,
+                    None => {
+                        println!("{} requires an address", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--base" { base = value; } else { ptr = Some(value); }
+            },
+            "--modules" | "--symbols" => {
+                let value = match arg_iter.next() {
+                    Some(value) => value.to_string(),
+                    None => {
+                        println!("{} requires a file name", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--modules" { modules_fname = Some(value); } else { symbol_files.push(value); }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 2 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read memory dump '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    let image = Image::new(&dump.data, base);
+    let module_ranges = match &modules_fname {
+        Some(fname) => resolve::parse_module_map(&std::fs::read_to_string(fname)?),
+        None => modules::find_modules(&image).iter().map(|m| m.to_range()).collect(),
+    };
+    println!("{} modules", module_ranges.len());
+
+    // Public symbols of all modules are kept in a single list
+    let public_symbols = match ptr.or(image.find_list_head().map(|(ptr, _)| ptr)) {
+        Some(ptr) => image.read_list(ptr).unwrap_or_else(|e| {
+            println!("WARNING: {}", e);
+            Vec::new()
+        }),
+        None => {
+            println!("WARNING: public symbol list not found, use --ptr to specify it");
+            Vec::new()
+        }
+    };
+
+    let mut resolver = Resolver::new(module_ranges.clone());
+    resolver.add_symbols(public_symbols);
+    for spec in &symbol_files {
+        let (fname, module) = match spec.rsplit_once('@') {
+            Some((fname, module)) => (fname, Some(module)),
+            None => (spec.as_str(), None),
+        };
+        let symbols = resolve::parse_symbol_file(&std::fs::read_to_string(fname)?);
+        match module {
+            Some(module) => {
+                if let Err(e) = resolver.add_module_symbols(module, symbols) {
+                    println!("{}", e);
+                    return Ok(())
+                }
+            },
+            None => resolver.add_symbols(symbols),
+        }
+    }
+    let mut seen: HashSet<(&str, u32)> = HashSet::new();
+    let all_symbols: Vec<Symbol> = resolver.symbols()
+        .filter(|s| seen.insert((s.name.as_str(), s.address)))
+        .cloned()
+        .collect();
+
+    let regions = modules::memory_regions(&image, &module_ranges);
+    for region in &regions {
+        println!("{:08x}-{:08x} {}", region.address, region.address as u64 + region.length as u64, region.name);
+    }
+    modules::write_memory_elf(&files[1], &image, &regions, &all_symbols)?;
+    println!("{} regions, {} symbols written", regions.len(), all_symbols.len());
+    Ok(())
+}
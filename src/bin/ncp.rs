@@ -0,0 +1,345 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use nlm_tools::bindery::{self, OT_USER};
+use nlm_tools::ncp::{self, NCPError};
+use nlm_tools::ncp::burst;
+use nlm_tools::ncp::connection::Connection;
+use nlm_tools::ncp::files::{self, Info};
+use nlm_tools::ncp::transport::{self, IpxTunnel, ServerLocation};
+use nlm_tools::nwfs::{self, volume};
+use nlm_tools::sap;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] command [arguments]", prog);
+    println!();
+    println!("Connects to a NetWare server using NCP over IPX, as tunneled over UDP by DOSBox");
+    println!("and similar emulators, or using NCP over TCP as NetWare 5 and later offer");
+    println!();
+    println!("commands:");
+    println!("  servers              list the servers which answer a SAP query");
+    println!("  info                 show information on the server");
+    println!("  ls [VOLUME:PATH]     list a directory, or the volumes if no path is given");
+    println!("  get VOLUME:PATH [LOCAL]");
+    println!("                       copy a file (or with --recursive, a directory) from the");
+    println!("                       server to LOCAL, or the current directory");
+    println!("  put LOCAL ... VOLUME:PATH");
+    println!("                       copy files to the server, into PATH if it is a directory");
+    println!();
+    println!("options:");
+    println!("  --tunnel HOST[:PORT] IPX tunnel server to use (port 213 by default)");
+    println!("  --tcp HOST[:PORT]    connect to the server using TCP (port 524 by default)");
+    println!("  --server NAME        server to connect to, by name or as NETWORK:NODE[:SOCKET];");
+    println!("                       the first server found is used by default");
+    println!("  --user NAME          user to log in as (GUEST by default)");
+    println!("  --password PASSWORD  password of the user");
+    println!("  --unencrypted        send the password as is rather than encrypted");
+    println!("  --long               show attributes, size, date and rights when listing");
+    println!("  --recursive          copy directories along with everything below them");
+    println!("  --no-burst           do not use packet burst to copy files over IPX");
+    println!("  --timezone TZ        time zone of the server, as UTC, +HH, +HHMM or +HH:MM;");
+    println!("                       used to keep the modification time of copied files");
+}
+
+struct Options {
+    tunnel: Option<String>,
+    tcp: Option<String>,
+    server: Option<String>,
+    user: String,
+    password: String,
+    unencrypted: bool,
+    long: bool,
+    recursive: bool,
+    burst: bool,
+    timezone: i32,
+}
+
+// Files are copied in blocks of this size, which packet burst sends at once
+const COPY_BUFFER_SIZE: usize = burst::MAX_BURST_SIZE;
+
+fn connect(options: &Options) -> Result<Connection, NCPError> {
+    let transport = transport::open(options.tcp.as_deref(), options.tunnel.as_deref(), options.server.as_deref())?;
+    let mut conn = Connection::create(transport)?;
+    conn.negotiate_buffer_size()?;
+    let login = if options.unencrypted {
+        conn.login_unencrypted(OT_USER, &options.user, &options.password)
+    } else {
+        conn.login_encrypted(OT_USER, &options.user, &options.password)
+    };
+    match login {
+        Err(NCPError::Completion(ncp::COMPLETION_PASSWORD_EXPIRED)) => eprintln!("warning: password has expired; logged in using a grace login"),
+        result => result?,
+    }
+    // Servers which do not offer packet burst are used without it
+    if options.burst && options.tcp.is_none() {
+        let _ = conn.negotiate_burst();
+    }
+    Ok(conn)
+}
+
+fn print_servers(servers: &[ServerLocation]) {
+    println!("{:<24} {:<47} {:<26} {:>4}", "type", "name", "address", "hops");
+    for l in servers {
+        let s = &l.service;
+        let type_name = bindery::object_type_name(s.service_type).map(|n| n.to_string()).unwrap_or_else(|| format!("type {:04x}", s.service_type));
+        println!("{:<24} {:<47} {:<26} {:>4}", type_name, s.name, l.address.to_string(), s.hops);
+    }
+}
+
+fn print_info(conn: &mut Connection) -> Result<(), NCPError> {
+    let info = conn.server_info()?;
+    println!("server name: {}", info.name);
+    println!("version: {}.{:02} revision {}", info.version.0, info.version.1, info.revision);
+    println!("connection number: {}", conn.number);
+    println!("connections: {} in use, {} at most, peak {}", info.connections_in_use, info.max_connections, info.peak_connections);
+    println!("SFT level: {}", info.sft_level);
+    println!("TTS level: {}", info.tts_level);
+    println!("buffer size: {}", conn.buffer_size);
+    match &conn.burst {
+        Some(burst) => println!("packet burst: packets of {} bytes", burst.packet_size),
+        None => println!("packet burst: not used"),
+    }
+    println!("volumes:");
+    for (number, name) in conn.volumes(info.max_volumes)? {
+        println!("  {:>3} {}", number, name);
+    }
+    Ok(())
+}
+
+fn print_entry(info: &Info, long: bool) {
+    let name = if info.is_directory() { format!("{}/", info.name) } else { info.name.clone() };
+    if long {
+        let size = if info.is_directory() { "-".to_string() } else { info.size.to_string() };
+        println!("{} {:>10} {:<19} {} {}", volume::attribute_string(info.attributes), size, nwfs::format_timestamp(info.modified),
+            volume::rights_string(info.rights_mask), name);
+    } else {
+        println!("{}", name);
+    }
+}
+
+fn list(conn: &mut Connection, path: Option<&str>, long: bool) -> Result<(), NCPError> {
+    let Some(path) = path else {
+        let info = conn.server_info()?;
+        for (_, name) in conn.volumes(info.max_volumes)? {
+            println!("{}:", name);
+        }
+        return Ok(())
+    };
+    let info = conn.lookup(path)?;
+    if !info.is_directory() {
+        print_entry(&info, long);
+        return Ok(())
+    }
+    let mut entries = conn.list_directory(&info)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for e in &entries {
+        print_entry(e, long);
+    }
+    Ok(())
+}
+
+// Path of name in the directory path refers to
+fn join_path(path: &str, name: &str) -> String {
+    let path = path.trim_end_matches(['/', '\\']);
+    if path.ends_with(':') { format!("{}{}", path, name) } else { format!("{}/{}", path, name) }
+}
+
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    bytes: u64,
+}
+
+fn set_local_modified(dest: &Path, timestamp: u32, options: &Options) -> Result<(), std::io::Error> {
+    if let Some(seconds) = nwfs::timestamp_to_unix(timestamp).and_then(|s| s.checked_add_signed(-options.timezone as i64)) {
+        File::options().write(true).open(dest)?.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))?;
+    }
+    Ok(())
+}
+
+fn get_file(conn: &mut Connection, directory: &Info, info: &Info, path: &str, dest: &Path, options: &Options, totals: &mut Totals) -> Result<(), NCPError> {
+    let mut file = conn.open_file(directory, &info.name, files::MODE_OPEN, files::ACCESS_READ | files::ACCESS_DENY_WRITE)?;
+    let mut out = BufWriter::with_capacity(COPY_BUFFER_SIZE, File::create(dest)?);
+    let copied = std::io::copy(&mut file, &mut out);
+    file.close()?;
+    let len = copied?;
+    drop(out);
+    set_local_modified(dest, info.modified, options)?;
+    println!("{} ({} bytes)", path, len);
+    totals.files += 1;
+    totals.bytes += len;
+    Ok(())
+}
+
+fn get_directory(conn: &mut Connection, directory: &Info, path: &str, dest: &Path, options: &Options, totals: &mut Totals) -> Result<(), NCPError> {
+    fs::create_dir_all(dest)?;
+    for e in conn.list_directory(directory)? {
+        let child_path = join_path(path, &e.name);
+        let result = if e.is_directory() {
+            get_directory(conn, &e, &child_path, &dest.join(&e.name), options, totals)
+        } else {
+            get_file(conn, directory, &e, &child_path, &dest.join(&e.name), options, totals)
+        };
+        if let Err(e) = result {
+            println!("WARNING: cannot copy {}: {}", child_path, e);
+        }
+    }
+    Ok(())
+}
+
+fn get(conn: &mut Connection, path: &str, local: Option<&str>, options: &Options) -> Result<(), NCPError> {
+    let info = conn.lookup(path)?;
+    // The root of a volume is named after it
+    let name = match files::split_path(path) {
+        Some((volume, components)) if components.is_empty() => volume.to_uppercase(),
+        _ => info.name.clone(),
+    };
+    let dest = match local {
+        Some(local) if !Path::new(local).is_dir() => PathBuf::from(local),
+        Some(local) => Path::new(local).join(&name),
+        None => PathBuf::from(&name),
+    };
+    let mut totals = Totals::default();
+    if info.is_directory() {
+        if !options.recursive {
+            return Err(NCPError::Unsupported(format!("'{}' is a directory; use --recursive to copy it", path)))
+        }
+        get_directory(conn, &info, path, &dest, options, &mut totals)?;
+        println!("{} files, {} bytes", totals.files, totals.bytes);
+    } else {
+        let (directory, _) = conn.lookup_parent(path)?;
+        get_file(conn, &directory, &info, path, &dest, options, &mut totals)?;
+    }
+    Ok(())
+}
+
+fn put_file(conn: &mut Connection, local: &Path, directory: &Info, name: &str, path: &str, options: &Options) -> Result<(), NCPError> {
+    let input = File::open(local)?;
+    let modified = input.metadata()?.modified()?;
+    let mut input = BufReader::with_capacity(COPY_BUFFER_SIZE, input);
+    let mut file = conn.open_file(directory, name, files::MODE_CREATE | files::MODE_REPLACE, files::ACCESS_READ | files::ACCESS_WRITE)?;
+    let copied = std::io::copy(&mut input, &mut file);
+    file.close()?;
+    let len = copied?;
+    if let Ok(seconds) = modified.duration_since(SystemTime::UNIX_EPOCH) {
+        conn.set_modified(directory, name, nwfs::unix_to_timestamp(seconds.as_secs().saturating_add_signed(options.timezone as i64)))?;
+    }
+    println!("{} ({} bytes)", path, len);
+    Ok(())
+}
+
+fn put(conn: &mut Connection, locals: &[String], path: &str, options: &Options) -> Result<(), NCPError> {
+    let directory = match conn.lookup(path) {
+        Ok(info) if info.is_directory() => Some(info),
+        Ok(_) | Err(NCPError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let Some(directory) = directory else {
+        if locals.len() > 1 {
+            return Err(NCPError::NotFound(format!("directory {}", path)))
+        }
+        let (directory, name) = conn.lookup_parent(path)?;
+        return put_file(conn, Path::new(&locals[0]), &directory, &name, path, options)
+    };
+    for local in locals {
+        let local = Path::new(local);
+        let Some(name) = local.file_name().map(|n| n.to_string_lossy().to_uppercase()) else { continue };
+        let remote = join_path(path, &name);
+        if local.is_dir() {
+            println!("WARNING: skipping directory {}", local.display());
+            continue;
+        }
+        if let Err(e) = put_file(conn, local, &directory, &name, &remote, options) {
+            println!("WARNING: cannot copy {}: {}", local.display(), e);
+        }
+    }
+    Ok(())
+}
+
+fn run(options: &Options, command: &str, arguments: &[String]) -> Result<(), NCPError> {
+    if command == "servers" || command == "slist" {
+        let Some(host) = &options.tunnel else {
+            return Err(NCPError::Unsupported("locating servers requires --tunnel".to_string()))
+        };
+        let mut tunnel = IpxTunnel::connect(host)?;
+        print_servers(&tunnel.find_servers(sap::SERVICE_ALL, transport::SAP_WAIT)?);
+        return Ok(())
+    }
+    let mut conn = connect(options)?;
+    let result = match command {
+        "info" => print_info(&mut conn),
+        "ls" | "dir" => list(&mut conn, arguments.first().map(|s| s.as_str()), options.long),
+        "get" if !arguments.is_empty() && arguments.len() <= 2 => get(&mut conn, &arguments[0], arguments.get(1).map(|s| s.as_str()), options),
+        "put" if arguments.len() >= 2 => put(&mut conn, &arguments[..arguments.len() - 1], &arguments[arguments.len() - 1], options),
+        "get" | "put" => Err(NCPError::Unsupported(format!("'{}' without the files to copy", command))),
+        _ => Err(NCPError::Unsupported(format!("command '{}'", command))),
+    };
+    conn.logout()?;
+    conn.destroy()?;
+    result
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options{ tunnel: None, tcp: None, server: None, user: "GUEST".to_string(), password: String::new(), unencrypted: false, long: false, recursive: false, burst: true, timezone: 0 };
+    let mut positional: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--long" => { options.long = true; },
+            "--recursive" => { options.recursive = true; },
+            "--no-burst" => { options.burst = false; },
+            "--unencrypted" => { options.unencrypted = true; },
+            "--timezone" => {
+                match arg_iter.next().and_then(|s| nwfs::parse_timezone(s)) {
+                    Some(timezone) => options.timezone = timezone,
+                    None => {
+                        println!("--timezone requires a time zone: UTC, +HH, +HHMM or +HH:MM");
+                        return Ok(())
+                    }
+                }
+            },
+            "--tunnel" | "--tcp" | "--server" | "--user" | "--password" => {
+                let Some(value) = arg_iter.next() else {
+                    println!("{} requires an argument", arg);
+                    return Ok(())
+                };
+                match arg.as_str() {
+                    "--tunnel" => { options.tunnel = Some(value.to_string()); },
+                    "--tcp" => { options.tcp = Some(value.to_string()); },
+                    "--server" => { options.server = Some(value.to_string()); },
+                    "--user" => { options.user = value.to_string(); },
+                    _ => { options.password = value.to_string(); },
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                positional.push(arg.to_string());
+            }
+        }
+    }
+    if positional.is_empty() {
+        usage(&args[0]);
+        return Ok(())
+    }
+    if options.tunnel.is_none() && options.tcp.is_none() {
+        println!("either --tunnel or --tcp is required");
+        return Ok(())
+    }
+
+    if let Err(e) = run(&options, &positional[0], &positional[1..]) {
+        println!("{}", e);
+    }
+    Ok(())
+}
@@ -0,0 +1,1861 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use nlm_tools::archive::{ArchiveWriter, TarWriter, ZipWriter};
+use nlm_tools::bindery::{self, Bindery};
+use nlm_tools::sha256::{self, Sha256};
+use nlm_tools::nwfs::{self, carve, check, hotfix, mirror, nwfs286, partition, tts, Device, NWFSError};
+use nlm_tools::nwfs::image::{self, ImageFormat};
+use nlm_tools::nwfs::partition::{DiskLayout, Partition, SEGMENT_FIELDS};
+use nlm_tools::nwfs::search::{self, Pattern};
+use nlm_tools::nwfs::volume::{self, DirEntry, Volume};
+use nlm_tools::nwfs::vtable::{Entry, VolumeTables};
+use nlm_tools::nwfs::write::VolumeWriter;
+use serde_json::{json, Value};
+
+fn usage(prog: &str) {
+    println!("usage: {} partitions IMAGE ...", prog);
+    println!("       {} volumes IMAGE ...", prog);
+    println!("       {} ls [options] IMAGE ... VOLUME[:PATH]", prog);
+    println!("       {} extract [options] IMAGE ... VOLUME[:PATH] DEST", prog);
+    println!("       {} extract --tar|--zip FILE [options] IMAGE ... VOLUME[:PATH]", prog);
+    println!("       {} salvage [options] IMAGE ... VOLUME[:PATH]", prog);
+    println!("       {} trustees [options] IMAGE ... VOLUME[:PATH]", prog);
+    println!("       {} check [options] IMAGE ... VOLUME", prog);
+    println!("       {} mount [options] IMAGE ... VOLUME MOUNTPOINT", prog);
+    println!("       {} put [options] IMAGE ... SOURCE VOLUME:PATH", prog);
+    println!("       {} mkfs [options] IMAGE SIZE VOLUME", prog);
+    println!("       {} rename [options] IMAGE ... VOLUME NAME", prog);
+    println!("       {} set-segment [options] IMAGE ... VOLUME SEGMENT FIELD=VALUE ...", prog);
+    println!("       {} fix-segments [options] IMAGE ... VOLUME", prog);
+    println!("       {} grep [options] IMAGE ... PATTERN VOLUME[:PATH]", prog);
+    println!("       {} stats [options] IMAGE ... VOLUME[:PATH]", prog);
+    println!("       {} carve IMAGE ...", prog);
+    println!("       {} mirrors [options] IMAGE ... VOLUME", prog);
+    println!("       {} tts [options] IMAGE ... VOLUME", prog);
+    println!("       {} badblocks [options] IMAGE ...", prog);
+    println!("       {} bindery [options] IMAGE ... [-- DUMP-BINDERY OPTIONS]", prog);
+    println!();
+    println!("Reads NetWare 2.x, 3.x and 4.x volumes from disk images, or images of just the");
+    println!("NetWare partition; volumes spanning multiple disks are read from all images");
+    println!("given");
+    println!();
+    println!("commands:");
+    println!("  partitions           show the partition table and the layout of every NetWare");
+    println!("                       partition: hotfix and mirror areas and volume segments");
+    println!("  volumes              list the volume segments of all NetWare partitions");
+    println!("  ls                   list the contents of directory PATH (the root of the");
+    println!("                       volume if omitted)");
+    println!("  extract              write file PATH to DEST, or the contents of directory");
+    println!("                       PATH to directory DEST, including all subdirectories");
+    println!("  salvage              list the deleted files below PATH which have not been");
+    println!("                       purged, and whether their data is still intact");
+    println!("  trustees             show the trustees and inherited rights mask of PATH, with");
+    println!("                       the names of the trustees taken from the bindery on SYS");
+    println!("  check                check the consistency of the volume as VREPAIR does, without");
+    println!("                       changing anything: FAT chains, cross-linked and lost blocks,");
+    println!("                       directory entries and name space entries");
+    println!("  mount                mount the volume read-only on MOUNTPOINT using FUSE, until");
+    println!("                       it is unmounted; requires building with --features fuse");
+    println!("  put                  write file SOURCE to PATH on the volume, replacing it if it");
+    println!("                       exists, or into directory PATH; this changes the images");
+    println!("  mkfs                 create IMAGE of SIZE bytes (with suffix K, M or G) holding");
+    println!("                       a NetWare 3.x partition with empty volume VOLUME");
+    println!("  rename               rename VOLUME to NAME in the volume tables and its root");
+    println!("                       directory; this changes the images");
+    println!("  set-segment          set fields of the volume table entries of segment SEGMENT");
+    println!("                       of VOLUME, on all mirrored partitions; VALUE is decimal,");
+    println!("                       or hex with prefix 0x. This changes the images");
+    println!("  fix-segments         make the volume table entries of VOLUME agree on the");
+    println!("                       signature, block size, FAT and directory, and renumber");
+    println!("                       the segments in order; this changes the images");
+    println!("  grep                 search the contents of file PATH, or all files below");
+    println!("                       directory PATH, for PATTERN and show every match");
+    println!("  stats                show the space used by the volume and the files below PATH:");
+    println!("                       largest files and directories, extensions, compression");
+    println!("                       and deleted files which can be salvaged");
+    println!("  carve                scan damaged or unpartitioned images for NetWare partitions,");
+    println!("                       volume tables and root directories, and show how likely");
+    println!("                       each is to be intact");
+    println!("  mirrors              show the mirrored partitions holding the volume, which copy");
+    println!("                       is read and where the copies differ");
+    println!("  tts                  show the TTS backout file and error log of the volume, and");
+    println!("                       the transactional files which incomplete transactions may");
+    println!("                       have changed");
+    println!("  badblocks            list the blocks of every NetWare partition which were");
+    println!("                       redirected by hotfix, and the files, FAT or directory");
+    println!("                       they hold, to show how much data of a failing disk is at");
+    println!("                       risk");
+    println!("  bindery              locate the bindery files (or NDS database) on volume SYS");
+    println!("                       and show them using dump-bindery with the options given");
+    println!();
+    println!("options:");
+    println!("  --namespace NS       name space to use for names: DOS, MAC, NFS or LONG (OS2);");
+    println!("                       the default is LONG if present. DOS names are used for");
+    println!("                       files without a name in the name space");
+    println!("  --timezone TZ        time zone of the server, as UTC, +HH, +HHMM or +HH:MM;");
+    println!("                       times are stored in local time and shown in UTC");
+    println!("  --bindery PATH       directory with the bindery files, or image with volume SYS,");
+    println!("                       used to show the names of owners and trustees");
+    println!("  --sector-size N      size of the sectors counted by the partition table, for");
+    println!("                       disks without 512 byte sectors");
+    println!("  --geometry C/H/S     locate partitions by their CHS address in the partition");
+    println!("                       table using this geometry, instead of the LBA address");
+    println!("  --partition-offset N byte offset of the NetWare partition (K, M and G may be");
+    println!("                       used), ignoring the partition table; the offset of its");
+    println!("                       data area may be given if the hotfix header is lost");
+    println!("  --cache-size N       bytes of every image kept in memory while reading (K, M");
+    println!("                       and G may be used; the default is 16M, 0 disables it)");
+    println!();
+    println!("extract options:");
+    println!("  --tar FILE           write PATH to tar archive FILE instead, with the NetWare");
+    println!("                       attributes and trustees written to FILE.json");
+    println!("  --zip FILE           likewise, but write a zip archive");
+    println!("  --previous FILE      only write the files which are new or changed since the");
+    println!("                       extraction which wrote manifest FILE: files with the same");
+    println!("                       size and modification time are skipped, as are files");
+    println!("                       whose SHA-256 hash did not change");
+    println!();
+    println!("extract and salvage options:");
+    println!("  --manifest FILE      write the path, size, timestamps and SHA-256 hash of every");
+    println!("                       file written to FILE as CSV");
+    println!("  --mac-forks FORMAT   how to write the resource fork and Finder info of files");
+    println!("                       stored by Macs: appledouble (the default) writes them to");
+    println!("                       ._NAME, rsrc to NAME.rsrc and NAME.finf, none skips them");
+    println!();
+    println!("ls options:");
+    println!("  --long               show attributes, size, creation, modification and archive");
+    println!("                       times and owner; @ marks extended attributes");
+    println!("  --recursive          list all subdirectories as well");
+    println!("  --json               write the listing with all metadata as JSON; along with");
+    println!("                       --recursive, the directory tree is written with the");
+    println!("                       contents of every directory in its children");
+    println!("  --csv                write the listing with all metadata as CSV");
+    println!();
+    println!("check options:");
+    println!("  --json               write the issues found as JSON");
+    println!();
+    println!("trustees options:");
+    println!("  --recursive          include all files and directories below PATH which have");
+    println!("                       trustees or do not inherit all rights");
+    println!();
+    println!("bindery options:");
+    println!("  --extract DIR        write the files to DIR instead of showing them");
+    println!();
+    println!("tts options:");
+    println!("  --extract DIR        write the backout file and error log to DIR");
+    println!();
+    println!("salvage options:");
+    println!("  --extract DIR        write the deleted files whose data is intact to DIR");
+    println!();
+    println!("grep options:");
+    println!("  --hex                PATTERN is given as hex bytes, such as '4e 57' or 4e57");
+    println!("  --ignore-case        ignore the case of ASCII letters");
+    println!("  --json               write the matches as JSON");
+    println!();
+    println!("stats options:");
+    println!("  --top N              number of largest files and directories shown (default 10)");
+    println!("  --json               write the statistics as JSON");
+    println!();
+    println!("set-segment fields:");
+    println!("  last-segment, segment-number, signature, volume-blocks, segment-sectors,");
+    println!("  first-sector, flags, sectors-per-block, first-fat, mirror-fat, first-directory,");
+    println!("  mirror-directory, first-block");
+    println!();
+    println!("fix-segments options:");
+    println!("  --dry-run            show the changes without making them");
+    println!();
+    println!("mkfs options:");
+    println!("  --block-size KB      block size of the volume: 4, 8, 16, 32 or 64 (the default");
+    println!("                       is 4); --timezone sets the time zone of the server");
+}
+
+fn open(images: &[String], spec: &str, options: &Options) -> Result<(Volume, u32), NWFSError> {
+    let (volume_name, path) = spec.split_once(':').unwrap_or((spec, ""));
+    let mut volume = volume::open_volume_set(images, volume_name, &options.disk)?;
+    if let Some(namespace) = options.namespace {
+        if !volume.namespaces().contains(&namespace) {
+            println!("WARNING: volume {} has no {} name space, using DOS names", volume.name(), volume::namespace_name(namespace));
+        }
+        volume.namespace = namespace;
+    }
+    volume.timezone = options.timezone;
+    let entry = volume.lookup(path).ok_or(NWFSError::FileNotFound(spec.to_string()))?.entry;
+    Ok((volume, entry))
+}
+
+// Entries of directory dir in the order listed: by name, followed by the
+// contents of every subdirectory if recursive
+fn collect_entries<'a>(volume: &'a Volume, dir: u32, recursive: bool, result: &mut Vec<&'a DirEntry>) {
+    let mut children: Vec<&DirEntry> = volume.children(dir).collect();
+    children.sort_by(|a, b| volume.display_name(a).cmp(volume.display_name(b)));
+    result.extend(&children);
+    if recursive {
+        for e in children.iter().filter(|e| e.is_directory()) {
+            collect_entries(volume, e.entry, recursive, result);
+        }
+    }
+}
+
+// Timestamps in UTC; those which cannot be converted are shown as stored
+fn time_string(volume: &Volume, timestamp: u32) -> Option<String> {
+    match volume.unix_time(timestamp) {
+        _ if timestamp == 0 => None,
+        Some(seconds) => Some(nwfs::format_unix_time(seconds)),
+        None => Some(nwfs::format_timestamp(timestamp)),
+    }
+}
+
+// Object IDs are shown as name if known
+fn object_string(names: &HashMap<u32, String>, object: u32) -> String {
+    names.get(&object).cloned().unwrap_or(format!("{:08x}", object))
+}
+
+// Entries with extended attributes are marked with @, as ls does on macOS
+fn long_line(volume: &Volume, names: &HashMap<u32, String>, e: &DirEntry, name: &str) -> String {
+    let time = |t: u32| time_string(volume, t).unwrap_or("-".to_string());
+    format!("{}{} {:>10} {:<20} {:<20} {:<20} {:<8} {}", volume::attribute_string(e.attributes),
+        if e.ea_handle != 0 { "@" } else { " " },
+        if e.is_directory() { "-".to_string() } else { e.size.to_string() },
+        time(e.created), time(e.modified), time(e.archived), object_string(names, e.owner), name)
+}
+
+fn entry_json(volume: &Volume, names: &HashMap<u32, String>, e: &DirEntry) -> Value {
+    let timestamp = |t: u32| json!(time_string(volume, t));
+    let object_name = |o: u32| json!(names.get(&o));
+    json!({
+        "path": volume.path(e),
+        "name": volume.display_name(e),
+        "dos_name": e.name,
+        "entry": e.entry,
+        "directory": e.is_directory(),
+        "size": e.size,
+        "attributes": e.attributes,
+        "attribute_names": volume::attribute_names(e.attributes),
+        "created": timestamp(e.created),
+        "modified": timestamp(e.modified),
+        "archived": timestamp(e.archived),
+        "owner": e.owner,
+        "owner_name": object_name(e.owner),
+        "modifier": e.modifier,
+        "modifier_name": object_name(e.modifier),
+        "archiver": e.archiver,
+        "archiver_name": object_name(e.archiver),
+        "inherited_rights": e.inherited_rights,
+        "ea_handle": if e.ea_handle != 0 { json!(e.ea_handle) } else { Value::Null },
+    })
+}
+
+// The directory along with everything below it, which is listed in the
+// children of every directory
+fn tree_json(volume: &Volume, names: &HashMap<u32, String>, dir: &DirEntry) -> Value {
+    let mut json = entry_json(volume, names, dir);
+    if dir.is_directory() {
+        let mut children: Vec<&DirEntry> = volume.children(dir.entry).collect();
+        children.sort_by(|a, b| volume.display_name(a).cmp(volume.display_name(b)));
+        json["children"] = children.iter().map(|e| tree_json(volume, names, e)).collect();
+    }
+    json
+}
+
+const CSV_COLUMNS: [ &str; 19 ] = [
+    "path", "name", "dos_name", "entry", "directory", "size", "attributes", "attribute_names",
+    "created", "modified", "archived", "owner", "owner_name", "modifier", "modifier_name",
+    "archiver", "archiver_name", "inherited_rights", "ea_handle",
+];
+
+// Fields are quoted if needed; lists are separated by spaces
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(a) => a.iter().map(csv_field).collect::<Vec<_>>().join(" "),
+        v => v.to_string(),
+    };
+    if field.contains([ ',', '"', '\n', '\r' ]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn list(volume: &Volume, names: &HashMap<u32, String>, entry: u32, options: &Options) {
+    let e = &volume.entries[entry as usize];
+    if options.json && options.recursive {
+        println!("{}", serde_json::to_string_pretty(&tree_json(volume, names, e)).unwrap());
+        return;
+    }
+    let mut entries: Vec<&DirEntry> = Vec::new();
+    if e.is_directory() {
+        collect_entries(volume, entry, options.recursive, &mut entries);
+    } else {
+        entries.push(e);
+    }
+    if options.json {
+        let output: Vec<Value> = entries.iter().map(|e| entry_json(volume, names, e)).collect();
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return;
+    }
+    if options.csv {
+        println!("{}", CSV_COLUMNS.join(","));
+        for e in entries {
+            let json = entry_json(volume, names, e);
+            println!("{}", CSV_COLUMNS.iter().map(|c| csv_field(&json[c])).collect::<Vec<_>>().join(","));
+        }
+        return;
+    }
+    for e in entries {
+        let name = if options.recursive || e.entry == entry { volume.path(e) } else { volume.display_name(e).to_string() };
+        if options.long {
+            println!("{}", long_line(volume, names, e, &name));
+        } else if e.is_directory() {
+            println!("{}/", name);
+        } else {
+            println!("{}", name);
+        }
+    }
+}
+
+// Names are used as-is, except for anything that would leave the output
+// directory
+fn file_name(name: &str, entry: u32) -> String {
+    match name {
+        "" | "." | ".." => format!("ENTRY{}", entry),
+        name => name.replace(['/', '\\'], "_"),
+    }
+}
+
+// AppleDouble version 2 file holding the Finder info and resource fork, as
+// written by macOS to ._NAME on file systems without forks
+fn apple_double(finder_info: &[u8], resource_fork: &[u8]) -> Vec<u8> {
+    let entries = [ (APPLEDOUBLE_FINDER_INFO, finder_info), (APPLEDOUBLE_RESOURCE_FORK, resource_fork) ];
+    let mut result: Vec<u8> = Vec::new();
+    result.extend(APPLEDOUBLE_MAGIC.to_be_bytes());
+    result.extend(APPLEDOUBLE_VERSION.to_be_bytes());
+    result.extend([ 0u8; 16 ]);
+    result.extend((entries.len() as u16).to_be_bytes());
+    let mut offset = result.len() + entries.len() * 12;
+    for (id, data) in &entries {
+        result.extend(id.to_be_bytes());
+        result.extend((offset as u32).to_be_bytes());
+        result.extend((data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in &entries {
+        result.extend(*data);
+    }
+    result
+}
+
+fn with_name(dest: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    dest.with_file_name(format!("{}{}{}", prefix, dest.file_name().unwrap_or_default().to_string_lossy(), suffix))
+}
+
+// Extracted files are written to a directory or to an archive, in which case
+// dest is the path within the archive. The NetWare metadata of everything
+// added to an archive is collected, as it cannot be stored in the archive.
+enum Target {
+    Directory,
+    Archive(Box<dyn ArchiveWriter>, Vec<Value>, HashMap<u32, String>),
+}
+
+// Hashes everything read through it, for the manifest
+struct HashingReader<'a, R: Read> {
+    inner: R,
+    hasher: Option<&'a mut Sha256>,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+// A file in the manifest of an earlier extraction, given by --previous
+struct PreviousFile {
+    size: u64,
+    modified: String,
+    sha256: String,
+    fields: Vec<String>,
+}
+
+// The manifest holds a line for every file written, if requested. Files
+// which did not change since the extraction described by the manifest
+// given by --previous are not written again; they keep their line
+struct Output {
+    target: Target,
+    manifest: Option<Vec<String>>,
+    previous: HashMap<String, PreviousFile>,
+    unchanged: usize,
+}
+
+const MANIFEST_COLUMNS: [ &str; 7 ] = [ "file", "path", "size", "created", "modified", "archived", "sha256" ];
+
+// Parses CSV as written by csv_field: fields are quoted if they contain a
+// comma, quote or line break, with quotes doubled
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            '\r' if !quoted => { },
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+// Files of a manifest written by --manifest, by the path they were written to
+fn read_manifest(fname: &str) -> Result<HashMap<String, PreviousFile>, NWFSError> {
+    let mut rows = parse_csv(&std::fs::read_to_string(fname)?).into_iter();
+    let header = rows.next().unwrap_or_default();
+    let column = |name: &str| header.iter().position(|c| c == name);
+    let (Some(file), Some(size), Some(modified), Some(sha256)) = (column("file"), column("size"), column("modified"), column("sha256")) else {
+        return Err(NWFSError::InvalidHeader(format!("{} is not a manifest written by --manifest", fname)));
+    };
+    Ok(rows.filter(|r| r.len() == header.len()).map(|r| {
+        let previous = PreviousFile{ size: r[size].parse().unwrap_or(u64::MAX), modified: r[modified].clone(), sha256: r[sha256].clone(), fields: r.clone() };
+        (r[file].clone(), previous)
+    }).collect())
+}
+
+// Read-only files lose their write permission; the DOS attributes are kept
+// as well in zip archives
+fn unix_mode(entry: &DirEntry) -> u32 {
+    if entry.is_directory() {
+        0o755
+    } else if entry.attributes & volume::ATTR_READ_ONLY != 0 {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+impl Output {
+    fn new(target: Target, options: &Options) -> Self {
+        Self{ target, manifest: options.manifest.as_ref().map(|_| Vec::new()), previous: HashMap::new(), unchanged: 0 }
+    }
+
+    fn read_previous(&mut self, options: &Options) -> Result<(), NWFSError> {
+        if let Some(fname) = &options.previous {
+            self.previous = read_manifest(fname)?;
+        }
+        Ok(())
+    }
+
+    // Unchanged files are skipped when writing to a directory only if they
+    // are still there; archives hold the files which changed
+    fn was_written(&self, dest: &Path) -> bool {
+        match self.target {
+            Target::Directory => dest.exists(),
+            Target::Archive(_, _, _) => true,
+        }
+    }
+
+    // Files with the same size and modification time as in the previous
+    // manifest are not read; their lines are kept, and those of their forks
+    fn is_unchanged(&mut self, volume: &Volume, dest: &Path, entry: &DirEntry) -> bool {
+        let unchanged = self.previous.get(dest.to_string_lossy().as_ref())
+            .is_some_and(|p| p.size == entry.size as u64 && p.modified == time_string(volume, entry.modified).unwrap_or_default());
+        if !unchanged || !self.was_written(dest) {
+            return false
+        }
+        if let Some(manifest) = &mut self.manifest {
+            for path in [ dest.to_path_buf(), with_name(dest, "._", ""), with_name(dest, "", ".rsrc"), with_name(dest, "", ".finf") ] {
+                if let Some(p) = self.previous.get(path.to_string_lossy().as_ref()) {
+                    manifest.push(p.fields.iter().map(|f| csv_field(&json!(f))).collect::<Vec<_>>().join(","));
+                }
+            }
+        }
+        self.unchanged += 1;
+        true
+    }
+
+    // Files whose contents have the same hash as in the previous manifest
+    // are not written again; only their modification time is updated
+    fn is_unchanged_data(&mut self, volume: &mut Volume, dest: &Path, entry: &DirEntry) -> Result<bool, NWFSError> {
+        let Some(previous) = self.previous.get(dest.to_string_lossy().as_ref()).map(|p| p.sha256.clone()) else {
+            return Ok(false)
+        };
+        if !self.was_written(dest) {
+            return Ok(false)
+        }
+        let mut hasher = Sha256::new();
+        volume.read_file_blocks(entry, |_, data| hasher.update(data))?;
+        let hash = hasher.finish();
+        if sha256::to_hex(&hash) != previous {
+            return Ok(false)
+        }
+        if let (Target::Directory, Some(seconds)) = (&self.target, volume.unix_time(entry.modified)) {
+            File::options().write(true).open(dest)?.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))?;
+        }
+        self.add_manifest_line(volume, dest, entry.size as usize, &hash, entry);
+        self.unchanged += 1;
+        Ok(true)
+    }
+
+    fn add_directory(&mut self, volume: &Volume, dest: &Path, entry: &DirEntry) -> Result<(), NWFSError> {
+        match &mut self.target {
+            Target::Directory => std::fs::create_dir_all(dest)?,
+            Target::Archive(archive, _, _) => {
+                archive.add_directory(&dest.to_string_lossy(), volume.unix_time(entry.modified).unwrap_or(0),
+                    unix_mode(entry), (entry.attributes & DOS_ATTRIBUTES) as u8)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, volume: &Volume, dest: &Path, data: &[u8], entry: &DirEntry) -> Result<(), NWFSError> {
+        let mtime = volume.unix_time(entry.modified);
+        match &mut self.target {
+            Target::Directory => {
+                std::fs::write(dest, data)?;
+                if let Some(seconds) = mtime {
+                    File::options().write(true).open(dest)?.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))?;
+                }
+            },
+            Target::Archive(archive, _, _) => {
+                archive.add_file(&dest.to_string_lossy(), data, mtime.unwrap_or(0), unix_mode(entry), (entry.attributes & DOS_ATTRIBUTES) as u8)?;
+            },
+        }
+        self.add_to_manifest(volume, dest, data, entry);
+        Ok(())
+    }
+
+    // Reads the file from the volume a block at a time, so it is never in
+    // memory as a whole. Holes are skipped when writing to a directory, so
+    // the output file is sparse as well; archives cannot describe holes, so
+    // they hold zeroes
+    fn add_volume_file(&mut self, volume: &mut Volume, dest: &Path, entry: &DirEntry, sparse: bool) -> Result<(), NWFSError> {
+        let blocks = volume.file_blocks(entry)?;
+        let mut hasher = self.manifest.as_ref().map(|_| Sha256::new());
+        let mtime = volume.unix_time(entry.modified);
+        match &mut self.target {
+            Target::Directory => {
+                let mut f = File::create(dest)?;
+                f.set_len(blocks.size as u64)?;
+                for index in 0..blocks.parts() {
+                    let data = volume.read_file_part(&blocks, index)?;
+                    if let Some(hasher) = &mut hasher {
+                        hasher.update(&data);
+                    }
+                    if blocks.is_hole(index) {
+                        continue;
+                    }
+                    f.seek(SeekFrom::Start((index * blocks.block_size) as u64))?;
+                    f.write_all(&data)?;
+                }
+                if let Some(seconds) = mtime {
+                    f.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))?;
+                }
+            },
+            Target::Archive(archive, _, _) => {
+                if sparse {
+                    println!("WARNING: {} is sparse; its holes are stored as zeroes", dest.display());
+                }
+                let mut reader = HashingReader{ inner: volume.open_file(entry)?, hasher: hasher.as_mut() };
+                archive.add_file_from(&dest.to_string_lossy(), blocks.size as u64, &mut reader, mtime.unwrap_or(0), unix_mode(entry),
+                    (entry.attributes & DOS_ATTRIBUTES) as u8)?;
+            },
+        }
+        if let Some(hasher) = hasher {
+            self.add_manifest_line(volume, dest, blocks.size, &hasher.finish(), entry);
+        }
+        Ok(())
+    }
+
+    fn add_to_manifest(&mut self, volume: &Volume, dest: &Path, data: &[u8], entry: &DirEntry) {
+        if self.manifest.is_some() {
+            self.add_manifest_line(volume, dest, data.len(), &sha256::sha256(data), entry);
+        }
+    }
+
+    fn add_manifest_line(&mut self, volume: &Volume, dest: &Path, size: usize, hash: &[u8], entry: &DirEntry) {
+        if let Some(manifest) = &mut self.manifest {
+            let time = |t: u32| json!(time_string(volume, t));
+            let fields = [ json!(dest.to_string_lossy()), json!(volume.path(entry)), json!(size),
+                time(entry.created), time(entry.modified), time(entry.archived), json!(sha256::to_hex(hash)) ];
+            manifest.push(fields.iter().map(csv_field).collect::<Vec<_>>().join(","));
+        }
+    }
+
+    // Writes the manifest as CSV to the file given by --manifest
+    fn write_manifest(&self, options: &Options) -> Result<(), NWFSError> {
+        if options.previous.is_some() {
+            println!("{} files unchanged since the previous extraction", self.unchanged);
+        }
+        if let (Some(manifest), Some(fname)) = (&self.manifest, &options.manifest) {
+            let mut out = BufWriter::new(File::create(fname)?);
+            writeln!(out, "{}", MANIFEST_COLUMNS.join(","))?;
+            for line in manifest {
+                writeln!(out, "{}", line)?;
+            }
+            out.flush()?;
+            println!("wrote manifest of {} files to {}", manifest.len(), fname);
+        }
+        Ok(())
+    }
+
+    fn add_metadata(&mut self, volume: &Volume, dest: &Path, entry: &DirEntry) {
+        if let Target::Archive(_, metadata, names) = &mut self.target {
+            let mut json = entry_json(volume, names, entry);
+            json["archive_path"] = json!(dest.to_string_lossy());
+            json["trustees"] = volume.trustees(entry).iter().map(|t| json!({ "object": t.object, "rights": t.rights })).collect();
+            metadata.push(json);
+        }
+    }
+}
+
+// Files without a resource fork or Finder info get no extra files
+fn extract_forks(volume: &mut Volume, entry: &DirEntry, dest: &Path, forks: Forks, output: &mut Output) -> Result<(), NWFSError> {
+    if forks == Forks::None {
+        return Ok(())
+    }
+    let (finder_info, resource_fork) = match volume.mac_forks(entry)? {
+        Some(m) if !m.resource_fork.is_empty() || m.finder_info.iter().any(|b| *b != 0) => (m.finder_info, m.resource_fork),
+        _ => return Ok(()),
+    };
+    match forks {
+        Forks::AppleDouble => output.add_file(volume, &with_name(dest, "._", ""), &apple_double(&finder_info, &resource_fork), entry)?,
+        Forks::Rsrc => {
+            if !resource_fork.is_empty() {
+                output.add_file(volume, &with_name(dest, "", ".rsrc"), &resource_fork, entry)?;
+            }
+            output.add_file(volume, &with_name(dest, "", ".finf"), &finder_info, entry)?;
+        },
+        Forks::None => {},
+    }
+    println!("{} (resource fork, {} bytes)", volume.path(entry), resource_fork.len());
+    Ok(())
+}
+
+fn extract(volume: &mut Volume, entry: &DirEntry, dest: &Path, forks: Forks, output: &mut Output) -> Result<(), NWFSError> {
+    if entry.is_directory() {
+        output.add_directory(volume, dest, entry)?;
+        output.add_metadata(volume, dest, entry);
+        let children: Vec<DirEntry> = volume.children(entry.entry).cloned().collect();
+        for child in &children {
+            let name = file_name(volume.display_name(child), child.entry);
+            if let Err(e) = extract(volume, child, &dest.join(name), forks, output) {
+                println!("WARNING: cannot extract {}: {}", volume.path(child), e);
+            }
+        }
+        return Ok(())
+    }
+    // Compressed files are written as stored, so they are not lost
+    let dest = if entry.is_compressed() { with_name(dest, "", ".compressed") } else { dest.to_path_buf() };
+    if output.is_unchanged(volume, &dest, entry) {
+        return Ok(())
+    }
+    if entry.is_compressed() {
+        println!("WARNING: {} is compressed, which is not supported; writing its compressed data to {}", volume.path(entry), dest.display());
+        let data = volume.read_stored(entry)?;
+        output.add_file(volume, &dest, &data, entry)?;
+        output.add_metadata(volume, &dest, entry);
+        println!("{} ({} bytes)", volume.path(entry), data.len());
+        return extract_forks(volume, entry, &dest, forks, output)
+    }
+    if output.is_unchanged_data(volume, &dest, entry)? {
+        println!("{} ({} bytes, unchanged)", volume.path(entry), entry.size);
+        return extract_forks(volume, entry, &dest, forks, output)
+    }
+    let holes = volume.holes(entry)?;
+    output.add_volume_file(volume, &dest, entry, !holes.is_empty())?;
+    output.add_metadata(volume, &dest, entry);
+    if holes.is_empty() {
+        println!("{} ({} bytes)", volume.path(entry), entry.size);
+    } else {
+        let hole_bytes: usize = holes.iter().map(|(_, len)| len).sum();
+        println!("{} ({} bytes, sparse: {} bytes in {} holes)", volume.path(entry), entry.size, hole_bytes, holes.len());
+    }
+    extract_forks(volume, entry, &dest, forks, output)
+}
+
+// Writes PATH to a tar or zip archive, along with the metadata in ARCHIVE.json
+fn extract_archive(images: &[String], spec: &str, archive: &(ArchiveFormat, String), options: &Options) -> Result<(), NWFSError> {
+    let (mut volume, entry) = open(images, spec, options)?;
+    let entry = volume.entries[entry as usize].clone();
+    let (format, fname) = archive;
+    let out = BufWriter::new(File::create(fname)?);
+    let writer: Box<dyn ArchiveWriter> = match format {
+        ArchiveFormat::Tar => Box::new(TarWriter::new(out)),
+        ArchiveFormat::Zip => Box::new(ZipWriter::new(out)),
+    };
+    let mut output = Output::new(Target::Archive(writer, Vec::new(), owner_names(images, options)), options);
+    output.read_previous(options)?;
+    let name = if entry.entry == volume.root() { volume.name().to_string() } else { file_name(volume.display_name(&entry), entry.entry) };
+    extract(&mut volume, &entry, Path::new(&name), options.forks, &mut output)?;
+    output.write_manifest(options)?;
+    if let Target::Archive(mut writer, metadata, _) = output.target {
+        writer.finish()?;
+        let sidecar = format!("{}.json", fname);
+        let count = metadata.len();
+        let json = json!({ "volume": volume.name(), "path": volume.path(&entry), "entries": metadata });
+        std::fs::write(&sidecar, serde_json::to_string_pretty(&json).unwrap())?;
+        println!("wrote {} entries to {}, metadata to {}", count, fname, sidecar);
+    }
+    Ok(())
+}
+
+fn partition_type_name(partition_type: u8) -> &'static str {
+    match partition_type {
+        partition::PARTITION_TYPE_NETWARE => "NetWare 386",
+        nwfs286::PARTITION_TYPE_NETWARE_286 => "NetWare 286",
+        partition::PARTITION_TYPE_NSS => "NetWare NSS",
+        0x01 | 0x04 | 0x06 | 0x0e => "DOS",
+        0x05 | 0x0f => "extended",
+        _ => "other",
+    }
+}
+
+fn show_partition(dev: &mut dyn Device, offset: u64) -> Result<(), NWFSError> {
+    let p = Partition::read(dev, offset)?;
+    println!("NetWare partition at offset {:x}", offset);
+    println!("  hotfix            id {:08x}, {} data sectors, {} redirection sectors at offset {:x}",
+        p.hotfix.partition_id, p.hotfix.data_sectors, p.hotfix.redirection_sectors,
+        offset + partition::REDIRECTION_SECTOR * nwfs::SECTOR_SIZE as u64);
+    println!("  redirection       {} of {} blocks in use", p.redirection.blocks.len(),
+        p.redirection.total_blocks.saturating_sub(p.redirection.table_blocks));
+    match &p.mirror {
+        Some(m) => {
+            let members: Vec<String> = m.members.iter().map(|id| format!("{:08x}", id)).collect();
+            println!("  mirror            group {:08x}, flags {:x}, members {}", m.group_id, m.flags,
+                if members.is_empty() { "-".to_string() } else { members.join(" ") });
+        },
+        None => println!("  mirror            no mirror header"),
+    }
+    println!("  data area         offset {:x}", p.data_offset());
+    for s in &p.segments {
+        println!("  segment           {} {}/{}, {} blocks of {} bytes at offset {:x}", s.name, s.segment_number,
+            s.last_segment + 1, s.segment_blocks(), s.block_size(), p.segment_offset(s));
+    }
+    Ok(())
+}
+
+// The bindery (or NDS) given by --bindery, which is either a directory with
+// the bindery files or an image holding volume SYS; without it, volume SYS
+// of the images is used
+fn read_bindery(images: &[String], options: &Options) -> Result<Bindery, NWFSError> {
+    let mut volume = match &options.bindery {
+        Some(path) if Path::new(path).is_dir() => {
+            let (format, files) = bindery::find_dir_files(Path::new(path)).ok_or(NWFSError::FileNotFound(format!("{}/NET$OBJ.SYS", path)))?;
+            return Ok(Bindery::from_files(format, &files)?);
+        },
+        Some(path) => volume::open_volume(path, "SYS")?,
+        None => volume::open_volume_set(images, "SYS", &options.disk)?,
+    };
+    let (format, file_data) = bindery::read_volume_files(&mut volume)?;
+    let file_slices: Vec<&[u8]> = file_data.iter().map(|d| d.as_slice()).collect();
+    Ok(Bindery::new(format, &file_slices)?)
+}
+
+// Locates the bindery files on volume SYS and writes them to --extract, or
+// to a temporary directory which dump-bindery is run on
+fn bindery(images: &[String], options: &Options) -> Result<(), NWFSError> {
+    let mut volume = volume::open_volume_set(images, "SYS", &options.disk)?;
+    volume.timezone = options.timezone;
+    let (format, entries) = bindery::find_volume_files(&volume)?;
+    let format_name = match format {
+        bindery::Format::NetWare2 => "NetWare 2.x bindery",
+        bindery::Format::NetWare3 => "NetWare 3.x bindery",
+        bindery::Format::NetWare4 => "NetWare 4.x NDS database",
+    };
+    println!("{} found:", format_name);
+    for e in &entries {
+        println!("  {:<24} {:>10} bytes [{}] {}", volume.path(e), e.size, volume::attribute_string(e.attributes),
+            time_string(&volume, e.modified).unwrap_or("-".to_string()));
+    }
+
+    let (dir, temporary) = match &options.extract_dir {
+        Some(dir) => (PathBuf::from(dir), false),
+        None => (env::temp_dir().join(format!("nwfs-bindery-{}", std::process::id())), true),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let mut paths = Vec::new();
+    for e in &entries {
+        let path = dir.join(volume.display_name(e));
+        std::fs::write(&path, volume.read_file(e)?)?;
+        paths.push(path);
+    }
+    if !temporary {
+        println!("written to {}", dir.display());
+        return Ok(())
+    }
+
+    // dump-bindery is expected next to nwfs, or otherwise in the PATH
+    let program = env::current_exe().ok().map(|exe| exe.with_file_name("dump-bindery")).filter(|p| p.exists())
+        .unwrap_or(PathBuf::from("dump-bindery"));
+    println!();
+    let status = Command::new(&program).args(&options.dump_args).args(&paths).status();
+    std::fs::remove_dir_all(&dir)?;
+    if let Err(e) = status {
+        println!("cannot run {}: {}", program.display(), e);
+    }
+    Ok(())
+}
+
+// Object names along with their type by ID
+fn read_object_names(images: &[String], options: &Options) -> Result<HashMap<u32, String>, NWFSError> {
+    let bindery = read_bindery(images, options)?;
+    Ok(bindery.objects.iter().map(|o| {
+        let type_name = bindery::object_type_name(o.objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", o.objtype));
+        (o.objid, format!("{} ({})", o.name, type_name))
+    }).collect())
+}
+
+// Names of owners and modifiers are only shown by listings if --bindery is
+// given, as volumes other than SYS may come without it
+fn owner_names(images: &[String], options: &Options) -> HashMap<u32, String> {
+    if options.bindery.is_none() {
+        return HashMap::new();
+    }
+    match read_bindery(images, options) {
+        Ok(bindery) => bindery.objects.iter().map(|o| (o.objid, o.name.clone())).collect(),
+        Err(e) => {
+            println!("WARNING: cannot read the bindery, object IDs are shown instead: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+// Entries without trustees which inherit all rights are only shown if asked
+// for explicitly
+fn show_trustees(volume: &Volume, names: &HashMap<u32, String>, entry: &DirEntry, recursive: bool, always: bool) {
+    let trustees = volume.trustees(entry);
+    if always || !trustees.is_empty() || entry.inherited_rights != volume::RIGHTS_ALL {
+        println!("{}{}", volume.path(entry), if entry.is_directory() { "/" } else { "" });
+        println!("  inherited rights mask         {}", volume::rights_string(entry.inherited_rights));
+        for t in trustees {
+            println!("  {:08x} {:<20} {}", t.object, names.get(&t.object).map(|n| n.as_str()).unwrap_or("?"),
+                volume::rights_string(t.rights));
+        }
+    }
+    if recursive && entry.is_directory() {
+        let mut children: Vec<&DirEntry> = volume.children(entry.entry).collect();
+        children.sort_by(|a, b| volume.display_name(a).cmp(volume.display_name(b)));
+        for child in children {
+            show_trustees(volume, names, child, recursive, false);
+        }
+    }
+}
+
+fn trustees(images: &[String], spec: &str, options: &Options) -> Result<(), NWFSError> {
+    let (volume, entry) = open(images, spec, options)?;
+    if volume.format == volume::Format::Nwfs286 {
+        println!("trustees of NetWare 286 volumes are not supported");
+        return Ok(())
+    }
+    let names = match read_object_names(images, options) {
+        Ok(names) => names,
+        Err(e) => {
+            println!("WARNING: cannot read the bindery, object names are not shown: {}", e);
+            HashMap::new()
+        }
+    };
+    show_trustees(&volume, &names, &volume.entries[entry as usize], options.recursive, true);
+    Ok(())
+}
+
+// Adds SOURCE to the volume, keeping its modification time
+fn put(images: &[String], source: &str, spec: &str, options: &Options) -> Result<(), NWFSError> {
+    let data = std::fs::read(source)?;
+    let mtime = std::fs::metadata(source)?.modified()?.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (volume_name, path) = spec.split_once(':').unwrap_or((spec, ""));
+    let mut writer = VolumeWriter::open(images, volume_name, &options.disk)?;
+    writer.volume.timezone = options.timezone;
+    if let Some(namespace) = options.namespace {
+        writer.volume.namespace = namespace;
+    }
+    let mut path = path.to_string();
+    if writer.volume.lookup(&path).is_some_and(|e| e.is_directory()) {
+        let name = Path::new(source).file_name().unwrap_or_default().to_string_lossy();
+        path = format!("{}/{}", path, name);
+    }
+    let (entry, replaced) = writer.put_file(&path, &data, mtime)?;
+    println!("{} {} ({} bytes)", if replaced { "replaced" } else { "added" }, writer.volume.path(&entry), data.len());
+    Ok(())
+}
+
+// Sizes are in bytes, or with a K, M or G suffix
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, unit) = match s.char_indices().last()? {
+        (n, 'K') | (n, 'k') => (&s[..n], 1 << 10),
+        (n, 'M') | (n, 'm') => (&s[..n], 1 << 20),
+        (n, 'G') | (n, 'g') => (&s[..n], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn mkfs(image: &str, size: &str, volume_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let Some(size) = parse_size(size) else {
+        println!("invalid size '{}'", size);
+        return Ok(())
+    };
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let timestamp = nwfs::unix_to_timestamp(now.saturating_add_signed(options.timezone as i64));
+    let block_size = options.block_size.unwrap_or(nwfs::mkfs::BLOCK_SIZES[0]);
+    let volume_name = volume_name.to_uppercase();
+    let blocks = nwfs::mkfs::mkfs(Path::new(image), size, &volume_name, block_size, timestamp)?;
+    println!("created volume {} of {} blocks of {} KB in {}", volume_name, blocks, block_size / 1024, image);
+    Ok(())
+}
+
+// Numbers are decimal, or hex with prefix 0x
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn field_string(field: &str, value: u32) -> String {
+    match field {
+        "signature" | "flags" => format!("0x{:08x}", value),
+        _ => value.to_string(),
+    }
+}
+
+fn entry_location(images: &[String], tables: &VolumeTables, entry: &Entry) -> String {
+    let (image, p) = &tables.partitions[entry.partition];
+    format!("{}: partition {:08x} entry {}", images[*image], p.hotfix.partition_id, entry.index)
+}
+
+fn rename(images: &[String], volume_name: &str, new_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let new_name = new_name.to_uppercase();
+    let mut tables = VolumeTables::open(images, &options.disk)?;
+    let (entries, directories) = tables.rename(volume_name, &new_name)?;
+    println!("renamed volume {} to {}: {} volume table entries, {} root directory entries", volume_name.to_uppercase(), new_name, entries, directories);
+    if directories == 0 {
+        println!("WARNING: root directory entry not found, only the volume table was changed");
+    }
+    Ok(())
+}
+
+fn set_segment(images: &[String], volume_name: &str, segment: &str, assignments: &[String], options: &Options) -> Result<(), NWFSError> {
+    let Ok(segment) = segment.parse::<u16>() else {
+        println!("invalid segment number '{}'", segment);
+        return Ok(())
+    };
+    let mut values = Vec::new();
+    for assignment in assignments {
+        match assignment.split_once('=').and_then(|(field, value)| Some((field, parse_number(value)?))) {
+            Some((field, value)) if SEGMENT_FIELDS.contains(&field) => values.push((field, value)),
+            _ => {
+                println!("invalid assignment '{}', expected FIELD=VALUE with FIELD one of {}", assignment, SEGMENT_FIELDS.join(", "));
+                return Ok(())
+            }
+        }
+    }
+    let mut tables = VolumeTables::open(images, &options.disk)?;
+    let entries: Vec<Entry> = tables.entries(volume_name).into_iter().filter(|e| e.segment.segment_number == segment).collect();
+    if entries.is_empty() {
+        println!("segment {} of volume {} not found", segment, volume_name.to_uppercase());
+        return Ok(())
+    }
+    for mut entry in entries {
+        let location = entry_location(images, &tables, &entry);
+        for (field, value) in &values {
+            let old = entry.segment.field(field).unwrap();
+            entry.segment.set_field(field, *value)?;
+            println!("{}: {} {} -> {}", location, field, field_string(field, old), field_string(field, *value));
+        }
+        tables.write_entry(&entry)?;
+    }
+    Ok(())
+}
+
+fn fix_segments(images: &[String], volume_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let mut tables = VolumeTables::open(images, &options.disk)?;
+    let plan = tables.plan_fix(volume_name)?;
+    if plan.is_empty() {
+        println!("the volume table entries of {} are consistent", volume_name.to_uppercase());
+        return Ok(())
+    }
+    for (entry, changes) in &plan {
+        let location = entry_location(images, &tables, entry);
+        for c in changes {
+            println!("{}: {} {} -> {}", location, c.field, field_string(c.field, c.old), field_string(c.field, c.new));
+        }
+        if !options.dry_run {
+            tables.write_entry(entry)?;
+        }
+    }
+    println!("{} {} volume table entries", if options.dry_run { "would change" } else { "changed" }, plan.len());
+    Ok(())
+}
+
+// Bytes of the context of a match which are not printable are shown as '.'
+fn printable(data: &[u8]) -> String {
+    data.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect()
+}
+
+fn grep(images: &[String], pattern: &str, spec: &str, options: &Options) -> Result<(), NWFSError> {
+    let pattern = if options.hex {
+        match Pattern::from_hex(pattern, options.ignore_case) {
+            Some(pattern) => pattern,
+            None => {
+                println!("invalid hex pattern '{}'", pattern);
+                return Ok(())
+            }
+        }
+    } else {
+        Pattern::new(pattern.as_bytes(), options.ignore_case)
+    };
+    let (mut volume, entry) = open(images, spec, options)?;
+    let mut entries: Vec<&DirEntry> = Vec::new();
+    if volume.entries[entry as usize].is_directory() {
+        collect_entries(&volume, entry, true, &mut entries);
+    } else {
+        entries.push(&volume.entries[entry as usize]);
+    }
+    let entries: Vec<DirEntry> = entries.into_iter().filter(|e| !e.is_directory()).cloned().collect();
+    let (mut matches, mut files) = (0, 0);
+    let mut output: Vec<Value> = Vec::new();
+    for e in &entries {
+        let path = volume.path(e);
+        let found = match search::search_file(&mut volume, e, &pattern) {
+            Ok(found) => found,
+            Err(err) => {
+                println!("WARNING: cannot search {}: {}", path, err);
+                continue;
+            }
+        };
+        for m in &found {
+            if options.json {
+                output.push(json!({ "path": path, "offset": m.offset, "context": printable(&m.context), "context_offset": m.context_offset }));
+            } else {
+                println!("{} offset {:x}: {}", path, m.offset, printable(&m.context));
+            }
+        }
+        matches += found.len();
+        files += if found.is_empty() { 0 } else { 1 };
+    }
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    } else {
+        println!("{} matches in {} of {} files", matches, files, entries.len());
+    }
+    Ok(())
+}
+
+// Bytes allocated to the file: its blocks, and the suballocated tail in
+// whole sectors
+fn allocated_bytes(volume: &Volume, e: &DirEntry) -> usize {
+    let block_size = volume.block_size();
+    match volume.chain_with_tail(e.first_block) {
+        Ok((chain, tail)) => {
+            let tail_size = (e.size as usize).saturating_sub(chain.len() * block_size).min(block_size);
+            chain.len() * block_size + if tail.is_some() { tail_size.next_multiple_of(volume::SUBALLOC_UNIT_SIZE) } else { 0 }
+        },
+        Err(_) => 0,
+    }
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+fn stats(images: &[String], spec: &str, options: &Options) -> Result<(), NWFSError> {
+    let (volume, dir) = open(images, spec, options)?;
+    let top = options.top.unwrap_or(10);
+    let block_size = volume.block_size();
+    let total_blocks = volume.total_blocks() as usize;
+    let used_blocks = volume.fat.iter().filter(|e| e.next != volume::FAT_FREE).count();
+
+    let entries: Vec<&DirEntry> = if volume.entries[dir as usize].is_directory() {
+        volume.walk(dir).collect()
+    } else {
+        vec![ &volume.entries[dir as usize] ]
+    };
+    let (directories, files): (Vec<&DirEntry>, Vec<&DirEntry>) = entries.into_iter().partition(|e| e.is_directory());
+    let file_bytes: usize = files.iter().map(|e| e.size as usize).sum();
+    let allocated: usize = files.iter().map(|e| allocated_bytes(&volume, e)).sum();
+
+    // Sizes of directories include everything below them
+    let mut dir_sizes: HashMap<u32, (usize, usize)> = HashMap::new();
+    for e in &files {
+        let mut parent = e.parent;
+        while let Some(p) = volume.entries.get(parent as usize).filter(|p| p.is_directory() && p.parent != volume::DIR_ROOT) {
+            let (bytes, count) = dir_sizes.entry(p.entry).or_default();
+            *bytes += e.size as usize;
+            *count += 1;
+            if p.entry == dir {
+                break;
+            }
+            parent = p.parent;
+        }
+    }
+    let mut largest_dirs: Vec<(&DirEntry, usize, usize)> = directories.iter().map(|d| {
+        let (bytes, count) = dir_sizes.get(&d.entry).copied().unwrap_or_default();
+        (*d, bytes, count)
+    }).collect();
+    largest_dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| volume.path(a.0).cmp(&volume.path(b.0))));
+    largest_dirs.truncate(top);
+    let mut largest_files = files.clone();
+    largest_files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| volume.path(a).cmp(&volume.path(b))));
+    largest_files.truncate(top);
+
+    let mut extensions: HashMap<String, (usize, usize)> = HashMap::new();
+    for e in &files {
+        let extension = e.name.rsplit_once('.').map(|(_, ext)| ext.to_uppercase()).unwrap_or_default();
+        let (count, bytes) = extensions.entry(extension).or_default();
+        *count += 1;
+        *bytes += e.size as usize;
+    }
+    let mut extensions: Vec<(String, usize, usize)> = extensions.into_iter().map(|(ext, (count, bytes))| (ext, count, bytes)).collect();
+    extensions.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    let compressed: Vec<&&DirEntry> = files.iter().filter(|e| e.is_compressed()).collect();
+    let compressed_bytes: usize = compressed.iter().map(|e| e.size as usize).sum();
+    let compressed_stored: usize = compressed.iter().map(|e| allocated_bytes(&volume, e)).sum();
+
+    let used = volume.used_blocks();
+    let deleted: Vec<&DirEntry> = volume.deleted_entries(dir).into_iter().filter(|e| !e.is_directory()).collect();
+    let salvageable: Vec<&&DirEntry> = deleted.iter().filter(|e| salvage_problem(&volume, &used, e).is_none()).collect();
+    let salvageable_bytes: usize = salvageable.iter().map(|e| e.size as usize).sum();
+
+    if options.json {
+        let output = json!({
+            "volume": volume.name(),
+            "path": volume.path(&volume.entries[dir as usize]),
+            "block_size": block_size,
+            "total_blocks": total_blocks,
+            "used_blocks": used_blocks,
+            "free_blocks": total_blocks - used_blocks,
+            "files": files.len(),
+            "directories": directories.len(),
+            "file_bytes": file_bytes,
+            "allocated_bytes": allocated,
+            "compressed": { "files": compressed.len(), "bytes": compressed_bytes, "stored_bytes": compressed_stored },
+            "deleted": { "files": deleted.len(), "salvageable_files": salvageable.len(), "salvageable_bytes": salvageable_bytes },
+            "largest_files": largest_files.iter().map(|e| json!({ "path": volume.path(e), "size": e.size })).collect::<Vec<_>>(),
+            "largest_directories": largest_dirs.iter().map(|(d, bytes, count)| json!({ "path": volume.path(d), "bytes": bytes, "files": count })).collect::<Vec<_>>(),
+            "extensions": extensions.iter().map(|(ext, count, bytes)| json!({ "extension": ext, "files": count, "bytes": bytes })).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return Ok(())
+    }
+    println!("volume {}: {} blocks of {} bytes ({} bytes)", volume.name(), total_blocks, block_size, total_blocks * block_size);
+    println!("  in use         {} blocks ({} bytes, {:.1}%)", used_blocks, used_blocks * block_size, percentage(used_blocks, total_blocks));
+    println!("  free           {} blocks ({} bytes)", total_blocks - used_blocks, (total_blocks - used_blocks) * block_size);
+    println!("{}", volume.path(&volume.entries[dir as usize]));
+    println!("  files          {}, {} bytes in {} bytes allocated", files.len(), file_bytes, allocated);
+    println!("  directories    {}", directories.len());
+    println!("  compressed     {} files, {} bytes stored in {} bytes ({:.1}%)", compressed.len(), compressed_bytes, compressed_stored, percentage(compressed_stored, compressed_bytes));
+    println!("  deleted        {} files, {} salvageable with {} bytes", deleted.len(), salvageable.len(), salvageable_bytes);
+    println!();
+    println!("largest files:");
+    for e in &largest_files {
+        println!("  {:>12} {}", e.size, volume.path(e));
+    }
+    println!();
+    println!("largest directories:");
+    for (d, bytes, count) in &largest_dirs {
+        println!("  {:>12} {} ({} files)", bytes, volume.path(d), count);
+    }
+    println!();
+    println!("extensions:");
+    for (ext, count, bytes) in &extensions {
+        println!("  {:<4} {:>8} files {:>12} bytes", if ext.is_empty() { "-" } else { ext }, count, bytes);
+    }
+    Ok(())
+}
+
+fn check(images: &[String], volume_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let (volume, _) = open(images, volume_name, options)?;
+    let issues = nwfs::check::check(&volume);
+    if options.json {
+        let output: Vec<Value> = issues.iter().map(|i| json!({
+            "kind": i.kind.name(),
+            "entry": i.entry,
+            "block": i.block,
+            "message": i.message,
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return Ok(())
+    }
+    for i in &issues {
+        println!("{:<16} {}", i.kind.name(), i.message);
+    }
+    println!("{}: {} entries, {} issues found", volume.name(), volume.entries.iter().filter(|e| e.is_in_use()).count(), issues.len());
+    Ok(())
+}
+
+#[cfg(feature = "fuse")]
+fn mount(images: &[String], volume_name: &str, mountpoint: &str, options: &Options) -> Result<(), NWFSError> {
+    let (volume, _) = open(images, volume_name, options)?;
+    println!("mounting volume {} on {}; unmount it using fusermount -u {}", volume.name(), mountpoint, mountpoint);
+    nwfs::mount::mount(volume, Path::new(mountpoint))
+}
+
+#[cfg(not(feature = "fuse"))]
+fn mount(_images: &[String], _volume_name: &str, _mountpoint: &str, _options: &Options) -> Result<(), NWFSError> {
+    println!("nwfs was built without FUSE support, rebuild it using --features fuse");
+    Ok(())
+}
+
+fn show_partitions(image: &str, layout: &DiskLayout) -> Result<(), NWFSError> {
+    let format = image::image_format(image)?;
+    let mut dev = image::open_image(image)?;
+    let dev = dev.as_mut();
+    if format != ImageFormat::Raw {
+        println!("{} image of {} bytes", format.name(), dev.seek(SeekFrom::End(0))?);
+    }
+    if let Some(offset) = layout.partition_offset {
+        println!("NetWare partition at offset {:x} as given", offset);
+        return show_partition(dev, offset);
+    }
+    if partition::is_partition_image(dev) {
+        println!("image of a single NetWare partition");
+        return show_partition(dev, 0);
+    }
+    let entries = partition::read_partition_table(dev, layout)?;
+    if entries.is_empty() {
+        println!("no partition table found");
+        return Ok(())
+    }
+    for e in &entries {
+        println!("partition {}{} type {:02x} ({}), sectors {}-{}, offset {:x}", e.index,
+            if e.bootable { " (active)" } else { "" }, e.partition_type, partition_type_name(e.partition_type),
+            e.first_sector, e.first_sector as u64 + e.sectors as u64, e.offset());
+    }
+    for e in entries.iter().filter(|e| e.is_netware()) {
+        if let Err(err) = show_partition(dev, e.offset()) {
+            println!("partition {}: {}", e.index, err);
+        }
+    }
+    for e in entries.iter().filter(|e| e.is_nss()) {
+        println!("partition {}: NSS pool, which cannot be read", e.index);
+    }
+    Ok(())
+}
+
+fn carve(image: &str) -> Result<(), NWFSError> {
+    let mut dev = image::open_image(image)?;
+    let dev = dev.as_mut();
+    let len = dev.seek(SeekFrom::End(0))?;
+    let candidates = carve::scan(dev, len)?;
+    if candidates.is_empty() {
+        println!("no NetWare structures found");
+        return Ok(())
+    }
+    println!("offset       kind           confidence details");
+    for c in &candidates {
+        println!("{:<12x} {:<14} {:<10} {}", c.offset, c.kind.name(), c.confidence.name(), c.message);
+        for v in &c.volumes {
+            println!("{:38} volume {}", "", v);
+        }
+    }
+    if let Some(c) = candidates.iter().filter(|c| c.kind != carve::Kind::RootDirectory).max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap()) {
+        println!("use --partition-offset {} to read the volumes found at offset {:x}", c.offset, c.offset);
+    }
+    Ok(())
+}
+
+// Every copy of a mirrored volume is checked, and compared to the copy which
+// is read
+fn mirrors(images: &[String], volume_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let mut volume = volume::open_volume_set(images, volume_name, &options.disk)?;
+    let groups = mirror::groups(&volume);
+    for (group, members) in groups.iter().filter(|(_, members)| members.len() > 1) {
+        let members: Vec<String> = members.iter().map(|n| {
+            let (dev, p) = &volume.partitions[*n];
+            format!("{:08x} ({} at offset {:x})", p.hotfix.partition_id, images[*dev], p.offset)
+        }).collect();
+        println!("mirror group {:08x}: {}", group, members.join(", "));
+    }
+    let num_copies = volume.mirror_copies();
+    if num_copies < 2 {
+        println!("volume {} is not mirrored", volume.name());
+        return Ok(())
+    }
+
+    let chosen = volume.mirror_copy;
+    let first_block = volume.segments[0].1.first_block;
+    let copies = volume.block_copies(first_block)?;
+    for copy in 0..num_copies {
+        let (n, _) = copies[copy.min(copies.len() - 1)];
+        let (dev, partition_id) = (volume.partitions[n].0, volume.partitions[n].1.hotfix.partition_id);
+        let status = match volume.select_mirror_copy(copy) {
+            Ok(_) => format!("{} issues", check::check(&volume).len()),
+            Err(e) => e.to_string(),
+        };
+        println!("copy {}: partition {:08x} on {}: {}{}", copy, partition_id, images[dev], status, if copy == chosen { " (read)" } else { "" });
+    }
+    volume.select_mirror_copy(chosen)?;
+
+    let divergences = mirror::compare(&mut volume)?;
+    for d in &divergences {
+        let copies: Vec<String> = d.copies.iter().map(|c| c.to_string()).collect();
+        let range = if d.blocks == 1 { format!("block {}", d.first_block) } else { format!("blocks {}-{}", d.first_block, d.first_block + d.blocks - 1) };
+        println!("{} ({} in use) differ in copy {}", range, d.used_blocks, copies.join(", "));
+    }
+    let differing: u32 = divergences.iter().map(|d| d.blocks).sum();
+    let used: u32 = divergences.iter().map(|d| d.used_blocks).sum();
+    println!("{} of {} blocks differ, {} of them in use", differing, volume.total_blocks(), used);
+    Ok(())
+}
+
+// A volume holding redirected blocks, with what its blocks hold
+struct BlockOwners {
+    volume: Volume,
+    owners: HashMap<u32, Vec<hotfix::Owner>>,
+}
+
+// What the redirected blocks hold, counted by the kind of data at risk
+#[derive(Default)]
+struct BadBlockTotals {
+    redirected: usize,
+    unusable: usize,
+    in_files: usize,
+    in_deleted: usize,
+    in_structures: usize,
+    in_table: usize,
+    free: usize,
+    files: HashSet<(String, u32)>,
+    file_bytes: u64,
+}
+
+fn block_users(volume: &BlockOwners, block: u32, totals: &mut BadBlockTotals) -> Vec<String> {
+    let owners = volume.owners.get(&block).map(|o| o.as_slice()).unwrap_or_default();
+    if owners.iter().any(|o| matches!(o, hotfix::Owner::File(_))) {
+        totals.in_files += 1;
+    } else if owners.iter().any(|o| matches!(o, hotfix::Owner::DeletedFile(_))) {
+        totals.in_deleted += 1;
+    } else if !owners.is_empty() {
+        totals.in_structures += 1;
+    } else {
+        totals.free += 1;
+        return vec![ "free".to_string() ]
+    }
+    let volume = &volume.volume;
+    owners.iter().map(|o| match o {
+        hotfix::Owner::Fat => "FAT".to_string(),
+        hotfix::Owner::Directory => "directory".to_string(),
+        hotfix::Owner::File(n) => {
+            let e = &volume.entries[*n as usize];
+            if totals.files.insert((volume.name().to_string(), *n)) {
+                totals.file_bytes += e.size as u64;
+            }
+            volume.path(e)
+        },
+        hotfix::Owner::DeletedFile(n) => format!("deleted {}", volume.path(&volume.entries[*n as usize])),
+    }).collect()
+}
+
+// Shows the redirected blocks of every NetWare partition of the images and
+// what they hold, followed by how much of the data is at risk
+fn bad_blocks(images: &[String], options: &Options) -> Result<(), NWFSError> {
+    let mut volumes: HashMap<String, Result<BlockOwners, NWFSError>> = HashMap::new();
+    let mut totals = BadBlockTotals::default();
+    for image in images {
+        let mut dev = image::open_image(image)?;
+        let partitions = match partition::read_partitions(dev.as_mut(), &options.disk) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("{}: {}", image, e);
+                continue;
+            }
+        };
+        for p in &partitions {
+            let blocks = hotfix::redirected_blocks(p);
+            println!("{}: partition {:08x} at offset {:x}: {} of {} redirection blocks in use, {} unusable", image, p.hotfix.partition_id, p.offset,
+                blocks.len(), p.redirection.total_blocks.saturating_sub(p.redirection.table_blocks), p.redirection.unusable.len());
+            totals.redirected += blocks.len();
+            totals.unusable += p.redirection.unusable.len();
+            if blocks.is_empty() {
+                continue;
+            }
+            println!("  data block redirection volume block         used by");
+            for b in &blocks {
+                let offset = p.redirection.offset + b.redirection_block as u64 * partition::HOTFIX_BLOCK_SIZE as u64;
+                let (location, used_by) = match &b.location {
+                    hotfix::Location::VolumeTable => {
+                        totals.in_table += 1;
+                        ("-".to_string(), vec![ "volume table".to_string() ])
+                    },
+                    hotfix::Location::Unused => ("-".to_string(), vec![ "no volume".to_string() ]),
+                    hotfix::Location::Volume(name, block) => {
+                        let volume = volumes.entry(name.to_uppercase()).or_insert_with(|| {
+                            let volume = volume::open_volume_set(images, name, &options.disk)?;
+                            Ok(BlockOwners{ owners: hotfix::block_owners(&volume), volume })
+                        });
+                        let used_by = match volume {
+                            Ok(volume) => block_users(volume, *block, &mut totals),
+                            Err(e) => vec![ format!("volume cannot be read: {}", e) ],
+                        };
+                        (format!("{}:{}", name, block), used_by)
+                    },
+                };
+                println!("  {:>10} {:<11x} {:<20} {}", b.data_block, offset, location, used_by.join(", "));
+            }
+        }
+    }
+    println!("{} redirected blocks: {} in {} files ({} bytes), {} in deleted files, {} in the FAT or directory, {} in the volume table, {} free",
+        totals.redirected, totals.in_files, totals.files.len(), totals.file_bytes, totals.in_deleted, totals.in_structures, totals.in_table, totals.free);
+    if totals.unusable > 0 {
+        println!("{} redirection blocks are unusable", totals.unusable);
+    }
+    Ok(())
+}
+
+fn show_tts(images: &[String], volume_name: &str, options: &Options) -> Result<(), NWFSError> {
+    let (mut volume, _) = open(images, volume_name, options)?;
+    let files = tts::find(&volume);
+    let time = |volume: &Volume, t: u32| time_string(volume, t).unwrap_or("-".to_string());
+    match &files.backout {
+        Some(e) => {
+            let used = tts::used_bytes(&mut volume, e)?;
+            println!("backout file {}: {} bytes, {} not zero, modified {}", volume.path(e), e.size, used, time(&volume, e.modified));
+        },
+        None => println!("no backout file {}:{}", volume.name(), tts::BACKOUT_FILE),
+    }
+    if let Some(e) = &files.log {
+        println!("error log {}: {} bytes, modified {}", volume.path(e), e.size, time(&volume, e.modified));
+        if let Ok(data) = volume.read_file(e) {
+            for line in String::from_utf8_lossy(&data).lines().filter(|l| !l.trim().is_empty()) {
+                println!("  {}", line.trim_end());
+            }
+        }
+    }
+    println!("{} transactional files", files.transactional.len());
+    for e in &files.transactional {
+        println!("  {:<20} {:>10} {}", time(&volume, e.modified), e.size, volume.path(e));
+    }
+    if let Some(dir) = &options.extract_dir {
+        std::fs::create_dir_all(dir)?;
+        for e in files.backout.iter().chain(&files.log) {
+            extract(&mut volume, e, &Path::new(dir).join(&e.name), Forks::None, &mut Output::new(Target::Directory, options))?;
+        }
+    }
+    if files.backout.is_some() {
+        println!("the format of the backout file is not known, so incomplete transactions cannot be backed out");
+    }
+    Ok(())
+}
+
+// Returns why the data of a deleted file cannot be salvaged, if so
+fn salvage_problem(volume: &Volume, used: &HashSet<u32>, entry: &DirEntry) -> Option<&'static str> {
+    if entry.is_directory() {
+        return None;
+    }
+    let chain = match volume.chain(entry.first_block) {
+        Ok(chain) => chain,
+        Err(_) => return Some("broken chain"),
+    };
+    // Sparse files have gaps in their block indices
+    let blocks = volume.block_indices(&chain).iter().max().map(|n| n + 1).unwrap_or(0);
+    if blocks < (entry.size as usize).div_ceil(volume.block_size()) {
+        return Some("truncated chain");
+    }
+    if chain.iter().any(|b| used.contains(b)) {
+        return Some("overwritten");
+    }
+    None
+}
+
+fn salvage(images: &[String], spec: &str, options: &Options) -> Result<(), NWFSError> {
+    let extract_dir = options.extract_dir.as_ref();
+    let (mut volume, dir) = open(images, spec, options)?;
+    let used = volume.used_blocks();
+    let names = owner_names(images, options);
+    let deleted: Vec<DirEntry> = volume.deleted_entries(dir).into_iter().cloned().collect();
+    let mut output = Output::new(Target::Directory, options);
+    println!("deleted              deleted by       size status       path");
+    let mut salvaged = 0;
+    for e in &deleted {
+        let problem = salvage_problem(&volume, &used, e);
+        println!("{:<20} {:<10} {:>10} {:<12} {}{}", time_string(&volume, e.deleted).unwrap_or("-".to_string()), object_string(&names, e.deleted_by),
+            if e.is_directory() { "-".to_string() } else { e.size.to_string() },
+            problem.unwrap_or("intact"), volume.path(e), if e.is_directory() { "/" } else { "" });
+        let dir = match extract_dir {
+            Some(dir) if problem.is_none() && !e.is_directory() => dir,
+            _ => continue,
+        };
+
+        // Files may have been deleted more than once, so existing files are
+        // not overwritten
+        let path = volume.path(e);
+        let mut dest = Path::new(dir).to_path_buf();
+        for component in path.split_once(':').map(|(_, p)| p).unwrap_or(&path).split('/') {
+            dest.push(file_name(component, e.entry));
+        }
+        if dest.exists() {
+            dest = dest.with_file_name(format!("{}.{}", file_name(volume.display_name(e), e.entry), e.entry));
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match extract(&mut volume, e, &dest, options.forks, &mut output) {
+            Ok(_) => salvaged += 1,
+            Err(err) => println!("WARNING: cannot extract {}: {}", path, err),
+        }
+    }
+    println!("{} deleted entries{}", deleted.len(), if extract_dir.is_some() { format!(", {} files salvaged", salvaged) } else { String::new() });
+    output.write_manifest(options)
+}
+
+const APPLEDOUBLE_MAGIC: u32 = 0x00051607;
+const APPLEDOUBLE_VERSION: u32 = 0x00020000;
+const APPLEDOUBLE_RESOURCE_FORK: u32 = 2;
+const APPLEDOUBLE_FINDER_INFO: u32 = 9;
+
+// How the resource forks and Finder info of Mac files are written
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Forks {
+    #[default]
+    AppleDouble,
+    Rsrc,
+    None,
+}
+
+const DOS_ATTRIBUTES: u32 = volume::ATTR_READ_ONLY | volume::ATTR_HIDDEN | volume::ATTR_SYSTEM | volume::ATTR_DIRECTORY | volume::ATTR_ARCHIVE;
+
+enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+#[derive(Default)]
+struct Options {
+    long: bool,
+    recursive: bool,
+    json: bool,
+    csv: bool,
+    extract_dir: Option<String>,
+    namespace: Option<u8>,
+    forks: Forks,
+    archive: Option<(ArchiveFormat, String)>,
+    timezone: i32,
+    bindery: Option<String>,
+    block_size: Option<usize>,
+    disk: DiskLayout,
+    hex: bool,
+    ignore_case: bool,
+    top: Option<usize>,
+    manifest: Option<String>,
+    previous: Option<String>,
+    dump_args: Vec<String>,
+    dry_run: bool,
+}
+
+// The FIELD=VALUE arguments of set-segment, which come last
+fn num_assignments(files: &[String]) -> usize {
+    files.iter().rev().take_while(|f| f.contains('=')).count()
+}
+
+fn run(command: &str, files: &[String], options: &Options) -> Result<(), NWFSError> {
+    match command {
+        "partitions" => {
+            for image in files {
+                if files.len() > 1 {
+                    println!("{}:", image);
+                }
+                if let Err(e) = show_partitions(image, &options.disk) {
+                    println!("{}", e);
+                }
+            }
+        },
+        "volumes" => {
+            println!("name            segment  blocks     block size first sector signature format       name spaces");
+            for image in files {
+                if files.len() > 1 {
+                    println!("{}:", image);
+                }
+                for s in volume::list_segments(image, &options.disk)? {
+                    let (format, namespaces) = match volume::open_volume_set(files, &s.name, &options.disk) {
+                        Ok(v) => (v.format.name(), v.namespaces().iter().map(|ns| volume::namespace_name(*ns)).collect::<Vec<_>>().join(" ")),
+                        Err(_) => ("?", "?".to_string()),
+                    };
+                    println!("{:<15} {:>3}/{:<3} {:>10} {:>10} {:<12x} {:08x}  {:<12} {}", s.name, s.segment_number, s.last_segment + 1,
+                        s.segment_blocks(), s.block_size(), s.first_sector, s.signature, format, namespaces);
+                }
+            }
+        },
+        "ls" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            let (volume, entry) = open(images, &spec[0], options)?;
+            list(&volume, &owner_names(images, options), entry, options);
+        },
+        "check" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            check(images, &spec[0], options)?;
+        },
+        "mount" => {
+            let (images, rest) = files.split_at(files.len() - 2);
+            mount(images, &rest[0], &rest[1], options)?;
+        },
+        "put" => {
+            let (images, rest) = files.split_at(files.len() - 2);
+            put(images, &rest[0], &rest[1], options)?;
+        },
+        "grep" => {
+            let (images, rest) = files.split_at(files.len() - 2);
+            grep(images, &rest[0], &rest[1], options)?;
+        },
+        "stats" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            stats(images, &spec[0], options)?;
+        },
+        "carve" => {
+            for image in files {
+                if files.len() > 1 {
+                    println!("{}:", image);
+                }
+                if let Err(e) = carve(image) {
+                    println!("{}", e);
+                }
+            }
+        },
+        "bindery" => {
+            bindery(files, options)?;
+        },
+        "tts" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            show_tts(images, &spec[0], options)?;
+        },
+        "badblocks" => {
+            bad_blocks(files, options)?;
+        },
+        "mirrors" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            mirrors(images, &spec[0], options)?;
+        },
+        "rename" => {
+            let (images, rest) = files.split_at(files.len() - 2);
+            rename(images, &rest[0], &rest[1], options)?;
+        },
+        "set-segment" => {
+            let (rest, assignments) = files.split_at(files.len() - num_assignments(files));
+            let (images, rest) = rest.split_at(rest.len() - 2);
+            set_segment(images, &rest[0], &rest[1], assignments, options)?;
+        },
+        "fix-segments" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            fix_segments(images, &spec[0], options)?;
+        },
+        "mkfs" => {
+            mkfs(&files[0], &files[1], &files[2], options)?;
+        },
+        "trustees" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            trustees(images, &spec[0], options)?;
+        },
+        "salvage" => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            salvage(images, &spec[0], options)?;
+        },
+        "extract" if options.archive.is_some() => {
+            let (images, spec) = files.split_at(files.len() - 1);
+            extract_archive(images, &spec[0], options.archive.as_ref().unwrap(), options)?;
+        },
+        "extract" => {
+            let (images, rest) = files.split_at(files.len() - 2);
+            let (mut volume, entry) = open(images, &rest[0], options)?;
+            let entry = volume.entries[entry as usize].clone();
+            let mut dest = Path::new(&rest[1]).to_path_buf();
+            if !entry.is_directory() && dest.is_dir() {
+                dest = dest.join(file_name(volume.display_name(&entry), entry.entry));
+            }
+            let mut output = Output::new(Target::Directory, options);
+            output.read_previous(options)?;
+            extract(&mut volume, &entry, &dest, options.forks, &mut output)?;
+            output.write_manifest(options)?;
+        },
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options::default();
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(2);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--long" => { options.long = true; },
+            "--recursive" => { options.recursive = true; },
+            "--json" => { options.json = true; },
+            "--csv" => { options.csv = true; },
+            "--hex" => { options.hex = true; },
+            "--ignore-case" => { options.ignore_case = true; },
+            "--dry-run" => { options.dry_run = true; },
+            "--extract" => {
+                options.extract_dir = arg_iter.next().cloned();
+                if options.extract_dir.is_none() {
+                    println!("--extract requires a directory");
+                    return Ok(())
+                }
+            },
+            "--manifest" => {
+                options.manifest = arg_iter.next().cloned();
+                if options.manifest.is_none() {
+                    println!("--manifest requires a file name");
+                    return Ok(())
+                }
+            },
+            "--previous" => {
+                options.previous = arg_iter.next().cloned();
+                if options.previous.is_none() {
+                    println!("--previous requires a manifest");
+                    return Ok(())
+                }
+            },
+            "--tar" | "--zip" => {
+                let format = if arg == "--tar" { ArchiveFormat::Tar } else { ArchiveFormat::Zip };
+                match arg_iter.next() {
+                    Some(fname) => options.archive = Some((format, fname.to_string())),
+                    None => {
+                        println!("{} requires a file name", arg);
+                        return Ok(())
+                    }
+                }
+            },
+            "--mac-forks" => {
+                options.forks = match arg_iter.next().map(|s| s.as_str()) {
+                    Some("appledouble") => Forks::AppleDouble,
+                    Some("rsrc") => Forks::Rsrc,
+                    Some("none") => Forks::None,
+                    _ => {
+                        println!("--mac-forks requires appledouble, rsrc or none");
+                        return Ok(())
+                    }
+                };
+            },
+            "--timezone" => {
+                match arg_iter.next().and_then(|s| nwfs::parse_timezone(s)) {
+                    Some(timezone) => options.timezone = timezone,
+                    None => {
+                        println!("--timezone requires a time zone: UTC, +HH, +HHMM or +HH:MM");
+                        return Ok(())
+                    }
+                }
+            },
+            "--bindery" => {
+                options.bindery = arg_iter.next().cloned();
+                if options.bindery.is_none() {
+                    println!("--bindery requires a directory or image");
+                    return Ok(())
+                }
+            },
+            "--block-size" => {
+                options.block_size = arg_iter.next().and_then(|s| s.parse::<usize>().ok()).map(|kb| kb * 1024)
+                    .filter(|size| nwfs::mkfs::BLOCK_SIZES.contains(size));
+                if options.block_size.is_none() {
+                    println!("--block-size requires a block size in KB: 4, 8, 16, 32 or 64");
+                    return Ok(())
+                }
+            },
+            "--sector-size" => {
+                match arg_iter.next().and_then(|s| s.parse::<u64>().ok()).filter(|n| n.is_power_of_two() && (128..=4096).contains(n)) {
+                    Some(size) => options.disk.sector_size = size,
+                    None => {
+                        println!("--sector-size requires a sector size: 128, 256, 512, 1024, 2048 or 4096");
+                        return Ok(())
+                    }
+                }
+            },
+            "--geometry" => {
+                options.disk.geometry = arg_iter.next().and_then(|s| DiskLayout::parse_geometry(s));
+                if options.disk.geometry.is_none() {
+                    println!("--geometry requires a geometry as C/H/S, with up to 256 heads and 63 sectors");
+                    return Ok(())
+                }
+            },
+            "--partition-offset" => {
+                options.disk.partition_offset = arg_iter.next().and_then(|s| parse_size(s));
+                if options.disk.partition_offset.is_none() {
+                    println!("--partition-offset requires an offset in bytes");
+                    return Ok(())
+                }
+            },
+            "--cache-size" => {
+                match arg_iter.next().and_then(|s| parse_size(s)).and_then(|n| usize::try_from(n).ok()) {
+                    Some(size) => options.disk.cache_size = size,
+                    None => {
+                        println!("--cache-size requires a size in bytes");
+                        return Ok(())
+                    }
+                }
+            },
+            "--" => {
+                options.dump_args = arg_iter.by_ref().cloned().collect();
+            },
+            "--top" => {
+                options.top = arg_iter.next().and_then(|s| s.parse::<usize>().ok());
+                if options.top.is_none() {
+                    println!("--top requires a number");
+                    return Ok(())
+                }
+            },
+            "--namespace" => {
+                options.namespace = arg_iter.next().and_then(|s| volume::find_namespace(s));
+                if options.namespace.is_none() {
+                    let names: Vec<&str> = volume::NAMESPACES.iter().map(|(_, name)| *name).collect();
+                    println!("--namespace requires a name space: {} or OS2", names.join(", "));
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    let valid = match args.get(1).map(|s| s.as_str()) {
+        Some("partitions") | Some("volumes") | Some("carve") | Some("bindery") | Some("badblocks") => !files.is_empty(),
+        Some("ls") | Some("salvage") | Some("trustees") | Some("check") | Some("stats") | Some("mirrors") | Some("tts") => files.len() >= 2,
+        Some("extract") if options.archive.is_some() => files.len() >= 2,
+        Some("extract") | Some("mount") | Some("put") => files.len() >= 3,
+        Some("mkfs") => files.len() == 3,
+        Some("rename") => files.len() >= 3,
+        Some("fix-segments") => files.len() >= 2,
+        Some("set-segment") => num_assignments(&files) > 0 && files.len() - num_assignments(&files) >= 3,
+        Some("grep") => files.len() >= 3 && !files[files.len() - 2].is_empty(),
+        _ => false,
+    };
+    if !valid {
+        usage(&args[0]);
+        return Ok(())
+    }
+    if let Err(e) = run(&args[1], &files, &options) {
+        println!("{}", e);
+    }
+    Ok(())
+}
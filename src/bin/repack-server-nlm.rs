@@ -0,0 +1,109 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::serverexe::{self, HereBlock};
+use nlm_tools::unpack;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.exe new.nlm out.exe", prog);
+    println!();
+    println!("Replaces an NLM embedded in server.exe by new.nlm, and writes the result to");
+    println!("out.exe; this is the inverse of extract-server-nlm");
+    println!();
+    println!("options:");
+    println!("  --replace NAME       replace the embedded NLM called NAME, as listed by");
+    println!("                       extract-server-nlm --all, instead of SERVER.NLM");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut replace: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--replace" => {
+                replace = arg_iter.next().cloned();
+                if replace.is_none() {
+                    println!("--replace requires a name");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 3 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let server_data = match unpack::unpack_exe(std::fs::read(&files[0])?) {
+        Ok((server_data, packer)) => {
+            if let Some(packer) = packer {
+                println!("unpacked {} compressed executable", packer.name());
+            }
+            server_data
+        },
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    let nlm_data = std::fs::read(&files[1])?;
+    let here = match HereBlock::find(&server_data) {
+        Ok(here) => here,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    let nlm_offset = match serverexe::first_nlm(&server_data, &here) {
+        Some(offset) => offset,
+        None => {
+            println!("Signature found, but no NLM present");
+            return Ok(())
+        }
+    };
+
+    // Names are assigned the same way extract-server-nlm --all does
+    let nlms = serverexe::find_nlms(&server_data, nlm_offset);
+    let mut names: Vec<String> = Vec::new();
+    for (n, nlm) in nlms.iter().enumerate() {
+        names.push(nlm.file_name(n, &names));
+    }
+    let index = match &replace {
+        Some(name) => match names.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+            Some(index) => index,
+            None => {
+                println!("no embedded NLM '{}', available are: {}", name, names.join(", "));
+                return Ok(())
+            }
+        },
+        None => 0,
+    };
+
+    let (result, updates) = match serverexe::replace_nlm(&server_data, &here, &nlms, index, &nlm_data) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    let old = &nlms[index];
+    println!("replaced {} at offset {:x}: {} -> {} bytes", names[index], old.offset, old.length, nlm_data.len());
+    for update in &updates {
+        println!("updated HERE block field {:02x}: {:x} -> {:x}", update.offset, update.old, update.new);
+    }
+    std::fs::write(&files[2], result)?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::io::BufRead;
+use nlm_tools::resolve::{self, Resolver};
+use nlm_tools::symbols;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] [address ...]", prog);
+    println!();
+    println!("Resolves addresses to module!symbol+offset. If no addresses are given, they are");
+    println!("read from standard input, one per line");
+    println!();
+    println!("options:");
+    println!("  --modules FILE       module map, with a line per module containing its name");
+    println!("                       followed by 'code ADDR+LEN' and optionally 'data ADDR+LEN'");
+    println!("                       (as printed by extract-loaded-modules)");
+    println!("  --symbols FILE       symbol file (text, nm or ghidra format) with absolute");
+    println!("                       addresses; may be given more than once");
+    println!("  --symbols FILE@NAME  symbol file of module NAME, at the addresses nlm2elf");
+    println!("                       uses; these are moved to where the module was loaded");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut modules_fname: Option<String> = None;
+    let mut symbol_files: Vec<String> = Vec::new();
+    let mut addresses: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--modules" | "--symbols" => {
+                let value = match arg_iter.next() {
+                    Some(value) => value.to_string(),
+                    None => {
+                        println!("{} requires a file name", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--modules" { modules_fname = Some(value); } else { symbol_files.push(value); }
+            },
+            "--help" => {
+                usage(&args[0]);
+                return Ok(())
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                addresses.push(arg.to_string());
+            }
+        }
+    }
+    if symbol_files.is_empty() && modules_fname.is_none() {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let modules = match &modules_fname {
+        Some(fname) => resolve::parse_module_map(&std::fs::read_to_string(fname)?),
+        None => Vec::new(),
+    };
+    let mut resolver = Resolver::new(modules);
+    for spec in &symbol_files {
+        let (fname, module) = match spec.rsplit_once('@') {
+            Some((fname, module)) => (fname, Some(module)),
+            None => (spec.as_str(), None),
+        };
+        let symbols = resolve::parse_symbol_file(&std::fs::read_to_string(fname)?);
+        match module {
+            Some(module) => {
+                if let Err(e) = resolver.add_module_symbols(module, symbols) {
+                    println!("{}", e);
+                    return Ok(())
+                }
+            },
+            None => resolver.add_symbols(symbols),
+        }
+    }
+
+    let resolve = |s: &str| {
+        match symbols::parse_address(s.trim()) {
+            Some(addr) => println!("{:08x} {}", addr, resolver.resolve(addr).unwrap_or("?".to_string())),
+            None => println!("{}: invalid address", s.trim()),
+        }
+    };
+    if addresses.is_empty() {
+        for line in std::io::stdin().lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() { continue; }
+            resolve(&line);
+        }
+    } else {
+        for addr in &addresses {
+            resolve(addr);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::time::{Duration, Instant, SystemTime};
+use nlm_tools::bindery::{self, OT_FILE_SERVER};
+use nlm_tools::ipx::{self, Address, IpxPacket};
+use nlm_tools::ncp::NCPError;
+use nlm_tools::ncp::transport::IpxTunnel;
+use nlm_tools::nwfs::format_unix_time;
+use nlm_tools::sap::{self, SapPacket, Service};
+
+// Socket advertised when no address is given, which is where NCP requests go
+const DEFAULT_SOCKET: u16 = 0x0451;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] NAME", prog);
+    println!();
+    println!("Advertises a service called NAME using SAP on an IPX network, as tunneled over");
+    println!("UDP by DOSBox and similar emulators: the service is broadcast periodically, and");
+    println!("queries for it are answered, until stopped");
+    println!();
+    println!("options:");
+    println!("  --tunnel HOST[:PORT] IPX tunnel server to use (port 213 by default)");
+    println!("  --type TYPE          type of the service (i.e. file-server or 0x0004); a file");
+    println!("                       server by default");
+    println!("  --address ADDRESS    address of the service as NETWORK:NODE[:SOCKET]; by default");
+    println!("                       the node address given by the tunnel, socket 0451");
+    println!("  --hops N             number of hops to the service (1 by default)");
+    println!("  --interval SECONDS   time between broadcasts (60 by default)");
+    println!("  --count N            stop after N broadcasts, and advertise the service as down");
+    println!("  --quiet              do not show the packets sent");
+}
+
+struct Options {
+    tunnel: Option<String>,
+    service_type: u16,
+    address: Option<Address>,
+    hops: u16,
+    interval: Duration,
+    count: Option<usize>,
+    quiet: bool,
+}
+
+fn type_string(service_type: u16) -> String {
+    match bindery::object_type_name(service_type) {
+        Some(name) => name.to_string(),
+        None => format!("type {:04x}", service_type),
+    }
+}
+
+fn log(message: &str) {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    println!("{} {}", format_unix_time(now), message);
+}
+
+fn send_response(tunnel: &IpxTunnel, destination: Address, nearest: bool, service: &Service) -> Result<(), NCPError> {
+    let response = SapPacket::Response{ nearest, services: vec![ service.clone() ] };
+    let source = Address::new(tunnel.address.network, tunnel.address.node, ipx::SOCKET_SAP);
+    tunnel.send(&IpxPacket::new(ipx::PACKET_TYPE_PEP, destination, source, response.to_bytes()))
+}
+
+fn broadcast(tunnel: &IpxTunnel, service: &Service) -> Result<(), NCPError> {
+    send_response(tunnel, Address::new(tunnel.address.network, ipx::BROADCAST_NODE, ipx::SOCKET_SAP), false, service)
+}
+
+fn advertise(options: &Options, host: &str, name: &str) -> Result<(), NCPError> {
+    let tunnel = IpxTunnel::connect(host)?;
+    let address = options.address.unwrap_or(Address::new(tunnel.address.network, tunnel.address.node, DEFAULT_SOCKET));
+    let mut service = Service{ service_type: options.service_type, name: name.to_string(), address, hops: options.hops };
+    if !options.quiet {
+        log(&format!("advertising {} {} at {}, {} hops, from {}", type_string(service.service_type), service.name, service.address, service.hops,
+            Address::new(tunnel.address.network, tunnel.address.node, ipx::SOCKET_SAP)));
+    }
+
+    let mut broadcasts = 0;
+    let mut next = Instant::now();
+    loop {
+        if Instant::now() >= next {
+            if options.count.is_some_and(|count| broadcasts >= count) {
+                break;
+            }
+            broadcast(&tunnel, &service)?;
+            broadcasts += 1;
+            next += options.interval;
+            if !options.quiet {
+                log("sent general response");
+            }
+        }
+        let Some(packet) = tunnel.receive(next.saturating_duration_since(Instant::now()))? else { continue };
+        if packet.destination.socket != ipx::SOCKET_SAP || packet.source.node == tunnel.address.node {
+            continue;
+        }
+        let Ok(SapPacket::Query{ nearest, service_type }) = SapPacket::parse(&packet.data) else { continue };
+        if service_type != sap::SERVICE_ALL && service_type != service.service_type {
+            continue;
+        }
+        send_response(&tunnel, packet.source, nearest, &service)?;
+        if !options.quiet {
+            let query = SapPacket::Query{ nearest, service_type }.operation();
+            log(&format!("answered {} from {}", sap::operation_name(query), packet.source));
+        }
+    }
+
+    // Let those who heard of the service know it is gone, rather than having
+    // them wait for it to age out
+    service.hops = sap::HOPS_UNREACHABLE;
+    broadcast(&tunnel, &service)?;
+    if !options.quiet {
+        log("advertised the service as down");
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options{ tunnel: None, service_type: OT_FILE_SERVER, address: None, hops: 1, interval: Duration::from_secs(sap::BROADCAST_INTERVAL), count: None, quiet: false };
+    let mut names: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--quiet" => { options.quiet = true; },
+            "--tunnel" => {
+                let Some(value) = arg_iter.next() else {
+                    println!("--tunnel requires an argument");
+                    return Ok(())
+                };
+                options.tunnel = Some(value.to_string());
+            },
+            "--type" => {
+                match arg_iter.next().and_then(|s| bindery::parse_object_type(s)) {
+                    Some(t) => { options.service_type = t; },
+                    None => {
+                        println!("--type requires an object type name or number");
+                        return Ok(())
+                    }
+                }
+            },
+            "--address" => {
+                match arg_iter.next().map(|s| s.parse::<Address>()) {
+                    Some(Ok(address)) => { options.address = Some(address); },
+                    Some(Err(e)) => {
+                        println!("--address: {}", e);
+                        return Ok(())
+                    },
+                    None => {
+                        println!("--address requires an address as NETWORK:NODE[:SOCKET]");
+                        return Ok(())
+                    }
+                }
+            },
+            "--hops" | "--interval" | "--count" => {
+                let Some(value) = arg_iter.next().and_then(|s| s.parse::<u64>().ok()) else {
+                    println!("{} requires a number", arg);
+                    return Ok(())
+                };
+                match arg.as_str() {
+                    "--hops" if value < sap::HOPS_UNREACHABLE as u64 => { options.hops = value as u16; },
+                    "--hops" => {
+                        println!("--hops must be below {}", sap::HOPS_UNREACHABLE);
+                        return Ok(())
+                    },
+                    "--interval" if value > 0 => { options.interval = Duration::from_secs(value); },
+                    "--interval" => {
+                        println!("--interval must be at least a second");
+                        return Ok(())
+                    },
+                    _ => { options.count = Some(value as usize); },
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                names.push(arg.to_string());
+            }
+        }
+    }
+    if names.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+    // Names are zero-terminated within their field
+    if names[0].is_empty() || names[0].len() >= sap::SERVICE_NAME_SIZE {
+        println!("the name must be 1 to {} characters", sap::SERVICE_NAME_SIZE - 1);
+        return Ok(())
+    }
+    let Some(host) = &options.tunnel else {
+        println!("--tunnel is required");
+        return Ok(())
+    };
+
+    if let Err(e) = advertise(&options, host, &names[0].to_uppercase()) {
+        println!("{}", e);
+    }
+    Ok(())
+}
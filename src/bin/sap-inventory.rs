@@ -0,0 +1,271 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use serde_json::{json, Value};
+use nlm_tools::bindery;
+use nlm_tools::ipx::{self, Address};
+use nlm_tools::nwfs::format_unix_time;
+use nlm_tools::pcap::Capture;
+use nlm_tools::sap::{self, Change, Inventory, SapPacket, Service};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] capture.pcap ...", prog);
+    println!();
+    println!("Decodes the Service Advertising Protocol packets in packet captures (pcap or");
+    println!("pcapng), which are read in the order given, and lists the services advertised");
+    println!("along with the routers and servers advertising them, and the networks they are");
+    println!("on");
+    println!();
+    println!("options:");
+    println!("  --type TYPE          only include services of this type (i.e. file-server or");
+    println!("                       0x0004)");
+    println!("  --packets            show every SAP packet as it is decoded");
+    println!("  --history            show when services appeared, went down or changed");
+    println!("  --json               print the inventory as json");
+}
+
+fn time_string(time: u64) -> String {
+    format_unix_time(time / 1_000_000_000)
+}
+
+fn type_string(service_type: u16) -> String {
+    match bindery::object_type_name(service_type) {
+        Some(name) => name.to_string(),
+        None => format!("type {:04x}", service_type),
+    }
+}
+
+fn hops_string(hops: u16) -> String {
+    if hops >= sap::HOPS_UNREACHABLE { "down".to_string() } else { hops.to_string() }
+}
+
+fn print_packet(time: u64, source: &Address, packet: &SapPacket) {
+    match packet {
+        SapPacket::Query{ service_type, .. } => {
+            let wanted = if *service_type == sap::SERVICE_ALL { "all services".to_string() } else { type_string(*service_type) };
+            println!("{} {} {} for {}", time_string(time), source, sap::operation_name(packet.operation()), wanted);
+        },
+        SapPacket::Response{ services, .. } => {
+            println!("{} {} {}, {} services", time_string(time), source, sap::operation_name(packet.operation()), services.len());
+            for s in services {
+                println!("    {:<24} {:<47} {} {} hops", type_string(s.service_type), s.name, s.address, hops_string(s.hops));
+            }
+        },
+    }
+}
+
+fn change_string(change: &Change) -> String {
+    match change {
+        Change::Appeared => "appeared".to_string(),
+        Change::Hops(hops) => format!("now {} hops away", hops),
+        Change::Moved(address) => format!("moved to {}", address),
+        Change::Unreachable => "went down".to_string(),
+        Change::Reachable => "came back".to_string(),
+    }
+}
+
+fn service_json(s: &Service) -> Value {
+    json!({
+        "type": s.service_type,
+        "type_name": bindery::object_type_name(s.service_type),
+        "name": s.name,
+        "address": s.address.to_string(),
+        "hops": s.hops,
+    })
+}
+
+#[derive(Default)]
+struct Network<'a> {
+    services: Vec<&'a Service>,
+    // The closest route: the advertiser and the hops through it
+    route: Option<(Address, u16)>,
+}
+
+// Networks the services are on, by number
+fn networks(inventory: &Inventory) -> BTreeMap<u32, Network<'_>> {
+    let mut result: BTreeMap<u32, Network> = BTreeMap::new();
+    for r in &inventory.services {
+        let network = result.entry(r.service.address.network).or_default();
+        network.services.push(&r.service);
+        for (advertiser, hops) in r.routes.iter().filter(|(_, hops)| *hops < sap::HOPS_UNREACHABLE) {
+            if network.route.is_none_or(|(_, h)| *hops < h) {
+                network.route = Some((*advertiser, *hops));
+            }
+        }
+    }
+    result
+}
+
+fn print_inventory(inventory: &Inventory, history: bool) {
+    println!("{:<24} {:<47} {:<26} {:>4} {:<20} {:<20}", "type", "name", "address", "hops", "first seen", "last seen");
+    let mut services: Vec<_> = inventory.services.iter().collect();
+    services.sort_by(|a, b| (a.service.service_type, &a.service.name).cmp(&(b.service.service_type, &b.service.name)));
+    for r in services {
+        let s = &r.service;
+        println!("{:<24} {:<47} {:<26} {:>4} {:<20} {:<20}", type_string(s.service_type), s.name, s.address.to_string(),
+            hops_string(s.hops), time_string(r.first_seen), time_string(r.last_seen));
+    }
+
+    println!();
+    println!("{:<26} {:>9} {:>9} {:<20} {:<20}", "advertised by", "responses", "services", "first seen", "last seen");
+    for a in &inventory.advertisers {
+        println!("{:<26} {:>9} {:>9} {:<20} {:<20}", format!("{:08x}:{}", a.address.network, ipx::node_string(&a.address.node)),
+            a.responses, a.services, time_string(a.first_seen), time_string(a.last_seen));
+    }
+
+    println!();
+    println!("{:<8} {:>4} {:<21} services", "network", "hops", "reached through");
+    for (number, network) in networks(inventory) {
+        let (through, hops) = match network.route {
+            Some((a, hops)) => (format!("{:08x}:{}", a.network, ipx::node_string(&a.node)), hops.to_string()),
+            None => ("-".to_string(), "down".to_string()),
+        };
+        let names: Vec<&str> = network.services.iter().map(|s| s.name.as_str()).collect();
+        println!("{:08x} {:>4} {:<21} {}", number, hops, through, names.join(", "));
+    }
+    println!();
+    println!("{} services, {} queries from {} addresses", inventory.services.len(), inventory.queries, inventory.queriers.len());
+
+    if history {
+        println!();
+        for e in &inventory.events {
+            println!("{} {} {} {}", time_string(e.time), type_string(e.service_type), e.name, change_string(&e.change));
+        }
+    }
+}
+
+fn inventory_json(inventory: &Inventory) -> Value {
+    let services: Vec<Value> = inventory.services.iter().map(|r| {
+        let mut result = service_json(&r.service);
+        result["first_seen"] = json!(time_string(r.first_seen));
+        result["last_seen"] = json!(time_string(r.last_seen));
+        result["advertisements"] = json!(r.advertisements);
+        result["routes"] = json!(r.routes.iter().map(|(a, hops)| json!({ "advertiser": a.to_string(), "hops": hops })).collect::<Vec<_>>());
+        result
+    }).collect();
+    let advertisers: Vec<Value> = inventory.advertisers.iter().map(|a| json!({
+        "address": a.address.to_string(),
+        "responses": a.responses,
+        "services": a.services,
+        "first_seen": time_string(a.first_seen),
+        "last_seen": time_string(a.last_seen),
+    })).collect();
+    let networks: Vec<Value> = networks(inventory).iter().map(|(number, network)| json!({
+        "network": format!("{:08x}", number),
+        "hops": network.route.map(|(_, hops)| hops),
+        "reached_through": network.route.map(|(a, _)| a.to_string()),
+        "services": network.services.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+    })).collect();
+    let events: Vec<Value> = inventory.events.iter().map(|e| {
+        let (change, detail) = match &e.change {
+            Change::Appeared => ("appeared", Value::Null),
+            Change::Hops(hops) => ("hops", json!(hops)),
+            Change::Moved(address) => ("moved", json!(address.to_string())),
+            Change::Unreachable => ("down", Value::Null),
+            Change::Reachable => ("up", Value::Null),
+        };
+        json!({ "time": time_string(e.time), "type": e.service_type, "name": e.name, "change": change, "detail": detail })
+    }).collect();
+    json!({
+        "services": services,
+        "advertisers": advertisers,
+        "networks": networks,
+        "queries": inventory.queries,
+        "queriers": inventory.queriers.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        "history": events,
+    })
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut service_type: Option<u16> = None;
+    let mut show_packets = false;
+    let mut history = false;
+    let mut json = false;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--packets" => { show_packets = true; },
+            "--history" => { history = true; },
+            "--json" => { json = true; },
+            "--type" => {
+                match arg_iter.next().and_then(|s| bindery::parse_object_type(s)) {
+                    Some(t) => { service_type = Some(t); },
+                    None => {
+                        println!("--type requires an object type name or number");
+                        return Ok(())
+                    }
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.is_empty() {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let mut inventory = Inventory::default();
+    for fname in &files {
+        let capture = match Capture::open(BufReader::new(File::open(fname)?)) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("cannot read '{}': {}", fname, e);
+                return Ok(())
+            }
+        };
+        for (n, packet) in capture.enumerate() {
+            let packet = packet?;
+            let frame = match ipx::parse_captured(packet.link_type, &packet.data) {
+                Ok(Some(frame)) if frame.packet.destination.socket == ipx::SOCKET_SAP || frame.packet.source.socket == ipx::SOCKET_SAP => frame,
+                Ok(_) => continue,
+                Err(e) => {
+                    if !json {
+                        println!("{}: packet {}: {}", fname, n + 1, e);
+                    }
+                    continue
+                }
+            };
+            let mut sap = match SapPacket::parse(&frame.packet.data) {
+                Ok(sap) => sap,
+                Err(e) => {
+                    if !json {
+                        println!("{}: packet {}: {}", fname, n + 1, e);
+                    }
+                    continue
+                }
+            };
+            if let (Some(t), SapPacket::Response{ services, .. }) = (service_type, &mut sap) {
+                services.retain(|s| s.service_type == t);
+            }
+            if show_packets && !json {
+                print_packet(packet.timestamp, &frame.packet.source, &sap);
+            }
+            inventory.add(packet.timestamp, &frame.packet.source, &sap);
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inventory_json(&inventory)).unwrap());
+        return Ok(())
+    }
+    if show_packets {
+        println!();
+    }
+    print_inventory(&inventory, history);
+    Ok(())
+}
@@ -0,0 +1,86 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::nlm::NLM;
+use nlm_tools::serverexe::{self, HereBlock};
+use nlm_tools::serverinfo::ServerInfo;
+use nlm_tools::unpack;
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] file", prog);
+    println!();
+    println!("Prints the NetWare version, build date, licensed user count, serial number and");
+    println!("copyright notices found in file, which is either a SERVER.EXE or a memory dump");
+    println!();
+    println!("options:");
+    println!("  --json               write the information as json");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut json = false;
+    let mut files: Vec<String> = Vec::new();
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
+            "--json" => { json = true; },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let dump = match MemoryDump::read(&files[0]) {
+        Ok(dump) => dump,
+        Err(e) => {
+            println!("cannot read '{}': {}", files[0], e);
+            return Ok(())
+        }
+    };
+    // A packed SERVER.EXE is unpacked, as its texts are compressed otherwise
+    let data = match unpack::unpack(&dump.data) {
+        Ok(Some((packer, data))) => {
+            println!("unpacked {} compressed executable", packer.name());
+            data
+        },
+        Ok(None) => dump.data,
+        Err(e) => {
+            println!("WARNING: {}", e);
+            dump.data
+        }
+    };
+    let mut info = ServerInfo::scan(&data);
+
+    // The texts of SERVER.NLM are not visible if it is packed within
+    // SERVER.EXE, so the embedded NLMs are unpacked and scanned as well
+    if let Ok(here) = HereBlock::find(&data) {
+        if let Some(offset) = serverexe::first_nlm(&data, &here) {
+            for embedded in serverexe::find_nlms(&data, offset) {
+                if let Ok(nlm) = NLM::new(embedded.data(&data)) {
+                    info.merge(ServerInfo::scan(nlm.data()));
+                }
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info.to_json()).unwrap());
+    } else {
+        for line in info.to_text() {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use serde_json::{json, Value};
+use nlm_tools::nlm::{self, NLM, NLM_DATA_VADDR};
+use nlm_tools::serverexe::{self, HereBlock, ServerVariant};
+use nlm_tools::symbols::{self, Image};
+use nlm_tools::unpack;
+use nlm_tools::versions::{self, KnownVersion};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] server.exe outdir", prog);
+    println!();
+    println!("Extracts all NLMs embedded in server.exe, and writes the unpacked NLM, the ELF");
+    println!("file and the internal symbols of every NLM to outdir, along with manifest.json");
+    println!();
+    println!("options:");
+    println!("  --netware-version V  use the known offsets of NetWare version V, i.e. 3.12;");
+    println!("                       this is detected from the version string if omitted");
+}
+
+// Looks up the pointer to the internal symbol list in the data segment
+fn find_symbol_list(image: &Image, known: Option<&KnownVersion>) -> Option<u32> {
+    let known_ptr = known.and_then(|v| v.server_sym_ptr)
+        .filter(|p| image.read_list(*p).is_ok_and(|l| l.len() >= symbols::MIN_SYMBOLS));
+    known_ptr.or(image.find_list_head().map(|(ptr, _)| ptr))
+}
+
+// Writes the files of the NLM and adds them to its manifest entry
+fn convert(data: &[u8], name: &str, outdir: &Path, version: Option<&str>, module: &mut Value) -> Result<(), Box<dyn Error>> {
+    let nlm = NLM::new(data)?;
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+    let nlm_name = format!("{}.NLM", stem);
+    let elf_name = format!("{}.elf", stem);
+    let sym_name = format!("{}.sym", stem);
+
+    nlm.write_nlm(&outdir.join(&nlm_name).to_string_lossy())?;
+    nlm.write_elf(&outdir.join(&elf_name).to_string_lossy())?;
+
+    // The version string is stored within SERVER.NLM, which is packed within
+    // SERVER.EXE, so detection is done using the unpacked NLM
+    let known = versions::select(version, nlm.data())?;
+    let data = nlm.data_segment()?;
+    let image = Image::new(&data, NLM_DATA_VADDR);
+    let symbols = match find_symbol_list(&image, known) {
+        Some(ptr) => {
+            let symbols = image.read_list(ptr)?;
+            let mut f = File::create(outdir.join(&sym_name))?;
+            for sym in &symbols {
+                writeln!(f, "{} 0x{:x}", sym.name, sym.address)?;
+            }
+            json!({ "file": sym_name, "pointer": ptr, "count": symbols.len() })
+        },
+        None => Value::Null,
+    };
+    module["packed"] = json!(nlm.header.load_version == 0x84);
+    module["type"] = json!(nlm.module_type().map(nlm::module_type_name));
+    module["flags"] = json!(nlm.flags().map(nlm::flag_names));
+    module["description"] = json!(nlm.description());
+    module["nlm"] = json!(nlm_name);
+    module["elf"] = json!(elf_name);
+    module["symbols"] = symbols;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    let mut version: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--netware-version" => {
+                version = arg_iter.next().cloned();
+                if version.is_none() {
+                    println!("--netware-version requires an argument");
+                    return Ok(())
+                }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 2 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    let server_fname = &files[0];
+    let outdir = Path::new(&files[1]);
+
+    let server_data = match unpack::unpack_exe(std::fs::read(server_fname)?) {
+        Ok((server_data, packer)) => {
+            if let Some(packer) = packer {
+                println!("unpacked {} compressed executable", packer.name());
+            }
+            server_data
+        },
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+    if let Err(e) = versions::select(version.as_deref(), &[]) {
+        println!("{}", e);
+        return Ok(())
+    }
+    let here = match HereBlock::find(&server_data) {
+        Ok(here) => here,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(())
+        }
+    };
+
+    std::fs::create_dir_all(outdir)?;
+    let mut names: Vec<String> = Vec::new();
+    let mut modules: Vec<Value> = Vec::new();
+    let nlm_offset = match serverexe::first_nlm(&server_data, &here) {
+        Some(offset) => offset,
+        None => {
+            println!("no embedded NLMs found");
+            return Ok(())
+        }
+    };
+    let nlms = serverexe::find_nlms(&server_data, nlm_offset);
+    let variant = ServerVariant::detect(&server_data, &nlms);
+    for (n, embedded) in nlms.iter().enumerate() {
+        let name = embedded.file_name(n, &names);
+        let mut module = json!({
+            "name": name,
+            "offset": embedded.offset,
+            "length": embedded.length,
+        });
+        let result = match &embedded.error {
+            Some(e) => Err(e.clone()),
+            None => convert(embedded.data(&server_data), &name, outdir, version.as_deref(), &mut module).map_err(|e| e.to_string()),
+        };
+        match result {
+            Ok(()) => {
+                let symbols = module["symbols"]["count"].as_u64().map(|n| format!(", {} symbols", n)).unwrap_or_default();
+                println!("{:08x} {:8} {}{}", embedded.offset, embedded.length, name, symbols);
+            },
+            Err(e) => {
+                println!("{:08x} {:8} {}: {}", embedded.offset, embedded.length, name, e);
+                module["error"] = json!(e);
+            }
+        }
+        modules.push(module);
+        names.push(name);
+    }
+
+    let manifest = json!({
+        "source": server_fname,
+        "variant": variant.name(),
+        "here": here.to_json(),
+        "modules": modules,
+    });
+    std::fs::write(outdir.join("manifest.json"), serde_json::to_string_pretty(&manifest)? + "\n")?;
+    Ok(())
+}
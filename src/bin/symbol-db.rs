@@ -0,0 +1,181 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use object::{Object, ObjectSection};
+use nlm_tools::memdump::MemoryDump;
+use nlm_tools::resolve;
+use nlm_tools::symboldb::{self, SymbolDb};
+use nlm_tools::symbols::{self, Image, Symbol};
+use nlm_tools::versions;
+
+fn usage(prog: &str) {
+    println!("usage: {} import-symbols [options] symbols.db server|loader FILE", prog);
+    println!("       {} query symbols.db NAME ...", prog);
+    println!("       {} versions symbols.db", prog);
+    println!();
+    println!("Keeps the symbols of SERVER.NLM (server) and the loader (loader) of many NetWare");
+    println!("versions in an SQLite database, so it can be tracked in which versions a symbol");
+    println!("is present and at which address");
+    println!();
+    println!("commands:");
+    println!("  import-symbols       import the symbol file written by extract-server-symbols");
+    println!("                       or extract-loader-symbols (text, nm or ghidra format),");
+    println!("                       replacing the symbols previously imported for the version");
+    println!("  query                show the address of symbol NAME (which may contain * and ?");
+    println!("                       wildcards) in every version imported");
+    println!("  versions             list the versions imported");
+    println!();
+    println!("import-symbols options:");
+    println!("  --netware-version V  version to store the symbols as, i.e. 3.12; this is");
+    println!("                       detected from FILE if omitted, which requires --extract");
+    println!("  --extract            extract the symbols from FILE instead, which is an ELF file");
+    println!("                       of SERVER.NLM (server) or a memory dump (loader)");
+    println!("  --base ADDR          linear address corresponding to the start of physical");
+    println!("                       memory, if not mapped 1:1 (the default is 0)");
+}
+
+// Extracts the symbols as extract-server-symbols does, using the known
+// pointer if it leads to a plausible list
+fn extract_server(data: &[u8], known_ptr: Option<u32>) -> Result<Vec<Symbol>, String> {
+    let elf = object::File::parse(data).map_err(|e| format!("cannot parse ELF file: {}", e))?;
+    let section = elf.sections().find(|s| s.kind() == object::SectionKind::Data)
+        .ok_or("unable to find data section".to_string())?;
+    let section_data = section.uncompressed_data().map_err(|e| e.to_string())?;
+    extract(&Image::new(&section_data, section.address() as u32), known_ptr)
+}
+
+fn extract(image: &Image, known_ptr: Option<u32>) -> Result<Vec<Symbol>, String> {
+    let known_ptr = known_ptr.filter(|p| image.read_list(*p).is_ok_and(|l| l.len() >= symbols::MIN_SYMBOLS));
+    let ptr = known_ptr.or(image.find_list_head().map(|(ptr, _)| ptr))
+        .ok_or("unable to locate the symbol list".to_string())?;
+    image.read_list(ptr)
+}
+
+fn import(args: &[String]) -> Result<(), String> {
+    let mut version: Option<String> = None;
+    let mut extract_symbols = false;
+    let mut base: u32 = 0;
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter();
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--extract" => { extract_symbols = true; },
+            "--netware-version" => {
+                version = Some(arg_iter.next().ok_or("--netware-version requires an argument".to_string())?.to_string());
+            },
+            "--base" => {
+                base = arg_iter.next().and_then(|s| symbols::parse_address(s)).ok_or("--base requires an address".to_string())?;
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    return Err(format!("unrecognized option '{}'", arg));
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 3 {
+        return Err("import-symbols requires a database, server or loader, and a file".to_string());
+    }
+    let source = files[1].as_str();
+    if !symboldb::SOURCES.contains(&source) {
+        return Err(format!("unknown symbol source '{}', expected {}", source, symboldb::SOURCES.join(" or ")));
+    }
+
+    let data = std::fs::read(&files[2]).map_err(|e| format!("cannot read '{}': {}", files[2], e))?;
+    let (version, symbols) = if extract_symbols {
+        let data = match source {
+            "loader" => MemoryDump::from_bytes(data).map_err(|e| format!("cannot read memory dump '{}': {}", files[2], e))?.data,
+            _ => data,
+        };
+        let version = version.or(versions::detect(&data))
+            .ok_or("unable to detect the NetWare version, use --netware-version to specify it".to_string())?;
+        let known = versions::find(&version);
+        let symbols = match source {
+            "loader" => extract(&Image::new(&data, base), known.and_then(|v| v.loader_sym_ptr))?,
+            _ => extract_server(&data, known.and_then(|v| v.server_sym_ptr))?,
+        };
+        (version, symbols)
+    } else {
+        let version = version.ok_or("--netware-version is required when importing a symbol file".to_string())?;
+        (version, resolve::parse_symbol_file(&String::from_utf8_lossy(&data)))
+    };
+    if symbols.is_empty() {
+        return Err(format!("no symbols found in '{}'", files[2]));
+    }
+
+    let mut db = SymbolDb::open(&files[0])?;
+    let count = db.import(&version, source, &symbols)?;
+    println!("imported {} {} symbols of version {}", count, source, version);
+    Ok(())
+}
+
+fn query(db_fname: &str, names: &[String]) -> Result<(), String> {
+    let db = SymbolDb::open(db_fname)?;
+    let versions = db.versions()?;
+    for pattern in names {
+        let rows = db.lookup(pattern)?;
+        if rows.is_empty() {
+            println!("{}: not found", pattern);
+            continue;
+        }
+        let mut current: Option<(&str, &str)> = None;
+        for row in &rows {
+            if current == Some((&row.name, &row.source)) { continue; }
+            current = Some((&row.name, &row.source));
+
+            // Every version of the source is listed, so it is visible where
+            // the symbol is missing; moves are relative to the previous one
+            println!("{} ({})", row.name, row.source);
+            let mut previous: Option<u32> = None;
+            for (version, _, _) in versions.iter().filter(|(_, s, _)| *s == row.source) {
+                let found: Vec<u32> = rows.iter()
+                    .filter(|r| r.name == row.name && r.source == row.source && r.version == *version)
+                    .map(|r| r.address)
+                    .collect();
+                if found.is_empty() {
+                    println!("  {:<12} -", version);
+                    continue;
+                }
+                let addresses: Vec<String> = found.iter().map(|a| format!("{:08x}", a)).collect();
+                let moved = match previous {
+                    Some(p) if p < found[0] => format!(" (+{:x})", found[0] - p),
+                    Some(p) if p > found[0] => format!(" (-{:x})", p - found[0]),
+                    _ => String::new(),
+                };
+                println!("  {:<12} {}{}", version, addresses.join(" "), moved);
+                previous = Some(found[0]);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_versions(db_fname: &str) -> Result<(), String> {
+    let db = SymbolDb::open(db_fname)?;
+    for (version, source, count) in db.versions()? {
+        println!("{:<12} {:<8} {} symbols", version, source, count);
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let result = match (args.get(1).map(|s| s.as_str()), args.len()) {
+        (Some("import-symbols"), n) if n >= 5 => import(&args[2..]),
+        (Some("query"), n) if n >= 4 => query(&args[2], &args[3..]),
+        (Some("versions"), 3) => list_versions(&args[2]),
+        _ => {
+            usage(&args[0]);
+            return Ok(())
+        }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::env;
+use nlm_tools::abendlog::{self, AbendLog};
+use nlm_tools::resolve::{self, Resolver};
+
+fn usage(prog: &str) {
+    println!("usage: {} [options] ABEND.LOG", prog);
+    println!();
+    println!("Prints a summary of the ABEND, followed by the log in which every code address");
+    println!("is annotated with its module!symbol+offset");
+    println!();
+    println!("options:");
+    println!("  --modules FILE       additional module map, as with resolve-address; the");
+    println!("                       modules listed in the log are always used");
+    println!("  --symbols FILE       symbol file with absolute addresses");
+    println!("  --symbols FILE@NAME  symbol file of module NAME, at the addresses nlm2elf uses");
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let mut modules_fname: Option<String> = None;
+    let mut symbol_files: Vec<String> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--modules" | "--symbols" => {
+                let value = match arg_iter.next() {
+                    Some(value) => value.to_string(),
+                    None => {
+                        println!("{} requires a file name", arg);
+                        return Ok(())
+                    }
+                };
+                if arg == "--modules" { modules_fname = Some(value); } else { symbol_files.push(value); }
+            },
+            _ => {
+                if arg.starts_with("--") {
+                    println!("unrecognized option '{}'", arg);
+                    return Ok(())
+                }
+                files.push(arg.to_string());
+            }
+        }
+    }
+    if files.len() != 1 {
+        usage(&args[0]);
+        return Ok(())
+    }
+
+    // ABEND.LOG is written in the codepage of the server
+    let log = AbendLog::parse(&String::from_utf8_lossy(&std::fs::read(&files[0])?));
+    let mut modules = log.modules.clone();
+    if let Some(fname) = &modules_fname {
+        modules.extend(resolve::parse_module_map(&std::fs::read_to_string(fname)?));
+    }
+    let mut resolver = Resolver::new(modules);
+    for spec in &symbol_files {
+        let (fname, module) = match spec.rsplit_once('@') {
+            Some((fname, module)) => (fname, Some(module)),
+            None => (spec.as_str(), None),
+        };
+        let symbols = resolve::parse_symbol_file(&std::fs::read_to_string(fname)?);
+        match module {
+            Some(module) => {
+                if let Err(e) = resolver.add_module_symbols(module, symbols) {
+                    println!("{}", e);
+                    return Ok(())
+                }
+            },
+            None => resolver.add_symbols(symbols),
+        }
+    }
+
+    println!("reason:          {}", log.reason.as_deref().unwrap_or("unknown"));
+    println!("running process: {}", log.process.as_deref().unwrap_or("unknown"));
+    if let Some(eip) = log.register("EIP") {
+        println!("failing address: {:08x} {}", eip, resolver.resolve(eip).unwrap_or("?".to_string()));
+    }
+    println!("{} modules, {} registers", resolver.modules.len(), log.registers.len());
+    println!();
+    for line in &log.lines {
+        println!("{}", abendlog::annotate(line, &resolver));
+    }
+    Ok(())
+}
@@ -0,0 +1,1408 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::io::{Cursor, Read};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::codepage::{self, Codepage};
+use crate::nds::{self, Dib};
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::{DirEntry, Volume};
+
+// Object and property flags; objects and properties lacking BF_DYNAMIC are
+// static and survive a server restart
+pub const BF_DYNAMIC: u8 = 0x01;
+pub const BF_SET: u8 = 0x02;
+
+pub const VALUE_SEGMENT_SIZE: usize = 128;
+pub const OBJECT_NAME_MAX: usize = 47;
+pub const PROPERTY_NAME_MAX: usize = 15;
+
+pub const OT_USER: u16 = 0x0001;
+pub const OT_GROUP: u16 = 0x0002;
+pub const OT_PRINT_QUEUE: u16 = 0x0003;
+pub const OT_FILE_SERVER: u16 = 0x0004;
+pub const OT_PRINT_SERVER: u16 = 0x0007;
+
+// Well-known object types
+pub const OBJECT_TYPES: [ (u16, &str); 18 ] = [
+    (0x0001, "user"),
+    (0x0002, "group"),
+    (0x0003, "print-queue"),
+    (0x0004, "file-server"),
+    (0x0005, "job-server"),
+    (0x0006, "gateway"),
+    (0x0007, "print-server"),
+    (0x0008, "archive-queue"),
+    (0x0009, "archive-server"),
+    (0x000a, "job-queue"),
+    (0x000b, "administration"),
+    (0x0026, "remote-bridge-server"),
+    (0x0027, "tcpip-gateway"),
+    (0x0047, "advertising-print-server"),
+    (0x0098, "access-server"),
+    (0x0107, "remote-console"),
+    (0x026b, "time-sync-server"),
+    (0x0278, "directory-server"),
+];
+
+pub fn object_type_name(objtype: u16) -> Option<&'static str> {
+    OBJECT_TYPES.iter().find(|(t, _)| *t == objtype).map(|(_, name)| *name)
+}
+
+pub fn parse_object_type(s: &str) -> Option<u16> {
+    if let Some((objtype, _)) = OBJECT_TYPES.iter().find(|(_, name)| name.eq_ignore_ascii_case(s)) {
+        return Some(*objtype);
+    }
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    s.parse::<u16>().ok()
+}
+
+// Classes and attributes presented by the NetWare 4.x bindery emulation. Some
+// attributes map to a different property depending on the object type
+const EMULATED_CLASSES: [ (&str, u16); 5 ] = [
+    ("User", OT_USER),
+    ("Group", OT_GROUP),
+    ("Print Queue", OT_PRINT_QUEUE),
+    ("NCP Server", OT_FILE_SERVER),
+    ("Print Server", OT_PRINT_SERVER),
+];
+const EMULATED_ATTRIBUTES: [ (Option<u16>, &str, &str, bool); 11 ] = [
+    (None, "Group Membership", "GROUPS_I'M_IN", true),
+    (None, "Member", "GROUP_MEMBERS", true),
+    (None, "Security Equals", "SECURITY_EQUALS", true),
+    (None, "Full Name", "IDENTIFICATION", false),
+    (None, "Network Address", "NET_ADDRESS", false),
+    (Some(OT_PRINT_QUEUE), "Queue Directory", "Q_DIRECTORY", false),
+    (Some(OT_PRINT_QUEUE), "Operator", "Q_OPERATORS", true),
+    (Some(OT_PRINT_QUEUE), "User", "Q_USERS", true),
+    (Some(OT_PRINT_QUEUE), "Server", "Q_SERVERS", true),
+    (Some(OT_PRINT_SERVER), "Operator", "PS_OPERATORS", true),
+    (Some(OT_PRINT_SERVER), "User", "PS_USERS", true),
+];
+// Logged in users may read, only the supervisor may write
+const EMULATED_SECURITY: u8 = 0x31;
+
+// Objects present on every server, which are not renamed by redact()
+const REDACT_KEEP: [ &str; 3 ] = [ "SUPERVISOR", "GUEST", "EVERYONE" ];
+
+// Item properties which hold only account settings and are kept by redact();
+// the values of all other items are cleared
+pub const REDACT_ALLOWED_PROPERTIES: [ &str; 3 ] = [ "LOGIN_CONTROL", "USER_DEFAULTS", "ACCOUNT_BALANCE" ];
+
+// Access levels used by the security byte; the low nibble contains the level
+// required to read and the high nibble the level required to write
+pub const SECURITY_LEVELS: [ &str; 5 ] = [ "anyone", "logged", "object", "supervisor", "netware" ];
+
+fn security_level_name(level: u8) -> String {
+    match SECURITY_LEVELS.get(level as usize) {
+        Some(name) => name.to_string(),
+        None => format!("{:x}", level)
+    }
+}
+
+pub fn security_string(security: u8) -> String {
+    format!("R:{} W:{}", security_level_name(security & 0xf), security_level_name(security >> 4))
+}
+
+// NetWare 3.x: net$obj.sys, net$prop.sys and net$val.sys, each containing
+// fixed-size records of a single kind
+pub const OBJECT_RECORD_SIZE: usize = 64;
+pub const PROPERTY_RECORD_SIZE: usize = 34;
+pub const VALUE_RECORD_SIZE: usize = 142;
+
+// NetWare 2.x: net$bind.sys contains both object and property records,
+// which are distinguished by a kind byte following the record id.
+// net$bval.sys contains the values, which lack the sequence field
+// This layout, including the record sizes and the kind byte at offset 4, is
+// not taken from documentation and has not been verified against the
+// bindery of a real 2.x server; a sample is needed to confirm it
+pub const BIND2_RECORD_SIZE: usize = 64;
+pub const BVAL2_RECORD_SIZE: usize = 140;
+const BIND2_KIND_OBJECT: u8 = 1;
+const BIND2_KIND_PROPERTY: u8 = 2;
+
+// Records are freed by zeroing them, so an ID of zero marks an unused record
+// which can be reused
+const UNUSED_ID: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    NetWare2,
+    NetWare3,
+    // Bindery emulation by NetWare 4.x, which is read from the NDS database
+    NetWare4,
+}
+
+impl Format {
+    // The format is determined by the number of files: 2.x uses two files,
+    // 3.x uses three and the 4.x NDS database four
+    pub fn detect(num_files: usize) -> Option<Format> {
+        match num_files {
+            2 => Some(Format::NetWare2),
+            3 => Some(Format::NetWare3),
+            4 => Some(Format::NetWare4),
+            _ => None
+        }
+    }
+
+    pub fn file_names(&self) -> &'static [ &'static str ] {
+        match self {
+            Format::NetWare2 => &[ "net$bind.sys", "net$bval.sys" ],
+            Format::NetWare3 => &[ "net$obj.sys", "net$prop.sys", "net$val.sys" ],
+            Format::NetWare4 => &nds::FILE_NAMES,
+        }
+    }
+
+    // Returns the file index containing the objects, properties and values
+    pub fn file_indices(&self) -> (usize, usize, usize) {
+        match self {
+            Format::NetWare2 => (0, 0, 1),
+            Format::NetWare3 => (0, 1, 2),
+            // Objects refer to entries and properties to their first value
+            Format::NetWare4 => (1, 2, 2),
+        }
+    }
+
+    pub fn record_sizes(&self) -> &'static [ usize ] {
+        match self {
+            Format::NetWare2 => &[ BIND2_RECORD_SIZE, BVAL2_RECORD_SIZE ],
+            Format::NetWare3 => &[ OBJECT_RECORD_SIZE, PROPERTY_RECORD_SIZE, VALUE_RECORD_SIZE ],
+            Format::NetWare4 => &[ nds::PARTITION_RECORD_SIZE, nds::ENTRY_RECORD_SIZE, nds::VALUE_RECORD_SIZE, nds::BLOCK_RECORD_SIZE ],
+        }
+    }
+
+    // Returns the offset of the name length byte within the record, and the
+    // size of the name field following it. NDS names are UCS-2 and have no
+    // field of their own
+    pub fn object_name_field(&self) -> Option<(usize, usize)> {
+        match self {
+            Format::NetWare2 => Some((9, 48)),
+            Format::NetWare3 => Some((6, 47)),
+            Format::NetWare4 => None,
+        }
+    }
+
+    pub fn property_name_field(&self) -> Option<(usize, usize)> {
+        match self {
+            Format::NetWare2 => Some((7, 15)),
+            Format::NetWare3 => Some((4, 15)),
+            Format::NetWare4 => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub offset: usize,
+    pub objid: u32,
+    pub objtype: u16,
+    pub name: String,
+    pub flags: u8,
+    pub security: u8,
+    pub property: u32,
+    pub unk1: u32,
+}
+
+impl Object {
+    pub fn is_dynamic(&self) -> bool {
+        (self.flags & BF_DYNAMIC) != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Property {
+    pub offset: usize,
+    pub propid: u32,
+    pub name: String,
+    pub flags: u8,
+    pub security: u8,
+    pub owner: u32,
+    pub next: u32,
+    pub value: u32,
+}
+
+impl Property {
+    pub fn is_dynamic(&self) -> bool {
+        (self.flags & BF_DYNAMIC) != 0
+    }
+
+    pub fn is_set(&self) -> bool {
+        (self.flags & BF_SET) != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Value {
+    pub offset: usize,
+    pub valueid: u32,
+    pub owner: u32,
+    pub next: u32,
+    pub sequence: u16,
+    pub data: [ u8; 128 ],
+}
+
+// Names are decoded leniently: the length is clamped to the field size (and
+// the zero padding), and control characters are replaced, so corrupt records
+// can still be shown
+fn decode_name(namelen: u8, nameval: &[u8], codepage: &Codepage) -> String {
+    let namelen = std::cmp::min(namelen as usize, nameval.len());
+    let namelen = nameval[0..namelen].iter().position(|b| *b == 0).unwrap_or(namelen);
+    codepage.decode(&nameval[0..namelen]).chars()
+        .map(|c| if c.is_control() { char::REPLACEMENT_CHARACTER } else { c })
+        .collect()
+}
+
+// Calls f with the offset and contents of every record read. Only complete
+// records are processed; trailing bytes are ignored (and reported by
+// dump-bindery --check)
+pub(crate) fn read_records<R: Read>(mut input: R, size: usize, mut f: impl FnMut(usize, &[u8]) -> Result<(), std::io::Error>) -> Result<(), std::io::Error> {
+    let mut record = vec![ 0u8; size ];
+    let mut offset = 0;
+    loop {
+        let mut filled = 0;
+        while filled < size {
+            match input.read(&mut record[filled..]) {
+                Ok(0) => return Ok(()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => { },
+                Err(e) => return Err(e),
+            }
+        }
+        f(offset, &record)?;
+        offset += size;
+    }
+}
+
+fn read_objects<R: Read>(input: R, codepage: &Codepage) -> Result<Vec<Object>, std::io::Error> {
+    let mut result: Vec<Object> = Vec::new();
+    read_records(input, OBJECT_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let objid = rdr.read_u32::<LittleEndian>()?;
+        let objtype = rdr.read_u16::<LittleEndian>()?;
+        let namelen = rdr.read_u8()?;
+        let mut nameval = [ 0u8; 47 ];
+        rdr.read_exact(&mut nameval)?;
+        let flags = rdr.read_u8()?;
+        let security = rdr.read_u8()?;
+        let property = rdr.read_u32::<LittleEndian>()?;
+        let unk1 = rdr.read_u32::<LittleEndian>()?;
+        if objid == UNUSED_ID {
+            return Ok(());
+        }
+
+        let name = decode_name(namelen, &nameval, codepage);
+
+        let object = Object{
+            offset,
+            objid,
+            objtype,
+            name,
+            flags,
+            security,
+            property,
+            unk1
+        };
+        result.push(object);
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn read_properties<R: Read>(input: R, codepage: &Codepage) -> Result<Vec<Property>, std::io::Error> {
+    let mut result: Vec<Property> = Vec::new();
+    read_records(input, PROPERTY_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let propid = rdr.read_u32::<LittleEndian>()?;
+        let namelen = rdr.read_u8()?;
+        let mut nameval = [ 0u8; 15 ];
+        rdr.read_exact(&mut nameval)?;
+        let flags = rdr.read_u8()?;
+        let security = rdr.read_u8()?;
+        let owner = rdr.read_u32::<LittleEndian>()?;
+        let next = rdr.read_u32::<LittleEndian>()?;
+        let value = rdr.read_u32::<LittleEndian>()?;
+        if propid == UNUSED_ID {
+            return Ok(());
+        }
+        let name = decode_name(namelen, &nameval, codepage);
+
+        let property = Property{
+            offset,
+            propid,
+            name,
+            flags,
+            security,
+            owner,
+            next,
+            value
+        };
+        result.push(property);
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn read_values<R: Read>(input: R) -> Result<Vec<Value>, std::io::Error> {
+    let mut result: Vec<Value> = Vec::new();
+    read_records(input, VALUE_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let valueid = rdr.read_u32::<LittleEndian>()?;
+        let owner = rdr.read_u32::<LittleEndian>()?;
+        let next = rdr.read_u32::<LittleEndian>()?;
+        let sequence = rdr.read_u16::<LittleEndian>()?;
+        if valueid == UNUSED_ID {
+            return Ok(());
+        }
+
+        let mut data = [ 0u8; 128 ];
+        rdr.read_exact(&mut data)?;
+
+        let value = Value{
+            offset,
+            valueid,
+            owner,
+            next,
+            sequence,
+            data
+        };
+        result.push(value);
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+// 2.x net$bind.sys record layout:
+//   00  u32  record id
+//   04  u8   kind (1 = object, 2 = property, anything else is unused)
+// object:
+//   05  u8   flags
+//   06  u8   security
+//   07  u16  object type
+//   09  u8   name length
+//   0a  48   name
+//   3a  u32  first property id
+// property:
+//   05  u8   flags
+//   06  u8   security
+//   07  u8   name length
+//   08  15   name
+//   17  u32  owner object id
+//   1b  u32  next property id
+//   1f  u32  first value id
+fn read_bind2<R: Read>(input: R, codepage: &Codepage) -> Result<(Vec<Object>, Vec<Property>), std::io::Error> {
+    let mut objects: Vec<Object> = Vec::new();
+    let mut properties: Vec<Property> = Vec::new();
+    read_records(input, BIND2_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let id = rdr.read_u32::<LittleEndian>()?;
+        let kind = rdr.read_u8()?;
+        let flags = rdr.read_u8()?;
+        let security = rdr.read_u8()?;
+        match kind {
+            BIND2_KIND_OBJECT => {
+                let objtype = rdr.read_u16::<LittleEndian>()?;
+                let namelen = rdr.read_u8()?;
+                let mut nameval = [ 0u8; 48 ];
+                rdr.read_exact(&mut nameval)?;
+                let property = rdr.read_u32::<LittleEndian>()?;
+                objects.push(Object{
+                    offset,
+                    objid: id,
+                    objtype,
+                    name: decode_name(namelen, &nameval, codepage),
+                    flags,
+                    security,
+                    property,
+                    unk1: 0
+                });
+            },
+            BIND2_KIND_PROPERTY => {
+                let namelen = rdr.read_u8()?;
+                let mut nameval = [ 0u8; 15 ];
+                rdr.read_exact(&mut nameval)?;
+                let owner = rdr.read_u32::<LittleEndian>()?;
+                let next = rdr.read_u32::<LittleEndian>()?;
+                let value = rdr.read_u32::<LittleEndian>()?;
+                properties.push(Property{
+                    offset,
+                    propid: id,
+                    name: decode_name(namelen, &nameval, codepage),
+                    flags,
+                    security,
+                    owner,
+                    next,
+                    value
+                });
+            },
+            _ => { }
+        }
+        Ok(())
+    })?;
+    Ok((objects, properties))
+}
+
+fn read_bval2<R: Read>(input: R) -> Result<Vec<Value>, std::io::Error> {
+    let mut result: Vec<Value> = Vec::new();
+    read_records(input, BVAL2_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let valueid = rdr.read_u32::<LittleEndian>()?;
+        let owner = rdr.read_u32::<LittleEndian>()?;
+        let next = rdr.read_u32::<LittleEndian>()?;
+        if valueid == UNUSED_ID {
+            return Ok(());
+        }
+        let mut data = [ 0u8; 128 ];
+        rdr.read_exact(&mut data)?;
+        result.push(Value{ offset, valueid, owner, next, sequence: 0, data });
+        Ok(())
+    })?;
+
+    // Segments are not numbered, so derive them from the chain order
+    let mut index: HashMap<u32, usize> = HashMap::new();
+    for (n, v) in result.iter().enumerate() {
+        index.entry(v.valueid).or_insert(n);
+    }
+    let referenced: HashSet<u32> = result.iter().map(|v| v.next).collect();
+    for i in 0..result.len() {
+        if referenced.contains(&result[i].valueid) { continue; }
+        let mut n = i;
+        let mut sequence = 0;
+        loop {
+            result[n].sequence = sequence;
+            sequence += 1;
+            let next = result[n].next;
+            if next == 0xffffffff || sequence as usize > result.len() { break; }
+            n = match index.get(&next) {
+                Some(n) => *n,
+                None => break
+            };
+        }
+    }
+    Ok(result)
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str, field_size: usize, codepage: &Codepage) {
+    let mut field = vec![ 0u8; field_size ];
+    let name = codepage.encode(name);
+    let name = &name[0..std::cmp::min(name.len(), field_size)];
+    field[0..name.len()].copy_from_slice(name);
+    out.push(name.len() as u8);
+    out.extend_from_slice(&field);
+}
+
+fn write_object(o: &Object, codepage: &Codepage) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(o.objid)?;
+    out.write_u16::<LittleEndian>(o.objtype)?;
+    write_name(&mut out, &o.name, 47, codepage);
+    out.write_u8(o.flags)?;
+    out.write_u8(o.security)?;
+    out.write_u32::<LittleEndian>(o.property)?;
+    out.write_u32::<LittleEndian>(o.unk1)?;
+    Ok(out)
+}
+
+fn write_property(p: &Property, codepage: &Codepage) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(p.propid)?;
+    write_name(&mut out, &p.name, 15, codepage);
+    out.write_u8(p.flags)?;
+    out.write_u8(p.security)?;
+    out.write_u32::<LittleEndian>(p.owner)?;
+    out.write_u32::<LittleEndian>(p.next)?;
+    out.write_u32::<LittleEndian>(p.value)?;
+    Ok(out)
+}
+
+fn write_value(v: &Value) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(v.valueid)?;
+    out.write_u32::<LittleEndian>(v.owner)?;
+    out.write_u32::<LittleEndian>(v.next)?;
+    out.write_u16::<LittleEndian>(v.sequence)?;
+    out.extend_from_slice(&v.data);
+    Ok(out)
+}
+
+fn write_bind2_object(o: &Object, codepage: &Codepage) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(o.objid)?;
+    out.write_u8(BIND2_KIND_OBJECT)?;
+    out.write_u8(o.flags)?;
+    out.write_u8(o.security)?;
+    out.write_u16::<LittleEndian>(o.objtype)?;
+    write_name(&mut out, &o.name, 48, codepage);
+    out.write_u32::<LittleEndian>(o.property)?;
+    out.resize(BIND2_RECORD_SIZE, 0);
+    Ok(out)
+}
+
+fn write_bind2_property(p: &Property, codepage: &Codepage) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(p.propid)?;
+    out.write_u8(BIND2_KIND_PROPERTY)?;
+    out.write_u8(p.flags)?;
+    out.write_u8(p.security)?;
+    write_name(&mut out, &p.name, 15, codepage);
+    out.write_u32::<LittleEndian>(p.owner)?;
+    out.write_u32::<LittleEndian>(p.next)?;
+    out.write_u32::<LittleEndian>(p.value)?;
+    out.resize(BIND2_RECORD_SIZE, 0);
+    Ok(out)
+}
+
+fn write_bval2(v: &Value) -> Result<Vec<u8>, std::io::Error> {
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u32::<LittleEndian>(v.valueid)?;
+    out.write_u32::<LittleEndian>(v.owner)?;
+    out.write_u32::<LittleEndian>(v.next)?;
+    out.extend_from_slice(&v.data);
+    Ok(out)
+}
+
+// Returns the lowest ID not in use, so ID's of deleted records are reused
+fn unused_id(ids: impl Iterator<Item = u32>) -> u32 {
+    let ids: HashSet<u32> = ids.collect();
+    (1..0xffffffff).find(|id| !ids.contains(id)).expect("out of ID's")
+}
+
+// Records are looked up by id using the indices, which map the id to the
+// position of the first record carrying it. They must be rebuilt using
+// reindex() when the records are modified directly. Every record keeps its
+// offset; changes made using the editing functions are tracked so that only
+// the modified records need to be written
+pub struct Bindery {
+    pub format: Format,
+    // Codepage of names and strings
+    pub codepage: &'static Codepage,
+    pub objects: Vec<Object>,
+    pub properties: Vec<Property>,
+    pub values: Vec<Value>,
+    object_index: HashMap<u32, usize>,
+    property_index: HashMap<u32, usize>,
+    value_index: HashMap<u32, usize>,
+    // File index and offset of the records modified or freed
+    changed: BTreeSet<(usize, usize)>,
+}
+
+impl Bindery {
+    pub fn new(format: Format, files: &[ &[u8] ]) -> Result<Self, std::io::Error> {
+        Self::from_readers(format, files.to_vec())
+    }
+
+    pub fn from_readers<R: Read>(format: Format, readers: Vec<R>) -> Result<Self, std::io::Error> {
+        Self::from_readers_with_codepage(format, readers, codepage::DEFAULT)
+    }
+
+    // Reads the bindery record by record from the given readers, which must
+    // be in the order of Format::file_names()
+    pub fn from_readers_with_codepage<R: Read>(format: Format, readers: Vec<R>, codepage: &'static Codepage) -> Result<Self, std::io::Error> {
+        let mut readers = readers.into_iter();
+        let mut next_reader = || readers.next().ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing bindery file"));
+        let (objects, properties, values) = match format {
+            Format::NetWare2 => {
+                let (objects, properties) = read_bind2(next_reader()?, codepage)?;
+                let values = read_bval2(next_reader()?)?;
+                (objects, properties, values)
+            },
+            Format::NetWare3 => {
+                let objects = read_objects(next_reader()?, codepage)?;
+                let properties = read_properties(next_reader()?, codepage)?;
+                let values = read_values(next_reader()?)?;
+                (objects, properties, values)
+            },
+            Format::NetWare4 => {
+                let dib = Dib::from_readers(readers.collect())?;
+                return Ok(Self::from_dib(&dib, &[], codepage));
+            }
+        };
+        Ok(Self::from_records(format, codepage, objects, properties, values))
+    }
+
+    // Bindery made up of the records given, such as when they were read from
+    // a live server
+    pub fn from_records(format: Format, codepage: &'static Codepage, objects: Vec<Object>, properties: Vec<Property>, values: Vec<Value>) -> Self {
+        let mut bindery = Self{ format, codepage, objects, properties, values, object_index: HashMap::new(), property_index: HashMap::new(), value_index: HashMap::new(), changed: BTreeSet::new() };
+        bindery.reindex();
+        bindery
+    }
+
+    pub fn from_files<P: AsRef<std::path::Path>>(format: Format, paths: &[P]) -> Result<Self, std::io::Error> {
+        let mut readers = Vec::new();
+        for path in paths {
+            readers.push(std::io::BufReader::new(std::fs::File::open(path)?));
+        }
+        Self::from_readers(format, readers)
+    }
+
+    // NetWare 4.x emulates the bindery using NDS: entries within the bindery
+    // context are presented as objects and their attributes as properties.
+    // Objects which only exist in the bindery (such as SUPERVISOR) are stored
+    // as Bindery Object entries, and properties without NDS counterpart as
+    // Bindery Property values. Object ID's are the entry ID's. When no
+    // context is given, all containers are used. Strings are converted to
+    // the codepage given. This relies on the unverified DIB layout of the nds
+    // module, and has not been checked against a real server either
+    pub fn from_dib(dib: &Dib, contexts: &[String], codepage: &'static Codepage) -> Self {
+        let mut objects: Vec<Object> = Vec::new();
+        let mut entries = Vec::new();
+        for e in dib.entries.iter().filter(|e| e.is_present()) {
+            let class = dib.class_name(e);
+            let (name, objtype) = if class == "Bindery Object" {
+                let (name, objtype) = e.name.split_once('+').unwrap_or((&e.name, ""));
+                let objtype = dib.entry_values(e).iter()
+                    .filter_map(|valueid| dib.find_value(*valueid))
+                    .find(|v| v.is_present() && dib.attribute_name(v) == "Bindery Type")
+                    .map(|v| dib.value_data(v))
+                    .filter(|data| data.len() >= 2)
+                    .map(|data| LittleEndian::read_u16(&data))
+                    .or(u16::from_str_radix(objtype, 16).ok());
+                (name.to_string(), objtype)
+            } else {
+                let in_context = contexts.is_empty() || dib.find_entry(e.parent).is_some_and(|p| contexts.iter().any(|c| c.eq_ignore_ascii_case(&dib.dn(p))));
+                let objtype = EMULATED_CLASSES.iter().find(|(c, _)| *c == class).map(|(_, t)| *t).filter(|_| in_context);
+                (e.name.to_ascii_uppercase(), objtype)
+            };
+            if let Some(objtype) = objtype {
+                objects.push(Object{ offset: e.offset, objid: e.id, objtype, name, flags: 0, security: EMULATED_SECURITY, property: 0xffffffff, unk1: 0 });
+                entries.push(e);
+            }
+        }
+        let emulated: HashSet<u32> = objects.iter().map(|o| o.objid).collect();
+
+        let mut properties: Vec<Property> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        for (o, e) in objects.iter_mut().zip(entries) {
+            // Values of the same attribute are combined into a single property
+            let mut items: Vec<(String, u8, u8, usize, Vec<u8>)> = Vec::new();
+            for v in dib.entry_values(e).iter().filter_map(|valueid| dib.find_value(*valueid)).filter(|v| v.is_present()) {
+                let attribute = dib.attribute_name(v);
+                let data = dib.value_data(v);
+                if attribute == "Bindery Property" {
+                    if data.len() >= 18 {
+                        let name = decode_name(data[0], &data[1..16], codepage);
+                        items.push((name, data[16], data[17], v.offset, data[18..].to_vec()));
+                    }
+                    continue;
+                }
+                let (name, set) = match EMULATED_ATTRIBUTES.iter().find(|(t, a, _, _)| *a == attribute && t.is_none_or(|t| t == o.objtype)) {
+                    Some((_, _, name, set)) => (name.to_string(), *set),
+                    None => continue,
+                };
+                let data = if set {
+                    let id = if data.len() == 4 { LittleEndian::read_u32(&data) } else { 0 };
+                    if !emulated.contains(&id) { continue; }
+                    id.to_be_bytes().to_vec()
+                } else if name == "NET_ADDRESS" {
+                    // NDS addresses are prefixed by their type; only IPX
+                    // addresses (type 0) exist in the bindery
+                    if data.len() < 16 || LittleEndian::read_u32(&data) != 0 { continue; }
+                    data[4..16].to_vec()
+                } else {
+                    let chars: Vec<u16> = data.chunks_exact(2).map(LittleEndian::read_u16).take_while(|c| *c != 0).collect();
+                    let mut result = codepage.encode(&String::from_utf16_lossy(&chars));
+                    result.push(0);
+                    result
+                };
+                match items.iter_mut().find(|(n, _, _, _, _)| *n == name) {
+                    Some((_, _, _, _, existing)) if set => existing.extend_from_slice(&data),
+                    Some(_) => { },
+                    None => items.push((name, if set { BF_SET } else { 0 }, EMULATED_SECURITY, v.offset, data)),
+                }
+            }
+
+            for (name, flags, security, offset, data) in items.into_iter().rev() {
+                let propid = properties.len() as u32 + 1;
+                let first_valueid = values.len() as u32 + 1;
+                let num_segments = std::cmp::max(data.len().div_ceil(VALUE_SEGMENT_SIZE), 1);
+                for n in 0..num_segments {
+                    let mut segment = [ 0u8; VALUE_SEGMENT_SIZE ];
+                    let chunk = &data[std::cmp::min(n * VALUE_SEGMENT_SIZE, data.len())..std::cmp::min((n + 1) * VALUE_SEGMENT_SIZE, data.len())];
+                    segment[0..chunk.len()].copy_from_slice(chunk);
+                    let next = if n + 1 < num_segments { first_valueid + n as u32 + 1 } else { 0xffffffff };
+                    values.push(Value{ offset, valueid: first_valueid + n as u32, owner: propid, next, sequence: n as u16, data: segment });
+                }
+                properties.push(Property{ offset, propid, name, flags, security, owner: o.objid, next: o.property, value: first_valueid });
+                o.property = propid;
+            }
+        }
+
+        Self::from_records(Format::NetWare4, codepage, objects, properties, values)
+    }
+
+    pub fn reindex(&mut self) {
+        self.object_index.clear();
+        for (n, o) in self.objects.iter().enumerate() {
+            self.object_index.entry(o.objid).or_insert(n);
+        }
+        self.property_index.clear();
+        for (n, p) in self.properties.iter().enumerate() {
+            self.property_index.entry(p.propid).or_insert(n);
+        }
+        self.value_index.clear();
+        for (n, v) in self.values.iter().enumerate() {
+            self.value_index.entry(v.valueid).or_insert(n);
+        }
+    }
+
+    pub fn find_object(&self, objid: u32) -> Option<&Object> {
+        self.object_index.get(&objid).map(|n| &self.objects[*n])
+    }
+
+    pub fn find_value(&self, valueid: u32) -> Option<&Value> {
+        self.value_index.get(&valueid).map(|n| &self.values[*n])
+    }
+
+    fn find_object_mut(&mut self, objid: u32) -> Option<&mut Object> {
+        self.object_index.get(&objid).map(|n| &mut self.objects[*n])
+    }
+
+    fn find_property_mut(&mut self, propid: u32) -> Option<&mut Property> {
+        self.property_index.get(&propid).map(|n| &mut self.properties[*n])
+    }
+
+    pub fn find_property(&self, o: &Object, name: &str) -> Option<&Property> {
+        self.object_properties(o).iter()
+            .filter_map(|propid| self.find_property_by_id(*propid))
+            .find(|p| p.name == name)
+    }
+
+    pub fn find_property_by_id(&self, propid: u32) -> Option<&Property> {
+        self.property_index.get(&propid).map(|n| &self.properties[*n])
+    }
+
+    pub fn find_object_by_name(&self, name: &str, objtype: Option<u16>) -> Option<&Object> {
+        self.objects.iter().find(|x| x.name.eq_ignore_ascii_case(name) && objtype.is_none_or(|t| t == x.objtype))
+    }
+
+    // Returns the property ID's of the object, in chain order
+    pub fn object_properties(&self, o: &Object) -> Vec<u32> {
+        let mut result: Vec<u32> = Vec::new();
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut propertyid = o.property;
+        while propertyid != 0xffffffff && seen.insert(propertyid) {
+            result.push(propertyid);
+            propertyid = match self.find_property_by_id(propertyid) {
+                Some(p) => p.next,
+                None => break,
+            };
+        }
+        result
+    }
+
+    // Returns the value ID's of the property, in chain order
+    pub fn property_values(&self, p: &Property) -> Vec<u32> {
+        let mut result: Vec<u32> = Vec::new();
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut valueid = p.value;
+        while valueid != 0xffffffff && seen.insert(valueid) {
+            result.push(valueid);
+            valueid = match self.find_value(valueid) {
+                Some(v) => v.next,
+                None => break,
+            };
+        }
+        result
+    }
+
+    // Returns the concatenated contents of all value segments of the property
+    pub fn property_data(&self, p: &Property) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        for valueid in self.property_values(p) {
+            if let Some(v) = self.find_value(valueid) {
+                result.extend_from_slice(&v.data);
+            }
+        }
+        result
+    }
+}
+
+impl Bindery {
+    // Serializes every record, as the file index (in the order of
+    // Format::file_names()), the offset within the file and the contents
+    fn records(&self) -> Result<Vec<(usize, usize, Vec<u8>)>, std::io::Error> {
+        let (obj_file, prop_file, val_file) = self.format.file_indices();
+        let mut result = Vec::new();
+        for o in &self.objects {
+            let record = match self.format {
+                Format::NetWare2 => write_bind2_object(o, self.codepage)?,
+                Format::NetWare3 => write_object(o, self.codepage)?,
+                Format::NetWare4 => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the NetWare 4.x bindery emulation cannot be written")),
+            };
+            result.push((obj_file, o.offset, record));
+        }
+        for p in &self.properties {
+            let record = match self.format {
+                Format::NetWare2 => write_bind2_property(p, self.codepage)?,
+                _ => write_property(p, self.codepage)?,
+            };
+            result.push((prop_file, p.offset, record));
+        }
+        for v in &self.values {
+            let record = match self.format {
+                Format::NetWare2 => write_bval2(v)?,
+                _ => write_value(v)?,
+            };
+            result.push((val_file, v.offset, record));
+        }
+        Ok(result)
+    }
+
+    // Serializes the bindery to the contents of the files, in the same order
+    // as taken by new(). Records are written at their offset; unused records
+    // are zeroed
+    pub fn to_files(&self) -> Result<Vec<Vec<u8>>, std::io::Error> {
+        let mut files: Vec<Vec<u8>> = vec![ Vec::new(); self.format.file_names().len() ];
+        for (file, offset, record) in self.records()? {
+            let data = &mut files[file];
+            if data.len() < offset + record.len() {
+                data.resize(offset + record.len(), 0);
+            }
+            data[offset..offset + record.len()].copy_from_slice(&record);
+        }
+        Ok(files)
+    }
+
+    // Returns the records modified since loading, as the file index, the
+    // offset and the contents. Records which were freed are returned zeroed.
+    // Writing these in place updates the files
+    pub fn changed_records(&self) -> Result<Vec<(usize, usize, Vec<u8>)>, std::io::Error> {
+        let sizes = self.format.record_sizes();
+        let mut records: HashMap<(usize, usize), Vec<u8>> = self.records()?.into_iter()
+            .filter(|(file, offset, _)| self.changed.contains(&(*file, *offset)))
+            .map(|(file, offset, record)| ((file, offset), record))
+            .collect();
+        Ok(self.changed.iter()
+            .map(|(file, offset)| (*file, *offset, records.remove(&(*file, *offset)).unwrap_or(vec![ 0u8; sizes[*file] ])))
+            .collect())
+    }
+
+    // Returns the lowest offset within the file which is not used by any
+    // record; this is either a freed record or the end of the file
+    fn allocate_record(&self, file: usize) -> usize {
+        let (obj_file, prop_file, val_file) = self.format.file_indices();
+        let mut used: HashSet<usize> = HashSet::new();
+        if file == obj_file { used.extend(self.objects.iter().map(|o| o.offset)); }
+        if file == prop_file { used.extend(self.properties.iter().map(|p| p.offset)); }
+        if file == val_file { used.extend(self.values.iter().map(|v| v.offset)); }
+        let size = self.format.record_sizes()[file];
+        (0..).map(|n| n * size).find(|offset| !used.contains(offset)).unwrap()
+    }
+
+    fn object_changed(&mut self, objid: u32) {
+        if let Some(o) = self.find_object(objid) {
+            self.changed.insert((self.format.file_indices().0, o.offset));
+        }
+    }
+
+    fn property_changed(&mut self, propid: u32) {
+        if let Some(p) = self.find_property_by_id(propid) {
+            self.changed.insert((self.format.file_indices().1, p.offset));
+        }
+    }
+
+    // Frees the value records of the property; the property itself is left
+    // as-is
+    fn free_values(&mut self, propid: u32) {
+        let p = self.find_property_by_id(propid).expect("property not found");
+        let values = self.property_values(p);
+        let val_file = self.format.file_indices().2;
+        for v in self.values.iter().filter(|v| values.contains(&v.valueid)) {
+            self.changed.insert((val_file, v.offset));
+        }
+        self.values.retain(|v| !values.contains(&v.valueid));
+        self.reindex();
+    }
+
+    // New objects get a random ID, as NetWare does, rather than the next
+    // one: trustee assignments and file owners on the volumes may still refer
+    // to the ID of a deleted object, which must not be handed out again. ID's
+    // in use or still referred to by sets are avoided
+    pub fn add_object(&mut self, name: &str, objtype: u16, security: u8) -> u32 {
+        let mut used: HashSet<u32> = self.objects.iter().map(|o| o.objid).collect();
+        for p in self.properties.iter().filter(|p| p.is_set()) {
+            used.extend(read_set(&self.property_data(p)));
+        }
+        let state = std::collections::hash_map::RandomState::new();
+        let objid = (0u64..).map(|n| state.hash_one(n) as u32)
+            .find(|id| *id != UNUSED_ID && *id != 0xffffffff && !used.contains(id))
+            .unwrap();
+        let offset = self.allocate_record(self.format.file_indices().0);
+        self.objects.push(Object{
+            offset,
+            objid,
+            objtype,
+            name: name.to_ascii_uppercase(),
+            flags: 0,
+            security,
+            property: 0xffffffff,
+            unk1: 0
+        });
+        self.reindex();
+        self.object_changed(objid);
+        objid
+    }
+
+    // Adds a property without any values to the end of the property chain of
+    // the object
+    pub fn add_property(&mut self, objid: u32, name: &str, flags: u8, security: u8) -> u32 {
+        let propid = unused_id(self.properties.iter().map(|p| p.propid));
+        let offset = self.allocate_record(self.format.file_indices().1);
+        let o = self.find_object(objid).expect("object not found");
+        let last = self.object_properties(o).last().copied();
+        self.properties.push(Property{
+            offset,
+            propid,
+            name: name.to_ascii_uppercase(),
+            flags,
+            security,
+            owner: objid,
+            next: 0xffffffff,
+            value: 0xffffffff
+        });
+        self.reindex();
+        match last {
+            Some(last) => {
+                self.find_property_mut(last).unwrap().next = propid;
+                self.property_changed(last);
+            },
+            None => {
+                self.find_object_mut(objid).unwrap().property = propid;
+                self.object_changed(objid);
+            }
+        }
+        self.property_changed(propid);
+        propid
+    }
+
+    // Replaces all values of the property with the data given, which is split
+    // into segments as needed. There is always at least one segment
+    pub fn set_property_data(&mut self, propid: u32, data: &[u8]) {
+        self.free_values(propid);
+
+        let val_file = self.format.file_indices().2;
+        let num_segments = std::cmp::max(data.len().div_ceil(VALUE_SEGMENT_SIZE), 1);
+        let mut valueids: Vec<u32> = Vec::new();
+        for _ in 0..num_segments {
+            valueids.push(unused_id(self.values.iter().map(|v| v.valueid).chain(valueids.iter().copied())));
+        }
+        for (n, valueid) in valueids.iter().enumerate() {
+            let mut segment = [ 0u8; VALUE_SEGMENT_SIZE ];
+            let chunk = &data[std::cmp::min(n * VALUE_SEGMENT_SIZE, data.len())..std::cmp::min((n + 1) * VALUE_SEGMENT_SIZE, data.len())];
+            segment[0..chunk.len()].copy_from_slice(chunk);
+            let offset = self.allocate_record(val_file);
+            self.values.push(Value{
+                offset,
+                valueid: *valueid,
+                owner: propid,
+                next: valueids.get(n + 1).copied().unwrap_or(0xffffffff),
+                sequence: n as u16,
+                data: segment
+            });
+            self.changed.insert((val_file, offset));
+        }
+        self.find_property_mut(propid).unwrap().value = valueids[0];
+        self.property_changed(propid);
+        self.reindex();
+    }
+
+    // Adds an object ID to a set property; returns false if it was already
+    // present
+    pub fn add_to_set(&mut self, propid: u32, objid: u32) -> bool {
+        let p = self.find_property_by_id(propid).expect("property not found");
+        let mut members = read_set(&self.property_data(p));
+        if members.contains(&objid) {
+            return false;
+        }
+        members.push(objid);
+        self.set_property_data(propid, &write_set(&members));
+        true
+    }
+
+    pub fn delete_property(&mut self, propid: u32) {
+        let p = match self.find_property_by_id(propid) {
+            Some(p) => p,
+            None => return,
+        };
+        let (owner, next, offset) = (p.owner, p.next, p.offset);
+        self.free_values(propid);
+
+        // Unlink the property from the chain
+        if self.find_object(owner).is_some_and(|o| o.property == propid) {
+            self.find_object_mut(owner).unwrap().property = next;
+            self.object_changed(owner);
+        }
+        if let Some(prev) = self.properties.iter().find(|x| x.next == propid).map(|x| x.propid) {
+            self.find_property_mut(prev).unwrap().next = next;
+            self.property_changed(prev);
+        }
+        self.properties.retain(|x| x.propid != propid);
+        self.changed.insert((self.format.file_indices().1, offset));
+        self.reindex();
+    }
+
+    // Removes an object ID from a set property; returns false if it was not
+    // present
+    pub fn remove_from_set(&mut self, propid: u32, objid: u32) -> bool {
+        let p = self.find_property_by_id(propid).expect("property not found");
+        let mut members = read_set(&self.property_data(p));
+        if !members.contains(&objid) {
+            return false;
+        }
+        members.retain(|x| *x != objid);
+        self.set_property_data(propid, &write_set(&members));
+        true
+    }
+
+    // Removes the object along with all its properties and values. Any set
+    // properties of other objects referring to it are updated as well; the
+    // number of such sets is returned
+    pub fn delete_object(&mut self, objid: u32) -> usize {
+        let o = match self.find_object(objid) {
+            Some(o) => o,
+            None => return 0,
+        };
+        let offset = o.offset;
+        for propid in self.object_properties(o) {
+            self.delete_property(propid);
+        }
+        self.objects.retain(|x| x.objid != objid);
+        self.changed.insert((self.format.file_indices().0, offset));
+        self.reindex();
+
+        let sets: Vec<u32> = self.properties.iter().filter(|p| p.is_set()).map(|p| p.propid).collect();
+        sets.into_iter().filter(|propid| self.remove_from_set(*propid, objid)).count()
+    }
+
+    // Pseudonymizes the bindery so it can be shared: objects are renamed to
+    // their type and a sequence number and IDENTIFICATION is replaced by the
+    // new name. Values of other items are cleared, except for those in
+    // REDACT_ALLOWED_PROPERTIES; the last bad login address is cleared from
+    // LOGIN_CONTROL. Sets only refer to objects and are kept. ID's, chains,
+    // flags and security are left intact, so the structure is preserved. The
+    // values are modified in place, without resizing
+    pub fn redact(&mut self) {
+        let mut counts: HashMap<u16, usize> = HashMap::new();
+        for o in self.objects.iter_mut() {
+            if REDACT_KEEP.contains(&o.name.as_str()) { continue; }
+            let count = counts.entry(o.objtype).or_insert(0);
+            *count += 1;
+            let type_name = object_type_name(o.objtype).map(|s| s.to_ascii_uppercase().replace('-', "_")).unwrap_or(format!("TYPE_{:X}", o.objtype));
+            o.name = format!("{}_{}", type_name, count);
+        }
+
+        let mut redactions: Vec<(u32, Vec<u8>)> = Vec::new();
+        for p in self.properties.iter().filter(|p| !p.is_set()) {
+            let data = self.property_data(p);
+            let new_data = match p.name.as_str() {
+                "IDENTIFICATION" => {
+                    let mut new_data = vec![ 0u8; data.len() ];
+                    if let Some(o) = self.find_object(p.owner) {
+                        let name = self.codepage.encode(&o.name);
+                        let len = name.len().min(data.len().saturating_sub(1));
+                        new_data[0..len].copy_from_slice(&name[0..len]);
+                    }
+                    new_data
+                },
+                "LOGIN_CONTROL" if data.len() >= LOGIN_CONTROL_BAD_LOGIN_ADDRESS + 12 => {
+                    let mut new_data = data.clone();
+                    new_data[LOGIN_CONTROL_BAD_LOGIN_ADDRESS..LOGIN_CONTROL_BAD_LOGIN_ADDRESS + 12].fill(0);
+                    new_data
+                },
+                name if REDACT_ALLOWED_PROPERTIES.contains(&name) => continue,
+                _ => vec![ 0u8; data.len() ]
+            };
+            redactions.push((p.propid, new_data));
+        }
+        for (propid, data) in redactions {
+            let p = self.find_property_by_id(propid).unwrap();
+            for (valueid, chunk) in self.property_values(p).into_iter().zip(data.chunks(VALUE_SEGMENT_SIZE)) {
+                if let Some(n) = self.value_index.get(&valueid) {
+                    self.values[*n].data.copy_from_slice(chunk);
+                }
+            }
+        }
+    }
+}
+
+pub fn write_set(members: &[u32]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    for objid in members {
+        result.extend_from_slice(&objid.to_be_bytes());
+    }
+    result
+}
+
+// Looks up the bindery files within the directory, regardless of case
+pub fn find_dir_files(dir: &std::path::Path) -> Option<(Format, Vec<std::path::PathBuf>)> {
+    let entries: Vec<std::path::PathBuf> = std::fs::read_dir(dir).ok()?.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    for format in [ Format::NetWare3, Format::NetWare2, Format::NetWare4 ] {
+        let files: Option<Vec<std::path::PathBuf>> = format.file_names().iter().map(|name| {
+            entries.iter().find(|p| p.file_name().is_some_and(|f| f.to_string_lossy().eq_ignore_ascii_case(name))).cloned()
+        }).collect();
+        if let Some(files) = files {
+            return Some((format, files));
+        }
+    }
+    None
+}
+
+// The bindery files live in SYS:SYSTEM; 3.x files are preferred if both
+// formats are present. NetWare 4.x has no bindery files, in which case the
+// NDS database is used
+pub fn find_volume_files(volume: &Volume) -> Result<(Format, Vec<DirEntry>), NWFSError> {
+    for format in [ Format::NetWare3, Format::NetWare2 ] {
+        let entries: Option<Vec<_>> = format.file_names().iter()
+            .map(|name| volume.lookup(&format!("SYSTEM/{}", name)).cloned())
+            .collect();
+        if let Some(entries) = entries {
+            return Ok((format, entries));
+        }
+    }
+    match nds::find_volume_files(volume) {
+        Ok(entries) => Ok((Format::NetWare4, entries)),
+        Err(NWFSError::FileNotFound(_)) => Err(NWFSError::FileNotFound("SYS:SYSTEM/NET$OBJ.SYS".to_string())),
+        Err(e) => Err(e)
+    }
+}
+
+pub fn read_volume_files(volume: &mut Volume) -> Result<(Format, Vec<Vec<u8>>), NWFSError> {
+    let (format, entries) = find_volume_files(volume)?;
+    let mut file_data = Vec::new();
+    for entry in &entries {
+        file_data.push(volume.read_file(entry)?);
+    }
+    Ok((format, file_data))
+}
+
+// Set values are lists of object ID's, stored in hi-lo order. Unused
+// slots are zero
+pub fn read_set(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4).map(BigEndian::read_u32).filter(|objid| *objid != 0).collect()
+}
+
+// String properties, such as IDENTIFICATION, are zero-terminated
+pub fn read_string_property(data: &[u8], codepage: &Codepage) -> String {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    codepage.decode(&data[0..len])
+}
+
+// Offset of bad_login_address within LOGIN_CONTROL
+const LOGIN_CONTROL_BAD_LOGIN_ADDRESS: usize = 74;
+
+// Contents of the LOGIN_CONTROL property of user objects
+#[derive(Debug)]
+pub struct LoginControl {
+    pub account_expires: [ u8; 3 ],
+    pub account_disabled: bool,
+    pub password_expires: [ u8; 3 ],
+    pub grace_logins_remaining: u8,
+    pub password_expire_interval: u16,
+    pub grace_login_reset: u8,
+    pub minimum_password_length: u8,
+    pub max_connections: u16,
+    pub login_time_bitmap: [ u8; 42 ],
+    pub last_login: [ u8; 6 ],
+    pub restriction_flags: u8,
+    pub max_disk_blocks: u32,
+    pub bad_login_count: u16,
+    pub next_reset_time: u32,
+    pub bad_login_address: [ u8; 12 ],
+}
+
+impl LoginControl {
+    pub fn from(data: &[u8]) -> Result<Self, std::io::Error> {
+        let mut rdr = Cursor::new(data);
+        let mut account_expires = [ 0u8; 3 ];
+        rdr.read_exact(&mut account_expires)?;
+        let account_disabled = rdr.read_u8()? != 0;
+        let mut password_expires = [ 0u8; 3 ];
+        rdr.read_exact(&mut password_expires)?;
+        let grace_logins_remaining = rdr.read_u8()?;
+        let password_expire_interval = rdr.read_u16::<BigEndian>()?;
+        let grace_login_reset = rdr.read_u8()?;
+        let minimum_password_length = rdr.read_u8()?;
+        let max_connections = rdr.read_u16::<BigEndian>()?;
+        let mut login_time_bitmap = [ 0u8; 42 ];
+        rdr.read_exact(&mut login_time_bitmap)?;
+        let mut last_login = [ 0u8; 6 ];
+        rdr.read_exact(&mut last_login)?;
+        let restriction_flags = rdr.read_u8()?;
+        let _unused = rdr.read_u8()?;
+        let max_disk_blocks = rdr.read_u32::<BigEndian>()?;
+        let bad_login_count = rdr.read_u16::<BigEndian>()?;
+        let next_reset_time = rdr.read_u32::<BigEndian>()?;
+        let mut bad_login_address = [ 0u8; 12 ];
+        rdr.read_exact(&mut bad_login_address)?;
+        Ok(Self{
+            account_expires,
+            account_disabled,
+            password_expires,
+            grace_logins_remaining,
+            password_expire_interval,
+            grace_login_reset,
+            minimum_password_length,
+            max_connections,
+            login_time_bitmap,
+            last_login,
+            restriction_flags,
+            max_disk_blocks,
+            bad_login_count,
+            next_reset_time,
+            bad_login_address
+        })
+    }
+}
+
+pub fn format_date(date: &[u8]) -> String {
+    if date.iter().all(|b| *b == 0) {
+        return "never".to_string();
+    }
+    let mut result = format!("{:04}-{:02}-{:02}", 1900 + date[0] as u32, date[1], date[2]);
+    if date.len() >= 6 {
+        result += &format!(" {:02}:{:02}:{:02}", date[3], date[4], date[5]);
+    }
+    result
+}
+
+// Network addresses are stored as network (4 bytes), node (6 bytes) and
+// socket (2 bytes)
+pub fn format_net_address(data: &[u8]) -> String {
+    let hex = |d: &[u8]| d.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    format!("{}:{}:{}", hex(&data[0..4]), hex(&data[4..10]), hex(&data[10..12]))
+}
+
+// Returns a human-readable representation of the property value, one line
+// per item. Sets are resolved to object names and well-known properties are
+// decoded; anything else is shown as string if possible, or as hex otherwise
+pub fn decode_property(bindery: &Bindery, p: &Property) -> Vec<String> {
+    let data = bindery.property_data(p);
+    if p.is_set() {
+        return read_set(&data).iter().map(|objid| match bindery.find_object(*objid) {
+            Some(o) => format!("{} ({})", o.name, object_type_name(o.objtype).map(|s| s.to_string()).unwrap_or(format!("type {:x}", o.objtype))),
+            None => format!("<unknown object {:x}>", objid)
+        }).collect();
+    }
+    match p.name.as_str() {
+        "PASSWORD" if data.len() >= 16 => {
+            return vec![ format!("hash {}", data[0..16].iter().map(|b| format!("{:02x}", b)).collect::<String>()) ];
+        },
+        "NET_ADDRESS" if data.len() >= 12 => {
+            return vec![ format_net_address(&data) ];
+        },
+        "LOGIN_CONTROL" => {
+            if let Ok(lc) = LoginControl::from(&data) {
+                return vec![
+                    format!("account disabled: {}", if lc.account_disabled { "yes" } else { "no" }),
+                    format!("account expires: {}", format_date(&lc.account_expires)),
+                    format!("password expires: {}", format_date(&lc.password_expires)),
+                    format!("password expire interval: {} days", lc.password_expire_interval),
+                    format!("grace logins remaining: {} of {}", lc.grace_logins_remaining, lc.grace_login_reset),
+                    format!("minimum password length: {}", lc.minimum_password_length),
+                    format!("maximum connections: {}", lc.max_connections),
+                    format!("last login: {}", format_date(&lc.last_login)),
+                    format!("bad login count: {}", lc.bad_login_count),
+                ];
+            }
+        },
+        _ => { }
+    }
+
+    let used = data.iter().rposition(|b| *b != 0).map(|n| n + 1).unwrap_or(0);
+    if used == 0 {
+        return vec![ "<empty>".to_string() ];
+    }
+    let text_len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    if text_len == used && data[0..used].iter().all(|b| *b >= 0x20 && *b != 0x7f) {
+        return vec![ format!("'{}'", read_string_property(&data, bindery.codepage)) ];
+    }
+    vec![ data[0..used].iter().map(|b| format!("{:02x}", b)).collect() ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(bindery: &Bindery, files: &[ Vec<u8> ]) -> Bindery {
+        let slices: Vec<&[u8]> = files.iter().map(|f| f.as_slice()).collect();
+        Bindery::new(bindery.format, &slices).unwrap()
+    }
+
+    // Applies the changed records to the files, as bindery-edit does
+    fn apply_changes(bindery: &Bindery, files: &mut [ Vec<u8> ]) {
+        for (file, offset, record) in bindery.changed_records().unwrap() {
+            let data = &mut files[file];
+            if data.len() < offset + record.len() {
+                data.resize(offset + record.len(), 0);
+            }
+            data[offset..offset + record.len()].copy_from_slice(&record);
+        }
+    }
+
+    #[test]
+    fn delete_then_add() {
+        for format in [ Format::NetWare2, Format::NetWare3 ] {
+            let mut bindery = Bindery::from_records(format, codepage::DEFAULT, Vec::new(), Vec::new(), Vec::new());
+            let group = bindery.add_object("STAFF", OT_GROUP, 0x31);
+            let members = bindery.add_property(group, "GROUP_MEMBERS", BF_SET, 0x31);
+            let alice = bindery.add_object("ALICE", OT_USER, 0x31);
+            let bob = bindery.add_object("BOB", OT_USER, 0x31);
+            bindery.add_to_set(members, alice);
+            bindery.add_to_set(members, bob);
+            let mut files = bindery.to_files().unwrap();
+
+            let mut bindery = reload(&bindery, &files);
+            assert_eq!(bindery.delete_object(bob), 1);
+            apply_changes(&bindery, &mut files);
+            let sizes: Vec<usize> = files.iter().map(|f| f.len()).collect();
+
+            // The new object reuses the record of the deleted one, but not
+            // its ID
+            let mut bindery = reload(&bindery, &files);
+            let carol = bindery.add_object("CAROL", OT_USER, 0x31);
+            assert_ne!(carol, bob);
+            bindery.add_to_set(members, carol);
+            apply_changes(&bindery, &mut files);
+            assert_eq!(files.iter().map(|f| f.len()).collect::<Vec<_>>(), sizes);
+            assert_eq!(files, bindery.to_files().unwrap());
+
+            let bindery = reload(&bindery, &files);
+            let names: Vec<&str> = bindery.objects.iter().map(|o| o.name.as_str()).collect();
+            assert_eq!(names, [ "STAFF", "ALICE", "CAROL" ]);
+            assert!(bindery.find_object(bob).is_none());
+            let p = bindery.find_property_by_id(members).unwrap();
+            assert_eq!(read_set(&bindery.property_data(p)), [ alice, carol ]);
+        }
+    }
+
+    #[test]
+    fn redact_items() {
+        let mut bindery = Bindery::from_records(Format::NetWare3, codepage::DEFAULT, Vec::new(), Vec::new(), Vec::new());
+        let everyone = bindery.add_object("EVERYONE", OT_GROUP, 0x31);
+        let members = bindery.add_property(everyone, "GROUP_MEMBERS", BF_SET, 0x31);
+        let alice = bindery.add_object("ALICE", OT_USER, 0x31);
+        bindery.add_to_set(members, alice);
+        let mut props = Vec::new();
+        for (name, value) in [ ("HOME_DIRECTORY", &b"SYS:HOME/ALICE\0"[..]), ("EMAIL", b"alice@example.com\0"), ("IDENTIFICATION", b"Alice Smith\0"), ("USER_DEFAULTS", b"\x01\x02") ] {
+            let propid = bindery.add_property(alice, name, 0, 0x31);
+            bindery.set_property_data(propid, value);
+            props.push(propid);
+        }
+        bindery.redact();
+
+        let data = |propid: u32| bindery.property_data(bindery.find_property_by_id(propid).unwrap());
+        assert!(data(props[0]).iter().all(|b| *b == 0));
+        assert!(data(props[1]).iter().all(|b| *b == 0));
+        assert!(data(props[2]).starts_with(b"USER_1\0"));
+        assert!(data(props[3]).starts_with(b"\x01\x02"));
+        assert_eq!(read_set(&data(members)), [ alice ]);
+    }
+}
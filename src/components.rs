@@ -0,0 +1,213 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Component boundaries within SERVER.NLM, which is linked from the object
+// files of many subsystems. These boundaries are not recorded anywhere, so
+// they are estimated: the code is split into blocks at the padding the linker
+// inserted between object files, and every block is attributed to the
+// component suggested by the names of the symbols within it and by the
+// strings its code refers to. Adjacent blocks of the same component are
+// merged into a single region.
+use std::collections::HashMap;
+use std::io::Write;
+use byteorder::{ByteOrder, LittleEndian};
+use serde_json::{json, Value};
+
+use crate::symbols::{self, Symbol, SymbolFormat};
+
+// Keywords are matched case-insensitively against symbol names and strings;
+// the first component with a matching keyword is used
+pub const COMPONENTS: [ (&str, &[ &str ]); 12 ] = [
+    ("lan", &[ "lsl", "lan", "ipx", "spx", "router", "packet", "ecb", "sap", "rip", "board" ]),
+    ("ncp", &[ "ncp" ]),
+    ("tts", &[ "tts", "transaction" ]),
+    ("bindery", &[ "bindery", "property", "object" ]),
+    ("filesystem", &[ "volume", "directory", "dir", "file", "fat", "turbo", "hash", "salvage", "trustee" ]),
+    ("disk", &[ "disk", "device", "mirror", "partition", "hotfix", "driver" ]),
+    ("memory", &[ "alloc", "free", "memory", "cache", "page", "pool" ]),
+    ("loader", &[ "load", "import", "export", "module", "nlm" ]),
+    ("process", &[ "process", "semaphore", "schedule", "thread", "sleep", "wakeup", "event" ]),
+    ("console", &[ "screen", "console", "keyboard", "display", "command" ]),
+    ("debugger", &[ "debug", "break", "abend", "trace" ]),
+    ("clock", &[ "time", "date", "clock", "timer" ]),
+];
+
+// Runs of padding bytes shorter than this are not considered to separate
+// object files
+pub const MIN_PADDING: usize = 4;
+
+const MIN_STRING: usize = 6;
+
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name: String,
+    pub address: u32,
+    pub length: u32,
+    // Number of symbols and string references attributed to the component
+    pub symbols: usize,
+    pub strings: usize,
+}
+
+pub fn classify(text: &str) -> Option<&'static str> {
+    let text = text.to_ascii_lowercase();
+    COMPONENTS.iter().find(|(_, keywords)| keywords.iter().any(|k| text.contains(k))).map(|(name, _)| *name)
+}
+
+// Splits the code into blocks at padding that ends at a paragraph boundary,
+// as inserted between object files; returns the start offsets
+fn find_blocks(code: &[u8]) -> Vec<usize> {
+    let mut result = vec![ 0 ];
+    let mut run_start: Option<usize> = None;
+    for (n, b) in code.iter().enumerate() {
+        if matches!(b, 0x00 | 0x90 | 0xcc) {
+            if run_start.is_none() || code[run_start.unwrap()] != *b {
+                run_start = Some(n);
+            }
+            continue;
+        }
+        if let Some(start) = run_start.take() {
+            if n - start >= MIN_PADDING && n % 16 == 0 && n > 0 {
+                result.push(n);
+            }
+        }
+    }
+    result
+}
+
+// Locates printable strings in data and classifies them; returns their
+// address and component
+fn find_strings(data: &[u8], data_address: u32) -> Vec<(u32, &'static str)> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    for (n, b) in data.iter().enumerate() {
+        if *b == b' ' || b.is_ascii_graphic() { continue; }
+        if *b == 0 && n - start >= MIN_STRING {
+            if let Some(component) = classify(&String::from_utf8_lossy(&data[start..n])) {
+                result.push((data_address + start as u32, component));
+            }
+        }
+        start = n + 1;
+    }
+    result
+}
+
+// Proposes the component regions of the code
+pub fn find_components(code: &[u8], code_address: u32, data: &[u8], data_address: u32, symbols: &[Symbol]) -> Vec<Component> {
+    let blocks = find_blocks(code);
+    let block_of = |addr: u32| -> Option<usize> {
+        let offset = addr.checked_sub(code_address)? as usize;
+        if offset >= code.len() { return None; }
+        Some(blocks.partition_point(|start| *start <= offset) - 1)
+    };
+
+    // Votes per block and component, as (symbols, strings)
+    let mut votes: Vec<Vec<(&str, usize, usize)>> = vec![ Vec::new(); blocks.len() ];
+    let mut vote = |block: usize, component: &'static str, is_symbol: bool| {
+        let entry = match votes[block].iter().position(|(c, _, _)| *c == component) {
+            Some(n) => &mut votes[block][n],
+            None => {
+                votes[block].push((component, 0, 0));
+                votes[block].last_mut().unwrap()
+            }
+        };
+        if is_symbol { entry.1 += 1; } else { entry.2 += 1; }
+    };
+    for sym in symbols {
+        if let (Some(block), Some(component)) = (block_of(sym.address), classify(&sym.name)) {
+            vote(block, component, true);
+        }
+    }
+    let strings = find_strings(data, data_address);
+    for offset in 0..code.len().saturating_sub(3) {
+        let value = LittleEndian::read_u32(&code[offset..]);
+        if let Ok(n) = strings.binary_search_by_key(&value, |(addr, _)| *addr) {
+            vote(block_of(code_address + offset as u32).unwrap(), strings[n].1, false);
+        }
+    }
+
+    // Blocks without votes are taken to be part of the component before them
+    let mut result: Vec<Component> = Vec::new();
+    for (n, start) in blocks.iter().enumerate() {
+        let end = blocks.get(n + 1).copied().unwrap_or(code.len());
+        let best = votes[n].iter().max_by_key(|(_, symbols, strings)| symbols * 2 + strings);
+        let name = match (best, result.last()) {
+            (Some((name, _, _)), _) => name.to_string(),
+            (None, Some(last)) => last.name.clone(),
+            (None, None) => "unknown".to_string(),
+        };
+        let (symbols, strings) = votes[n].iter().find(|(c, _, _)| *c == name).map(|(_, s, t)| (*s, *t)).unwrap_or((0, 0));
+        match result.last_mut() {
+            Some(last) if last.name == name => {
+                last.length += (end - start) as u32;
+                last.symbols += symbols;
+                last.strings += strings;
+            },
+            _ => result.push(Component{ name, address: code_address + *start as u32, length: (end - start) as u32, symbols, strings }),
+        }
+    }
+
+    // Components occurring more than once are numbered
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for c in &result {
+        *counts.entry(c.name.clone()).or_insert(0) += 1;
+    }
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for c in result.iter_mut() {
+        if counts[&c.name] > 1 {
+            let index = seen.entry(c.name.clone()).or_insert(0);
+            *index += 1;
+            c.name = format!("{}.{}", c.name, index);
+        }
+    }
+    result
+}
+
+pub fn write_components<W: Write>(w: &mut W, components: &[Component], format: SymbolFormat) -> Result<(), std::io::Error> {
+    match format {
+        SymbolFormat::Text => {
+            for c in components {
+                writeln!(w, "0x{:08x} 0x{:08x} {} ({} symbols, {} strings)", c.address, c.address + c.length, c.name, c.symbols, c.strings)?;
+            }
+        },
+        SymbolFormat::Nm => {
+            for c in components {
+                writeln!(w, "{:08x} t component.{}", c.address, c.name)?;
+            }
+        },
+        SymbolFormat::Map => {
+            // Regions within the code segment, which is segment 1 as with
+            // write_symbols()
+            let base = components.first().map(|c| c.address).unwrap_or(0);
+            writeln!(w)?;
+            writeln!(w, " Start         Length     Name                   Class")?;
+            for c in components {
+                writeln!(w, " {:04X}:{:08X} {:08X}H {:<23} CODE", 1, c.address - base, c.length, c.name)?;
+            }
+        },
+        SymbolFormat::Ghidra => {
+            for c in components {
+                writeln!(w, "component.{} 0x{:08x} l", c.name, c.address)?;
+            }
+        },
+        SymbolFormat::GhidraScript => {
+            let names: Vec<String> = components.iter().map(|c| format!("component.{}", c.name)).collect();
+            let labels: Vec<(&str, u32, bool)> = components.iter().zip(&names).map(|(c, n)| (n.as_str(), c.address, false)).collect();
+            symbols::write_ghidra_script(w, &labels)?;
+        },
+        SymbolFormat::Json => {
+            let list: Vec<Value> = components.iter().map(|c| json!({
+                "name": c.name,
+                "address": c.address,
+                "length": c.length,
+                "symbols": c.symbols,
+                "strings": c.strings,
+            })).collect();
+            writeln!(w, "{}", serde_json::to_string_pretty(&list).unwrap())?;
+        },
+    }
+    Ok(())
+}
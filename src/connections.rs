@@ -0,0 +1,285 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Connection and open file tables of a running server. The connection table
+// is an array with an entry per connection slot, which is either empty or
+// holds the object logged in along with its network address. Open files are
+// kept in separately allocated entries, which refer to the connection by
+// number. The layout of both differs per NetWare version, and as their
+// location differs per build as well, they are located by scanning memory.
+//
+// None of the layouts below comes from documentation or has been confirmed
+// against a memory dump of a real server; they are marked as unverified, and
+// are only used when asked for explicitly. Layouts confirmed using a dump
+// should be marked as verified.
+use std::collections::HashSet;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::symbols::Image;
+use crate::versions;
+
+pub struct ConnectionLayout {
+    pub description: &'static str,
+    pub versions: &'static [ &'static str ],
+    // Whether the layout was confirmed against a memory dump of the version
+    pub verified: bool,
+    // Connection table entry
+    pub entry_size: u32,
+    pub object_id: u32,
+    pub object_type: u32,
+    pub address: u32,
+    pub login_time: u32,
+    // Open file entry
+    pub file_connection: u32,
+    pub file_task: u32,
+    pub file_access: u32,
+    pub file_volume: u32,
+    pub file_entry: u32,
+    pub file_name: u32,
+}
+
+pub const CONNECTION_LAYOUTS: [ ConnectionLayout; 2 ] = [
+    ConnectionLayout{
+        description: "NetWare 3.x",
+        versions: &[ "3.10", "3.11", "3.12", "3.20" ],
+        verified: false,
+        entry_size: 0x40,
+        object_id: 0x00,
+        object_type: 0x04,
+        address: 0x06,
+        login_time: 0x12,
+        file_connection: 0x04,
+        file_task: 0x06,
+        file_access: 0x07,
+        file_volume: 0x08,
+        file_entry: 0x0c,
+        file_name: 0x10,
+    },
+    ConnectionLayout{
+        description: "NetWare 4.x",
+        versions: &[ "4.00", "4.01", "4.02", "4.10", "4.11", "4.20" ],
+        verified: false,
+        entry_size: 0x60,
+        object_id: 0x00,
+        object_type: 0x04,
+        address: 0x08,
+        login_time: 0x18,
+        file_connection: 0x04,
+        file_task: 0x08,
+        file_access: 0x09,
+        file_volume: 0x0a,
+        file_entry: 0x0c,
+        file_name: 0x14,
+    },
+];
+
+// Connection tables with fewer logged in connections are not reported
+pub const MIN_CONNECTIONS: usize = 1;
+
+const FILE_NAME_MAX: usize = 12;
+
+pub fn find_layout(version: &str) -> Option<&'static ConnectionLayout> {
+    let version = version.trim_start_matches(['v', 'V']);
+    CONNECTION_LAYOUTS.iter().find(|l| l.versions.contains(&version))
+}
+
+// Selects the layout of the version given by the user, or the one detected
+// from data
+pub fn select_layout(version: Option<&str>, data: &[u8]) -> Result<&'static ConnectionLayout, String> {
+    let version = match version {
+        Some(version) => version.to_string(),
+        None => versions::detect(data).ok_or("cannot detect the NetWare version, use --netware-version to specify it")?,
+    };
+    find_layout(&version).ok_or_else(|| {
+        let known: Vec<&str> = CONNECTION_LAYOUTS.iter().flat_map(|l| l.versions.iter().copied()).collect();
+        format!("no connection table layout for NetWare version '{}', known versions are: {}", version, known.join(", "))
+    })
+}
+
+// NetWare date and time: year since 1900, month, day, hour, minute, second
+// and day of the week
+#[derive(Debug, Clone, Copy)]
+pub struct LoginTime {
+    pub raw: [ u8; 7 ],
+}
+
+impl LoginTime {
+    fn from(raw: &[u8]) -> Option<Self> {
+        let t = Self{ raw: raw.try_into().ok()? };
+        let [ year, month, day, hour, minute, second, weekday ] = t.raw;
+        if year < 80 || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 || weekday > 6 {
+            return None;
+        }
+        Some(t)
+    }
+
+    pub fn to_text(&self) -> String {
+        let [ year, month, day, hour, minute, second, _ ] = self.raw;
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", 1900 + year as u32, month, day, hour, minute, second)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub number: usize,
+    pub object_id: u32,
+    pub object_type: u16,
+    pub network: u32,
+    pub node: [ u8; 6 ],
+    pub socket: u16,
+    pub login_time: LoginTime,
+}
+
+impl Connection {
+    pub fn address_text(&self) -> String {
+        let node: Vec<String> = self.node.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{:08x}:{}:{:04x}", self.network, node.join(""), self.socket)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionTable {
+    pub address: u32,
+    pub slots: usize,
+    pub connections: Vec<Connection>,
+}
+
+enum Slot {
+    Empty,
+    Used(Connection),
+}
+
+// Object IDs and network addresses are stored in high-low order, as they are
+// on the wire
+fn read_slot(image: &Image, layout: &ConnectionLayout, address: u32, number: usize) -> Option<Slot> {
+    let entry = image.slice(address, layout.entry_size as usize)?;
+    if entry.iter().all(|b| *b == 0) {
+        return Some(Slot::Empty);
+    }
+    let object_id = BigEndian::read_u32(&entry[layout.object_id as usize..]);
+    let object_type = BigEndian::read_u16(&entry[layout.object_type as usize..]);
+    let address = &entry[layout.address as usize..layout.address as usize + 12];
+    let node: [ u8; 6 ] = address[4..10].try_into().unwrap();
+    if object_id == 0 || object_id == 0xffffffff || object_type == 0 || node.iter().all(|b| *b == 0) {
+        return None;
+    }
+    let login_time = LoginTime::from(&entry[layout.login_time as usize..layout.login_time as usize + 7])?;
+    Some(Slot::Used(Connection{
+        number, object_id, object_type, node, login_time,
+        network: BigEndian::read_u32(&address[0..4]),
+        socket: BigEndian::read_u16(&address[10..12]),
+    }))
+}
+
+// Reads the table at address; slots are numbered from 1 and the table ends at
+// the first entry that is neither empty nor valid
+pub fn read_connection_table(image: &Image, layout: &ConnectionLayout, address: u32) -> ConnectionTable {
+    let mut connections: Vec<Connection> = Vec::new();
+    let mut slots = 0;
+    while let Some(slot) = address.checked_add(slots as u32 * layout.entry_size).and_then(|a| read_slot(image, layout, a, slots + 1)) {
+        if let Slot::Used(conn) = slot {
+            connections.push(conn);
+        }
+        slots += 1;
+    }
+    // Empty slots past the last connection cannot be told apart from memory
+    // that follows the table
+    slots = connections.last().map(|c| c.number).unwrap_or(0);
+    ConnectionTable{ address, slots, connections }
+}
+
+// Scans the image for the connection table, which is taken to be the run of
+// entries holding the most connections
+pub fn find_connection_table(image: &Image, layout: &ConnectionLayout) -> Option<ConnectionTable> {
+    let mut best: Option<ConnectionTable> = None;
+    let mut covered: HashSet<u32> = HashSet::new();
+    for offset in (0..image.data.len().saturating_sub(3)).step_by(4) {
+        let addr = image.base.checked_add(offset as u32)?;
+        if covered.contains(&addr) { continue; }
+        if !matches!(read_slot(image, layout, addr, 1), Some(Slot::Used(_))) { continue; }
+        let table = read_connection_table(image, layout, addr);
+        // Any entry within the table would yield a shorter run of the same table
+        for n in 0..table.slots as u32 {
+            covered.insert(addr + n * layout.entry_size);
+        }
+        if table.connections.len() >= MIN_CONNECTIONS && best.as_ref().is_none_or(|b| table.connections.len() > b.connections.len()) {
+            best = Some(table);
+        }
+    }
+    best
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenFile {
+    pub address: u32,
+    pub connection: usize,
+    pub task: u8,
+    pub access: u8,
+    pub volume: u8,
+    pub entry: u32,
+    pub name: String,
+}
+
+impl OpenFile {
+    pub fn access_text(&self) -> String {
+        let flags = [ (0x01, "read"), (0x02, "write"), (0x04, "deny-read"), (0x08, "deny-write") ];
+        let names: Vec<&str> = flags.iter().filter(|(bit, _)| self.access & bit != 0).map(|(_, name)| *name).collect();
+        names.join(",")
+    }
+}
+
+// File names are DOS names, so at most 8.3 characters in upper case
+fn is_dos_name(name: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(name);
+    let (stem, ext) = text.split_once('.').unwrap_or((&text, ""));
+    !stem.is_empty() && stem.len() <= 8 && ext.len() <= 3 && !ext.contains('.') &&
+        name.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b"._-$~!#%&".contains(b))
+}
+
+fn read_open_file(image: &Image, layout: &ConnectionLayout, address: u32, table: &ConnectionTable) -> Option<OpenFile> {
+    let name_addr = address.checked_add(layout.file_name)?;
+    let len = *image.slice(name_addr, 1)?.first()? as usize;
+    if len == 0 || len > FILE_NAME_MAX {
+        return None;
+    }
+    let name = image.slice(name_addr + 1, len)?;
+    if !is_dos_name(name) {
+        return None;
+    }
+    let connection = LittleEndian::read_u16(image.slice(address.checked_add(layout.file_connection)?, 2)?) as usize;
+    if !table.connections.iter().any(|c| c.number == connection) {
+        return None;
+    }
+    let byte = |offset: u32| image.slice(address + offset, 1).map(|b| b[0]);
+    let access = byte(layout.file_access)?;
+    if access & 0x03 == 0 || access & 0xf0 != 0 {
+        return None;
+    }
+    Some(OpenFile{
+        address, connection, access,
+        task: byte(layout.file_task)?,
+        volume: byte(layout.file_volume)?,
+        entry: image.read_u32(address + layout.file_entry)?,
+        name: String::from_utf8_lossy(name).to_string(),
+    })
+}
+
+// Scans the image for open file entries of the connections in table
+pub fn find_open_files(image: &Image, layout: &ConnectionLayout, table: &ConnectionTable) -> Vec<OpenFile> {
+    let mut result: Vec<OpenFile> = Vec::new();
+    for offset in (0..image.data.len().saturating_sub(3)).step_by(4) {
+        let addr = match image.base.checked_add(offset as u32) {
+            Some(addr) => addr,
+            None => break,
+        };
+        if let Some(file) = read_open_file(image, layout, addr, table) {
+            result.push(file);
+        }
+    }
+    result.sort_by_key(|f| (f.connection, f.task, f.address));
+    result
+}
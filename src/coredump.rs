@@ -0,0 +1,130 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Core dumps written by the internal debugger after an ABEND. The file starts
+// with a header containing the register state at the time of the ABEND and
+// the reason, followed by a table of the memory ranges that were dumped; the
+// contents of the ranges follow.
+//
+// The signature and offsets below are not taken from any documentation and
+// have not been verified against a core dump written by a real server, so
+// they may well be wrong; a sample dump is needed to confirm them.
+use byteorder::{ByteOrder, LittleEndian};
+
+pub const CORE_SIGNATURE: &[u8; 8] = b"COREDUMP";
+
+const CORE_HEADER_LENGTH: u32 = 0x08;
+const CORE_RANGE_COUNT: u32 = 0x0c;
+const CORE_REGISTERS: usize = 0x10;
+const CORE_REASON: usize = 0x5c;
+const CORE_PROCESS: usize = 0xb0;
+const CORE_RANGES: usize = 0xe0;
+const CORE_RANGE_SIZE: usize = 12;
+
+pub const REGISTER_NAMES: [ &str; 19 ] = [
+    "eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "eip", "eflags",
+    "cs", "ds", "es", "fs", "gs", "ss", "cr0", "cr2", "cr3",
+];
+
+#[derive(Debug, Clone)]
+pub struct MemoryRange {
+    pub address: u32,
+    pub length: u32,
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreDump {
+    pub registers: [ u32; 19 ],
+    pub reason: String,
+    pub process: String,
+    pub ranges: Vec<MemoryRange>,
+}
+
+// Strings are stored as length byte followed by the characters
+fn read_string(data: &[u8], offset: usize) -> Result<String, String> {
+    let len = *data.get(offset).ok_or(format!("string at {:x} is truncated", offset))? as usize;
+    let chars = data.get(offset + 1..offset + 1 + len).ok_or(format!("string at {:x} is truncated", offset))?;
+    Ok(String::from_utf8_lossy(chars).trim_end().to_string())
+}
+
+impl CoreDump {
+    pub fn is_core_dump(data: &[u8]) -> bool {
+        data.starts_with(CORE_SIGNATURE)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !Self::is_core_dump(data) {
+            return Err("not a core dump".to_string());
+        }
+        if data.len() < CORE_RANGES {
+            return Err("core dump header is truncated".to_string());
+        }
+        let header_length = LittleEndian::read_u32(&data[CORE_HEADER_LENGTH as usize..]) as usize;
+        let range_count = LittleEndian::read_u32(&data[CORE_RANGE_COUNT as usize..]) as usize;
+        let ranges_end = range_count.checked_mul(CORE_RANGE_SIZE).and_then(|n| n.checked_add(CORE_RANGES));
+        if ranges_end.is_none_or(|end| end > header_length || header_length > data.len()) {
+            return Err(format!("invalid core dump header (length {:x}, {} memory ranges)", header_length, range_count));
+        }
+
+        let mut registers = [ 0u32; 19 ];
+        LittleEndian::read_u32_into(&data[CORE_REGISTERS..CORE_REGISTERS + 4 * registers.len()], &mut registers);
+        let mut ranges = Vec::new();
+        for n in 0..range_count {
+            let entry = &data[CORE_RANGES + n * CORE_RANGE_SIZE..];
+            let range = MemoryRange{
+                address: LittleEndian::read_u32(&entry[0..4]),
+                length: LittleEndian::read_u32(&entry[4..8]),
+                offset: LittleEndian::read_u32(&entry[8..12]),
+            };
+            if (range.offset as usize).checked_add(range.length as usize).is_none_or(|end| end > data.len()) {
+                return Err(format!("memory range at {:x} is truncated", range.address));
+            }
+            ranges.push(range);
+        }
+        Ok(Self{
+            registers, ranges,
+            reason: read_string(data, CORE_REASON)?,
+            process: read_string(data, CORE_PROCESS)?,
+        })
+    }
+
+    pub fn register(&self, name: &str) -> Option<u32> {
+        REGISTER_NAMES.iter().position(|n| *n == name).map(|n| self.registers[n])
+    }
+
+    // Places every memory range at its physical address
+    pub fn flatten(&self, data: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        for range in &self.ranges {
+            let start = range.address as usize;
+            let length = range.length as usize;
+            if result.len() < start + length {
+                result.resize(start + length, 0);
+            }
+            result[start..start + length].copy_from_slice(&data[range.offset as usize..range.offset as usize + length]);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_string() {
+        // A header without any ranges, whose reason runs past the end
+        let mut data = vec![ 0u8; CORE_RANGES ];
+        data[0..8].copy_from_slice(CORE_SIGNATURE);
+        LittleEndian::write_u32(&mut data[CORE_HEADER_LENGTH as usize..], CORE_RANGES as u32);
+        data[CORE_PROCESS] = 0xff;
+        assert!(CoreDump::parse(&data).is_err());
+        data[CORE_PROCESS] = 0;
+        assert!(CoreDump::parse(&data).is_ok());
+    }
+}
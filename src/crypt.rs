@@ -0,0 +1,167 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// NetWare 3.x bindery password hashing, see nw-crypt.c for the reference
+// implementation
+
+const NIBBLE_TABLE: [ u8; 256 ] = [
+    0x7, 0x8, 0x0, 0x8, 0x6, 0x4, 0xE, 0x4,
+    0x5, 0xC, 0x1, 0x7, 0xB, 0xF, 0xA, 0x8,
+    0xF, 0x8, 0xC, 0xC, 0x9, 0x4, 0x1, 0xE,
+    0x4, 0x6, 0x2, 0x4, 0x0, 0xA, 0xB, 0x9,
+    0x2, 0xF, 0xB, 0x1, 0xD, 0x2, 0x1, 0x9,
+    0x5, 0xE, 0x7, 0x0, 0x0, 0x2, 0x6, 0x6,
+    0x0, 0x7, 0x3, 0x8, 0x2, 0x9, 0x3, 0xF,
+    0x7, 0xF, 0xC, 0xF, 0x6, 0x4, 0xA, 0x0,
+    0x2, 0x3, 0xA, 0xB, 0xD, 0x8, 0x3, 0xA,
+    0x1, 0x7, 0xC, 0xF, 0x1, 0x8, 0x9, 0xD,
+    0x9, 0x1, 0x9, 0x4, 0xE, 0x4, 0xC, 0x5,
+    0x5, 0xC, 0x8, 0xB, 0x2, 0x3, 0x9, 0xE,
+    0x7, 0x7, 0x6, 0x9, 0xE, 0xF, 0xC, 0x8,
+    0xD, 0x1, 0xA, 0x6, 0xE, 0xD, 0x0, 0x7,
+    0x7, 0xA, 0x0, 0x1, 0xF, 0x5, 0x4, 0xB,
+    0x7, 0xB, 0xE, 0xC, 0x9, 0x5, 0xD, 0x1,
+    0xB, 0xD, 0x1, 0x3, 0x5, 0xD, 0xE, 0x6,
+    0x3, 0x0, 0xB, 0xB, 0xF, 0x3, 0x6, 0x4,
+    0x9, 0xD, 0xA, 0x3, 0x1, 0x4, 0x9, 0x4,
+    0x8, 0x3, 0xB, 0xE, 0x5, 0x0, 0x5, 0x2,
+    0xC, 0xB, 0xD, 0x5, 0xD, 0x5, 0xD, 0x2,
+    0xD, 0x9, 0xA, 0xC, 0xA, 0x0, 0xB, 0x3,
+    0x5, 0x3, 0x6, 0x9, 0x5, 0x1, 0xE, 0xE,
+    0x0, 0xE, 0x8, 0x2, 0xD, 0x2, 0x2, 0x0,
+    0x4, 0xF, 0x8, 0x5, 0x9, 0x6, 0x8, 0x6,
+    0xB, 0xA, 0xB, 0xF, 0x0, 0x7, 0x2, 0x8,
+    0xC, 0x7, 0x3, 0xA, 0x1, 0x4, 0x2, 0x5,
+    0xF, 0x7, 0xA, 0xC, 0xE, 0x5, 0x9, 0x3,
+    0xE, 0x7, 0x1, 0x2, 0xE, 0x1, 0xF, 0x4,
+    0xA, 0x6, 0xC, 0x6, 0xF, 0x4, 0x3, 0x0,
+    0xC, 0x0, 0x3, 0x6, 0xF, 0x8, 0x7, 0xB,
+    0x2, 0xD, 0xC, 0x6, 0xA, 0xA, 0x8, 0xD,
+];
+
+const KEY_TABLE: [ u8; 32 ] = [
+    0x48, 0x93, 0x46, 0x67, 0x98, 0x3D, 0xE6, 0x8D,
+    0xB7, 0x10, 0x7A, 0x26, 0x5A, 0xB9, 0xB1, 0x35,
+    0x6B, 0x0F, 0xD5, 0x70, 0xAE, 0xFB, 0xAD, 0x11,
+    0xF4, 0x47, 0xDC, 0xA7, 0xEC, 0xCF, 0x50, 0xC0,
+];
+
+pub const PASSWORD_HASH_LEN: usize = 16;
+pub const LOGIN_KEY_LEN: usize = 8;
+
+// Also known as shuffle()
+fn nw_hash(salt: &[u8], input: &[u8; 32]) -> [ u8; 16 ] {
+    // Apply salt to input data
+    let mut temp = [ 0u8; 32 ];
+    for n in 0..32 {
+        temp[n] = input[n] ^ salt[n & 3];
+    }
+
+    // Two rounds
+    let mut last: u8 = 0;
+    for _ in 0..2 {
+        for index in 0..32 {
+            let v = temp[(last as usize + index) & 0x1f].wrapping_sub(KEY_TABLE[index]);
+            let new_value = temp[index].wrapping_add(last) ^ v;
+            last = last.wrapping_add(new_value);
+            temp[index] = new_value;
+        }
+    }
+
+    // Combine 32 bytes to 16 by using every byte as nibble
+    let mut out = [ 0u8; 16 ];
+    for (index, o) in out.iter_mut().enumerate() {
+        *o = NIBBLE_TABLE[temp[index * 2] as usize] |
+             NIBBLE_TABLE[temp[index * 2 + 1] as usize] << 4;
+    }
+    out
+}
+
+// Expands input to 32 bytes
+fn stretch_input(input: &[u8]) -> [ u8; 32 ] {
+    // Determine input length - it is zero-padded at the end so we need to
+    // avoid those
+    let mut input = input;
+    while let Some((0, rest)) = input.split_last() {
+        input = rest;
+    }
+
+    // If the input exceeds 32 bytes, XOR the first blocks of 32 bytes
+    // into the output
+    let mut out = [ 0u8; 32 ];
+    while input.len() > 32 {
+        for n in 0..32 {
+            out[n] ^= input[n];
+        }
+        input = &input[32..];
+    }
+
+    let mut in_pos = 0;
+    for n in 0..32 {
+        if in_pos == input.len() {
+            out[n] ^= KEY_TABLE[n];
+            in_pos = 0;
+        } else {
+            out[n] ^= input[in_pos];
+            in_pos += 1;
+        }
+    }
+    out
+}
+
+// Calculates the hash as stored in the PASSWORD property of the object.
+// Note that clients convert the password to uppercase before hashing it
+pub fn hash_object_password(object_id: u32, password: &[u8]) -> [ u8; PASSWORD_HASH_LEN ] {
+    let key = object_id.to_be_bytes();
+    let expanded_in = stretch_input(password);
+    nw_hash(&key, &expanded_in)
+}
+
+// Encrypts a password hash using the login key obtained from the server
+pub fn nw_encrypt(key: &[u8; LOGIN_KEY_LEN], input: &[u8; PASSWORD_HASH_LEN]) -> [ u8; LOGIN_KEY_LEN ] {
+    // Expand input to 32 bytes
+    let expanded_in = stretch_input(input);
+
+    // Shuffle with the key to obtain 32 bytes
+    let mut temp = [ 0u8; 32 ];
+    temp[0..16].copy_from_slice(&nw_hash(&key[0..4], &expanded_in));
+    temp[16..32].copy_from_slice(&nw_hash(&key[4..8], &expanded_in));
+
+    let mut out = [ 0u8; LOGIN_KEY_LEN ];
+    for (n, o) in out.iter_mut().enumerate() {
+        *o = temp[n] ^ temp[31 - n] ^ temp[15 - n] ^ temp[16 + n];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vectors from main() in nw-crypt.c
+    const OBJECT_ID: u32 = 0x5000026;
+    const HASH_HELLO123: [ u8; PASSWORD_HASH_LEN ] = [ 0xa3, 0xc2, 0xa1, 0x66, 0x47, 0x6a, 0x77, 0x4d, 0x52, 0xed, 0xba, 0x3d, 0xd1, 0x97, 0x4b, 0x56 ];
+    const HASH_HORSE: [ u8; PASSWORD_HASH_LEN ] = [ 0x74, 0x57, 0x7f, 0x98, 0x07, 0x90, 0x06, 0xf3, 0x53, 0x9a, 0x8e, 0x94, 0xeb, 0xde, 0xe9, 0x19 ];
+    const LOGIN_KEY: [ u8; LOGIN_KEY_LEN ] = [ 0x3f, 0xb1, 0x7e, 0x62, 0xfc, 0x11, 0xf8, 0x6f ];
+    // As computed by determine_client_login_hash() in nw-crypt.c
+    const LOGIN_HORSE: [ u8; LOGIN_KEY_LEN ] = [ 0x97, 0x78, 0xd5, 0x36, 0x73, 0x1d, 0x29, 0x74 ];
+
+    #[test]
+    fn password_hash() {
+        assert_eq!(hash_object_password(OBJECT_ID, b"HELLO123"), HASH_HELLO123);
+        assert_eq!(hash_object_password(OBJECT_ID, b"HORSE BATTERY STABLE NETWARE"), HASH_HORSE);
+    }
+
+    #[test]
+    fn login_encryption() {
+        // The client encrypts the hash of the password it was given, the
+        // server the hash it stored; both must agree
+        let client = nw_encrypt(&LOGIN_KEY, &hash_object_password(OBJECT_ID, b"HORSE BATTERY STABLE NETWARE"));
+        assert_eq!(client, LOGIN_HORSE);
+        assert_eq!(nw_encrypt(&LOGIN_KEY, &HASH_HORSE), LOGIN_HORSE);
+    }
+}
@@ -0,0 +1,118 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Commands of the internal debugger. Like the symbol list, these are kept as
+// a linked list of nodes { next, handler, name, help }, where name and help
+// point to length-prefixed strings. The variable pointing to the first node
+// differs per build, so it is located by scanning for the pointer leading to
+// the longest chain of such nodes.
+use std::collections::HashMap;
+
+use crate::symbols::{Image, Symbol};
+
+pub const COMMAND_NODE_SIZE: usize = 16;
+
+// Chains shorter than this are not considered to be the command list
+pub const MIN_COMMANDS: usize = 4;
+
+const COMMAND_NAME_MAX: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct DebugCommand {
+    pub name: String,
+    pub handler: u32,
+    pub help: String,
+}
+
+impl DebugCommand {
+    // Symbol for the handler; command names such as '?' or '.a' are not
+    // valid identifiers, so other characters are replaced by their code
+    pub fn to_symbol(&self) -> Symbol {
+        let name: String = self.name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_string() } else { format!("_{:02x}", c as u32) })
+            .collect();
+        Symbol{ name: format!("debugcmd_{}", name), address: self.handler }
+    }
+}
+
+// Help texts contain spaces, which Image::read_name() does not accept
+fn read_text(image: &Image, addr: u32) -> Option<String> {
+    let len = *image.slice(addr, 1)?.first()? as usize;
+    let text = image.slice(addr.checked_add(1)?, len)?;
+    if !text.iter().all(|b| *b == b' ' || b.is_ascii_graphic()) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(text).to_string())
+}
+
+fn read_node(image: &Image, addr: u32) -> Option<(u32, DebugCommand)> {
+    if !addr.is_multiple_of(4) { return None; }
+    let next = image.read_u32(addr)?;
+    let handler = image.read_u32(addr.checked_add(4)?)?;
+    let name = image.read_name(image.read_u32(addr.checked_add(8)?)?)?;
+    let help = read_text(image, image.read_u32(addr.checked_add(12)?)?)?;
+    if handler == 0 || name.len() > COMMAND_NAME_MAX {
+        return None;
+    }
+    Some((next, DebugCommand{ name, handler, help }))
+}
+
+fn chain_length(image: &Image, addr: u32, lengths: &mut HashMap<u32, usize>) -> usize {
+    let mut path: Vec<u32> = Vec::new();
+    let mut current = addr;
+    let mut tail = 0;
+    while current != 0 {
+        if let Some(len) = lengths.get(&current) {
+            tail = *len;
+            break;
+        }
+        if path.contains(&current) { break; }
+        match read_node(image, current) {
+            Some((next, _)) => {
+                path.push(current);
+                current = next;
+            },
+            None => break,
+        }
+    }
+    for (n, node) in path.iter().rev().enumerate() {
+        lengths.insert(*node, tail + n + 1);
+    }
+    path.len() + tail
+}
+
+// Scans the image for the variable pointing to the command list; returns its
+// address and the number of commands
+pub fn find_list_head(image: &Image) -> Option<(u32, usize)> {
+    let mut lengths: HashMap<u32, usize> = HashMap::new();
+    let mut best: Option<(u32, usize)> = None;
+    for offset in (0..image.data.len().saturating_sub(3)).step_by(4) {
+        let addr = image.base.checked_add(offset as u32)?;
+        let ptr = image.read_u32(addr)?;
+        if ptr == 0 || image.slice(ptr, COMMAND_NODE_SIZE).is_none() { continue; }
+        let len = chain_length(image, ptr, &mut lengths);
+        if len >= MIN_COMMANDS && best.is_none_or(|(_, n)| len > n) {
+            best = Some((addr, len));
+        }
+    }
+    best
+}
+
+// Reads the command list whose first node is pointed to by head
+pub fn read_list(image: &Image, head: u32) -> Result<Vec<DebugCommand>, String> {
+    let mut result = Vec::new();
+    let mut ptr = image.read_u32(head).ok_or(format!("list head {:x} is outside of the image", head))?;
+    while ptr != 0 {
+        let (next, command) = read_node(image, ptr).ok_or(format!("invalid command node at {:x}", ptr))?;
+        result.push(command);
+        if result.len() > image.data.len() / COMMAND_NODE_SIZE {
+            return Err(format!("command list starting at {:x} loops", head));
+        }
+        ptr = next;
+    }
+    Ok(result)
+}
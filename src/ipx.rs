@@ -0,0 +1,409 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// IPX packets, as carried by Ethernet. NetWare supports four ways of putting
+// them in an Ethernet frame, which it calls frame types, and a network may
+// use several of them at once; servers bind IPX to every frame type in use,
+// with a network number of its own.
+//
+// ETHERNET_802.3 (raw): the length field is followed by the IPX packet
+// directly, which is recognized by its checksum of 0xffff, as there is no
+// 802.2 header. This was the default up to NetWare 3.11.
+//
+// ETHERNET_802.2: an 802.2 LLC header with SAP 0xe0 precedes the packet;
+// the default from NetWare 3.12 on.
+//
+// ETHERNET_II: the type field is 0x8137.
+//
+// ETHERNET_SNAP: an 802.2 header with SAP 0xaa and a SNAP header with type
+// 0x8137.
+//
+// Packets captured on Linux using the 'any' interface have a cooked header
+// in place of the Ethernet header, whose protocol field tells which of these
+// was used.
+//
+// All fields of the IPX header are big endian. The checksum is usually not
+// used and set to 0xffff; if it is, it covers the whole packet except for
+// the checksum itself and the transport control field, which routers change.
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::pcap::{LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL};
+
+pub const ETHERTYPE_IPX: u16 = 0x8137;
+pub const LLC_SAP_IPX: u8 = 0xe0;
+pub const LLC_SAP_SNAP: u8 = 0xaa;
+pub const LLC_UI: u8 = 0x03;
+
+pub const IPX_HEADER_SIZE: usize = 30;
+pub const IPX_MAX_PACKET_SIZE: usize = 576;
+pub const NO_CHECKSUM: u16 = 0xffff;
+
+pub const PACKET_TYPE_UNKNOWN: u8 = 0;
+pub const PACKET_TYPE_RIP: u8 = 1;
+pub const PACKET_TYPE_ECHO: u8 = 2;
+pub const PACKET_TYPE_ERROR: u8 = 3;
+pub const PACKET_TYPE_PEP: u8 = 4;
+pub const PACKET_TYPE_SPX: u8 = 5;
+pub const PACKET_TYPE_NCP: u8 = 17;
+pub const PACKET_TYPE_NETBIOS: u8 = 20;
+
+pub const SOCKET_NCP: u16 = 0x0451;
+pub const SOCKET_SAP: u16 = 0x0452;
+pub const SOCKET_RIP: u16 = 0x0453;
+pub const SOCKET_NETBIOS: u16 = 0x0455;
+pub const SOCKET_DIAGNOSTIC: u16 = 0x0456;
+pub const SOCKET_SERIALIZATION: u16 = 0x0457;
+
+pub const BROADCAST_NODE: [ u8; 6 ] = [ 0xff; 6 ];
+
+// Linux cooked capture header, and the protocols it uses for frames without
+// Ethernet type
+const SLL_HEADER_SIZE: usize = 16;
+const SLL_PROTOCOL_802_3: u16 = 0x0001;
+const SLL_PROTOCOL_802_2: u16 = 0x0004;
+
+// Frames are padded to the minimum Ethernet frame size, excluding the CRC
+const ETHERNET_HEADER_SIZE: usize = 14;
+const ETHERNET_MIN_FRAME_SIZE: usize = 60;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encapsulation {
+    Ethernet8023,
+    Ethernet8022,
+    EthernetII,
+    EthernetSnap,
+}
+
+impl Encapsulation {
+    pub const ALL: [ Encapsulation; 4 ] = [ Encapsulation::Ethernet8023, Encapsulation::Ethernet8022, Encapsulation::EthernetII, Encapsulation::EthernetSnap ];
+
+    // The name of the frame type, as used by NetWare
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encapsulation::Ethernet8023 => "ETHERNET_802.3",
+            Encapsulation::Ethernet8022 => "ETHERNET_802.2",
+            Encapsulation::EthernetII => "ETHERNET_II",
+            Encapsulation::EthernetSnap => "ETHERNET_SNAP",
+        }
+    }
+
+    // Accepts the NetWare name, or just the part after ETHERNET_
+    pub fn find(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        Self::ALL.into_iter().find(|e| e.name() == name || e.name().strip_prefix("ETHERNET_") == Some(name.as_str()))
+    }
+}
+
+impl fmt::Display for Encapsulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// An IPX address, which is shown as NETWORK:NODE:SOCKET in hex, i.e.
+// 00000001:00001b1e0c10:0451
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address {
+    pub network: u32,
+    pub node: [ u8; 6 ],
+    pub socket: u16,
+}
+
+impl Address {
+    pub fn new(network: u32, node: [ u8; 6 ], socket: u16) -> Self {
+        Self{ network, node, socket }
+    }
+
+    pub fn read(cursor: &mut Cursor<&[u8]>) -> Result<Self, std::io::Error> {
+        let network = cursor.read_u32::<BigEndian>()?;
+        let mut node = [ 0u8; 6 ];
+        std::io::Read::read_exact(cursor, &mut node)?;
+        let socket = cursor.read_u16::<BigEndian>()?;
+        Ok(Self{ network, node, socket })
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.write_u32::<BigEndian>(self.network).unwrap();
+        out.extend_from_slice(&self.node);
+        out.write_u16::<BigEndian>(self.socket).unwrap();
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.node == BROADCAST_NODE
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}:{}:{:04x}", self.network, node_string(&self.node), self.socket)
+    }
+}
+
+// Parses NETWORK:NODE:SOCKET; the socket may be left out, in which case it is 0
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(format!("expected NETWORK:NODE[:SOCKET] instead of '{}'", s));
+        }
+        let network = u32::from_str_radix(parts[0], 16).map_err(|_| format!("invalid network number '{}'", parts[0]))?;
+        let node = parse_node(parts[1]).ok_or(format!("invalid node address '{}'", parts[1]))?;
+        let socket = match parts.get(2) {
+            Some(socket) => u16::from_str_radix(socket, 16).map_err(|_| format!("invalid socket number '{}'", socket))?,
+            None => 0,
+        };
+        Ok(Self{ network, node, socket })
+    }
+}
+
+pub fn node_string(node: &[ u8; 6 ]) -> String {
+    node.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Parses a node address of 12 hex digits, which may be separated by - or .
+pub fn parse_node(s: &str) -> Option<[ u8; 6 ]> {
+    let digits: String = s.chars().filter(|c| *c != '-' && *c != '.').collect();
+    if digits.len() != 12 || !digits.is_ascii() {
+        return None
+    }
+    let mut node = [ 0u8; 6 ];
+    for (n, b) in node.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&digits[n * 2..n * 2 + 2], 16).ok()?;
+    }
+    Some(node)
+}
+
+pub fn packet_type_name(packet_type: u8) -> Option<&'static str> {
+    match packet_type {
+        PACKET_TYPE_UNKNOWN => Some("unknown"),
+        PACKET_TYPE_RIP => Some("RIP"),
+        PACKET_TYPE_ECHO => Some("echo"),
+        PACKET_TYPE_ERROR => Some("error"),
+        PACKET_TYPE_PEP => Some("PEP"),
+        PACKET_TYPE_SPX => Some("SPX"),
+        PACKET_TYPE_NCP => Some("NCP"),
+        PACKET_TYPE_NETBIOS => Some("NetBIOS"),
+        _ => None
+    }
+}
+
+// Well-known sockets; workstations use dynamic sockets from 0x4000 on, and
+// receive watchdog and broadcast message packets on the two sockets after
+// the one of their NCP connection
+pub fn socket_name(socket: u16) -> Option<&'static str> {
+    match socket {
+        0x0002 => Some("echo"),
+        0x0003 => Some("error"),
+        SOCKET_NCP => Some("NCP"),
+        SOCKET_SAP => Some("SAP"),
+        SOCKET_RIP => Some("RIP"),
+        SOCKET_NETBIOS => Some("NetBIOS"),
+        SOCKET_DIAGNOSTIC => Some("diagnostic"),
+        SOCKET_SERIALIZATION => Some("serialization"),
+        0x8063 => Some("NVT"),
+        0x9001 => Some("NLSP"),
+        0x9004 => Some("IPXWAN"),
+        0x9086 => Some("ping"),
+        _ => None
+    }
+}
+
+// The IPX checksum of a packet, including its header; this is 0 or 0xffff if
+// the sum is 0, as 0xffff means there is no checksum
+pub fn checksum(packet: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (n, word) in packet.chunks(2).enumerate() {
+        let word = match n {
+            0 => continue,
+            2 => word[1] as u32,
+            _ => ((word[0] as u32) << 8) | word.get(1).copied().unwrap_or(0) as u32,
+        };
+        sum += word;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    if sum == 0 { 0 } else { !(sum as u16) }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpxPacket {
+    pub checksum: u16,
+    // Number of routers the packet passed
+    pub transport_control: u8,
+    pub packet_type: u8,
+    pub destination: Address,
+    pub source: Address,
+    pub data: Vec<u8>,
+}
+
+impl IpxPacket {
+    pub fn new(packet_type: u8, destination: Address, source: Address, data: Vec<u8>) -> Self {
+        Self{ checksum: NO_CHECKSUM, transport_control: 0, packet_type, destination, source, data }
+    }
+
+    // Parses the packet at the start of data; anything past the length in
+    // its header, such as the padding of short Ethernet frames, is ignored
+    pub fn parse(data: &[u8]) -> Result<Self, std::io::Error> {
+        let mut cursor = Cursor::new(data);
+        let checksum = cursor.read_u16::<BigEndian>()?;
+        let length = cursor.read_u16::<BigEndian>()? as usize;
+        if length < IPX_HEADER_SIZE || length > data.len() {
+            return Err(invalid(format!("invalid IPX packet length {} ({} bytes available)", length, data.len())));
+        }
+        let transport_control = cursor.read_u8()?;
+        let packet_type = cursor.read_u8()?;
+        let destination = Address::read(&mut cursor)?;
+        let source = Address::read(&mut cursor)?;
+        let data = data[IPX_HEADER_SIZE..length].to_vec();
+        Ok(Self{ checksum, transport_control, packet_type, destination, source, data })
+    }
+
+    pub fn len(&self) -> usize {
+        IPX_HEADER_SIZE + self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // The packet as sent, with the checksum as in self.checksum
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::with_capacity(self.len());
+        result.write_u16::<BigEndian>(self.checksum).unwrap();
+        result.write_u16::<BigEndian>(self.len() as u16).unwrap();
+        result.push(self.transport_control);
+        result.push(self.packet_type);
+        self.destination.write(&mut result);
+        self.source.write(&mut result);
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    pub fn set_checksum(&mut self) {
+        self.checksum = checksum(&self.to_bytes());
+    }
+
+    // Whether the checksum matches the contents; None if there is none
+    pub fn checksum_valid(&self) -> Option<bool> {
+        if self.checksum == NO_CHECKSUM {
+            return None
+        }
+        Some(checksum(&self.to_bytes()) == self.checksum)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    // MAC addresses of the frame; the destination is unknown (zero) for
+    // Linux cooked captures
+    pub destination: [ u8; 6 ],
+    pub source: [ u8; 6 ],
+    pub encapsulation: Encapsulation,
+    pub packet: IpxPacket,
+}
+
+// Locates the IPX packet in the payload of an 802.3 frame, which follows
+// the length field
+fn parse_802_3(payload: &[u8]) -> Option<(Encapsulation, &[u8])> {
+    match payload {
+        [ 0xff, 0xff, .. ] => Some((Encapsulation::Ethernet8023, payload)),
+        [ LLC_SAP_IPX, LLC_SAP_IPX, LLC_UI, packet @ .. ] => Some((Encapsulation::Ethernet8022, packet)),
+        [ LLC_SAP_SNAP, LLC_SAP_SNAP, LLC_UI, _, _, _, 0x81, 0x37, packet @ .. ] => Some((Encapsulation::EthernetSnap, packet)),
+        _ => None
+    }
+}
+
+fn parse_frame(destination: [ u8; 6 ], source: [ u8; 6 ], found: Option<(Encapsulation, &[u8])>) -> Result<Option<Frame>, std::io::Error> {
+    let Some((encapsulation, packet)) = found else {
+        return Ok(None)
+    };
+    let packet = IpxPacket::parse(packet)?;
+    Ok(Some(Frame{ destination, source, encapsulation, packet }))
+}
+
+// Parses an Ethernet frame (without CRC); returns None if it does not hold
+// an IPX packet
+pub fn parse_ethernet(frame: &[u8]) -> Result<Option<Frame>, std::io::Error> {
+    if frame.len() < ETHERNET_HEADER_SIZE {
+        return Err(invalid(format!("Ethernet frame of {} bytes is too short", frame.len())));
+    }
+    let destination: [ u8; 6 ] = frame[0..6].try_into().unwrap();
+    let source: [ u8; 6 ] = frame[6..12].try_into().unwrap();
+    let type_length = u16::from_be_bytes([ frame[12], frame[13] ]);
+    let payload = &frame[ETHERNET_HEADER_SIZE..];
+    let found = match type_length {
+        ETHERTYPE_IPX => Some((Encapsulation::EthernetII, payload)),
+        0x0600.. => None,
+        length => parse_802_3(&payload[..payload.len().min(length as usize)]),
+    };
+    parse_frame(destination, source, found)
+}
+
+// Parses a Linux cooked capture frame; returns None if it does not hold an
+// IPX packet
+pub fn parse_linux_sll(frame: &[u8]) -> Result<Option<Frame>, std::io::Error> {
+    if frame.len() < SLL_HEADER_SIZE {
+        return Err(invalid(format!("cooked capture frame of {} bytes is too short", frame.len())));
+    }
+    let address_len = u16::from_be_bytes([ frame[4], frame[5] ]);
+    let mut source = [ 0u8; 6 ];
+    if address_len == 6 {
+        source.copy_from_slice(&frame[6..12]);
+    }
+    let payload = &frame[SLL_HEADER_SIZE..];
+    let found = match u16::from_be_bytes([ frame[14], frame[15] ]) {
+        ETHERTYPE_IPX => Some((Encapsulation::EthernetII, payload)),
+        SLL_PROTOCOL_802_3 => Some((Encapsulation::Ethernet8023, payload)),
+        SLL_PROTOCOL_802_2 => parse_802_3(payload),
+        _ => None,
+    };
+    parse_frame([ 0u8; 6 ], source, found)
+}
+
+// Parses a captured frame of the given link type; returns None if it does
+// not hold an IPX packet
+pub fn parse_captured(link_type: u32, frame: &[u8]) -> Result<Option<Frame>, std::io::Error> {
+    match link_type {
+        LINKTYPE_ETHERNET => parse_ethernet(frame),
+        LINKTYPE_LINUX_SLL => parse_linux_sll(frame),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("unsupported link type {}", link_type))),
+    }
+}
+
+// Builds an Ethernet frame holding the packet, padded to the minimum size;
+// packets sent as ETHERNET_802.3 must not have a checksum, or they will not
+// be recognized
+pub fn encapsulate(destination: &[ u8; 6 ], source: &[ u8; 6 ], encapsulation: Encapsulation, packet: &IpxPacket) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    match encapsulation {
+        Encapsulation::Ethernet8023 | Encapsulation::EthernetII => { },
+        Encapsulation::Ethernet8022 => payload.extend_from_slice(&[ LLC_SAP_IPX, LLC_SAP_IPX, LLC_UI ]),
+        Encapsulation::EthernetSnap => payload.extend_from_slice(&[ LLC_SAP_SNAP, LLC_SAP_SNAP, LLC_UI, 0, 0, 0, 0x81, 0x37 ]),
+    }
+    payload.extend(packet.to_bytes());
+
+    let mut result: Vec<u8> = Vec::with_capacity(ETHERNET_HEADER_SIZE + payload.len());
+    result.extend_from_slice(destination);
+    result.extend_from_slice(source);
+    let type_length = if encapsulation == Encapsulation::EthernetII { ETHERTYPE_IPX } else { payload.len() as u16 };
+    result.write_u16::<BigEndian>(type_length).unwrap();
+    result.extend(payload);
+    if result.len() < ETHERNET_MIN_FRAME_SIZE {
+        result.resize(ETHERNET_MIN_FRAME_SIZE, 0);
+    }
+    result
+}
@@ -0,0 +1,35 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+pub mod abendlog;
+pub mod archive;
+pub mod bindery;
+pub mod codepage;
+pub mod components;
+pub mod connections;
+pub mod coredump;
+pub mod crypt;
+pub mod debugcmds;
+pub mod ipx;
+pub mod loader;
+pub mod memdump;
+pub mod modules;
+pub mod msgfile;
+pub mod ncp;
+pub mod nds;
+pub mod nlm;
+pub mod nwfs;
+pub mod pcap;
+pub mod resolve;
+pub mod sap;
+pub mod screens;
+pub mod serverexe;
+pub mod serverinfo;
+pub mod sha256;
+pub mod symboldb;
+pub mod symbols;
+pub mod unpack;
+pub mod versions;
@@ -0,0 +1,165 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// The DOS loader at the start of SERVER.EXE, as distributed separately as
+// LOADER.EXE in loader patches. It consists of a real-mode stub, which is the
+// load image described by the MZ header, followed by the protected-mode
+// loader image. The embedded NLMs follow the loader; the HERE block within
+// the loader refers to the first of them. Applying a loader patch, as
+// LSWAP.EXE does, replaces everything before the first NLM by the new loader
+// and updates the HERE block accordingly.
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::serverexe::{self, AddressMap, HereBlock, PointerKind, HERE_NLM_OFFSET, NLM_ALIGNMENT};
+
+pub const MZ_SIGNATURE: &[u8; 2] = b"MZ";
+pub const MZ_HEADER_SIZE: usize = 0x1c;
+
+#[derive(Debug, Clone)]
+pub struct MzHeader {
+    pub last_page_bytes: u16,
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_offset: u16,
+}
+
+impl MzHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < MZ_HEADER_SIZE || !data.starts_with(MZ_SIGNATURE) {
+            return Err("not a DOS executable".to_string());
+        }
+        let word = |offset: usize| LittleEndian::read_u16(&data[offset..]);
+        let header = Self{
+            last_page_bytes: word(0x02),
+            pages: word(0x04),
+            relocations: word(0x06),
+            header_paragraphs: word(0x08),
+            min_alloc: word(0x0a),
+            max_alloc: word(0x0c),
+            ss: word(0x0e),
+            sp: word(0x10),
+            ip: word(0x14),
+            cs: word(0x16),
+            relocation_offset: word(0x18),
+        };
+        // The number of bytes used in the last page requires there to be one
+        if header.last_page_bytes != 0 && header.pages.checked_sub(1).is_none() {
+            return Err(format!("invalid DOS executable header ({} bytes in the last of {} pages)", header.last_page_bytes, header.pages));
+        }
+        Ok(header)
+    }
+
+    pub fn header_length(&self) -> usize {
+        self.header_paragraphs as usize * 16
+    }
+
+    // File length covered by the MZ header, which is where the real-mode stub
+    // ends
+    pub fn image_end(&self) -> usize {
+        let pages = self.pages as usize * 512;
+        if self.last_page_bytes == 0 { pages } else { pages.saturating_sub(512) + self.last_page_bytes as usize }
+    }
+
+    // Segment:offset pairs to relocate
+    pub fn relocation_table(&self, data: &[u8]) -> Vec<(u16, u16)> {
+        let start = self.relocation_offset as usize;
+        (0..self.relocations as usize)
+            .filter_map(|n| data.get(start + n * 4..start + n * 4 + 4))
+            .map(|r| (LittleEndian::read_u16(&r[2..]), LittleEndian::read_u16(&r[0..])))
+            .collect()
+    }
+}
+
+pub struct Loader {
+    pub header: MzHeader,
+    pub here: HereBlock,
+    // File offset where the loader ends, which is the first NLM (if any)
+    pub length: usize,
+}
+
+impl Loader {
+    // Parses the loader of a SERVER.EXE or LOADER.EXE
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let header = MzHeader::parse(data)?;
+        let here = HereBlock::find(data)?;
+        let length = serverexe::first_nlm(data, &here).unwrap_or(data.len());
+        if header.image_end() > length {
+            return Err(format!("real-mode image ends at {:x}, beyond the end of the loader at {:x}", header.image_end(), length));
+        }
+        Ok(Self{ header, here, length })
+    }
+
+    pub fn to_text(&self, data: &[u8]) -> Vec<String> {
+        let h = &self.header;
+        let mut result = Vec::new();
+        result.push(format!("loader length        {:x}", self.length));
+        result.push(format!("MZ header            {:x} bytes", h.header_length()));
+        result.push(format!("real-mode stub       {:x}-{:x}", h.header_length(), h.image_end()));
+        result.push(format!("  entry point        {:04x}:{:04x}", h.cs, h.ip));
+        result.push(format!("  stack              {:04x}:{:04x}", h.ss, h.sp));
+        result.push(format!("  extra memory       {:x}-{:x} paragraphs", h.min_alloc, h.max_alloc));
+        result.push(format!("  relocations        {} at {:x}", h.relocations, h.relocation_offset));
+        for (segment, offset) in h.relocation_table(data) {
+            result.push(format!("    {:04x}:{:04x}", segment, offset));
+        }
+        result.push(format!("protected-mode image {:x}-{:x}", h.image_end(), self.length));
+        result.extend(self.here.to_text());
+        result
+    }
+}
+
+// Writes the loader of a SERVER.EXE as a LOADER.EXE
+pub fn extract_loader(server: &[u8]) -> Result<Vec<u8>, String> {
+    let loader = Loader::parse(server)?;
+    Ok(server[0..loader.length].to_vec())
+}
+
+// Replaces the loader of a SERVER.EXE by that in new_loader, as LSWAP.EXE
+// does; the NLMs are moved to the next paragraph after the new loader
+pub fn replace_loader(server: &[u8], new_loader: &[u8]) -> Result<Vec<u8>, String> {
+    let old = Loader::parse(server)?;
+    if old.length == server.len() {
+        return Err("server executable does not contain any NLMs".to_string());
+    }
+    let new = Loader::parse(new_loader)?;
+    if new.length != new_loader.len() {
+        return Err("new loader already contains NLMs".to_string());
+    }
+
+    let nlm_offset = new.length.div_ceil(NLM_ALIGNMENT) * NLM_ALIGNMENT;
+    let mut result = new_loader.to_vec();
+    result.resize(nlm_offset, 0);
+    // The new loader is taken to refer to the NLMs the same way the old one did
+    let kind = serverexe::here_nlm(server, &old.here).map(|(_, kind)| kind).unwrap_or(PointerKind::FileOffset);
+    let here_nlm_offset = AddressMap::new(new_loader).from_file(nlm_offset, kind)
+        .ok_or("new loader is too large".to_string())?;
+    LittleEndian::write_u32(&mut result[new.here.offset + HERE_NLM_OFFSET..], here_nlm_offset);
+    result.extend_from_slice(&server[old.length..]);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mz_header_without_pages() {
+        let mut data = [ 0u8; MZ_HEADER_SIZE ];
+        data[0..2].copy_from_slice(MZ_SIGNATURE);
+        LittleEndian::write_u16(&mut data[0x02..], 0x100);
+        assert!(MzHeader::parse(&data).is_err());
+        LittleEndian::write_u16(&mut data[0x04..], 2);
+        assert_eq!(MzHeader::parse(&data).unwrap().image_end(), 0x300);
+    }
+}
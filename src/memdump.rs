@@ -0,0 +1,102 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Memory dumps of a running server, as taken from an emulator. Flat images of
+// physical memory are used as-is: QEMU's pmemsave, Bochs' writemem, 86Box
+// and VMware .vmem files are all of this kind. ELF core files, as written by
+// QEMU's dump-guest-memory and VirtualBox's dumpvmcore, are flattened by
+// placing every PT_LOAD segment at its physical address. Core dumps written
+// by the internal debugger after an ABEND are flattened likewise.
+use object::elf;
+use object::read::elf::{FileHeader, ProgramHeader};
+
+use crate::coredump::CoreDump;
+
+// NetWare is a 32-bit OS, so anything beyond 4GB is of no interest
+const MAX_PHYSICAL: u64 = 0x1_0000_0000;
+
+const ELF_IDENT_SIZE: usize = 16;
+const ELF_IDENT_CLASS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Raw,
+    ElfCore,
+    CoreDump,
+}
+
+impl DumpFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::ElfCore => "ELF core",
+            Self::CoreDump => "NetWare core",
+        }
+    }
+}
+
+pub struct MemoryDump {
+    pub format: DumpFormat,
+    // Physical memory, starting at address 0
+    pub data: Vec<u8>,
+    // Register state and reason of a NetWare core dump
+    pub core: Option<CoreDump>,
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn flatten_elf<Elf: FileHeader>(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let header = Elf::parse(data).map_err(|e| invalid(format!("cannot parse ELF header: {}", e)))?;
+    let endian = header.endian().map_err(|e| invalid(format!("cannot parse ELF header: {}", e)))?;
+    if header.e_type(endian) != elf::ET_CORE {
+        return Err(invalid("ELF file is not a core file".to_string()));
+    }
+    let program_headers = header.program_headers(endian, data).map_err(|e| invalid(format!("cannot parse ELF program headers: {}", e)))?;
+
+    let mut result: Vec<u8> = Vec::new();
+    for ph in program_headers {
+        if ph.p_type(endian) != elf::PT_LOAD { continue; }
+        let paddr: u64 = ph.p_paddr(endian).into();
+        let segment = ph.data(endian, data).map_err(|_| invalid(format!("segment at physical address {:x} is truncated", paddr)))?;
+        if paddr >= MAX_PHYSICAL { continue; }
+        let length = (segment.len() as u64).min(MAX_PHYSICAL - paddr) as usize;
+        let start = paddr as usize;
+        if result.len() < start + length {
+            result.resize(start + length, 0);
+        }
+        result[start..start + length].copy_from_slice(&segment[0..length]);
+    }
+    if result.is_empty() {
+        return Err(invalid("ELF core file does not contain any memory".to_string()));
+    }
+    Ok(result)
+}
+
+impl MemoryDump {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, std::io::Error> {
+        if data.len() >= ELF_IDENT_SIZE && data[0..4] == elf::ELFMAG {
+            let flattened = match data[ELF_IDENT_CLASS] {
+                elf::ELFCLASS32 => flatten_elf::<elf::FileHeader32<object::Endianness>>(&data)?,
+                elf::ELFCLASS64 => flatten_elf::<elf::FileHeader64<object::Endianness>>(&data)?,
+                class => return Err(invalid(format!("unsupported ELF class {}", class))),
+            };
+            return Ok(Self{ format: DumpFormat::ElfCore, data: flattened, core: None })
+        }
+        if CoreDump::is_core_dump(&data) {
+            let core = CoreDump::parse(&data).map_err(invalid)?;
+            let flattened = core.flatten(&data);
+            return Ok(Self{ format: DumpFormat::CoreDump, data: flattened, core: Some(core) })
+        }
+        Ok(Self{ format: DumpFormat::Raw, data, core: None })
+    }
+
+    pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+}
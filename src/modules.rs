@@ -0,0 +1,450 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Modules loaded by a running server. Every loaded NLM is described by a
+// load definition structure, which are kept in a linked list using their
+// first field. As the list head differs per build, the structures are located
+// by scanning memory for anything that looks like one; the list is then
+// followed from the structure that no other structure refers to.
+use std::collections::HashSet;
+use object::elf;
+
+use crate::resolve::ModuleRange;
+use crate::symbols::{Image, Symbol};
+
+// Offsets within the load definition structure
+const LD_LINK: u32 = 0x00;
+const LD_IDENTIFICATION: u32 = 0x10;
+const LD_CODE_OFFSET: u32 = 0x14;
+const LD_CODE_LENGTH: u32 = 0x18;
+const LD_DATA_OFFSET: u32 = 0x1c;
+const LD_DATA_LENGTH: u32 = 0x20;
+const LD_UNINITIALIZED_LENGTH: u32 = 0x24;
+const LD_FLAGS: u32 = 0x30;
+const LD_TYPE: u32 = 0x34;
+const LD_START: u32 = 0x38;
+const LD_EXIT: u32 = 0x3c;
+const LD_CHECK: u32 = 0x40;
+const LD_FILE_NAME: u32 = 0x48;
+const LD_NAME: u32 = 0x6c;
+
+const LD_FILE_NAME_MAX: usize = 35;
+
+// Alignment of the regions in the memory image ELF file; the file offset of
+// every region is congruent to its address modulo this
+pub const PAGE_SIZE: usize = 0x1000;
+
+// Part of the memory written to the memory image ELF file, either the code or
+// data of a module or the memory between these
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub address: u32,
+    pub length: u32,
+    pub flags: u32,
+}
+
+impl MemoryRegion {
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.address && addr - self.address < self.length
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedModule {
+    // Address of the load definition structure
+    pub address: u32,
+    pub next: u32,
+    pub id: u32,
+    pub file_name: String,
+    pub description: String,
+    pub code_address: u32,
+    pub code_length: u32,
+    pub data_address: u32,
+    pub data_length: u32,
+    pub uninitialized_length: u32,
+    pub flags: u32,
+    pub module_type: u32,
+    pub start: u32,
+    pub exit: u32,
+    pub check: u32,
+}
+
+impl LoadedModule {
+    pub fn to_range(&self) -> ModuleRange {
+        ModuleRange{
+            name: self.file_name.clone(),
+            code_address: self.code_address,
+            code_length: self.code_length,
+            data_address: self.data_address,
+            data_length: self.data_length + self.uninitialized_length,
+        }
+    }
+
+    // Returns the module at addr, if the structure there looks valid
+    pub fn read(image: &Image, address: u32) -> Option<Self> {
+        let field = |offset: u32| image.read_u32(address.checked_add(offset)?);
+        let file_name = image.read_name(address.checked_add(LD_FILE_NAME)?)?;
+        if file_name.len() > LD_FILE_NAME_MAX || !file_name.contains('.') {
+            return None;
+        }
+        let code_address = field(LD_CODE_OFFSET)?;
+        let code_length = field(LD_CODE_LENGTH)?;
+        let data_address = field(LD_DATA_OFFSET)?;
+        let data_length = field(LD_DATA_LENGTH)?;
+        if code_length == 0 || image.slice(code_address, code_length as usize).is_none() {
+            return None;
+        }
+        if data_length != 0 && image.slice(data_address, data_length as usize).is_none() {
+            return None;
+        }
+        let next = field(LD_LINK)?;
+        if next != 0 && image.slice(next, 4).is_none() {
+            return None;
+        }
+        // The description may contain spaces, so it is read leniently
+        let description = image.slice(address.checked_add(LD_NAME)?, 1)
+            .and_then(|len| image.slice(address + LD_NAME + 1, len[0] as usize))
+            .map(|d| String::from_utf8_lossy(d).to_string())
+            .unwrap_or_default();
+        Some(Self{
+            address, next, file_name, description,
+            id: field(LD_IDENTIFICATION)?,
+            code_address, code_length, data_address, data_length,
+            uninitialized_length: field(LD_UNINITIALIZED_LENGTH)?,
+            flags: field(LD_FLAGS)?,
+            module_type: field(LD_TYPE)?,
+            start: field(LD_START)?,
+            exit: field(LD_EXIT)?,
+            check: field(LD_CHECK)?,
+        })
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        (addr >= self.code_address && addr - self.code_address < self.code_length) ||
+        (addr >= self.data_address && addr - self.data_address < self.data_length + self.uninitialized_length)
+    }
+
+    pub fn is_code(&self, addr: u32) -> bool {
+        addr >= self.code_address && addr - self.code_address < self.code_length
+    }
+}
+
+// Locates all load definition structures, in list order; structures which
+// cannot be reached from the list head are appended
+pub fn find_modules(image: &Image) -> Vec<LoadedModule> {
+    let mut candidates: Vec<LoadedModule> = Vec::new();
+    for offset in (0..image.data.len().saturating_sub(3)).step_by(4) {
+        let addr = match image.base.checked_add(offset as u32) {
+            Some(addr) => addr,
+            None => break,
+        };
+        if let Some(module) = LoadedModule::read(image, addr) {
+            candidates.push(module);
+        }
+    }
+
+    let referenced: HashSet<u32> = candidates.iter().map(|m| m.next).collect();
+    let mut result: Vec<LoadedModule> = Vec::new();
+    let mut seen: HashSet<u32> = HashSet::new();
+    for head in candidates.iter().filter(|m| !referenced.contains(&m.address)) {
+        let mut current = Some(head);
+        while let Some(module) = current {
+            if !seen.insert(module.address) { break; }
+            result.push(module.clone());
+            current = candidates.iter().find(|m| m.address == module.next);
+        }
+    }
+    for module in &candidates {
+        if seen.insert(module.address) {
+            result.push(module.clone());
+        }
+    }
+    result
+}
+
+// Writes the module as loaded in memory to an ELF file; as the memory has
+// been relocated already, there are no relocations
+pub fn write_module_elf(fname: &str, image: &Image, module: &LoadedModule, symbols: &[Symbol]) -> Result<(), std::io::Error> {
+    let code = image.slice(module.code_address, module.code_length as usize).unwrap_or_default();
+    let data_length = module.data_length + module.uninitialized_length;
+    let data = image.slice(module.data_address, data_length as usize)
+        .or(image.slice(module.data_address, module.data_length as usize))
+        .unwrap_or_default();
+
+    let mut names: Vec<(String, u32)> = symbols.iter()
+        .filter(|s| module.contains(s.address))
+        .map(|s| (s.name.clone(), s.address))
+        .collect();
+    for (name, value) in [ ("nlm_start", module.start), ("nlm_terminate", module.exit), ("nlm_check", module.check) ] {
+        if module.is_code(value) {
+            names.push((name.to_string(), value));
+        }
+    }
+
+    let mut out_data = Vec::new();
+    let mut writer = object::write::elf::Writer::new(object::Endianness::Little, false, &mut out_data);
+    writer.reserve_file_header();
+    writer.reserve_program_headers(2);
+
+    let align = 16;
+    let code_index = writer.reserve_section_index();
+    let code_offset = writer.reserve(code.len(), align);
+    let code_str_id = writer.add_section_name(b".text");
+    let data_index = writer.reserve_section_index();
+    let data_offset = writer.reserve(data.len(), align);
+    let data_str_id = writer.add_section_name(b".data");
+
+    writer.reserve_null_symbol_index();
+    let mut elf_symbols = Vec::new();
+    for (name, value) in &names {
+        let section = if module.is_code(*value) { code_index } else { data_index };
+        let name = writer.add_string(name.as_bytes());
+        writer.reserve_symbol_index(Some(section));
+        let st_type = if section == code_index { elf::STT_FUNC } else { elf::STT_OBJECT };
+        elf_symbols.push((name, section, *value, (elf::STB_GLOBAL << 4) + st_type));
+    }
+
+    writer.reserve_symtab_section_index();
+    writer.reserve_symtab();
+    if writer.symtab_shndx_needed() {
+        writer.reserve_symtab_shndx_section_index();
+    }
+    writer.reserve_symtab_shndx();
+    writer.reserve_strtab_section_index();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab_section_index();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer.write_file_header(&object::write::elf::FileHeader{
+        os_abi: 0,
+        e_type: elf::ET_EXEC,
+        abi_version: elf::EV_CURRENT,
+        e_machine: elf::EM_386,
+        e_entry: module.start as u64,
+        e_flags: 0,
+    }).map_err(|e| std::io::Error::other(e.to_string()))?;
+    for (offset, address, length, memsz, flags) in [
+        (code_offset, module.code_address, code.len(), code.len(), elf::PF_R | elf::PF_X),
+        (data_offset, module.data_address, data.len(), data_length as usize, elf::PF_R | elf::PF_W),
+    ] {
+        writer.write_program_header(&object::write::elf::ProgramHeader{
+            p_type: elf::PT_LOAD,
+            p_align: align as u64,
+            p_filesz: length as u64,
+            p_memsz: memsz.max(length) as u64,
+            p_offset: offset as u64,
+            p_flags: flags,
+            p_paddr: address as u64,
+            p_vaddr: address as u64,
+        });
+    }
+
+    // Nothing is reserved for empty sections, so there is nothing to align
+    for section in [ code, data ] {
+        if !section.is_empty() {
+            writer.write_align(align);
+            writer.write(section);
+        }
+    }
+
+    writer.write_null_symbol();
+    for (name, section, value, info) in &elf_symbols {
+        writer.write_symbol(&object::write::elf::Sym{
+            name: Some(*name),
+            section: Some(*section),
+            st_info: *info,
+            st_other: elf::STV_DEFAULT,
+            st_shndx: 0,
+            st_value: *value as u64,
+            st_size: 0,
+        });
+    }
+    writer.write_symtab_shndx();
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for (str_id, address, offset, length, flags) in [
+        (code_str_id, module.code_address, code_offset, code.len(), elf::SHF_ALLOC | elf::SHF_EXECINSTR),
+        (data_str_id, module.data_address, data_offset, data.len(), elf::SHF_ALLOC | elf::SHF_WRITE),
+    ] {
+        writer.write_section_header(&object::write::elf::SectionHeader{
+            name: Some(str_id),
+            sh_type: elf::SHT_PROGBITS,
+            sh_flags: flags as u64,
+            sh_addr: address as u64,
+            sh_offset: offset as u64,
+            sh_size: length as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: align as u64,
+            sh_entsize: 0,
+        });
+    }
+    writer.write_symtab_section_header(1);
+    writer.write_symtab_shndx_section_header();
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+    assert_eq!(writer.reserved_len(), writer.len());
+
+    std::fs::write(fname, &out_data)
+}
+
+// Splits the memory in regions: the code and data of every module, and the
+// memory between them. Segments of modules which are not within the memory
+// or overlap an earlier segment are left out.
+pub fn memory_regions(image: &Image, modules: &[ModuleRange]) -> Vec<MemoryRegion> {
+    let mut segments: Vec<MemoryRegion> = Vec::new();
+    for m in modules {
+        let stem = m.name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&m.name);
+        segments.push(MemoryRegion{ name: format!(".text.{}", stem), address: m.code_address, length: m.code_length, flags: elf::PF_R | elf::PF_X });
+        segments.push(MemoryRegion{ name: format!(".data.{}", stem), address: m.data_address, length: m.data_length, flags: elf::PF_R | elf::PF_W });
+    }
+    segments.retain(|s| s.length != 0 && image.slice(s.address, s.length as usize).is_some());
+    segments.sort_by_key(|s| s.address);
+
+    let memory = |from: u64, to: u64| MemoryRegion{
+        name: format!(".mem.{:08x}", from),
+        address: from as u32,
+        length: (to - from) as u32,
+        flags: elf::PF_R | elf::PF_W | elf::PF_X,
+    };
+    let end = image.base as u64 + image.data.len() as u64;
+    let mut pos = image.base as u64;
+    let mut result: Vec<MemoryRegion> = Vec::new();
+    for segment in segments {
+        let address = segment.address as u64;
+        if address < pos { continue; }
+        if address > pos {
+            result.push(memory(pos, address));
+        }
+        pos = address + segment.length as u64;
+        result.push(segment);
+    }
+    if pos < end {
+        result.push(memory(pos, end));
+    }
+    result
+}
+
+// Writes the regions to a single ELF file with a program header and section
+// per region, along with all symbols; symbols outside of the regions are
+// absolute
+pub fn write_memory_elf(fname: &str, image: &Image, regions: &[MemoryRegion], symbols: &[Symbol]) -> Result<(), std::io::Error> {
+    let mut out_data = Vec::new();
+    let mut writer = object::write::elf::Writer::new(object::Endianness::Little, false, &mut out_data);
+    writer.reserve_file_header();
+    writer.reserve_program_headers(regions.len() as u32);
+
+    let mut sections = Vec::new();
+    for region in regions {
+        let index = writer.reserve_section_index();
+        let str_id = writer.add_section_name(region.name.as_bytes());
+        let len = writer.reserved_len();
+        let offset = len + (region.address as usize + PAGE_SIZE - len % PAGE_SIZE) % PAGE_SIZE;
+        writer.reserve_until(offset);
+        writer.reserve(region.length as usize, 1);
+        sections.push((index, str_id, offset));
+    }
+
+    writer.reserve_null_symbol_index();
+    let mut elf_symbols = Vec::new();
+    for sym in symbols {
+        let region = regions.iter().position(|r| r.contains(sym.address));
+        let section = region.map(|n| sections[n].0);
+        let st_type = match region {
+            Some(n) if regions[n].flags == elf::PF_R | elf::PF_X => elf::STT_FUNC,
+            Some(n) if regions[n].flags == elf::PF_R | elf::PF_W => elf::STT_OBJECT,
+            _ => elf::STT_NOTYPE,
+        };
+        let name = writer.add_string(sym.name.as_bytes());
+        writer.reserve_symbol_index(section);
+        elf_symbols.push((name, section, sym.address, (elf::STB_GLOBAL << 4) + st_type));
+    }
+
+    writer.reserve_symtab_section_index();
+    writer.reserve_symtab();
+    if writer.symtab_shndx_needed() {
+        writer.reserve_symtab_shndx_section_index();
+    }
+    writer.reserve_symtab_shndx();
+    writer.reserve_strtab_section_index();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab_section_index();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer.write_file_header(&object::write::elf::FileHeader{
+        os_abi: 0,
+        e_type: elf::ET_EXEC,
+        abi_version: elf::EV_CURRENT,
+        e_machine: elf::EM_386,
+        e_entry: 0,
+        e_flags: 0,
+    }).map_err(|e| std::io::Error::other(e.to_string()))?;
+    for (region, (_, _, offset)) in regions.iter().zip(&sections) {
+        writer.write_program_header(&object::write::elf::ProgramHeader{
+            p_type: elf::PT_LOAD,
+            p_align: PAGE_SIZE as u64,
+            p_filesz: region.length as u64,
+            p_memsz: region.length as u64,
+            p_offset: *offset as u64,
+            p_flags: region.flags,
+            p_paddr: region.address as u64,
+            p_vaddr: region.address as u64,
+        });
+    }
+
+    for (region, (_, _, offset)) in regions.iter().zip(&sections) {
+        writer.pad_until(*offset);
+        writer.write(image.slice(region.address, region.length as usize).unwrap_or_default());
+    }
+
+    writer.write_null_symbol();
+    for (name, section, value, info) in &elf_symbols {
+        writer.write_symbol(&object::write::elf::Sym{
+            name: Some(*name),
+            section: *section,
+            st_info: *info,
+            st_other: elf::STV_DEFAULT,
+            st_shndx: if section.is_some() { 0 } else { elf::SHN_ABS },
+            st_value: *value as u64,
+            st_size: 0,
+        });
+    }
+    writer.write_symtab_shndx();
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for (region, (_, str_id, offset)) in regions.iter().zip(&sections) {
+        let mut flags = elf::SHF_ALLOC;
+        if region.flags & elf::PF_X != 0 { flags |= elf::SHF_EXECINSTR; }
+        if region.flags & elf::PF_W != 0 { flags |= elf::SHF_WRITE; }
+        writer.write_section_header(&object::write::elf::SectionHeader{
+            name: Some(*str_id),
+            sh_type: elf::SHT_PROGBITS,
+            sh_flags: flags as u64,
+            sh_addr: region.address as u64,
+            sh_offset: *offset as u64,
+            sh_size: region.length as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 1,
+            sh_entsize: 0,
+        });
+    }
+    writer.write_symtab_section_header(1);
+    writer.write_symtab_shndx_section_header();
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+    assert_eq!(writer.reserved_len(), writer.len());
+
+    std::fs::write(fname, &out_data)
+}
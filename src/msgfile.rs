@@ -0,0 +1,108 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Message files (SERVER.MSG and the .MSG files of NLMs), as written by the
+// Novell message librarian. The signature is followed by the language ID and
+// the number of messages, a table with the file offset of every message and
+// the messages themselves, which are zero-terminated strings in the codepage
+// of the server.
+use std::io::Cursor;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::codepage::Codepage;
+
+pub const MSG_SIGNATURE: &[u8; 50] = b"Novell Message Librarian Data File Version 1.00\r\n\x1a";
+
+#[derive(Debug, Clone)]
+pub struct MessageFile {
+    pub language: u32,
+    pub messages: Vec<Vec<u8>>,
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+impl MessageFile {
+    pub fn parse(data: &[u8]) -> Result<Self, std::io::Error> {
+        if !data.starts_with(MSG_SIGNATURE) {
+            return Err(invalid("not a message file".to_string()));
+        }
+        let mut cursor = Cursor::new(&data[MSG_SIGNATURE.len()..]);
+        let language = cursor.read_u32::<LittleEndian>()?;
+        let count = cursor.read_u32::<LittleEndian>()? as usize;
+        if count > data.len() / 4 {
+            return Err(invalid(format!("invalid message count {}", count)));
+        }
+        let mut messages = Vec::with_capacity(count);
+        for n in 0..count {
+            let offset = cursor.read_u32::<LittleEndian>()? as usize;
+            let text = data.get(offset..).ok_or(invalid(format!("message {} is outside of the file", n)))?;
+            let len = text.iter().position(|b| *b == 0).ok_or(invalid(format!("message {} is not terminated", n)))?;
+            messages.push(text[0..len].to_vec());
+        }
+        Ok(Self{ language, messages })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        result.extend_from_slice(MSG_SIGNATURE);
+        result.write_u32::<LittleEndian>(self.language).unwrap();
+        result.write_u32::<LittleEndian>(self.messages.len() as u32).unwrap();
+        let mut offset = result.len() + 4 * self.messages.len();
+        for m in &self.messages {
+            result.write_u32::<LittleEndian>(offset as u32).unwrap();
+            offset += m.len() + 1;
+        }
+        for m in &self.messages {
+            result.extend_from_slice(m);
+            result.push(0);
+        }
+        result
+    }
+}
+
+// Messages contain line breaks and format characters, which are escaped so
+// every message fits on a single line
+pub fn escape(message: &[u8], codepage: &Codepage) -> String {
+    let mut result = String::new();
+    for b in message {
+        match b {
+            b'\n' => result.push_str("\\n"),
+            b'\r' => result.push_str("\\r"),
+            b'\t' => result.push_str("\\t"),
+            b'\\' => result.push_str("\\\\"),
+            0..=0x1f | 0x7f => result.push_str(&format!("\\x{:02x}", b)),
+            _ => result.push_str(&codepage.decode(&[ *b ])),
+        }
+    }
+    result
+}
+
+pub fn unescape(text: &str, codepage: &Codepage) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.extend(codepage.encode(&c.to_string()));
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push(b'\n'),
+            Some('r') => result.push(b'\r'),
+            Some('t') => result.push(b'\t'),
+            Some('\\') => result.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                result.push(u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\x{}", hex))?);
+            },
+            Some(c) => return Err(format!("invalid escape \\{}", c)),
+            None => return Err("escape at end of message".to_string()),
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,221 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Reading the bindery of a live server using the bindery functions (23).
+// Objects are scanned by type and name pattern, starting from the ID of the
+// object last returned; the properties of an object by its type and name,
+// using a search instance; values are read by segment of 128 bytes,
+// numbered from 1. Object IDs are big endian, as they are in set properties.
+//
+// What is returned depends on the rights of the object logged in as: a user
+// sees the objects and properties it may read, which for GUEST are few;
+// SUPERVISOR sees everything but the passwords, which no client may read.
+// The result is a Bindery as if it were read from the NET$*.SYS files, with
+// the record offsets left zero and PASSWORD properties without value.
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::bindery::{self, Bindery, Format, Object, Property, Value};
+use crate::codepage;
+use crate::ncp::{self, NCPError};
+use crate::ncp::connection::{self, Connection, FN_BINDERY};
+
+const SUB_GET_OBJECT_ID: u8 = 53;
+const SUB_SCAN_OBJECT: u8 = 55;
+const SUB_SCAN_PROPERTY: u8 = 60;
+const SUB_READ_PROPERTY_VALUE: u8 = 61;
+
+pub const OBJECT_TYPE_ALL: u16 = 0xffff;
+const SCAN_START: u32 = 0xffffffff;
+const NO_LINK: u32 = 0xffffffff;
+
+pub const COMPLETION_NO_SUCH_SEGMENT: u8 = 0xec;
+
+#[derive(Clone, Debug)]
+pub struct ObjectInfo {
+    pub objid: u32,
+    pub objtype: u16,
+    pub name: String,
+    pub flags: u8,
+    pub security: u8,
+    pub has_properties: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PropertyInfo {
+    pub name: String,
+    pub flags: u8,
+    pub security: u8,
+    pub has_value: bool,
+}
+
+impl Connection {
+    // ID of the object of objtype called name; this may be asked before
+    // logging in
+    pub fn object_id(&mut self, objtype: u16, name: &str) -> Result<u32, NCPError> {
+        let mut request: Vec<u8> = Vec::new();
+        request.write_u16::<BigEndian>(objtype).unwrap();
+        connection::add_pstring(&mut request, name);
+        let reply = self.request_sub(FN_BINDERY, SUB_GET_OBJECT_ID, &request)?;
+        if reply.len() < 4 {
+            return Err(NCPError::InvalidReply("object ID reply too short".to_string()))
+        }
+        Ok(BigEndian::read_u32(&reply))
+    }
+
+    // Objects of objtype (or OBJECT_TYPE_ALL) whose name matches pattern,
+    // which may hold * and ? wildcards
+    pub fn scan_objects(&mut self, objtype: u16, pattern: &str) -> Result<Vec<ObjectInfo>, NCPError> {
+        let mut result = Vec::new();
+        let mut last = SCAN_START;
+        loop {
+            let mut request: Vec<u8> = Vec::new();
+            request.write_u32::<BigEndian>(last).unwrap();
+            request.write_u16::<BigEndian>(objtype).unwrap();
+            connection::add_pstring(&mut request, pattern);
+            let reply = match self.request_sub(FN_BINDERY, SUB_SCAN_OBJECT, &request) {
+                Ok(reply) => reply,
+                Err(NCPError::Completion(ncp::COMPLETION_NO_SUCH_OBJECT)) => break,
+                Err(e) => return Err(e),
+            };
+            if reply.len() < 57 {
+                return Err(NCPError::InvalidReply("scan object reply too short".to_string()))
+            }
+            let object = ObjectInfo{
+                objid: BigEndian::read_u32(&reply),
+                objtype: BigEndian::read_u16(&reply[4..]),
+                name: connection::fixed_string(&reply[6..54]),
+                flags: reply[54],
+                security: reply[55],
+                has_properties: reply[56] != 0,
+            };
+            // Servers should not return the same object twice, but a loop
+            // would never end
+            if object.objid == last || result.iter().any(|o: &ObjectInfo| o.objid == object.objid) {
+                break;
+            }
+            last = object.objid;
+            result.push(object);
+        }
+        Ok(result)
+    }
+
+    // Properties of an object whose name matches pattern
+    pub fn scan_properties(&mut self, objtype: u16, name: &str, pattern: &str) -> Result<Vec<PropertyInfo>, NCPError> {
+        let mut result = Vec::new();
+        let mut instance = SCAN_START;
+        loop {
+            let mut request: Vec<u8> = Vec::new();
+            request.write_u16::<BigEndian>(objtype).unwrap();
+            connection::add_pstring(&mut request, name);
+            request.write_u32::<BigEndian>(instance).unwrap();
+            connection::add_pstring(&mut request, pattern);
+            let reply = match self.request_sub(FN_BINDERY, SUB_SCAN_PROPERTY, &request) {
+                Ok(reply) => reply,
+                Err(NCPError::Completion(ncp::COMPLETION_NO_SUCH_PROPERTY)) => break,
+                Err(e) => return Err(e),
+            };
+            if reply.len() < 24 {
+                return Err(NCPError::InvalidReply("scan property reply too short".to_string()))
+            }
+            result.push(PropertyInfo{
+                name: connection::fixed_string(&reply[0..16]),
+                flags: reply[16],
+                security: reply[17],
+                has_value: reply[22] != 0,
+            });
+            instance = BigEndian::read_u32(&reply[18..]);
+            if reply[23] == 0 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    // Segments of the value of a property, in order
+    pub fn read_property_value(&mut self, objtype: u16, name: &str, property: &str) -> Result<Vec<[ u8; bindery::VALUE_SEGMENT_SIZE ]>, NCPError> {
+        let mut result = Vec::new();
+        for segment in 1..=255u8 {
+            let mut request: Vec<u8> = Vec::new();
+            request.write_u16::<BigEndian>(objtype).unwrap();
+            connection::add_pstring(&mut request, name);
+            request.push(segment);
+            connection::add_pstring(&mut request, property);
+            let reply = match self.request_sub(FN_BINDERY, SUB_READ_PROPERTY_VALUE, &request) {
+                Ok(reply) => reply,
+                Err(NCPError::Completion(COMPLETION_NO_SUCH_SEGMENT)) if segment > 1 => break,
+                Err(e) => return Err(e),
+            };
+            if reply.len() < bindery::VALUE_SEGMENT_SIZE + 1 {
+                return Err(NCPError::InvalidReply("property value reply too short".to_string()))
+            }
+            let mut data = [ 0u8; bindery::VALUE_SEGMENT_SIZE ];
+            data.copy_from_slice(&reply[..bindery::VALUE_SEGMENT_SIZE]);
+            result.push(data);
+            if reply[bindery::VALUE_SEGMENT_SIZE] == 0 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+// Reads every object, property and value the connection may see. Properties
+// which cannot be read are left out, and given as warnings
+pub fn read_bindery(conn: &mut Connection, format: Format) -> Result<(Bindery, Vec<String>), NCPError> {
+    let mut objects: Vec<Object> = Vec::new();
+    let mut properties: Vec<Property> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+    for o in conn.scan_objects(OBJECT_TYPE_ALL, "*")? {
+        let mut object = Object{ offset: 0, objid: o.objid, objtype: o.objtype, name: o.name.clone(), flags: o.flags, security: o.security, property: NO_LINK, unk1: 0 };
+        let scanned = if o.has_properties { conn.scan_properties(o.objtype, &o.name, "*") } else { Ok(Vec::new()) };
+        let scanned = match scanned {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                warnings.push(format!("cannot scan the properties of '{}': {}", o.name, e));
+                Vec::new()
+            }
+        };
+        // Properties are linked in the order scanned
+        let mut previous: Option<usize> = None;
+        for p in scanned {
+            let propid = properties.len() as u32 + 1;
+            let first_valueid = values.len() as u32 + 1;
+            // Passwords can never be read, which is not worth a warning for
+            // every user; the property is kept without value, as it shows
+            // the user has a password
+            let segments = if p.name == "PASSWORD" {
+                Ok(Vec::new())
+            } else if p.has_value {
+                conn.read_property_value(o.objtype, &o.name, &p.name).map(|s| if s.is_empty() { vec![ [ 0u8; bindery::VALUE_SEGMENT_SIZE ] ] } else { s })
+            } else {
+                Ok(vec![ [ 0u8; bindery::VALUE_SEGMENT_SIZE ] ])
+            };
+            let segments = match segments {
+                Ok(segments) => segments,
+                Err(e) => {
+                    warnings.push(format!("cannot read property {} of '{}': {}", p.name, o.name, e));
+                    continue;
+                }
+            };
+            let count = segments.len();
+            for (n, data) in segments.into_iter().enumerate() {
+                let next = if n + 1 < count { first_valueid + n as u32 + 1 } else { NO_LINK };
+                values.push(Value{ offset: 0, valueid: first_valueid + n as u32, owner: propid, next, sequence: n as u16, data });
+            }
+            let value = if count > 0 { first_valueid } else { NO_LINK };
+            properties.push(Property{ offset: 0, propid, name: p.name, flags: p.flags, security: p.security, owner: o.objid, next: NO_LINK, value });
+            match previous {
+                Some(n) => properties[n].next = propid,
+                None => object.property = propid,
+            }
+            previous = Some(properties.len() - 1);
+        }
+        objects.push(object);
+    }
+    Ok((Bindery::from_records(format, codepage::DEFAULT, objects, properties, values), warnings))
+}
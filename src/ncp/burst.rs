@@ -0,0 +1,324 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Packet burst, which NetWare 3.12 and later (and 3.11 with PBURST.NLM) offer
+// to read and write files in blocks of up to 64KB rather than a buffer size
+// at a time, waiting for a reply to every packet.
+//
+// A client asks for it using function 101, giving an ID of its choosing, the
+// largest packet it takes and the socket to send burst packets to; the server
+// replies with its own ID and largest packet. From then on, reads and writes
+// are sent as bursts: a request, and its reply, is split into fragments which
+// are sent one after the other as packets of type 0x7777, the last of which is
+// flagged as the end of the burst. Every fragment gives the length of the
+// whole burst and its offset within it. A side which misses fragments sends a
+// system packet listing them (as offset and length), and the other side sends
+// those again; a request which gets no reply at all is sent again whole.
+//
+// The header of a burst packet is big endian. The request within a burst has
+// the function (1 to read, 2 to write) and result of its reply little endian,
+// and the file offset and length big endian.
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+
+use crate::ncp::NCPError;
+use crate::ncp::connection::{self, Connection};
+use crate::ncp::files::FileHandle;
+
+const FN_BURST_CONNECTION: u8 = 101;
+
+pub const REQUEST_BURST: u16 = 0x7777;
+const STREAM_TYPE_BIG_SEND: u8 = 2;
+
+// Flags
+const FLAG_ABORT: u8 = 0x04;
+const FLAG_BUSY: u8 = 0x08;
+const FLAG_END_OF_BURST: u8 = 0x10;
+const FLAG_SYSTEM: u8 = 0x80;
+
+const HEADER_SIZE: usize = 36;
+// Offset and length of a missing fragment
+const MISSING_SIZE: usize = 6;
+
+const BURST_READ: u32 = 1;
+const BURST_WRITE: u32 = 2;
+// Function, file handle, reserved, offset and length
+const REQUEST_SIZE: usize = 24;
+// Result and number of bytes read
+const READ_REPLY_SIZE: usize = 8;
+const RESULT_OK: u32 = 0;
+const RESULT_NO_DATA: u32 = 3;
+
+// Largest burst to send or receive
+pub const MAX_BURST_SIZE: usize = 65536;
+// Gap between the fragments of a write, in units of 100 microseconds; it is
+// widened when the server misses fragments
+const MAX_SEND_DELAY: u32 = 100;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Burst {
+    local_id: u32,
+    remote_id: u32,
+    // Largest packet either side takes
+    pub packet_size: usize,
+    packet_sequence: u32,
+    burst_sequence: u16,
+    send_delay: u32,
+}
+
+impl Burst {
+    fn fragment_size(&self) -> usize {
+        self.packet_size - HEADER_SIZE
+    }
+
+    fn packet(&mut self, flags: u8, total: usize, offset: usize, missing: &[(usize, usize)], data: &[u8]) -> Vec<u8> {
+        let mut packet: Vec<u8> = Vec::with_capacity(HEADER_SIZE + missing.len() * MISSING_SIZE + data.len());
+        packet.write_u16::<BigEndian>(REQUEST_BURST).unwrap();
+        packet.extend_from_slice(&[ flags, STREAM_TYPE_BIG_SEND ]);
+        packet.write_u32::<BigEndian>(self.local_id).unwrap();
+        packet.write_u32::<BigEndian>(self.remote_id).unwrap();
+        packet.write_u32::<BigEndian>(self.packet_sequence).unwrap();
+        packet.write_u32::<BigEndian>(self.send_delay).unwrap();
+        packet.write_u16::<BigEndian>(self.burst_sequence).unwrap();
+        packet.write_u16::<BigEndian>(self.burst_sequence).unwrap();
+        packet.write_u32::<BigEndian>(total as u32).unwrap();
+        packet.write_u32::<BigEndian>(offset as u32).unwrap();
+        packet.write_u16::<BigEndian>(data.len() as u16).unwrap();
+        packet.write_u16::<BigEndian>(missing.len() as u16).unwrap();
+        for (offset, len) in missing {
+            packet.write_u32::<BigEndian>(*offset as u32).unwrap();
+            packet.write_u16::<BigEndian>(*len as u16).unwrap();
+        }
+        packet.extend_from_slice(data);
+        self.packet_sequence = self.packet_sequence.wrapping_add(1);
+        packet
+    }
+}
+
+// A burst packet received from the server
+struct Fragment {
+    flags: u8,
+    burst_sequence: u16,
+    total: usize,
+    offset: usize,
+    missing: Vec<(usize, usize)>,
+    data: Vec<u8>,
+}
+
+impl Fragment {
+    fn parse(burst: &Burst, packet: &[u8]) -> Option<Self> {
+        if packet.len() < HEADER_SIZE || BigEndian::read_u16(packet) != REQUEST_BURST {
+            return None
+        }
+        if BigEndian::read_u32(&packet[4..]) != burst.remote_id || BigEndian::read_u32(&packet[8..]) != burst.local_id {
+            return None
+        }
+        let len = BigEndian::read_u16(&packet[32..]) as usize;
+        let count = BigEndian::read_u16(&packet[34..]) as usize;
+        let start = HEADER_SIZE + count * MISSING_SIZE;
+        if packet.len() < start + len {
+            return None
+        }
+        let missing = packet[HEADER_SIZE..start].chunks(MISSING_SIZE).map(|m| (BigEndian::read_u32(m) as usize, BigEndian::read_u16(&m[4..]) as usize)).collect();
+        Some(Self{
+            flags: packet[2],
+            burst_sequence: BigEndian::read_u16(&packet[20..]),
+            total: BigEndian::read_u32(&packet[24..]) as usize,
+            offset: BigEndian::read_u32(&packet[28..]) as usize,
+            missing,
+            data: packet[start..start + len].to_vec(),
+        })
+    }
+}
+
+// Parts of 0..total not covered by the ranges received, as offset and length
+fn gaps(received: &mut [(usize, usize)], total: usize) -> Vec<(usize, usize)> {
+    received.sort();
+    let mut result = Vec::new();
+    let mut offset = 0;
+    for (start, len) in received.iter() {
+        if *start > offset {
+            result.push((offset, start - offset));
+        }
+        offset = offset.max(start + len);
+    }
+    if offset < total {
+        result.push((offset, total - offset));
+    }
+    result
+}
+
+fn rw_request(function: u32, handle: &FileHandle, offset: u32, len: usize) -> Vec<u8> {
+    let mut request: Vec<u8> = Vec::with_capacity(REQUEST_SIZE);
+    request.write_u32::<LittleEndian>(function).unwrap();
+    request.extend_from_slice(&handle.0[2..]);
+    request.extend_from_slice(&[ 0u8; 8 ]);
+    request.write_u32::<BigEndian>(offset).unwrap();
+    request.write_u32::<BigEndian>(len as u32).unwrap();
+    request
+}
+
+fn check_result(result: u32) -> Result<(), NCPError> {
+    match result {
+        RESULT_OK | RESULT_NO_DATA => Ok(()),
+        result => Err(NCPError::InvalidReply(format!("burst failed with result {}", result))),
+    }
+}
+
+impl Connection {
+    // Sets up packet burst for the transfers on this connection; fails if the
+    // transport or server does not offer it, in which case the plain read
+    // and write functions remain in use
+    pub fn negotiate_burst(&mut self) -> Result<&Burst, NCPError> {
+        let Some(socket) = self.transport.burst_socket() else {
+            return Err(NCPError::Unsupported("packet burst over this transport".to_string()))
+        };
+        let local_id = std::process::id() << 16 | self.number as u32;
+        let packet_size = self.transport.max_packet_size();
+        let mut request: Vec<u8> = Vec::new();
+        request.write_u32::<BigEndian>(local_id).unwrap();
+        request.write_u32::<BigEndian>(packet_size as u32).unwrap();
+        request.write_u16::<BigEndian>(socket).unwrap();
+        request.write_u32::<BigEndian>(MAX_BURST_SIZE as u32).unwrap();
+        request.write_u32::<BigEndian>(MAX_BURST_SIZE as u32).unwrap();
+        let reply = self.request(FN_BURST_CONNECTION, &request)?;
+        if reply.len() < 8 {
+            return Err(NCPError::InvalidReply("packet burst reply too short".to_string()))
+        }
+        let packet_size = packet_size.min(BigEndian::read_u32(&reply[4..]) as usize);
+        if packet_size <= HEADER_SIZE + READ_REPLY_SIZE {
+            return Err(NCPError::Unsupported(format!("packet burst with packets of {} bytes", packet_size)))
+        }
+        let burst = Burst{ local_id, remote_id: BigEndian::read_u32(&reply), packet_size, packet_sequence: 0, burst_sequence: 0, send_delay: 0 };
+        Ok(self.burst.insert(burst))
+    }
+
+    // Sends request as a burst, and returns the burst replying to it
+    fn burst_exchange(&mut self, request: &[u8]) -> Result<Vec<u8>, NCPError> {
+        let Some(mut burst) = self.burst else {
+            return Err(NCPError::Unsupported("packet burst was not negotiated".to_string()))
+        };
+        burst.burst_sequence = burst.burst_sequence.wrapping_add(1);
+        let result = self.burst_transfer(&mut burst, request);
+        self.burst = Some(burst);
+        result
+    }
+
+    fn burst_transfer(&mut self, burst: &mut Burst, request: &[u8]) -> Result<Vec<u8>, NCPError> {
+        let fragment_size = burst.fragment_size();
+        // Parts of the request to send, and of the reply received
+        let mut unsent = vec![ (0, request.len()) ];
+        let mut reply: Vec<u8> = Vec::new();
+        let mut received: Vec<(usize, usize)> = Vec::new();
+        let mut attempts = 0;
+        let mut busy = 0;
+        while attempts < self.retries {
+            attempts += 1;
+            let fragments: Vec<(usize, usize)> = unsent.iter().flat_map(|(offset, len)| {
+                (*offset..offset + len).step_by(fragment_size).map(move |o| (o, fragment_size.min(offset + len - o)))
+            }).collect();
+            for (n, (offset, len)) in fragments.iter().enumerate() {
+                if n > 0 && burst.send_delay > 0 {
+                    std::thread::sleep(Duration::from_micros(burst.send_delay as u64 * 100));
+                }
+                let flags = if n + 1 == fragments.len() { FLAG_END_OF_BURST } else { 0 };
+                let packet = burst.packet(flags, request.len(), *offset, &[], &request[*offset..offset + len]);
+                self.transport.send(&packet)?;
+            }
+            unsent.clear();
+
+            let mut deadline = Instant::now() + self.timeout;
+            while let Some(packet) = self.transport.receive(deadline.saturating_duration_since(Instant::now()))? {
+                let Some(fragment) = Fragment::parse(burst, &packet) else { continue };
+                if fragment.burst_sequence != burst.burst_sequence {
+                    continue;
+                }
+                if fragment.flags & FLAG_SYSTEM != 0 {
+                    if fragment.flags & FLAG_ABORT != 0 {
+                        return Err(NCPError::ConnectionLost("server aborted the burst".to_string()))
+                    }
+                    if fragment.flags & FLAG_BUSY != 0 {
+                        busy += 1;
+                        if busy > connection::MAX_BUSY_REPLIES {
+                            return Err(NCPError::Timeout)
+                        }
+                        deadline = Instant::now() + self.timeout;
+                        continue;
+                    }
+                    if !fragment.missing.is_empty() {
+                        // The server missed part of the request: send it again,
+                        // leaving more time between the fragments
+                        unsent = fragment.missing.into_iter().filter(|(o, l)| o + l <= request.len()).collect();
+                        burst.send_delay = (burst.send_delay * 2).clamp(1, MAX_SEND_DELAY);
+                        break;
+                    }
+                    continue;
+                }
+                if fragment.total > MAX_BURST_SIZE || fragment.offset + fragment.data.len() > fragment.total {
+                    continue;
+                }
+                if reply.len() != fragment.total {
+                    reply = vec![ 0u8; fragment.total ];
+                    received.clear();
+                }
+                reply[fragment.offset..fragment.offset + fragment.data.len()].copy_from_slice(&fragment.data);
+                received.push((fragment.offset, fragment.data.len()));
+                let missing = gaps(&mut received, reply.len());
+                if missing.is_empty() {
+                    return Ok(reply)
+                }
+                if fragment.flags & FLAG_END_OF_BURST != 0 {
+                    let packet = burst.packet(FLAG_SYSTEM, 0, 0, &missing, &[]);
+                    self.transport.send(&packet)?;
+                    deadline = Instant::now() + self.timeout;
+                }
+            }
+            if !unsent.is_empty() {
+                continue;
+            }
+            if received.is_empty() {
+                // Nothing came back; the request may not have arrived
+                unsent = vec![ (0, request.len()) ];
+            } else {
+                let missing = gaps(&mut received, reply.len());
+                let packet = burst.packet(FLAG_SYSTEM, 0, 0, &missing, &[]);
+                self.transport.send(&packet)?;
+            }
+        }
+        Err(NCPError::Timeout)
+    }
+
+    // Reads up to len bytes at offset in a single burst; an empty result
+    // means the end of the file
+    pub fn burst_read(&mut self, handle: &FileHandle, offset: u32, len: usize) -> Result<Vec<u8>, NCPError> {
+        let len = len.min(MAX_BURST_SIZE - READ_REPLY_SIZE);
+        let reply = self.burst_exchange(&rw_request(BURST_READ, handle, offset, len))?;
+        if reply.len() < READ_REPLY_SIZE {
+            return Err(NCPError::InvalidReply("burst read reply too short".to_string()))
+        }
+        check_result(LittleEndian::read_u32(&reply))?;
+        let count = (BigEndian::read_u32(&reply[4..]) as usize).min(len);
+        if reply.len() < READ_REPLY_SIZE + count {
+            return Err(NCPError::InvalidReply("burst read reply shorter than its length".to_string()))
+        }
+        Ok(reply[READ_REPLY_SIZE..READ_REPLY_SIZE + count].to_vec())
+    }
+
+    // Writes as much of data at offset as fits in a single burst, and returns
+    // how much that is
+    pub fn burst_write(&mut self, handle: &FileHandle, offset: u32, data: &[u8]) -> Result<usize, NCPError> {
+        let len = data.len().min(MAX_BURST_SIZE - REQUEST_SIZE);
+        let mut request = rw_request(BURST_WRITE, handle, offset, len);
+        request.extend_from_slice(&data[..len]);
+        let reply = self.burst_exchange(&request)?;
+        if reply.len() < 4 {
+            return Err(NCPError::InvalidReply("burst write reply too short".to_string()))
+        }
+        check_result(LittleEndian::read_u32(&reply))?;
+        Ok(len)
+    }
+}
@@ -0,0 +1,235 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// An NCP service connection, and the functions which set it up: negotiating
+// the buffer size, which limits the data of file reads and writes, logging
+// in and out and the information of the server.
+//
+// Clients log in using an encrypted password: they get a login key of 8
+// bytes from the server, and send the hash of the password (as stored in
+// the bindery, which is salted with the object ID) encrypted with that key,
+// so the password itself is never sent. Logging in using function 23/20
+// sends the password as is, which servers refuse unless SET ALLOW
+// UNENCRYPTED PASSWORDS = ON is given; objects without password can log in
+// either way. A user whose password has expired is logged in as long as
+// grace logins are left, but the login fails with completion code 0xdf.
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::codepage;
+use crate::crypt::{self, LOGIN_KEY_LEN};
+use crate::ncp::{self, NCPError};
+use crate::ncp::burst::Burst;
+use crate::ncp::transport::Transport;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_RETRIES: usize = 5;
+// Time to wait for a reply over a reliable transport, where requests are not
+// sent again
+pub const RELIABLE_TIMEOUT: Duration = Duration::from_secs(30);
+// Buffer size to propose; servers settle on what the network carries
+pub const PROPOSED_BUFFER_SIZE: u16 = 1024;
+// Task number used for all requests
+const TASK: u8 = 1;
+// Servers send a busy reply about once a second while working on a request;
+// give up after this many
+pub const MAX_BUSY_REPLIES: usize = 60;
+
+const FN_NEGOTIATE_BUFFER_SIZE: u8 = 33;
+const FN_LOGOUT: u8 = 25;
+pub const FN_BINDERY: u8 = 23;
+const SUB_LOGIN_OBJECT: u8 = 20;
+const SUB_GET_LOGIN_KEY: u8 = 23;
+const SUB_KEYED_LOGIN: u8 = 24;
+const SUB_GET_SERVER_INFO: u8 = 17;
+
+#[derive(Clone, Debug)]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: (u8, u8),
+    pub revision: u8,
+    pub max_connections: u16,
+    pub connections_in_use: u16,
+    pub peak_connections: u16,
+    pub max_volumes: u16,
+    pub sft_level: u8,
+    pub tts_level: u8,
+}
+
+pub struct Connection {
+    pub(crate) transport: Box<dyn Transport>,
+    pub number: u16,
+    sequence: u8,
+    // Largest amount of data which may be read or written at once
+    pub buffer_size: usize,
+    pub timeout: Duration,
+    pub retries: usize,
+    // Set once packet burst is negotiated, after which files are read and
+    // written using it
+    pub burst: Option<Burst>,
+}
+
+// Zero-terminated string of fixed size
+pub fn fixed_string(data: &[u8]) -> String {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    codepage::DEFAULT.decode(&data[..len])
+}
+
+// String preceded by its length
+pub fn add_pstring(out: &mut Vec<u8>, s: &str) {
+    let s = codepage::DEFAULT.encode(s);
+    let len = s.len().min(255);
+    out.push(len as u8);
+    out.extend_from_slice(&s[..len]);
+}
+
+impl Connection {
+    // Creates a service connection on the transport
+    pub fn create(transport: Box<dyn Transport>) -> Result<Self, NCPError> {
+        let buffer_size = transport.max_packet_size();
+        let (timeout, retries) = if transport.is_reliable() { (RELIABLE_TIMEOUT, 1) } else { (DEFAULT_TIMEOUT, DEFAULT_RETRIES) };
+        let mut conn = Self{ transport, number: 0xffff, sequence: 0, buffer_size, timeout, retries, burst: None };
+        let reply = conn.exchange(ncp::REQUEST_CREATE_CONNECTION, 0, &[])?;
+        conn.number = (reply[5] as u16) << 8 | reply[3] as u16;
+        Ok(conn)
+    }
+
+    fn packet(&self, request_type: u16, function: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet: Vec<u8> = Vec::with_capacity(ncp::REQUEST_HEADER_SIZE + data.len());
+        packet.write_u16::<BigEndian>(request_type).unwrap();
+        packet.extend_from_slice(&[ self.sequence, self.number as u8, TASK, (self.number >> 8) as u8, function ]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    // Sends the request until its reply arrives, and returns the whole reply
+    fn exchange(&mut self, request_type: u16, function: u8, data: &[u8]) -> Result<Vec<u8>, NCPError> {
+        let packet = self.packet(request_type, function, data);
+        let mut busy = 0;
+        for _ in 0..self.retries {
+            self.transport.send(&packet)?;
+            let mut deadline = Instant::now() + self.timeout;
+            while let Some(reply) = self.transport.receive(deadline.saturating_duration_since(Instant::now()))? {
+                if reply.len() < ncp::REPLY_HEADER_SIZE - 2 || reply[2] != self.sequence {
+                    continue;
+                }
+                match BigEndian::read_u16(&reply) {
+                    ncp::REPLY_BUSY => {
+                        // The request arrived; keep waiting for its reply
+                        busy += 1;
+                        if busy > MAX_BUSY_REPLIES {
+                            return Err(NCPError::Timeout)
+                        }
+                        deadline = Instant::now() + self.timeout;
+                    },
+                    ncp::REPLY if reply.len() >= ncp::REPLY_HEADER_SIZE => return Ok(reply),
+                    _ => { },
+                }
+            }
+        }
+        Err(NCPError::Timeout)
+    }
+
+    // Sends a request, and returns the data of its reply
+    pub fn request(&mut self, function: u8, data: &[u8]) -> Result<Vec<u8>, NCPError> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut reply = self.exchange(ncp::REQUEST, function, data)?;
+        let (completion, status) = (reply[6], reply[7]);
+        if status & (ncp::STATUS_BAD_CONNECTION | ncp::STATUS_NO_CONNECTION | ncp::STATUS_SERVER_DOWN) != 0 {
+            let reason = if status & ncp::STATUS_SERVER_DOWN != 0 { "server is going down" } else { "server no longer knows the connection" };
+            return Err(NCPError::ConnectionLost(reason.to_string()))
+        }
+        if completion != ncp::COMPLETION_OK {
+            return Err(NCPError::Completion(completion))
+        }
+        Ok(reply.split_off(ncp::REPLY_HEADER_SIZE))
+    }
+
+    // Sends a request to a function which takes a subfunction, preceded by
+    // the length of the subfunction and its data
+    pub fn request_sub(&mut self, function: u8, subfunction: u8, data: &[u8]) -> Result<Vec<u8>, NCPError> {
+        let mut request: Vec<u8> = Vec::with_capacity(3 + data.len());
+        request.write_u16::<BigEndian>(1 + data.len() as u16).unwrap();
+        request.push(subfunction);
+        request.extend_from_slice(data);
+        self.request(function, &request)
+    }
+
+    // Agrees on the buffer size with the server
+    pub fn negotiate_buffer_size(&mut self) -> Result<usize, NCPError> {
+        let reply = self.request(FN_NEGOTIATE_BUFFER_SIZE, &PROPOSED_BUFFER_SIZE.to_be_bytes())?;
+        if reply.len() < 2 {
+            return Err(NCPError::InvalidReply("buffer size reply too short".to_string()))
+        }
+        let size = (BigEndian::read_u16(&reply) as usize).min(PROPOSED_BUFFER_SIZE as usize);
+        self.buffer_size = size.min(self.transport.max_packet_size());
+        Ok(self.buffer_size)
+    }
+
+    pub fn server_info(&mut self) -> Result<ServerInfo, NCPError> {
+        let reply = self.request_sub(FN_BINDERY, SUB_GET_SERVER_INFO, &[])?;
+        if reply.len() < 61 {
+            return Err(NCPError::InvalidReply("server information too short".to_string()))
+        }
+        Ok(ServerInfo{
+            name: fixed_string(&reply[0..48]),
+            version: (reply[48], reply[49]),
+            max_connections: BigEndian::read_u16(&reply[50..]),
+            connections_in_use: BigEndian::read_u16(&reply[52..]),
+            max_volumes: BigEndian::read_u16(&reply[54..]),
+            revision: reply[56],
+            sft_level: reply[57],
+            tts_level: reply[58],
+            peak_connections: BigEndian::read_u16(&reply[59..]),
+        })
+    }
+
+    // Logs in using the unencrypted password; names and passwords are in
+    // uppercase, as clients send them
+    pub fn login_unencrypted(&mut self, object_type: u16, name: &str, password: &str) -> Result<(), NCPError> {
+        let mut request: Vec<u8> = Vec::new();
+        request.write_u16::<BigEndian>(object_type).unwrap();
+        add_pstring(&mut request, &name.to_uppercase());
+        add_pstring(&mut request, &password.to_uppercase());
+        self.request_sub(FN_BINDERY, SUB_LOGIN_OBJECT, &request)?;
+        Ok(())
+    }
+
+    // Logs in using the password encrypted with a login key from the server
+    pub fn login_encrypted(&mut self, object_type: u16, name: &str, password: &str) -> Result<(), NCPError> {
+        let name = name.to_uppercase();
+        let objid = self.object_id(object_type, &name)?;
+        let reply = self.request_sub(FN_BINDERY, SUB_GET_LOGIN_KEY, &[])?;
+        if reply.len() < LOGIN_KEY_LEN {
+            return Err(NCPError::InvalidReply("login key too short".to_string()))
+        }
+        let mut key = [ 0u8; LOGIN_KEY_LEN ];
+        key.copy_from_slice(&reply[..LOGIN_KEY_LEN]);
+        let hash = crypt::hash_object_password(objid, &codepage::DEFAULT.encode(&password.to_uppercase()));
+        let mut request = crypt::nw_encrypt(&key, &hash).to_vec();
+        request.write_u16::<BigEndian>(object_type).unwrap();
+        add_pstring(&mut request, &name);
+        self.request_sub(FN_BINDERY, SUB_KEYED_LOGIN, &request)?;
+        Ok(())
+    }
+
+    pub fn logout(&mut self) -> Result<(), NCPError> {
+        self.request(FN_LOGOUT, &[])?;
+        Ok(())
+    }
+
+    // Ends the service connection; the server is not waited for long
+    pub fn destroy(mut self) -> Result<(), NCPError> {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.retries = 1;
+        self.timeout = DEFAULT_TIMEOUT;
+        match self.exchange(ncp::REQUEST_DESTROY_CONNECTION, 0, &[]) {
+            Ok(_) | Err(NCPError::Timeout) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
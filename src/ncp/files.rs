@@ -0,0 +1,412 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Looking up and listing files using the name space functions (87), which
+// NetWare 3.x and later offer. Rather than directory handles, these refer to
+// directories by their volume number and directory base, which is the number
+// of their directory entry, optionally followed by path components: a
+// 'handle path'. Only the DOS name space is used, which every volume has.
+//
+// The information returned on an entry (RIM_ALL) is a fixed structure of 77
+// bytes followed by the name, where dates and times are in DOS format and, as
+// elsewhere in these functions, little endian; object IDs are big endian as
+// in the bindery.
+//
+// Files are opened and created using the name space functions as well, which
+// return a handle of four bytes. The older functions which read (72), write
+// (73) and close (66) files take handles of six bytes, the first word of
+// which is the second one plus one; their offsets and lengths are big endian.
+// Reads should not cross a multiple of the buffer size, and a read starting
+// at an odd offset has a byte of padding before its data.
+use std::io::{Read, Write};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+
+use crate::codepage;
+use crate::ncp::{self, NCPError};
+use crate::ncp::connection::{self, Connection};
+use crate::nwfs::volume;
+
+const FN_DIRECTORY: u8 = 22;
+const SUB_GET_VOLUME_NAME: u8 = 6;
+
+const FN_CLOSE_FILE: u8 = 66;
+const FN_READ_FILE: u8 = 72;
+const FN_WRITE_FILE: u8 = 73;
+
+const FN_NAME_SPACE: u8 = 87;
+const NS_OPEN_CREATE: u8 = 1;
+const NS_OBTAIN_INFO: u8 = 6;
+const NS_MODIFY_DOS_INFO: u8 = 7;
+const NS_INITIALIZE_SEARCH: u8 = 2;
+const NS_SEARCH: u8 = 3;
+const NS_GENERATE_DIR_BASE: u8 = 22;
+
+pub const NAME_SPACE_DOS: u8 = 0;
+// Hidden and system entries, files and directories
+const SEARCH_ALL: u16 = 0x8006;
+const RIM_ALL: u32 = 0x0fff;
+// Flag of a handle path giving a directory base rather than a handle
+const DIR_BASE: u8 = 1;
+const NO_DIR_BASE: u8 = 0xff;
+const WILDCARD_ALL: [ u8; 3 ] = [ 2, 0xff, b'*' ];
+
+// Open/create modes
+pub const MODE_OPEN: u8 = 0x01;
+pub const MODE_REPLACE: u8 = 0x02;
+pub const MODE_CREATE: u8 = 0x08;
+// Access rights to open a file with
+pub const ACCESS_READ: u16 = 0x0001;
+pub const ACCESS_WRITE: u16 = 0x0002;
+pub const ACCESS_DENY_WRITE: u16 = 0x0008;
+// Fields of the DOS information to modify
+const MODIFY_DATE: u32 = 0x0100;
+const MODIFY_TIME: u32 = 0x0200;
+const DOS_INFO_SIZE: usize = 38;
+
+const INFO_SIZE: usize = 77;
+// Search sequence: volume, directory base and position
+const SEARCH_SEQUENCE_SIZE: usize = 9;
+
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub name: String,
+    pub attributes: u32,
+    pub size: u32,
+    // DOS date << 16 | time, as nwfs::format_timestamp() takes
+    pub created: u32,
+    pub modified: u32,
+    pub archived: u32,
+    // Date only
+    pub accessed: u16,
+    pub creator: u32,
+    pub modifier: u32,
+    pub archiver: u32,
+    pub rights_mask: u16,
+    // Directory base of the entry
+    pub entry: u32,
+    pub volume: u8,
+}
+
+impl Info {
+    fn parse(data: &[u8]) -> Result<Self, NCPError> {
+        if data.len() < INFO_SIZE || data.len() < INFO_SIZE + data[INFO_SIZE - 1] as usize {
+            return Err(NCPError::InvalidReply("entry information too short".to_string()))
+        }
+        let timestamp = |offset: usize| (LittleEndian::read_u16(&data[offset + 2..]) as u32) << 16 | LittleEndian::read_u16(&data[offset..]) as u32;
+        let name_len = data[INFO_SIZE - 1] as usize;
+        Ok(Self{
+            name: codepage::DEFAULT.decode(&data[INFO_SIZE..INFO_SIZE + name_len]),
+            attributes: LittleEndian::read_u32(&data[4..]),
+            size: LittleEndian::read_u32(&data[10..]),
+            created: timestamp(20),
+            creator: BigEndian::read_u32(&data[24..]),
+            modified: timestamp(28),
+            modifier: BigEndian::read_u32(&data[32..]),
+            accessed: LittleEndian::read_u16(&data[36..]),
+            archived: timestamp(38),
+            archiver: BigEndian::read_u32(&data[42..]),
+            rights_mask: LittleEndian::read_u16(&data[46..]),
+            entry: LittleEndian::read_u32(&data[48..]),
+            volume: data[56],
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & volume::ATTR_DIRECTORY != 0
+    }
+}
+
+// Splits VOLUME:PATH into the volume and the path components; both slashes
+// and backslashes separate them
+pub fn split_path(path: &str) -> Option<(&str, Vec<&str>)> {
+    let (volume, rest) = path.split_once(':')?;
+    if volume.is_empty() {
+        return None
+    }
+    Some((volume, rest.split(['/', '\\']).filter(|c| !c.is_empty()).collect()))
+}
+
+fn add_handle_path(request: &mut Vec<u8>, volume: u8, dir_base: Option<u32>, components: &[&str]) {
+    request.push(volume);
+    request.write_u32::<LittleEndian>(dir_base.unwrap_or(0)).unwrap();
+    request.push(if dir_base.is_some() { DIR_BASE } else { NO_DIR_BASE });
+    request.push(components.len() as u8);
+    for c in components {
+        connection::add_pstring(request, &c.to_uppercase());
+    }
+}
+
+// Handle of an open file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileHandle(pub [ u8; 6 ]);
+
+impl FileHandle {
+    fn from_ns(handle: &[u8]) -> Self {
+        let (low, high) = (LittleEndian::read_u16(handle), LittleEndian::read_u16(&handle[2..]));
+        let mut result = [ 0u8; 6 ];
+        LittleEndian::write_u16(&mut result, low.wrapping_add(1));
+        LittleEndian::write_u16(&mut result[2..], low);
+        LittleEndian::write_u16(&mut result[4..], high);
+        Self(result)
+    }
+}
+
+// An open file on the server, which is read and written at the current
+// offset. Reads and writes are limited to the buffer size, or a burst if
+// packet burst is negotiated, so they may return less than asked for
+pub struct RemoteFile<'a> {
+    conn: &'a mut Connection,
+    pub handle: FileHandle,
+    pub info: Info,
+    pub offset: u32,
+}
+
+impl RemoteFile<'_> {
+    pub fn close(self) -> Result<(), NCPError> {
+        self.conn.close_file(&self.handle)
+    }
+}
+
+impl Read for RemoteFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = if self.conn.burst.is_some() {
+            self.conn.burst_read(&self.handle, self.offset, buf.len())
+        } else {
+            self.conn.read_file(&self.handle, self.offset, buf.len())
+        };
+        let data = data.map_err(std::io::Error::other)?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.offset += data.len() as u32;
+        Ok(data.len())
+    }
+}
+
+impl Write for RemoteFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = if self.conn.burst.is_some() {
+            self.conn.burst_write(&self.handle, self.offset, buf)
+        } else {
+            self.conn.write_file(&self.handle, self.offset, buf)
+        };
+        let len = len.map_err(std::io::Error::other)?;
+        self.offset += len as u32;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn not_found(e: NCPError, path: &str) -> NCPError {
+    match e {
+        NCPError::Completion(ncp::COMPLETION_FAILURE) | NCPError::Completion(0x98) | NCPError::Completion(0x9c) | NCPError::NotFound(_) => NCPError::NotFound(path.to_string()),
+        e => e,
+    }
+}
+
+impl Connection {
+    // Name of the volume mounted as number, or None if there is none
+    pub fn volume_name(&mut self, number: u8) -> Result<Option<String>, NCPError> {
+        let reply = match self.request_sub(FN_DIRECTORY, SUB_GET_VOLUME_NAME, &[ number ]) {
+            Ok(reply) => reply,
+            Err(NCPError::Completion(0x98)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = *reply.first().unwrap_or(&0) as usize;
+        if len == 0 || reply.len() < 1 + len {
+            return Ok(None)
+        }
+        Ok(Some(codepage::DEFAULT.decode(&reply[1..1 + len])))
+    }
+
+    // Mounted volumes, by number
+    pub fn volumes(&mut self, max_volumes: u16) -> Result<Vec<(u8, String)>, NCPError> {
+        let mut result = Vec::new();
+        for number in 0..max_volumes.min(256) {
+            if let Some(name) = self.volume_name(number as u8)? {
+                result.push((number as u8, name));
+            }
+        }
+        Ok(result)
+    }
+
+    // Volume number and directory base of the root of a volume
+    pub fn volume_root(&mut self, name: &str) -> Result<(u8, u32), NCPError> {
+        let mut request = vec![ NS_GENERATE_DIR_BASE, NAME_SPACE_DOS, 0, 0, 0 ];
+        add_handle_path(&mut request, 0, None, &[ name ]);
+        let reply = self.request(FN_NAME_SPACE, &request).map_err(|e| not_found(e, name))?;
+        if reply.len() < 9 {
+            return Err(NCPError::InvalidReply("directory base reply too short".to_string()))
+        }
+        Ok((reply[8], LittleEndian::read_u32(&reply[4..])))
+    }
+
+    // Information on the entry in a directory, or on the directory itself if
+    // name is None
+    pub fn obtain_info(&mut self, volume: u8, dir_base: u32, name: Option<&str>) -> Result<Info, NCPError> {
+        let mut request = vec![ NS_OBTAIN_INFO, NAME_SPACE_DOS, NAME_SPACE_DOS ];
+        request.write_u16::<LittleEndian>(SEARCH_ALL).unwrap();
+        request.write_u32::<LittleEndian>(RIM_ALL).unwrap();
+        let components: Vec<&str> = name.into_iter().collect();
+        add_handle_path(&mut request, volume, Some(dir_base), &components);
+        let reply = self.request(FN_NAME_SPACE, &request).map_err(|e| not_found(e, name.unwrap_or("")))?;
+        Info::parse(&reply)
+    }
+
+    // Walks VOLUME:PATH one component at a time, and returns the entry it
+    // refers to
+    pub fn lookup(&mut self, path: &str) -> Result<Info, NCPError> {
+        let Some((volume, components)) = split_path(path) else {
+            return Err(NCPError::NotFound(path.to_string()))
+        };
+        let (volume, mut dir_base) = self.volume_root(volume).map_err(|e| not_found(e, path))?;
+        let mut info = self.obtain_info(volume, dir_base, None)?;
+        for c in components {
+            if !info.is_directory() {
+                return Err(NCPError::NotFound(path.to_string()))
+            }
+            info = self.obtain_info(volume, dir_base, Some(c)).map_err(|e| not_found(e, path))?;
+            dir_base = info.entry;
+        }
+        Ok(info)
+    }
+
+    // Entries of a directory, as returned by lookup()
+    pub fn list_directory(&mut self, directory: &Info) -> Result<Vec<Info>, NCPError> {
+        let mut request = vec![ NS_INITIALIZE_SEARCH, NAME_SPACE_DOS, 0 ];
+        add_handle_path(&mut request, directory.volume, Some(directory.entry), &[]);
+        let reply = self.request(FN_NAME_SPACE, &request)?;
+        if reply.len() < SEARCH_SEQUENCE_SIZE {
+            return Err(NCPError::InvalidReply("search sequence too short".to_string()))
+        }
+        let mut sequence = reply[..SEARCH_SEQUENCE_SIZE].to_vec();
+
+        let mut result = Vec::new();
+        loop {
+            let mut request = vec![ NS_SEARCH, NAME_SPACE_DOS, 0 ];
+            request.write_u16::<LittleEndian>(SEARCH_ALL).unwrap();
+            request.write_u32::<LittleEndian>(RIM_ALL).unwrap();
+            request.extend_from_slice(&sequence);
+            request.extend_from_slice(&WILDCARD_ALL);
+            let reply = match self.request(FN_NAME_SPACE, &request) {
+                Ok(reply) => reply,
+                // No more entries
+                Err(NCPError::Completion(ncp::COMPLETION_FAILURE)) => break,
+                Err(e) => return Err(e),
+            };
+            if reply.len() < SEARCH_SEQUENCE_SIZE + 1 {
+                return Err(NCPError::InvalidReply("search reply too short".to_string()))
+            }
+            sequence.copy_from_slice(&reply[..SEARCH_SEQUENCE_SIZE]);
+            result.push(Info::parse(&reply[SEARCH_SEQUENCE_SIZE + 1..])?);
+        }
+        Ok(result)
+    }
+
+    // Looks up the directory holding the entry path refers to, which need
+    // not exist, and returns it along with the name of the entry
+    pub fn lookup_parent(&mut self, path: &str) -> Result<(Info, String), NCPError> {
+        let Some((volume, mut components)) = split_path(path) else {
+            return Err(NCPError::NotFound(path.to_string()))
+        };
+        let Some(name) = components.pop() else {
+            return Err(NCPError::NotFound(path.to_string()))
+        };
+        let parent = format!("{}:{}", volume, components.join("/"));
+        let info = self.lookup(&parent)?;
+        if !info.is_directory() {
+            return Err(NCPError::NotFound(parent))
+        }
+        Ok((info, name.to_uppercase()))
+    }
+
+    // Opens or creates the entry called name in directory, as given by mode,
+    // with the given access rights
+    pub fn open_file(&mut self, directory: &Info, name: &str, mode: u8, access: u16) -> Result<RemoteFile<'_>, NCPError> {
+        let mut request = vec![ NS_OPEN_CREATE, NAME_SPACE_DOS, mode ];
+        request.write_u16::<LittleEndian>(SEARCH_ALL).unwrap();
+        request.write_u32::<LittleEndian>(RIM_ALL).unwrap();
+        request.write_u32::<LittleEndian>(0).unwrap();
+        request.write_u16::<LittleEndian>(access).unwrap();
+        add_handle_path(&mut request, directory.volume, Some(directory.entry), &[ name ]);
+        let reply = self.request(FN_NAME_SPACE, &request).map_err(|e| not_found(e, name))?;
+        if reply.len() < 6 {
+            return Err(NCPError::InvalidReply("open file reply too short".to_string()))
+        }
+        let handle = FileHandle::from_ns(&reply);
+        let info = Info::parse(&reply[6..])?;
+        Ok(RemoteFile{ conn: self, handle, info, offset: 0 })
+    }
+
+    // Opens the file path refers to for reading
+    pub fn open_path(&mut self, path: &str) -> Result<RemoteFile<'_>, NCPError> {
+        let (directory, name) = self.lookup_parent(path)?;
+        self.open_file(&directory, &name, MODE_OPEN, ACCESS_READ | ACCESS_DENY_WRITE).map_err(|e| not_found(e, path))
+    }
+
+    // Creates the file path refers to, replacing it if it exists
+    pub fn create_path(&mut self, path: &str) -> Result<RemoteFile<'_>, NCPError> {
+        let (directory, name) = self.lookup_parent(path)?;
+        self.open_file(&directory, &name, MODE_CREATE | MODE_REPLACE, ACCESS_READ | ACCESS_WRITE)
+    }
+
+    // Reads up to len bytes at offset, not crossing a multiple of the buffer
+    // size; an empty result means the end of the file
+    pub fn read_file(&mut self, handle: &FileHandle, offset: u32, len: usize) -> Result<Vec<u8>, NCPError> {
+        let len = len.min(self.buffer_size - offset as usize % self.buffer_size);
+        let mut request = vec![ 0 ];
+        request.extend_from_slice(&handle.0);
+        request.write_u32::<BigEndian>(offset).unwrap();
+        request.write_u16::<BigEndian>(len as u16).unwrap();
+        let reply = self.request(FN_READ_FILE, &request)?;
+        let start = 2 + (offset as usize & 1);
+        if reply.len() < 2 {
+            return Err(NCPError::InvalidReply("read reply too short".to_string()))
+        }
+        let count = (BigEndian::read_u16(&reply) as usize).min(len);
+        if reply.len() < start + count {
+            return Err(NCPError::InvalidReply("read reply shorter than its length".to_string()))
+        }
+        Ok(reply[start..start + count].to_vec())
+    }
+
+    // Writes as much of data at offset as fits in the buffer size, and returns
+    // how much that is
+    pub fn write_file(&mut self, handle: &FileHandle, offset: u32, data: &[u8]) -> Result<usize, NCPError> {
+        let len = data.len().min(self.buffer_size);
+        let mut request = vec![ 0 ];
+        request.extend_from_slice(&handle.0);
+        request.write_u32::<BigEndian>(offset).unwrap();
+        request.write_u16::<BigEndian>(len as u16).unwrap();
+        request.extend_from_slice(&data[..len]);
+        self.request(FN_WRITE_FILE, &request)?;
+        Ok(len)
+    }
+
+    pub fn close_file(&mut self, handle: &FileHandle) -> Result<(), NCPError> {
+        let mut request = vec![ 0 ];
+        request.extend_from_slice(&handle.0);
+        self.request(FN_CLOSE_FILE, &request)?;
+        Ok(())
+    }
+
+    // Sets the modification time of the entry called name in directory, as
+    // DOS date << 16 | time
+    pub fn set_modified(&mut self, directory: &Info, name: &str, timestamp: u32) -> Result<(), NCPError> {
+        let mut request = vec![ NS_MODIFY_DOS_INFO, NAME_SPACE_DOS, 0 ];
+        request.write_u16::<LittleEndian>(SEARCH_ALL).unwrap();
+        request.write_u32::<LittleEndian>(MODIFY_DATE | MODIFY_TIME).unwrap();
+        let mut info = [ 0u8; DOS_INFO_SIZE ];
+        LittleEndian::write_u16(&mut info[16..], (timestamp >> 16) as u16);
+        LittleEndian::write_u16(&mut info[18..], timestamp as u16);
+        request.extend_from_slice(&info);
+        add_handle_path(&mut request, directory.volume, Some(directory.entry), &[ name ]);
+        self.request(FN_NAME_SPACE, &request).map_err(|e| not_found(e, name))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// NetWare Core Protocol, which workstations use to talk to a file server.
+//
+// A client first creates a service connection, which gives it a connection
+// number, and then sends requests on it, one at a time. Every request has a
+// sequence number, the connection number, a task number and a function
+// code, followed by the data of the function; many functions (such as 22
+// for directory services and 23 for the bindery) have subfunctions, which
+// follow a length word. The reply echoes the sequence and connection
+// numbers, and has a completion code (0 for success) and connection status.
+// Requests which get no reply are sent again using the same sequence
+// number, in which case the server sends its last reply again; a server
+// which needs more time replies with a 'request being processed' packet.
+//
+// Unlike most of NetWare, the older functions use big endian fields; those
+// added with NetWare 3.x, such as the name space functions of 87, use
+// little endian.
+//
+// How the packets are carried is up to the transport: IPX is used up to
+// NetWare 4, TCP from NetWare 5 onwards; see transport. Files may be read and
+// written using packet burst over IPX; see burst.
+pub mod bindery;
+pub mod burst;
+pub mod connection;
+pub mod files;
+pub mod transport;
+
+use std::fmt;
+
+pub const REQUEST_CREATE_CONNECTION: u16 = 0x1111;
+pub const REQUEST: u16 = 0x2222;
+pub const REPLY: u16 = 0x3333;
+pub const REQUEST_DESTROY_CONNECTION: u16 = 0x5555;
+pub const REPLY_BUSY: u16 = 0x9999;
+
+// Request header: type, sequence, connection low, task, connection high and
+// function; reply header: type, sequence, connection low, task, connection
+// high, completion code and connection status
+pub const REQUEST_HEADER_SIZE: usize = 7;
+pub const REPLY_HEADER_SIZE: usize = 8;
+
+// Connection status flags
+pub const STATUS_BAD_CONNECTION: u8 = 0x01;
+pub const STATUS_NO_CONNECTION: u8 = 0x04;
+pub const STATUS_SERVER_DOWN: u8 = 0x10;
+pub const STATUS_MESSAGE_WAITING: u8 = 0x40;
+
+// Completion codes
+pub const COMPLETION_OK: u8 = 0x00;
+pub const COMPLETION_PASSWORD_EXPIRED: u8 = 0xdf;
+pub const COMPLETION_NO_SUCH_PROPERTY: u8 = 0xfb;
+pub const COMPLETION_NO_SUCH_OBJECT: u8 = 0xfc;
+pub const COMPLETION_FAILURE: u8 = 0xff;
+
+pub fn completion_message(code: u8) -> &'static str {
+    match code {
+        0x80 => "file in use",
+        0x81 => "out of file handles",
+        0x84 => "no create privileges",
+        0x85 => "no create or delete privileges",
+        0x88 => "invalid file handle",
+        0x89 => "no search privileges",
+        0x8a => "no delete privileges",
+        0x8b => "no rename privileges",
+        0x8c => "no modify privileges",
+        0x8d | 0x8e => "some or all files in use",
+        0x90 => "file is read-only",
+        0x93 => "no read privileges",
+        0x94 => "no write privileges",
+        0x96 => "server out of memory",
+        0x98 => "volume does not exist",
+        0x9b => "invalid directory handle",
+        0x9c => "invalid path",
+        0xbf => "invalid name space",
+        0xc5 => "account locked by intruder detection",
+        0xd9 => "no more connections allowed",
+        0xda => "login not allowed at this time",
+        0xdb => "login not allowed from this station",
+        0xdc => "account disabled",
+        0xde => "password expired and no grace logins left",
+        COMPLETION_PASSWORD_EXPIRED => "password expired",
+        0xec => "no such segment",
+        0xef => "invalid name",
+        0xf0 => "wildcard not allowed",
+        0xf1 => "invalid bindery security",
+        0xf9 => "no property read privilege",
+        COMPLETION_NO_SUCH_PROPERTY => "no such property",
+        COMPLETION_NO_SUCH_OBJECT => "no such object",
+        COMPLETION_FAILURE => "failure (not found or access denied)",
+        _ => "unknown error",
+    }
+}
+
+#[derive(Debug)]
+pub enum NCPError {
+    IoError(std::io::Error),
+    // The server did not reply in time
+    Timeout,
+    // The request failed with this completion code
+    Completion(u8),
+    ConnectionLost(String),
+    InvalidReply(String),
+    NotFound(String),
+    Unsupported(String),
+}
+
+impl From<std::io::Error> for NCPError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl fmt::Display for NCPError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NCPError::IoError(e) => write!(f, "{}", e),
+            NCPError::Timeout => write!(f, "no reply from server"),
+            NCPError::Completion(code) => write!(f, "{} (completion code {:02x})", completion_message(*code), code),
+            NCPError::ConnectionLost(s) => write!(f, "connection lost: {}", s),
+            NCPError::InvalidReply(s) => write!(f, "invalid reply: {}", s),
+            NCPError::NotFound(s) => write!(f, "'{}' not found", s),
+            NCPError::Unsupported(s) => write!(f, "not supported: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for NCPError { }
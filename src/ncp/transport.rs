@@ -0,0 +1,330 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Transports carrying NCP packets to the server and back.
+//
+// Up to NetWare 4, NCP is carried by IPX: requests are sent to socket 0x451
+// of the server using packet type 17, from a socket of the client's choice.
+// Servers check whether idle clients are still there by sending a watchdog
+// packet to the socket after it, which is answered to keep the connection.
+//
+// Emulators (DOSBox and its descendants, and bridges such as ipxbox) tunnel
+// IPX over UDP: every IPX packet is sent as a datagram to a tunnel server,
+// port 213 by default, which passes it on to the client whose node address
+// is the destination, or to all of them for broadcasts. The node address of
+// a client is its IP address and UDP port, which it learns by registering: it
+// sends an echo packet (socket 2) without addresses, and the reply is sent
+// to its node address.
+//
+// Servers are located by a SAP query. The tunnel delivers by node address
+// only, so a server is reached through the address it sent its SAP reply
+// from; servers with an internal network could otherwise not be reached.
+//
+// NetWare 5 and later also accept NCP over TCP, port 524. Every request is
+// preceded by a header of 16 bytes: the signature 'DmdT', the length of the
+// frame including the header, a version (1) and the largest reply the client
+// accepts; replies are preceded by the signature 'tNcP' and the length of the
+// frame. As TCP does not lose requests, they are not sent again.
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::bindery::OT_FILE_SERVER;
+use crate::ipx::{self, Address, IpxPacket};
+use crate::ncp::NCPError;
+use crate::sap::{self, SapPacket, Service};
+
+pub const DEFAULT_TUNNEL_PORT: u16 = 213;
+// The socket NCP requests are sent from; the server sends watchdog packets
+// to the one after it
+pub const CLIENT_SOCKET: u16 = 0x4003;
+pub const DEFAULT_TCP_PORT: u16 = 524;
+
+const ECHO_SOCKET: u16 = 0x0002;
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(2);
+const REGISTRATION_ATTEMPTS: usize = 3;
+// Time to wait for replies to a SAP query
+pub const SAP_WAIT: Duration = Duration::from_secs(1);
+const WATCHDOG_QUERY: u8 = b'?';
+const WATCHDOG_REPLY: u8 = b'Y';
+
+const TCP_REQUEST_SIGNATURE: u32 = 0x446d6454;
+const TCP_REPLY_SIGNATURE: u32 = 0x744e6350;
+const TCP_VERSION: u32 = 1;
+const TCP_REQUEST_HEADER_SIZE: usize = 16;
+const TCP_REPLY_HEADER_SIZE: usize = 8;
+// Largest reply accepted, which limits the buffer size
+const TCP_MAX_REPLY_SIZE: usize = 65536;
+
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), NCPError>;
+    // Next packet from the server, or None if none arrived in time
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, NCPError>;
+    // Largest NCP packet which can be sent or received in one go
+    fn max_packet_size(&self) -> usize;
+    // Whether requests arrive without having to send them again
+    fn is_reliable(&self) -> bool {
+        false
+    }
+    // Socket the server sends packet burst packets to, if the transport
+    // carries them
+    fn burst_socket(&self) -> Option<u16> {
+        None
+    }
+}
+
+pub struct IpxTunnel {
+    socket: UdpSocket,
+    // Our IPX address; the network is 0 until a packet from a server on the
+    // same network tells it
+    pub address: Address,
+}
+
+// A server found by find_servers()
+#[derive(Clone, Debug)]
+pub struct ServerLocation {
+    pub service: Service,
+    // Where to send requests to
+    pub address: Address,
+}
+
+impl IpxTunnel {
+    // Registers with the tunnel server at host, or host:port
+    pub fn connect(host: &str) -> Result<Self, NCPError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        if host.contains(':') {
+            socket.connect(host)?;
+        } else {
+            socket.connect((host, DEFAULT_TUNNEL_PORT))?;
+        }
+        let mut tunnel = Self{ socket, address: Address::default() };
+        let echo = Address::new(0, [ 0u8; 6 ], ECHO_SOCKET);
+        let registration = IpxPacket::new(ipx::PACKET_TYPE_UNKNOWN, echo, echo, Vec::new());
+        for _ in 0..REGISTRATION_ATTEMPTS {
+            tunnel.send(&registration)?;
+            let deadline = Instant::now() + REGISTRATION_TIMEOUT;
+            while let Some(reply) = tunnel.receive(deadline.saturating_duration_since(Instant::now()))? {
+                if reply.destination.socket == ECHO_SOCKET && reply.source.socket == ECHO_SOCKET {
+                    tunnel.address = Address::new(reply.destination.network, reply.destination.node, CLIENT_SOCKET);
+                    return Ok(tunnel)
+                }
+            }
+        }
+        Err(NCPError::ConnectionLost(format!("no reply from IPX tunnel server {}", host)))
+    }
+
+    pub fn send(&self, packet: &IpxPacket) -> Result<(), NCPError> {
+        self.socket.send(&packet.to_bytes())?;
+        Ok(())
+    }
+
+    // Next IPX packet, or None if none arrived in time; datagrams which do not
+    // hold an IPX packet are skipped
+    pub fn receive(&self, timeout: Duration) -> Result<Option<IpxPacket>, NCPError> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![ 0u8; 65536 ];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None)
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if let Ok(packet) = IpxPacket::parse(&buf[..len]) {
+                return Ok(Some(packet))
+            }
+        }
+    }
+
+    // Broadcasts a SAP query for services of service_type and collects the
+    // replies for the given time
+    pub fn find_servers(&mut self, service_type: u16, wait: Duration) -> Result<Vec<ServerLocation>, NCPError> {
+        let query = SapPacket::Query{ nearest: false, service_type };
+        let destination = Address::new(self.address.network, ipx::BROADCAST_NODE, ipx::SOCKET_SAP);
+        self.send(&IpxPacket::new(ipx::PACKET_TYPE_PEP, destination, self.address, query.to_bytes()))?;
+
+        let mut result: Vec<ServerLocation> = Vec::new();
+        let deadline = Instant::now() + wait;
+        while let Some(packet) = self.receive(deadline.saturating_duration_since(Instant::now()))? {
+            if packet.source.socket != ipx::SOCKET_SAP || packet.destination.socket != self.address.socket {
+                continue;
+            }
+            let Ok(SapPacket::Response{ services, .. }) = SapPacket::parse(&packet.data) else { continue };
+            if packet.transport_control == 0 && self.address.network == 0 {
+                self.address.network = packet.source.network;
+            }
+            for s in services.into_iter().filter(|s| !s.is_unreachable() && (service_type == sap::SERVICE_ALL || s.service_type == service_type)) {
+                if result.iter().any(|l| l.service.name == s.name && l.service.service_type == s.service_type) {
+                    continue;
+                }
+                // The server itself advertises its internal network at one hop
+                let address = if s.hops <= 1 && s.address.network != packet.source.network {
+                    Address::new(packet.source.network, packet.source.node, s.address.socket)
+                } else {
+                    s.address
+                };
+                result.push(ServerLocation{ service: s, address });
+            }
+        }
+        Ok(result)
+    }
+}
+
+// Finds a file server by name, or the first one to answer if no name is
+// given; an address as NETWORK:NODE[:SOCKET] is used as is
+pub fn find_file_server(tunnel: &mut IpxTunnel, name: Option<&str>) -> Result<Address, NCPError> {
+    if let Some(address) = name.and_then(|s| s.parse::<Address>().ok()) {
+        return Ok(address)
+    }
+    let servers = tunnel.find_servers(OT_FILE_SERVER, SAP_WAIT)?;
+    let server = match name {
+        Some(name) => servers.iter().find(|l| l.service.name.eq_ignore_ascii_case(name)),
+        None => servers.first(),
+    };
+    match server {
+        Some(l) => Ok(l.address),
+        None => Err(NCPError::NotFound(name.unwrap_or("file server").to_string())),
+    }
+}
+
+// Transport to a server over TCP if a host is given, otherwise over the IPX
+// tunnel to the server given by find_file_server()
+pub fn open(tcp: Option<&str>, tunnel: Option<&str>, server: Option<&str>) -> Result<Box<dyn Transport>, NCPError> {
+    if let Some(host) = tcp {
+        return Ok(Box::new(TcpTransport::connect(host)?))
+    }
+    let Some(host) = tunnel else {
+        return Err(NCPError::Unsupported("no IPX tunnel or TCP host given".to_string()))
+    };
+    let mut tunnel = IpxTunnel::connect(host)?;
+    let server = find_file_server(&mut tunnel, server)?;
+    Ok(Box::new(IpxTransport::new(tunnel, server)))
+}
+
+pub struct IpxTransport {
+    tunnel: IpxTunnel,
+    pub server: Address,
+}
+
+impl IpxTransport {
+    pub fn new(tunnel: IpxTunnel, server: Address) -> Self {
+        Self{ tunnel, server }
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.tunnel.address
+    }
+}
+
+impl Transport for IpxTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), NCPError> {
+        self.tunnel.send(&IpxPacket::new(ipx::PACKET_TYPE_NCP, self.server, self.tunnel.address, packet.to_vec()))
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, NCPError> {
+        let deadline = Instant::now() + timeout;
+        while let Some(packet) = self.tunnel.receive(deadline.saturating_duration_since(Instant::now()))? {
+            let socket = self.tunnel.address.socket;
+            if packet.destination.socket == socket.wrapping_add(1) && packet.data.get(1) == Some(&WATCHDOG_QUERY) {
+                let mut reply = IpxPacket::new(packet.packet_type, packet.source, packet.destination, packet.data.clone());
+                reply.data[1] = WATCHDOG_REPLY;
+                self.tunnel.send(&reply)?;
+                continue;
+            }
+            if packet.destination.socket == socket && packet.source.socket == self.server.socket {
+                return Ok(Some(packet.data))
+            }
+        }
+        Ok(None)
+    }
+
+    fn max_packet_size(&self) -> usize {
+        ipx::IPX_MAX_PACKET_SIZE - ipx::IPX_HEADER_SIZE
+    }
+
+    fn burst_socket(&self) -> Option<u16> {
+        Some(self.tunnel.address.socket)
+    }
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+    // Data received which does not make up a whole reply yet
+    buffer: Vec<u8>,
+}
+
+impl TcpTransport {
+    // Connects to host, or host:port
+    pub fn connect(host: &str) -> Result<Self, NCPError> {
+        let stream = if host.contains(':') {
+            TcpStream::connect(host)?
+        } else {
+            TcpStream::connect((host, DEFAULT_TCP_PORT))?
+        };
+        stream.set_nodelay(true)?;
+        Ok(Self{ stream, buffer: Vec::new() })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), NCPError> {
+        let mut frame: Vec<u8> = Vec::with_capacity(TCP_REQUEST_HEADER_SIZE + packet.len());
+        frame.write_u32::<BigEndian>(TCP_REQUEST_SIGNATURE).unwrap();
+        frame.write_u32::<BigEndian>((TCP_REQUEST_HEADER_SIZE + packet.len()) as u32).unwrap();
+        frame.write_u32::<BigEndian>(TCP_VERSION).unwrap();
+        frame.write_u32::<BigEndian>(TCP_MAX_REPLY_SIZE as u32).unwrap();
+        frame.extend_from_slice(packet);
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, NCPError> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![ 0u8; 65536 ];
+        loop {
+            if self.buffer.len() >= TCP_REPLY_HEADER_SIZE {
+                if BigEndian::read_u32(&self.buffer) != TCP_REPLY_SIGNATURE {
+                    return Err(NCPError::InvalidReply("bad signature in NCP over TCP frame".to_string()))
+                }
+                let len = BigEndian::read_u32(&self.buffer[4..]) as usize;
+                if !(TCP_REPLY_HEADER_SIZE..=TCP_REPLY_HEADER_SIZE + TCP_MAX_REPLY_SIZE).contains(&len) {
+                    return Err(NCPError::InvalidReply(format!("NCP over TCP frame of {} bytes", len)))
+                }
+                if self.buffer.len() >= len {
+                    let rest = self.buffer.split_off(len);
+                    let frame = std::mem::replace(&mut self.buffer, rest);
+                    return Ok(Some(frame[TCP_REPLY_HEADER_SIZE..].to_vec()))
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None)
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(NCPError::ConnectionLost("server closed the connection".to_string())),
+                Ok(len) => self.buffer.extend_from_slice(&buf[..len]),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn max_packet_size(&self) -> usize {
+        TCP_MAX_REPLY_SIZE
+    }
+
+    fn is_reliable(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,436 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// NetWare Directory Services database (DIB), as used by NetWare 4.x.
+//
+// The DIB is stored in SYS:_NETWARE as four files: PARTITIO.NDS
+// (partitions), ENTRY.NDS (entries), VALUE.NDS (attribute values) and
+// BLOCK.NDS (blocks holding value data that does not fit the value record).
+// Entries refer to their parent entry, so the tree is rebuilt by following
+// the parent links up to [Root]. Values of an entry form a chain.
+//
+// The layout of the records below (fixed-size records, their sizes and the
+// field offsets) is not documented and has not been checked against the DIB
+// of a real server, so it is unlikely to match; sample DIB files are needed
+// to confirm or correct it.
+//
+// The schema is stored in the DIB as well: classes and attributes are entries
+// of the Schema partition, and entries and values refer to their class and
+// attribute by entry ID. Names are UCS-2 and all fields are little endian.
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::bindery::read_records;
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::{DirEntry, Volume};
+
+pub const FILE_NAMES: [ &str; 4 ] = [ "PARTITIO.NDS", "ENTRY.NDS", "VALUE.NDS", "BLOCK.NDS" ];
+
+pub const PARTITION_RECORD_SIZE: usize = 64;
+pub const ENTRY_RECORD_SIZE: usize = 128;
+pub const VALUE_RECORD_SIZE: usize = 64;
+pub const BLOCK_RECORD_SIZE: usize = 128;
+
+pub const PARTITION_NAME_MAX: usize = 21;
+pub const ENTRY_NAME_MAX: usize = 43;
+pub const VALUE_INLINE_MAX: usize = 28;
+pub const BLOCK_DATA_SIZE: usize = 116;
+
+pub const ID_NONE: u32 = 0xffffffff;
+
+// Entry flags; entries and values lacking the present flag have been deleted
+// but not yet purged by the janitor
+pub const EF_PRESENT: u32 = 0x01;
+pub const EF_ALIAS: u32 = 0x02;
+pub const EF_PARTITION_ROOT: u32 = 0x04;
+pub const EF_CONTAINER: u32 = 0x08;
+
+pub const VF_PRESENT: u32 = 0x01;
+pub const VF_NAMING: u32 = 0x02;
+
+pub const REPLICA_TYPES: [ &str; 4 ] = [ "master", "read/write", "read-only", "subordinate reference" ];
+
+pub const SCHEMA_PARTITION: &str = "Schema";
+
+// Naming attribute by class; anything else is named by CN
+const NAMING_ATTRIBUTES: [ (&str, &str); 4 ] = [
+    ("Country", "C"),
+    ("Locality", "L"),
+    ("Organization", "O"),
+    ("Organizational Unit", "OU"),
+];
+
+#[derive(Debug)]
+pub struct Partition {
+    pub offset: usize,
+    pub id: u32,
+    pub root_entry: u32,
+    pub replica_type: u32,
+    pub state: u32,
+    pub flags: u32,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub offset: usize,
+    pub id: u32,
+    pub partition: u32,
+    pub parent: u32,
+    pub class: u32,
+    pub first_value: u32,
+    pub flags: u32,
+    pub subordinates: u32,
+    pub created: u32,
+    pub modified: u32,
+    pub name: String,
+}
+
+impl Entry {
+    pub fn is_present(&self) -> bool {
+        (self.flags & EF_PRESENT) != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Value {
+    pub offset: usize,
+    pub id: u32,
+    pub entry: u32,
+    pub attribute: u32,
+    pub next: u32,
+    pub flags: u32,
+    pub modified: u32,
+    pub length: u32,
+    pub first_block: u32,
+    pub data: [ u8; VALUE_INLINE_MAX ],
+}
+
+impl Value {
+    pub fn is_present(&self) -> bool {
+        (self.flags & VF_PRESENT) != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub offset: usize,
+    pub id: u32,
+    pub value: u32,
+    pub next: u32,
+    pub data: [ u8; BLOCK_DATA_SIZE ],
+}
+
+// Names are stored as length-prefixed UCS-2; the length is clamped to the
+// field so corrupt records can still be shown
+fn decode_ucs2(namelen: u8, data: &[u8]) -> String {
+    let chars: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([ c[0], c[1] ])).collect();
+    let namelen = std::cmp::min(namelen as usize, chars.len());
+    String::from_utf16_lossy(&chars[0..namelen]).chars()
+        .map(|c| if c.is_control() { char::REPLACEMENT_CHARACTER } else { c })
+        .collect()
+}
+
+fn read_partitions<R: Read>(input: R) -> Result<Vec<Partition>, std::io::Error> {
+    let mut result = Vec::new();
+    read_records(input, PARTITION_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let id = rdr.read_u32::<LittleEndian>()?;
+        let root_entry = rdr.read_u32::<LittleEndian>()?;
+        let replica_type = rdr.read_u32::<LittleEndian>()?;
+        let state = rdr.read_u32::<LittleEndian>()?;
+        let flags = rdr.read_u32::<LittleEndian>()?;
+        let namelen = rdr.read_u8()?;
+        let _reserved = rdr.read_u8()?;
+        let mut nameval = [ 0u8; PARTITION_NAME_MAX * 2 ];
+        rdr.read_exact(&mut nameval)?;
+        let name = decode_ucs2(namelen, &nameval);
+        result.push(Partition{ offset, id, root_entry, replica_type, state, flags, name });
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn read_entries<R: Read>(input: R) -> Result<Vec<Entry>, std::io::Error> {
+    let mut result = Vec::new();
+    read_records(input, ENTRY_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let id = rdr.read_u32::<LittleEndian>()?;
+        let partition = rdr.read_u32::<LittleEndian>()?;
+        let parent = rdr.read_u32::<LittleEndian>()?;
+        let class = rdr.read_u32::<LittleEndian>()?;
+        let first_value = rdr.read_u32::<LittleEndian>()?;
+        let flags = rdr.read_u32::<LittleEndian>()?;
+        let subordinates = rdr.read_u32::<LittleEndian>()?;
+        let created = rdr.read_u32::<LittleEndian>()?;
+        let _created_replica = rdr.read_u16::<LittleEndian>()?;
+        let _created_event = rdr.read_u16::<LittleEndian>()?;
+        let modified = rdr.read_u32::<LittleEndian>()?;
+        let namelen = rdr.read_u8()?;
+        let _reserved = rdr.read_u8()?;
+        let mut nameval = [ 0u8; ENTRY_NAME_MAX * 2 ];
+        rdr.read_exact(&mut nameval)?;
+        let name = decode_ucs2(namelen, &nameval);
+        result.push(Entry{ offset, id, partition, parent, class, first_value, flags, subordinates, created, modified, name });
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn read_values<R: Read>(input: R) -> Result<Vec<Value>, std::io::Error> {
+    let mut result = Vec::new();
+    read_records(input, VALUE_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let id = rdr.read_u32::<LittleEndian>()?;
+        let entry = rdr.read_u32::<LittleEndian>()?;
+        let attribute = rdr.read_u32::<LittleEndian>()?;
+        let next = rdr.read_u32::<LittleEndian>()?;
+        let flags = rdr.read_u32::<LittleEndian>()?;
+        let modified = rdr.read_u32::<LittleEndian>()?;
+        let _modified_replica = rdr.read_u16::<LittleEndian>()?;
+        let _modified_event = rdr.read_u16::<LittleEndian>()?;
+        let length = rdr.read_u32::<LittleEndian>()?;
+        let first_block = rdr.read_u32::<LittleEndian>()?;
+        let mut data = [ 0u8; VALUE_INLINE_MAX ];
+        rdr.read_exact(&mut data)?;
+        result.push(Value{ offset, id, entry, attribute, next, flags, modified, length, first_block, data });
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn read_blocks<R: Read>(input: R) -> Result<Vec<Block>, std::io::Error> {
+    let mut result = Vec::new();
+    read_records(input, BLOCK_RECORD_SIZE, |offset, record| {
+        let mut rdr = Cursor::new(record);
+        let id = rdr.read_u32::<LittleEndian>()?;
+        let value = rdr.read_u32::<LittleEndian>()?;
+        let next = rdr.read_u32::<LittleEndian>()?;
+        let mut data = [ 0u8; BLOCK_DATA_SIZE ];
+        rdr.read_exact(&mut data)?;
+        result.push(Block{ offset, id, value, next, data });
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+// Records are looked up by id using the indices, which map the id to the
+// position of the first record carrying it
+pub struct Dib {
+    pub partitions: Vec<Partition>,
+    pub entries: Vec<Entry>,
+    pub values: Vec<Value>,
+    pub blocks: Vec<Block>,
+    entry_index: HashMap<u32, usize>,
+    value_index: HashMap<u32, usize>,
+    block_index: HashMap<u32, usize>,
+    children: HashMap<u32, Vec<usize>>,
+}
+
+impl Dib {
+    pub fn new(files: &[ &[u8] ]) -> Result<Self, std::io::Error> {
+        Self::from_readers(files.to_vec())
+    }
+
+    // Reads the DIB from the given readers, which must be in the order of
+    // FILE_NAMES
+    pub fn from_readers<R: Read>(readers: Vec<R>) -> Result<Self, std::io::Error> {
+        let mut readers = readers.into_iter();
+        let mut next_reader = || readers.next().ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing DIB file"));
+        let partitions = read_partitions(next_reader()?)?;
+        let entries = read_entries(next_reader()?)?;
+        let values = read_values(next_reader()?)?;
+        let blocks = read_blocks(next_reader()?)?;
+
+        let mut entry_index = HashMap::new();
+        let mut children: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (n, e) in entries.iter().enumerate() {
+            entry_index.entry(e.id).or_insert(n);
+            if e.parent != e.id {
+                children.entry(e.parent).or_default().push(n);
+            }
+        }
+        let mut value_index = HashMap::new();
+        for (n, v) in values.iter().enumerate() {
+            value_index.entry(v.id).or_insert(n);
+        }
+        let mut block_index = HashMap::new();
+        for (n, b) in blocks.iter().enumerate() {
+            block_index.entry(b.id).or_insert(n);
+        }
+        Ok(Self{ partitions, entries, values, blocks, entry_index, value_index, block_index, children })
+    }
+
+    pub fn from_files<P: AsRef<std::path::Path>>(paths: &[P]) -> Result<Self, std::io::Error> {
+        let mut readers = Vec::new();
+        for path in paths {
+            readers.push(std::io::BufReader::new(std::fs::File::open(path)?));
+        }
+        Self::from_readers(readers)
+    }
+
+    pub fn find_entry(&self, id: u32) -> Option<&Entry> {
+        self.entry_index.get(&id).map(|n| &self.entries[*n])
+    }
+
+    pub fn find_value(&self, id: u32) -> Option<&Value> {
+        self.value_index.get(&id).map(|n| &self.values[*n])
+    }
+
+    pub fn find_partition(&self, id: u32) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.id == id)
+    }
+
+    // [Root] is the only entry without parent
+    pub fn root(&self) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.parent == ID_NONE)
+    }
+
+    pub fn children(&self, id: u32) -> impl Iterator<Item = &Entry> {
+        self.children.get(&id).into_iter().flatten().map(|n| &self.entries[*n])
+    }
+
+    pub fn class_name(&self, e: &Entry) -> String {
+        self.find_entry(e.class).map(|c| c.name.clone()).unwrap_or(format!("class {:x}", e.class))
+    }
+
+    pub fn attribute_name(&self, v: &Value) -> String {
+        self.find_entry(v.attribute).map(|a| a.name.clone()).unwrap_or(format!("attribute {:x}", v.attribute))
+    }
+
+    pub fn is_schema(&self, e: &Entry) -> bool {
+        self.find_partition(e.partition).is_some_and(|p| p.name == SCHEMA_PARTITION)
+    }
+
+    // Relative distinguished name, i.e. OU=Sales
+    pub fn rdn(&self, e: &Entry) -> String {
+        let class = self.class_name(e);
+        let attribute = NAMING_ATTRIBUTES.iter().find(|(c, _)| *c == class).map(|(_, a)| *a).unwrap_or("CN");
+        format!("{}={}", attribute, e.name)
+    }
+
+    // Typeful distinguished name, i.e. CN=Admin.O=ACME; [Root] itself is not
+    // part of it
+    pub fn dn(&self, e: &Entry) -> String {
+        if e.parent == ID_NONE {
+            return "[Root]".to_string();
+        }
+        let mut parts = vec![ self.rdn(e) ];
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut current = e;
+        while seen.insert(current.id) {
+            current = match self.find_entry(current.parent) {
+                Some(parent) if parent.parent != ID_NONE => parent,
+                Some(_) => break,
+                None => {
+                    parts.push(format!("<unknown entry {:x}>", current.parent));
+                    break;
+                }
+            };
+            parts.push(self.rdn(current));
+        }
+        parts.join(".")
+    }
+
+    // Returns the value ID's of the entry, in chain order
+    pub fn entry_values(&self, e: &Entry) -> Vec<u32> {
+        let mut result: Vec<u32> = Vec::new();
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut valueid = e.first_value;
+        while valueid != ID_NONE && seen.insert(valueid) {
+            result.push(valueid);
+            valueid = match self.find_value(valueid) {
+                Some(v) => v.next,
+                None => break,
+            };
+        }
+        result
+    }
+
+    // Returns the contents of the value; data that does not fit the value
+    // record is stored in a chain of blocks. Missing data is left out
+    pub fn value_data(&self, v: &Value) -> Vec<u8> {
+        let length = v.length as usize;
+        if v.first_block == ID_NONE {
+            return v.data[0..length.min(VALUE_INLINE_MAX)].to_vec();
+        }
+        let mut result: Vec<u8> = Vec::new();
+        let mut seen: HashSet<u32> = HashSet::new();
+        let mut blockid = v.first_block;
+        while blockid != ID_NONE && result.len() < length && seen.insert(blockid) {
+            let b = match self.block_index.get(&blockid) {
+                Some(n) => &self.blocks[*n],
+                None => break,
+            };
+            result.extend_from_slice(&b.data);
+            blockid = b.next;
+        }
+        result.truncate(length);
+        result
+    }
+}
+
+// Attributes referring to other entries; their values are entry ID's
+const DN_ATTRIBUTES: [ &str; 15 ] = [
+    "Aliased Object Name", "Default Queue", "Equivalent To Me", "Group Membership",
+    "Host Server", "Member", "Operator", "Owner", "Profile", "Queue", "Reference",
+    "Security Equals", "See Also", "Server", "User",
+];
+
+// Returns a human-readable representation of the value, one line per item.
+// References to other entries are resolved and strings (UCS-2) recognized;
+// anything else is shown as number or hex
+pub fn decode_value(dib: &Dib, v: &Value) -> Vec<String> {
+    let data = dib.value_data(v);
+    if data.is_empty() {
+        return vec![ "<empty>".to_string() ];
+    }
+    if data.len() == 4 {
+        let id = u32::from_le_bytes([ data[0], data[1], data[2], data[3] ]);
+        if DN_ATTRIBUTES.contains(&dib.attribute_name(v).as_str()) {
+            return vec![ match dib.find_entry(id) {
+                Some(e) => dib.dn(e),
+                None => format!("<unknown entry {:x}>", id),
+            } ];
+        }
+        return vec![ format!("{}", id) ];
+    }
+    if data.len().is_multiple_of(2) {
+        let chars: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([ c[0], c[1] ])).collect();
+        let len = chars.iter().position(|c| *c == 0).unwrap_or(chars.len());
+        let ascii = chars[0..len].iter().filter(|c| **c < 0x80).count();
+        if len > 0 && ascii * 2 >= len && chars[len..].iter().all(|c| *c == 0) {
+            if let Ok(s) = String::from_utf16(&chars[0..len]) {
+                if s.chars().all(|c| !c.is_control() || c == '\r' || c == '\n' || c == '\t') {
+                    return s.lines().map(|line| format!("'{}'", line)).collect();
+                }
+            }
+        }
+    }
+    vec![ data.iter().map(|b| format!("{:02x}", b)).collect() ]
+}
+
+pub fn format_replica_type(replica_type: u32) -> String {
+    REPLICA_TYPES.get(replica_type as usize).map(|s| s.to_string()).unwrap_or(format!("type {}", replica_type))
+}
+
+// The DIB files live in SYS:_NETWARE, which is hidden from clients
+pub fn find_volume_files(volume: &Volume) -> Result<Vec<DirEntry>, NWFSError> {
+    FILE_NAMES.iter().map(|name| {
+        let path = format!("_NETWARE/{}", name);
+        volume.lookup(&path).cloned().ok_or(NWFSError::FileNotFound(format!("SYS:{}", path)))
+    }).collect()
+}
+
+pub fn read_volume_files(volume: &mut Volume) -> Result<Vec<Vec<u8>>, NWFSError> {
+    let mut file_data = Vec::new();
+    for entry in find_volume_files(volume)? {
+        file_data.push(volume.read_file(&entry)?);
+    }
+    Ok(file_data)
+}
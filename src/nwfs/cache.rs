@@ -0,0 +1,111 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Caches the parts of an image which were read most recently. Images are
+// never read as a whole, as volumes can be far larger than memory; but
+// reading a volume revisits the same parts of it, such as the FAT and the
+// directory, and the grain and cluster tables of virtual disks. The cache
+// holds a bounded number of blocks of CACHE_BLOCK_SIZE bytes, and evicts
+// the one used least recently when it is full. Writes to the image bypass
+// the cache, so images which are changed must be read without it.
+use std::collections::HashMap;
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use crate::nwfs::Device;
+
+pub const CACHE_BLOCK_SIZE: usize = 64 * 1024;
+pub const DEFAULT_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+pub struct BlockCache<D: Device> {
+    dev: D,
+    pos: u64,
+    // Maximum number of blocks; nothing is cached if 0
+    capacity: usize,
+    // Contents of the blocks by their index, which are shorter at the end
+    // of the image, along with when they were last used
+    blocks: HashMap<u64, (Vec<u8>, u64)>,
+    clock: u64,
+}
+
+impl<D: Device> BlockCache<D> {
+    // Caches up to size bytes of the image, rounded down to whole blocks
+    pub fn new(dev: D, size: usize) -> Self {
+        Self{ dev, pos: 0, capacity: size / CACHE_BLOCK_SIZE, blocks: HashMap::new(), clock: 0 }
+    }
+
+    fn block(&mut self, index: u64) -> Result<&[u8]> {
+        self.clock += 1;
+        if !self.blocks.contains_key(&index) {
+            if self.blocks.len() >= self.capacity {
+                let oldest = self.blocks.iter().min_by_key(|(_, (_, used))| *used).map(|(n, _)| *n);
+                if let Some(oldest) = oldest {
+                    self.blocks.remove(&oldest);
+                }
+            }
+            self.dev.seek(SeekFrom::Start(index * CACHE_BLOCK_SIZE as u64))?;
+            let mut data = Vec::with_capacity(CACHE_BLOCK_SIZE);
+            (&mut self.dev).take(CACHE_BLOCK_SIZE as u64).read_to_end(&mut data)?;
+            self.blocks.insert(index, (data, 0));
+        }
+        let (data, used) = self.blocks.get_mut(&index).unwrap();
+        *used = self.clock;
+        Ok(data)
+    }
+}
+
+impl<D: Device> Read for BlockCache<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.capacity == 0 {
+            self.dev.seek(SeekFrom::Start(self.pos))?;
+            let len = self.dev.read(buf)?;
+            self.pos += len as u64;
+            return Ok(len)
+        }
+        let offset = (self.pos % CACHE_BLOCK_SIZE as u64) as usize;
+        let data = self.block(self.pos / CACHE_BLOCK_SIZE as u64)?;
+        // The last block is short, and reading past it is the end of the
+        // image
+        if offset >= data.len() {
+            return Ok(0)
+        }
+        let len = buf.len().min(data.len() - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<D: Device> Seek for BlockCache<D> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(_) => self.dev.seek(pos)?,
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta)
+                .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start of the image"))?,
+        };
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_past_short_block() {
+        let image: Vec<u8> = (0..512).map(|n| n as u8).collect();
+        let mut cache = BlockCache::new(Cursor::new(image), DEFAULT_CACHE_SIZE);
+        let mut buf = [ 0u8; 16 ];
+        cache.seek(SeekFrom::Start(504)).unwrap();
+        assert_eq!(cache.read(&mut buf).unwrap(), 8);
+        assert_eq!(buf[..8], [ 248, 249, 250, 251, 252, 253, 254, 255 ]);
+        assert_eq!(cache.read(&mut buf).unwrap(), 0);
+        cache.seek(SeekFrom::Start(4096)).unwrap();
+        assert_eq!(cache.read(&mut buf).unwrap(), 0);
+    }
+}
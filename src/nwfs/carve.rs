@@ -0,0 +1,164 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Locates NetWare 386 partitions and volumes on images whose partition table
+// or headers are damaged, by scanning every sector for the structures
+// involved: the hotfix header at the start of a partition, the volume table
+// at the start of its data area, and the root directory entry at the start
+// of the directory of a volume, which carries the name of the volume.
+//
+// Every structure found is verified as far as possible. A partition whose
+// volumes can be opened is found with high confidence; a volume table
+// without hotfix header can still be read, as the data area starts with it,
+// which is found with medium confidence if the root directory of a volume
+// is where the table says. A root directory entry which is not part of any
+// volume found is all that is left of a volume whose table is lost, and is
+// reported with low confidence; it cannot be read.
+use std::collections::HashSet;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::nwfs::{read_at, Device, NWFSError, SECTOR_SIZE};
+use crate::nwfs::mkfs::is_valid_volume_name;
+use crate::nwfs::partition::{Partition, HOTFIX_SECTOR, HOTFIX_SIGNATURE, VOLUME_TABLE_SIGNATURE};
+use crate::nwfs::volume::{ATTR_DIRECTORY, DIR_ENTRY_SIZE, DIR_ROOT, NS_DOS};
+
+const SCAN_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl Confidence {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Partition,
+    VolumeTable,
+    RootDirectory,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Partition => "partition",
+            Kind::VolumeTable => "volume-table",
+            Kind::RootDirectory => "root-directory",
+        }
+    }
+}
+
+pub struct Candidate {
+    // Byte offset within the image: of the partition, of its data area or
+    // of the directory
+    pub offset: u64,
+    pub kind: Kind,
+    pub confidence: Confidence,
+    // Names of the volumes of which segments were found
+    pub volumes: Vec<String>,
+    pub message: String,
+}
+
+// Name of the volume if the sector starts with a root directory entry
+fn root_directory_name(sector: &[u8]) -> Option<String> {
+    let entry = &sector[0..DIR_ENTRY_SIZE];
+    let (parent, attributes, namespace, name_len) = (LittleEndian::read_u32(&entry[0..]), LittleEndian::read_u32(&entry[4..]), entry[0x0a], entry[0x0b] as usize);
+    if parent != DIR_ROOT || attributes & ATTR_DIRECTORY == 0 || namespace != NS_DOS || name_len > 15 {
+        return None;
+    }
+    let name = std::str::from_utf8(&entry[0x0c..0x0c + name_len]).ok()?;
+    is_valid_volume_name(name).then(|| name.to_string())
+}
+
+// The volumes of the partition, and the offsets of their root directories
+// which are where they should be
+fn verify_partition(dev: &mut dyn Device, partition: &Partition) -> (Vec<String>, Vec<u64>) {
+    let mut volumes = Vec::new();
+    let mut directories = Vec::new();
+    for s in &partition.segments {
+        volumes.push(format!("{} {}/{} ({} blocks of {} bytes)", s.name, s.segment_number, s.last_segment + 1, s.segment_blocks(), s.block_size()));
+        if s.segment_number != 0 {
+            continue;
+        }
+        for block in [ s.first_directory, s.mirror_directory ] {
+            let offset = partition.segment_offset(s) + block as u64 * s.block_size() as u64;
+            let found = read_at(dev, offset, SECTOR_SIZE).ok().and_then(|sector| root_directory_name(&sector));
+            if found.is_some_and(|name| name.eq_ignore_ascii_case(&s.name)) {
+                directories.push(offset);
+            }
+        }
+    }
+    (volumes, directories)
+}
+
+// Scans the image, which is len bytes, for partitions and volumes
+pub fn scan(dev: &mut dyn Device, len: u64) -> Result<Vec<Candidate>, NWFSError> {
+    let mut hotfixes: Vec<u64> = Vec::new();
+    let mut volume_tables: Vec<u64> = Vec::new();
+    let mut root_directories: Vec<(u64, String)> = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let chunk = read_at(dev, offset, SCAN_CHUNK_SIZE.min((len - offset) as usize))?;
+        for (n, sector) in chunk.chunks_exact(SECTOR_SIZE).enumerate() {
+            let pos = offset + (n * SECTOR_SIZE) as u64;
+            if sector.starts_with(HOTFIX_SIGNATURE) {
+                hotfixes.push(pos);
+            } else if sector.starts_with(VOLUME_TABLE_SIGNATURE) {
+                volume_tables.push(pos);
+            } else if let Some(name) = root_directory_name(sector) {
+                root_directories.push((pos, name));
+            }
+        }
+        offset += chunk.len() as u64;
+    }
+
+    let mut result = Vec::new();
+    let mut explained: HashSet<u64> = HashSet::new();
+    for hotfix in hotfixes {
+        let Some(partition_offset) = hotfix.checked_sub(HOTFIX_SECTOR * SECTOR_SIZE as u64) else { continue };
+        match Partition::read(dev, partition_offset) {
+            Ok(partition) => {
+                let (volumes, directories) = verify_partition(dev, &partition);
+                let confidence = if !volumes.is_empty() && !directories.is_empty() { Confidence::High } else { Confidence::Medium };
+                explained.insert(partition.data_offset());
+                explained.extend(directories);
+                let message = format!("partition id {:08x}, data area at {:x}", partition.hotfix.partition_id, partition.data_offset());
+                result.push(Candidate{ offset: partition_offset, kind: Kind::Partition, confidence, volumes, message });
+            },
+            Err(e) => result.push(Candidate{ offset: partition_offset, kind: Kind::Partition, confidence: Confidence::Low, volumes: Vec::new(), message: e.to_string() }),
+        }
+    }
+    volume_tables.retain(|t| !explained.contains(t));
+    for table in volume_tables {
+        match Partition::from_data_area(dev, table) {
+            Ok(partition) => {
+                let (volumes, directories) = verify_partition(dev, &partition);
+                let confidence = if !volumes.is_empty() && !directories.is_empty() { Confidence::Medium } else { Confidence::Low };
+                explained.extend(directories);
+                let message = "no hotfix header, hotfixed blocks cannot be read".to_string();
+                result.push(Candidate{ offset: table, kind: Kind::VolumeTable, confidence, volumes, message });
+            },
+            Err(e) => result.push(Candidate{ offset: table, kind: Kind::VolumeTable, confidence: Confidence::Low, volumes: Vec::new(), message: e.to_string() }),
+        }
+    }
+    for (offset, name) in root_directories.into_iter().filter(|(offset, _)| !explained.contains(offset)) {
+        let message = "volume table lost, the volume cannot be read".to_string();
+        result.push(Candidate{ offset, kind: Kind::RootDirectory, confidence: Confidence::Low, volumes: vec![ name ], message });
+    }
+    result.sort_by_key(|c| c.offset);
+    Ok(result)
+}
@@ -0,0 +1,271 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Consistency check of a volume, along the lines of VREPAIR but without
+// changing anything. Every block must be free or belong to exactly one
+// chain: the FAT, the directory, or a file (deleted files keep their blocks
+// until purged, and MAC name space entries have a resource fork chain).
+// Suballocated tails share blocks, so they are checked by sector instead.
+// Free FAT entries have a next block of 0; no chain can lead there, as the
+// first block of the volume is never part of a file.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::nwfs::nwfs286;
+use crate::nwfs::volume::{DirEntry, Format, Volume, DIR_ROOT, FAT_CHAIN_END, FAT_FREE, FAT_SUBALLOC, NS_DOS, SUBALLOC_UNIT_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    BadFatEntry,
+    BrokenChain,
+    ShortChain,
+    LongChain,
+    BadBlockIndex,
+    CrossLinked,
+    LostBlocks,
+    BadParent,
+    DirectoryLoop,
+    BadName,
+    DuplicateName,
+    OrphanName,
+    NameMismatch,
+}
+
+impl IssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BadFatEntry => "bad-fat-entry",
+            Self::BrokenChain => "broken-chain",
+            Self::ShortChain => "short-chain",
+            Self::LongChain => "long-chain",
+            Self::BadBlockIndex => "bad-block-index",
+            Self::CrossLinked => "cross-linked",
+            Self::LostBlocks => "lost-blocks",
+            Self::BadParent => "bad-parent",
+            Self::DirectoryLoop => "directory-loop",
+            Self::BadName => "bad-name",
+            Self::DuplicateName => "duplicate-name",
+            Self::OrphanName => "orphan-name",
+            Self::NameMismatch => "name-mismatch",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub entry: Option<u32>,
+    pub block: Option<u32>,
+    pub message: String,
+}
+
+// Characters which cannot occur in DOS names
+pub const INVALID_NAME_CHARS: &str = "\"*+,/:;<=>?[\\]|";
+
+#[derive(Default)]
+struct Checker {
+    issues: Vec<Issue>,
+    // First owner of every block, which is an entry or None for the FAT
+    // and directory
+    owners: HashMap<u32, Option<u32>>,
+    referenced: HashSet<u32>,
+    tails: BTreeMap<u32, (u32, u32)>,
+}
+
+impl Checker {
+    fn add(&mut self, kind: IssueKind, entry: Option<u32>, block: Option<u32>, message: String) {
+        self.issues.push(Issue{ kind, entry, block, message });
+    }
+
+    // Blocks of deleted files are only accounted for, as they may be reused
+    // at any time
+    fn claim(&mut self, volume: &Volume, owner: Option<u32>, chain: &[u32], deleted: bool) {
+        self.referenced.extend(chain);
+        if deleted { return; }
+        let mut shared: BTreeMap<Option<u32>, Vec<u32>> = BTreeMap::new();
+        for block in chain {
+            match self.owners.get(block) {
+                Some(other) => shared.entry(*other).or_default().push(*block),
+                None => { self.owners.insert(*block, owner); },
+            }
+        }
+        let describe = |o: &Option<u32>| o.map(|e| volume.path(&volume.entries[e as usize])).unwrap_or("the FAT or directory".to_string());
+        for (other, blocks) in shared {
+            let message = format!("{} blocks (starting with block {}) are used by both {} and {}", blocks.len(), blocks[0], describe(&other), describe(&owner));
+            self.add(IssueKind::CrossLinked, owner, Some(blocks[0]), message);
+        }
+    }
+
+    fn claim_tail(&mut self, volume: &Volume, entry: &DirEntry, sector: u32, len: usize, deleted: bool) {
+        let sectors_per_block = volume.segments[0].1.sectors_per_block;
+        let sectors = len.div_ceil(SUBALLOC_UNIT_SIZE).max(1) as u32;
+        self.referenced.extend(sector / sectors_per_block..=(sector + sectors - 1) / sectors_per_block);
+        if deleted { return; }
+        let overlap = self.tails.range(..sector + sectors).next_back()
+            .filter(|(start, (count, _))| **start + *count > sector)
+            .map(|(_, (_, other))| *other);
+        if let Some(other) = overlap {
+            let message = format!("suballocated tail at sector {} overlaps with that of {}", sector, volume.path(&volume.entries[other as usize]));
+            self.add(IssueKind::CrossLinked, Some(entry.entry), None, message);
+        }
+        self.tails.insert(sector, (sectors, entry.entry));
+    }
+}
+
+fn check_fat(volume: &Volume, checker: &mut Checker) {
+    let total_blocks = volume.total_blocks();
+    for (block, e) in volume.fat.iter().enumerate() {
+        let next = e.next;
+        let valid = next == FAT_CHAIN_END || next == FAT_FREE || next < total_blocks
+            || (volume.format == Format::Nwfs386 && next & FAT_SUBALLOC != 0);
+        if !valid {
+            checker.add(IssueKind::BadFatEntry, None, Some(block as u32), format!("FAT entry of block {} refers to block {}", block, next));
+        }
+    }
+
+    let first_fat = volume.segments[0].1.first_fat;
+    let fat_chain = match volume.format {
+        Format::Nwfs286 => Ok((first_fat..first_fat + nwfs286::fat_blocks(total_blocks, volume.block_size())).collect()),
+        Format::Nwfs386 => volume.chain(first_fat),
+    };
+    match fat_chain {
+        Ok(chain) => checker.claim(volume, None, &chain, false),
+        Err(e) => checker.add(IssueKind::BrokenChain, None, Some(first_fat), format!("FAT chain: {}", e)),
+    }
+    let first_directory = volume.segments[0].1.first_directory;
+    match volume.chain(first_directory) {
+        Ok(chain) => checker.claim(volume, None, &chain, false),
+        Err(e) => checker.add(IssueKind::BrokenChain, None, Some(first_directory), format!("directory chain: {}", e)),
+    }
+    let mirrors = volume.mirror_fat().map(|b| (b, "mirror FAT")).into_iter().chain(volume.mirror_directory().map(|b| (b, "mirror directory")));
+    for (first, what) in mirrors {
+        match volume.chain(first) {
+            Ok(chain) => checker.claim(volume, None, &chain, false),
+            Err(e) => checker.add(IssueKind::BrokenChain, None, Some(first), format!("{} chain: {}", what, e)),
+        }
+    }
+}
+
+fn check_chain(volume: &Volume, checker: &mut Checker, e: &DirEntry) {
+    let path = volume.path(e);
+    let deleted = e.is_deleted();
+    let (chain, tail) = match volume.chain_with_tail(e.first_block) {
+        Ok(result) => result,
+        Err(err) => {
+            checker.add(IssueKind::BrokenChain, Some(e.entry), Some(e.first_block), format!("{}: {}", path, err));
+            return;
+        }
+    };
+    checker.claim(volume, Some(e.entry), &chain, deleted);
+    let block_size = volume.block_size();
+    let mut indices = volume.block_indices(&chain);
+    indices.sort();
+    let blocks = indices.last().map(|n| n + 1).unwrap_or(0);
+    if let Some(sector) = tail {
+        let len = (e.size as usize).saturating_sub(blocks * block_size);
+        checker.claim_tail(volume, e, sector, len, deleted);
+    }
+    if deleted { return; }
+
+    // Compressed files are smaller than their size. Gaps in the block
+    // indices are holes of a sparse file, but an index cannot occur twice
+    let needed = (e.size as usize).div_ceil(block_size);
+    let present = blocks + if tail.is_some() { 1 } else { 0 };
+    if present < needed && !e.is_compressed() {
+        checker.add(IssueKind::ShortChain, Some(e.entry), None, format!("{} is {} bytes, but has only {} of {} blocks", path, e.size, present, needed));
+    } else if present > needed {
+        checker.add(IssueKind::LongChain, Some(e.entry), None, format!("{} is {} bytes, but has {} blocks instead of {}", path, e.size, present, needed));
+    }
+    if indices.windows(2).any(|w| w[0] == w[1]) {
+        checker.add(IssueKind::BadBlockIndex, Some(e.entry), None, format!("{} has duplicate block indices", path));
+    }
+}
+
+fn check_entry(volume: &Volume, checker: &mut Checker, e: &DirEntry) {
+    let path = volume.path(e);
+    if e.parent == DIR_ROOT {
+        if e.entry != volume.root() {
+            checker.add(IssueKind::BadParent, Some(e.entry), None, format!("entry {} claims to be the root directory", e.entry));
+        }
+    } else {
+        match volume.entries.get(e.parent as usize) {
+            Some(p) if p.is_in_use() && p.is_directory() && p.namespace == NS_DOS => {},
+            _ => checker.add(IssueKind::BadParent, Some(e.entry), None, format!("{}: parent {} is not a directory", path, e.parent)),
+        }
+        let mut current = e;
+        for _ in 0..volume.entries.len() {
+            current = match volume.entries.get(current.parent as usize) {
+                Some(parent) if current.parent != DIR_ROOT => parent,
+                _ => break,
+            };
+            if current.entry == e.entry {
+                checker.add(IssueKind::DirectoryLoop, Some(e.entry), None, format!("{} is its own parent", path));
+                break;
+            }
+        }
+    }
+    if e.namespace == NS_DOS && e.entry != volume.root() && (e.name.is_empty() || e.name.chars().any(|c| c < ' ' || INVALID_NAME_CHARS.contains(c))) {
+        checker.add(IssueKind::BadName, Some(e.entry), None, format!("{}: invalid name '{}'", path, e.name.escape_default()));
+    }
+    if e.namespace != NS_DOS {
+        match volume.entries.get(e.primary as usize) {
+            Some(p) if p.is_in_use() && p.namespace == NS_DOS => {
+                if p.is_directory() != e.is_directory() {
+                    checker.add(IssueKind::NameMismatch, Some(e.entry), None, format!("name space entry {} and {} do not agree on being a directory", e.entry, path));
+                }
+            },
+            _ => checker.add(IssueKind::OrphanName, Some(e.entry), None, format!("name space entry {} ('{}') refers to entry {}, which is not in use", e.entry, e.name, e.primary)),
+        }
+    }
+}
+
+// Names must be unique within a directory in every name space; DOS names
+// are not case sensitive
+fn check_names(volume: &Volume, checker: &mut Checker) {
+    let mut seen: HashMap<(u32, u8, String), u32> = HashMap::new();
+    let mut names: HashMap<(u32, u8), u32> = HashMap::new();
+    for e in volume.entries.iter().filter(|e| e.is_in_use() && !e.is_deleted()) {
+        if e.namespace != NS_DOS {
+            if let Some(other) = names.insert((e.primary, e.namespace), e.entry) {
+                checker.add(IssueKind::DuplicateName, Some(e.entry), None, format!("entry {} has name space entries {} and {}", e.primary, other, e.entry));
+            }
+        }
+        let name = if e.namespace == NS_DOS { e.name.to_ascii_uppercase() } else { e.name.clone() };
+        if let Some(other) = seen.insert((e.parent, e.namespace, name), e.entry) {
+            let path = volume.path(e);
+            checker.add(IssueKind::DuplicateName, Some(e.entry), None, format!("{}: name is also used by entry {}", path, other));
+        }
+    }
+}
+
+pub fn check(volume: &Volume) -> Vec<Issue> {
+    let mut checker = Checker::default();
+    check_fat(volume, &mut checker);
+    for e in volume.entries.iter().filter(|e| e.is_in_use()) {
+        check_entry(volume, &mut checker, e);
+        if !e.is_directory() && (e.namespace == NS_DOS || e.size > 0) {
+            check_chain(volume, &mut checker, e);
+        }
+    }
+    check_names(volume, &mut checker);
+
+    // Blocks which are in use according to the FAT without being part of
+    // any chain are reported as ranges
+    let mut lost: Vec<(u32, u32)> = Vec::new();
+    for (block, e) in volume.fat.iter().enumerate() {
+        let block = block as u32;
+        if e.next == FAT_FREE || checker.referenced.contains(&block) { continue; }
+        match lost.last_mut() {
+            Some((_, last)) if *last + 1 == block => *last = block,
+            _ => lost.push((block, block)),
+        }
+    }
+    for (first, last) in lost {
+        let message = if first == last { format!("block {} is in use, but not part of any chain", first) } else { format!("blocks {}-{} are in use, but not part of any chain", first, last) };
+        checker.add(IssueKind::LostBlocks, None, Some(first), message);
+    }
+    checker.issues
+}
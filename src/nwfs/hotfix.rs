@@ -0,0 +1,106 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Reports the blocks of a partition which went bad and were redirected by
+// hotfix, to assess how much of the data of a failing disk is at risk. The
+// redirected blocks of the data area are located within the volume
+// segments, and the volume blocks they are part of are looked up in the FAT,
+// the directory and the files of the volume.
+//
+// Redirected blocks were written to their redirection block from then on,
+// so their contents can still be read; but a disk which keeps growing bad
+// blocks is likely to lose more, and redirection blocks may go bad as well.
+use std::collections::HashMap;
+
+use crate::nwfs::{nwfs286, SECTOR_SIZE};
+use crate::nwfs::partition::{Partition, HOTFIX_BLOCK_SIZE};
+use crate::nwfs::volume::{Format, Volume};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Owner {
+    Fat,
+    Directory,
+    // DOS entry of the file
+    File(u32),
+    DeletedFile(u32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    // The start of the data area, which holds the volume table
+    VolumeTable,
+    // Name of the volume and the block within it
+    Volume(String, u32),
+    // Not part of any volume segment
+    Unused,
+}
+
+pub struct RedirectedBlock {
+    // Block of the data area, in units of HOTFIX_BLOCK_SIZE, and the block
+    // of the redirection area which replaces it
+    pub data_block: u32,
+    pub redirection_block: u32,
+    pub location: Location,
+}
+
+// Redirected blocks of the partition, in the order of the data area
+pub fn redirected_blocks(partition: &Partition) -> Vec<RedirectedBlock> {
+    let first_segment = partition.segments.iter().map(|s| s.first_sector as u64 * SECTOR_SIZE as u64).min().unwrap_or(u64::MAX);
+    partition.redirection.blocks.iter().map(|(data_block, redirection_block)| {
+        let offset = *data_block as u64 * HOTFIX_BLOCK_SIZE as u64;
+        let segment = partition.segments.iter().find(|s| {
+            let start = s.first_sector as u64 * SECTOR_SIZE as u64;
+            offset >= start && offset - start < s.segment_sectors as u64 * SECTOR_SIZE as u64
+        });
+        let location = match segment {
+            Some(s) => {
+                let block = s.first_block + ((offset - s.first_sector as u64 * SECTOR_SIZE as u64) / s.block_size() as u64) as u32;
+                Location::Volume(s.name.clone(), block)
+            },
+            None if offset < first_segment => Location::VolumeTable,
+            None => Location::Unused,
+        };
+        RedirectedBlock{ data_block: *data_block, redirection_block: *redirection_block, location }
+    }).collect()
+}
+
+// What the blocks of the volume which are in use hold; a block may have more
+// than one owner if the volume is damaged, or if it is also part of a
+// deleted file. Blocks without owner are free
+pub fn block_owners(volume: &Volume) -> HashMap<u32, Vec<Owner>> {
+    let mut result: HashMap<u32, Vec<Owner>> = HashMap::new();
+    let mut add = |blocks: &[u32], owner: Owner| {
+        for block in blocks {
+            let owners = result.entry(*block).or_default();
+            if !owners.contains(&owner) {
+                owners.push(owner);
+            }
+        }
+    };
+    let segment = &volume.segments[0].1;
+    let fat: Vec<u32> = match volume.format {
+        Format::Nwfs286 => (segment.first_fat..segment.first_fat + nwfs286::fat_blocks(volume.total_blocks(), volume.block_size())).collect(),
+        Format::Nwfs386 => volume.chain(segment.first_fat).unwrap_or_default(),
+    };
+    add(&fat, Owner::Fat);
+    add(&volume.chain(segment.first_directory).unwrap_or_default(), Owner::Directory);
+    if let Some(first) = volume.mirror_fat() {
+        add(&volume.chain(first).unwrap_or_default(), Owner::Fat);
+    }
+    if let Some(first) = volume.mirror_directory() {
+        add(&volume.chain(first).unwrap_or_default(), Owner::Directory);
+    }
+    // Resource forks of Mac files are kept by their MAC name space entry
+    let sectors_per_block = segment.sectors_per_block;
+    for e in volume.entries.iter().filter(|e| e.is_in_use() && !e.is_directory()) {
+        if let Ok((mut chain, tail)) = volume.chain_with_tail(e.first_block) {
+            chain.extend(tail.map(|sector| sector / sectors_per_block));
+            add(&chain, if e.is_deleted() { Owner::DeletedFile(e.primary) } else { Owner::File(e.primary) });
+        }
+    }
+    result
+}
@@ -0,0 +1,412 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Virtual disk formats, which are read as if they were raw disk images.
+//
+// VMDK images consist of a descriptor listing extents, which are either raw
+// (flat), sparse or all zeroes; monolithic sparse images hold a single sparse
+// extent with the descriptor embedded. Sparse extents map grains through a
+// grain directory and grain tables. VHD images end with a footer; fixed VHD
+// images are raw images, whereas dynamic ones map blocks through the block
+// allocation table (BAT), where every block starts with a sector bitmap.
+// qcow2 images map clusters through a two level table. Anything not mapped
+// reads as zeroes.
+//
+// Compressed and differencing images (those which need a parent or backing
+// file) are not supported.
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::nwfs::{read_at, Device, NWFSError, SECTOR_SIZE};
+
+const VMDK_SPARSE_SIGNATURE: &[u8; 4] = b"KDMV";
+const VMDK_DESCRIPTOR_SIGNATURE: &[u8] = b"# Disk DescriptorFile";
+const VHD_SIGNATURE: &[u8; 8] = b"conectix";
+const QCOW_SIGNATURE: &[u8; 4] = b"QFI\xfb";
+
+const VMDK_GD_AT_END: u64 = u64::MAX;
+const VHD_TYPE_FIXED: u32 = 2;
+const VHD_TYPE_DYNAMIC: u32 = 3;
+const VHD_BAT_UNUSED: u32 = 0xffffffff;
+const QCOW_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const QCOW_COMPRESSED: u64 = 1 << 62;
+const QCOW_ZERO: u64 = 1;
+// Only the dirty and corrupt bits can be ignored when reading
+const QCOW_KNOWN_INCOMPATIBLE: u64 = 0x3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Raw,
+    Vmdk,
+    Vhd,
+    Qcow2,
+}
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Raw => "raw",
+            ImageFormat::Vmdk => "VMDK",
+            ImageFormat::Vhd => "VHD",
+            ImageFormat::Qcow2 => "qcow2",
+        }
+    }
+}
+
+// Determines the format of the image by its signature
+pub fn image_format(path: &str) -> Result<ImageFormat, NWFSError> {
+    let mut file = File::open(path)?;
+    let mut header = [ 0u8; SECTOR_SIZE ];
+    let len = file.read(&mut header)?;
+    let header = &header[..len];
+    if header.starts_with(VMDK_SPARSE_SIGNATURE) || header.starts_with(VMDK_DESCRIPTOR_SIGNATURE) {
+        return Ok(ImageFormat::Vmdk);
+    }
+    if header.starts_with(QCOW_SIGNATURE) {
+        return Ok(ImageFormat::Qcow2);
+    }
+    if header.starts_with(VHD_SIGNATURE) || vhd_footer(&mut file)?.is_some() {
+        return Ok(ImageFormat::Vhd);
+    }
+    Ok(ImageFormat::Raw)
+}
+
+// Opens the image for reading, decoding it if it is not a raw image
+pub fn open_image(path: &str) -> Result<Box<dyn Device>, NWFSError> {
+    let file = File::open(path)?;
+    match image_format(path)? {
+        ImageFormat::Raw => Ok(Box::new(file)),
+        ImageFormat::Vmdk => Ok(Box::new(open_vmdk(path, file)?)),
+        ImageFormat::Vhd => Ok(Box::new(open_vhd(file)?)),
+        ImageFormat::Qcow2 => Ok(Box::new(open_qcow2(file)?)),
+    }
+}
+
+// Part of a VMDK image, in bytes from the start of the disk
+struct Extent {
+    start: u64,
+    size: u64,
+    kind: ExtentKind,
+}
+
+enum ExtentKind {
+    Flat{ file: usize, offset: u64 },
+    Sparse{ file: usize, grain_size: u64, gtes_per_gt: u64, gd: Vec<u32> },
+    Zero,
+}
+
+enum Layout {
+    Extents(Vec<Extent>),
+    Vhd{ block_size: u64, bitmap_size: u64, bat: Vec<u32> },
+    Qcow2{ cluster_bits: u32, l1: Vec<u64> },
+}
+
+pub struct Image {
+    files: Vec<File>,
+    size: u64,
+    position: u64,
+    layout: Layout,
+}
+
+fn unsupported(what: &str) -> NWFSError {
+    NWFSError::Unsupported(what.to_string())
+}
+
+fn corrupt(what: &str) -> NWFSError {
+    NWFSError::Corrupt(what.to_string())
+}
+
+// Converts a number of sectors from the header to bytes
+fn sectors_to_bytes(sectors: u64) -> Result<u64, NWFSError> {
+    sectors.checked_mul(SECTOR_SIZE as u64).ok_or(corrupt("sector count is too large"))
+}
+
+// Reads a table of count entries of T from the image. The count comes from
+// the header, so tables which cannot fit in the file are refused before
+// allocating anything
+fn read_table<T>(file: &mut File, offset: u64, count: usize, entry_size: usize, parse: fn(&[u8]) -> T) -> Result<Vec<T>, NWFSError> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let len = count.checked_mul(entry_size)
+        .filter(|len| offset.checked_add(*len as u64).is_some_and(|end| end <= file_len))
+        .ok_or(corrupt("table extends beyond the end of the image"))?;
+    let data = read_at(file, offset, len)?;
+    Ok(data.chunks_exact(entry_size).map(parse).collect())
+}
+
+// The header of a sparse VMDK extent, as (size, kind) of the extent
+fn vmdk_sparse_extent(file: &mut File, index: usize) -> Result<(u64, ExtentKind), NWFSError> {
+    let header = read_at(file, 0, SECTOR_SIZE)?;
+    if !header.starts_with(VMDK_SPARSE_SIGNATURE) {
+        return Err(NWFSError::InvalidHeader("sparse VMDK extent without header".to_string()));
+    }
+    let capacity = LittleEndian::read_u64(&header[12..]);
+    let grain_sectors = LittleEndian::read_u64(&header[20..]);
+    let gtes_per_gt = LittleEndian::read_u32(&header[44..]) as u64;
+    let gd_offset = LittleEndian::read_u64(&header[56..]);
+    let compression = LittleEndian::read_u16(&header[77..]);
+    if compression != 0 || gd_offset == VMDK_GD_AT_END {
+        return Err(unsupported("compressed (stream-optimized) VMDK images"));
+    }
+    if grain_sectors == 0 || gtes_per_gt == 0 {
+        return Err(corrupt("invalid VMDK grain size"));
+    }
+    let grains = capacity.div_ceil(grain_sectors);
+    let gd = read_table(file, sectors_to_bytes(gd_offset)?, grains.div_ceil(gtes_per_gt) as usize, 4, LittleEndian::read_u32)?;
+    let grain_size = sectors_to_bytes(grain_sectors)?;
+    Ok((sectors_to_bytes(capacity)?, ExtentKind::Sparse{ file: index, grain_size, gtes_per_gt, gd }))
+}
+
+// Extent lines of descriptors look like 'RW 2048 FLAT "disk-flat.vmdk" 0';
+// file names are relative to the descriptor
+fn open_vmdk(path: &str, mut file: File) -> Result<Image, NWFSError> {
+    if read_at(&mut file, 0, 4)? == VMDK_SPARSE_SIGNATURE {
+        let (size, kind) = vmdk_sparse_extent(&mut file, 0)?;
+        return Ok(Image{ files: vec![ file ], size, position: 0, layout: Layout::Extents(vec![ Extent{ start: 0, size, kind } ]) });
+    }
+    let mut descriptor = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.take(1 << 20).read_to_string(&mut descriptor).map_err(|_| corrupt("VMDK descriptor is not text"))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut files = Vec::new();
+    let mut extents = Vec::new();
+    let mut start = 0;
+    for line in descriptor.lines().map(|l| l.trim()) {
+        if let Some(cid) = line.strip_prefix("parentCID=") {
+            if !cid.eq_ignore_ascii_case("ffffffff") {
+                return Err(unsupported("VMDK images with a parent disk"));
+            }
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || ![ "RW", "RDONLY", "NOACCESS" ].contains(&fields[0]) {
+            continue;
+        }
+        let sectors = fields[1].parse::<u64>().map_err(|_| corrupt("invalid VMDK extent size"))?;
+        let fname = line.split('"').nth(1);
+        let open = |fname: Option<&str>| -> Result<File, NWFSError> {
+            let fname = fname.ok_or(corrupt("VMDK extent without file name"))?;
+            Ok(File::open(dir.join(fname))?)
+        };
+        let kind = match fields[2] {
+            "FLAT" | "VMFS" => {
+                let offset = line.rsplit('"').next().and_then(|s| s.trim().parse::<u64>().ok()).unwrap_or(0);
+                files.push(open(fname)?);
+                ExtentKind::Flat{ file: files.len() - 1, offset: sectors_to_bytes(offset)? }
+            },
+            "SPARSE" => {
+                files.push(open(fname)?);
+                let index = files.len() - 1;
+                vmdk_sparse_extent(&mut files[index], index)?.1
+            },
+            "ZERO" => ExtentKind::Zero,
+            t => return Err(NWFSError::Unsupported(format!("VMDK extents of type {}", t))),
+        };
+        let size = sectors_to_bytes(sectors)?;
+        extents.push(Extent{ start, size, kind });
+        start = start.checked_add(size).ok_or(corrupt("VMDK extents are too large"))?;
+    }
+    if extents.is_empty() {
+        return Err(corrupt("VMDK descriptor without extents"));
+    }
+    Ok(Image{ files, size: start, position: 0, layout: Layout::Extents(extents) })
+}
+
+// The footer at the end of the image, if it is a VHD image
+fn vhd_footer(file: &mut File) -> Result<Option<Vec<u8>>, NWFSError> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < SECTOR_SIZE as u64 {
+        return Ok(None);
+    }
+    let footer = read_at(file, len - SECTOR_SIZE as u64, SECTOR_SIZE)?;
+    Ok(if footer.starts_with(VHD_SIGNATURE) { Some(footer) } else { None })
+}
+
+// Dynamic images keep a copy of the footer at the start, which is used if
+// the footer at the end is missing
+fn open_vhd(mut file: File) -> Result<Image, NWFSError> {
+    let footer = match vhd_footer(&mut file)? {
+        Some(footer) => footer,
+        None => read_at(&mut file, 0, SECTOR_SIZE)?,
+    };
+    let size = BigEndian::read_u64(&footer[48..]);
+    let layout = match BigEndian::read_u32(&footer[60..]) {
+        VHD_TYPE_FIXED => Layout::Extents(vec![ Extent{ start: 0, size, kind: ExtentKind::Flat{ file: 0, offset: 0 } } ]),
+        VHD_TYPE_DYNAMIC => {
+            let header = read_at(&mut file, BigEndian::read_u64(&footer[16..]), 1024)?;
+            if &header[0..8] != b"cxsparse" {
+                return Err(NWFSError::InvalidHeader("VHD dynamic disk header not found".to_string()));
+            }
+            let table_offset = BigEndian::read_u64(&header[16..]);
+            let entries = BigEndian::read_u32(&header[28..]) as usize;
+            let block_size = BigEndian::read_u32(&header[32..]) as u64;
+            if block_size == 0 || !block_size.is_multiple_of(SECTOR_SIZE as u64) {
+                return Err(corrupt("invalid VHD block size"));
+            }
+            let bitmap_size = (block_size / SECTOR_SIZE as u64).div_ceil(8).next_multiple_of(SECTOR_SIZE as u64);
+            let bat = read_table(&mut file, table_offset, entries, 4, BigEndian::read_u32)?;
+            Layout::Vhd{ block_size, bitmap_size, bat }
+        },
+        _ => return Err(unsupported("differencing VHD images")),
+    };
+    Ok(Image{ files: vec![ file ], size, position: 0, layout })
+}
+
+fn open_qcow2(mut file: File) -> Result<Image, NWFSError> {
+    let header = read_at(&mut file, 0, 104)?;
+    let version = BigEndian::read_u32(&header[4..]);
+    if version < 2 {
+        return Err(unsupported("qcow images before version 2"));
+    }
+    if BigEndian::read_u64(&header[8..]) != 0 {
+        return Err(unsupported("qcow2 images with a backing file"));
+    }
+    if BigEndian::read_u32(&header[32..]) != 0 {
+        return Err(unsupported("encrypted qcow2 images"));
+    }
+    if version >= 3 && BigEndian::read_u64(&header[72..]) & !QCOW_KNOWN_INCOMPATIBLE != 0 {
+        return Err(unsupported("qcow2 images using extended features"));
+    }
+    let cluster_bits = BigEndian::read_u32(&header[20..]);
+    if !(9..=21).contains(&cluster_bits) {
+        return Err(corrupt("invalid qcow2 cluster size"));
+    }
+    let size = BigEndian::read_u64(&header[24..]);
+    let l1_size = BigEndian::read_u32(&header[36..]) as usize;
+    let l1 = read_table(&mut file, BigEndian::read_u64(&header[40..]), l1_size, 8, BigEndian::read_u64)?;
+    Ok(Image{ files: vec![ file ], size, position: 0, layout: Layout::Qcow2{ cluster_bits, l1 } })
+}
+
+impl Image {
+    // Where the data at offset is stored, as file and offset within that
+    // file, or None if it reads as zeroes; along with the number of bytes
+    // stored there contiguously
+    fn locate(&mut self, offset: u64) -> Result<(Option<(usize, u64)>, u64), NWFSError> {
+        match &self.layout {
+            Layout::Extents(extents) => {
+                let extent = extents.iter().find(|e| offset >= e.start && offset < e.start + e.size).ok_or(corrupt("offset beyond the last VMDK extent"))?;
+                let (offset, left) = (offset - extent.start, extent.start + extent.size - offset);
+                match &extent.kind {
+                    ExtentKind::Flat{ file, offset: base } => Ok((Some((*file, base + offset)), left)),
+                    ExtentKind::Zero => Ok((None, left)),
+                    ExtentKind::Sparse{ file, grain_size, gtes_per_gt, gd } => {
+                        let grain = offset / grain_size;
+                        let left = left.min(grain_size - offset % grain_size);
+                        let gt = gd.get((grain / gtes_per_gt) as usize).copied().unwrap_or(0);
+                        if gt == 0 {
+                            return Ok((None, left));
+                        }
+                        let (file, gte_offset) = (*file, gt as u64 * SECTOR_SIZE as u64 + (grain % gtes_per_gt) * 4);
+                        let grain_offset = offset % grain_size;
+                        // Grain table entries of 0 are unallocated and 1 are zero grains
+                        match LittleEndian::read_u32(&read_at(&mut self.files[file], gte_offset, 4)?) {
+                            0 | 1 => Ok((None, left)),
+                            sector => Ok((Some((file, sector as u64 * SECTOR_SIZE as u64 + grain_offset)), left)),
+                        }
+                    },
+                }
+            },
+            Layout::Vhd{ block_size, bitmap_size, bat } => {
+                let left = block_size - offset % block_size;
+                match bat.get((offset / block_size) as usize).copied().unwrap_or(VHD_BAT_UNUSED) {
+                    VHD_BAT_UNUSED => Ok((None, left)),
+                    sector => Ok((Some((0, sector as u64 * SECTOR_SIZE as u64 + bitmap_size + offset % block_size)), left)),
+                }
+            },
+            Layout::Qcow2{ cluster_bits, l1 } => {
+                let cluster_size = 1u64 << cluster_bits;
+                let l2_bits = cluster_bits - 3;
+                let left = cluster_size - offset % cluster_size;
+                let l2_offset = l1.get((offset >> (cluster_bits + l2_bits)) as usize).copied().unwrap_or(0) & QCOW_OFFSET_MASK;
+                if l2_offset == 0 {
+                    return Ok((None, left));
+                }
+                let l2_index = (offset >> cluster_bits) & ((1 << l2_bits) - 1);
+                let entry = BigEndian::read_u64(&read_at(&mut self.files[0], l2_offset + l2_index * 8, 8)?);
+                if entry & QCOW_COMPRESSED != 0 {
+                    return Err(unsupported("compressed qcow2 clusters"));
+                }
+                match entry & QCOW_OFFSET_MASK {
+                    _ if entry & QCOW_ZERO != 0 => Ok((None, left)),
+                    0 => Ok((None, left)),
+                    host => Ok((Some((0, host + offset % cluster_size)), left)),
+                }
+            },
+        }
+    }
+}
+
+impl Read for Image {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let (location, left) = self.locate(self.position).map_err(|e| match e {
+            NWFSError::IoError(e) => e,
+            e => std::io::Error::other(e.to_string()),
+        })?;
+        let len = (buf.len() as u64).min(left).min(self.size - self.position) as usize;
+        match location {
+            Some((file, offset)) => {
+                self.files[file].seek(SeekFrom::Start(offset))?;
+                self.files[file].read_exact(&mut buf[..len])?;
+            },
+            None => buf[..len].fill(0),
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Image {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start of the image"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a 4 KB image starting with the header given
+    fn crafted_image(name: &str, header: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("nlm-tools-{}-{}", std::process::id(), name));
+        let mut data = vec![ 0u8; 4096 ];
+        data[..header.len()].copy_from_slice(header);
+        std::fs::write(&path, data).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn oversized_tables() {
+        let mut qcow2 = [ 0u8; 104 ];
+        qcow2[0..4].copy_from_slice(QCOW_SIGNATURE);
+        BigEndian::write_u32(&mut qcow2[4..], 3);
+        BigEndian::write_u32(&mut qcow2[20..], 16);
+        BigEndian::write_u32(&mut qcow2[36..], 0xffffffff);
+        BigEndian::write_u64(&mut qcow2[40..], 512);
+        let path = crafted_image("qcow2", &qcow2);
+        assert!(matches!(open_image(&path), Err(NWFSError::Corrupt(_))));
+        std::fs::remove_file(path).unwrap();
+
+        let mut vmdk = [ 0u8; 80 ];
+        vmdk[0..4].copy_from_slice(VMDK_SPARSE_SIGNATURE);
+        LittleEndian::write_u64(&mut vmdk[12..], u64::MAX / 2);
+        LittleEndian::write_u64(&mut vmdk[20..], 1);
+        LittleEndian::write_u32(&mut vmdk[44..], 1);
+        LittleEndian::write_u64(&mut vmdk[56..], 1);
+        let path = crafted_image("vmdk", &vmdk);
+        assert!(matches!(open_image(&path), Err(NWFSError::Corrupt(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Compares the copies of a volume kept by mirrored partitions. The partitions
+// of a mirror group share the group ID in their mirror header, and hold the
+// same volume segments; NetWare writes every block to all of them, so they
+// only differ if a partition went out of sync, such as when a disk was
+// offline for a while and was never remirrored. Blocks are compared by their
+// contents, after hotfix redirection, and runs of blocks which differ are
+// reported along with the copies which disagree with the one being read.
+use std::collections::HashSet;
+
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::Volume;
+
+pub struct Divergence {
+    pub first_block: u32,
+    pub blocks: u32,
+    // Blocks of the run in use by the FAT, the directory or a file
+    pub used_blocks: u32,
+    // Copies whose contents differ from those of volume.mirror_copy
+    pub copies: Vec<usize>,
+}
+
+// Compares every block of the volume which has more than one copy
+pub fn compare(volume: &mut Volume) -> Result<Vec<Divergence>, NWFSError> {
+    let used = volume.used_blocks();
+    let mut result: Vec<Divergence> = Vec::new();
+    for block in 0..volume.total_blocks() {
+        let num_copies = volume.block_copies(block)?.len();
+        if num_copies < 2 {
+            continue;
+        }
+        let current = volume.mirror_copy.min(num_copies - 1);
+        let data = volume.read_block_copy(block, current)?;
+        let mut copies = Vec::new();
+        for copy in (0..num_copies).filter(|c| *c != current) {
+            // Unreadable copies differ as well
+            if volume.read_block_copy(block, copy).map(|d| d != data).unwrap_or(true) {
+                copies.push(copy);
+            }
+        }
+        if copies.is_empty() {
+            continue;
+        }
+        let in_use = used.contains(&block) as u32;
+        match result.last_mut() {
+            Some(d) if d.first_block + d.blocks == block && d.copies == copies => {
+                d.blocks += 1;
+                d.used_blocks += in_use;
+            },
+            _ => result.push(Divergence{ first_block: block, blocks: 1, used_blocks: in_use, copies }),
+        }
+    }
+    Ok(result)
+}
+
+// Mirror groups of the partitions holding the volume, with the indices of
+// their partitions in volume.partitions
+pub fn groups(volume: &Volume) -> Vec<(u32, Vec<usize>)> {
+    let mut result: Vec<(u32, Vec<usize>)> = Vec::new();
+    let used: HashSet<usize> = volume.segments.iter()
+        .flat_map(|(_, s)| volume.block_copies(s.first_block).unwrap_or_default())
+        .map(|(n, _)| n)
+        .collect();
+    for (n, (_, p)) in volume.partitions.iter().enumerate().filter(|(n, _)| used.contains(n)) {
+        let Some(mirror) = &p.mirror else { continue };
+        match result.iter_mut().find(|(group, _)| *group == mirror.group_id) {
+            Some((_, members)) => members.push(n),
+            None => result.push((mirror.group_id, vec![ n ])),
+        }
+    }
+    result
+}
@@ -0,0 +1,187 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Creates disk images holding a NetWare 386 partition with a single empty
+// volume, laid out as the NetWare 3.x installer does: the partition starts at
+// sector 63 and reserves about 2% for hotfix redirection, and the volume
+// starts right after the volume table. The FAT is followed by its mirror
+// copy, and then by the directory and its mirror copy, which hold just the
+// root directory; the directory grows as files are added. Only the blocks in
+// use are written, so the image is sparse.
+use std::fs::File;
+use std::path::Path;
+use std::time::SystemTime;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::nwfs::{write_at, NWFSError, SECTOR_SIZE};
+use crate::nwfs::partition::{HOTFIX_BLOCK_SIZE, HOTFIX_SECTOR, HOTFIX_SIGNATURE, MIRROR_SECTOR, MIRROR_SIGNATURE, PARTITION_TYPE_NETWARE, REDIRECTION_SECTOR, VOLUME_NAME_MAX, VOLUME_TABLE_ENTRY_SIZE, VOLUME_TABLE_HEADER_SIZE, VOLUME_TABLE_SECTORS, VOLUME_TABLE_SIGNATURE};
+use crate::nwfs::volume::{ATTR_DIRECTORY, DIR_ENTRY_SIZE, DIR_FREE, DIR_ROOT, FAT_CHAIN_END, FAT_ENTRY_SIZE, FAT_FREE, RIGHTS_ALL};
+
+pub const PARTITION_FIRST_SECTOR: u32 = 63;
+pub const BLOCK_SIZES: [ usize; 5 ] = [ 4096, 8192, 16384, 32768, 65536 ];
+// The root directory is owned by SUPERVISOR, which has this object ID in
+// every bindery
+pub const SUPERVISOR_ID: u32 = 1;
+
+// Volume names consist of 2 to 15 letters, digits and a few other characters
+pub fn is_valid_volume_name(name: &str) -> bool {
+    (2..=VOLUME_NAME_MAX).contains(&name.len()) && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "_-!@#$%&()".contains(c))
+}
+
+// Sizes and positions of the parts of the new image, in sectors relative to
+// the partition or the data area, and in blocks within the volume
+struct Layout {
+    partition_sectors: u32,
+    redirection_sectors: u32,
+    data_sectors: u32,
+    first_sector: u32,
+    sectors_per_block: u32,
+    volume_blocks: u32,
+    fat_blocks: u32,
+}
+
+impl Layout {
+    fn new(size: u64, block_size: usize) -> Result<Self, NWFSError> {
+        let hotfix_sectors = (HOTFIX_BLOCK_SIZE / SECTOR_SIZE) as u32;
+        let total_sectors = (size / SECTOR_SIZE as u64).min(u32::MAX as u64) as u32;
+        let partition_sectors = total_sectors.saturating_sub(PARTITION_FIRST_SECTOR);
+        let redirection_sectors = (partition_sectors / 50).next_multiple_of(hotfix_sectors).max(2 * hotfix_sectors);
+        let data_sectors = partition_sectors.saturating_sub(REDIRECTION_SECTOR as u32 + redirection_sectors);
+        let sectors_per_block = (block_size / SECTOR_SIZE) as u32;
+        let first_sector = VOLUME_TABLE_SECTORS as u32;
+        let volume_blocks = data_sectors.saturating_sub(first_sector) / sectors_per_block;
+        let fat_blocks = (volume_blocks as usize * FAT_ENTRY_SIZE).div_ceil(block_size) as u32;
+        if volume_blocks < 2 * fat_blocks + 16 {
+            return Err(NWFSError::NoSpace(format!("{} bytes is too small for a volume", size)));
+        }
+        Ok(Self{ partition_sectors, redirection_sectors, data_sectors, first_sector, sectors_per_block, volume_blocks, fat_blocks })
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        let sector = PARTITION_FIRST_SECTOR as u64 + REDIRECTION_SECTOR + self.redirection_sectors as u64
+            + self.first_sector as u64 + block as u64 * self.sectors_per_block as u64;
+        sector * SECTOR_SIZE as u64
+    }
+}
+
+fn master_boot_record(layout: &Layout) -> Vec<u8> {
+    let mut mbr = vec![ 0u8; SECTOR_SIZE ];
+    // CHS addresses are not used; these values tell to use LBA instead
+    mbr[0x1be..0x1c6].copy_from_slice(&[ 0, 0xfe, 0xff, 0xff, PARTITION_TYPE_NETWARE, 0xfe, 0xff, 0xff ]);
+    LittleEndian::write_u32(&mut mbr[0x1c6..], PARTITION_FIRST_SECTOR);
+    LittleEndian::write_u32(&mut mbr[0x1ca..], layout.partition_sectors);
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+    mbr
+}
+
+fn hotfix_header(layout: &Layout, partition_id: u32) -> Vec<u8> {
+    let mut hotfix = vec![ 0u8; SECTOR_SIZE ];
+    hotfix[0..8].copy_from_slice(HOTFIX_SIGNATURE);
+    LittleEndian::write_u32(&mut hotfix[8..], partition_id);
+    LittleEndian::write_u32(&mut hotfix[20..], layout.data_sectors);
+    LittleEndian::write_u32(&mut hotfix[24..], layout.redirection_sectors);
+    hotfix
+}
+
+// The partition is the only member of its mirror group
+fn mirror_header(layout: &Layout, partition_id: u32) -> Vec<u8> {
+    let mut mirror = vec![ 0u8; SECTOR_SIZE ];
+    mirror[0..8].copy_from_slice(MIRROR_SIGNATURE);
+    LittleEndian::write_u32(&mut mirror[12..], partition_id);
+    LittleEndian::write_u32(&mut mirror[16..], partition_id);
+    LittleEndian::write_u32(&mut mirror[20..], layout.data_sectors);
+    LittleEndian::write_u32(&mut mirror[24..], 1);
+    LittleEndian::write_u32(&mut mirror[28..], partition_id);
+    mirror
+}
+
+fn volume_table(layout: &Layout, name: &str, signature: u32) -> Vec<u8> {
+    let (fat, mirror_fat, directory, mirror_directory) = (0, layout.fat_blocks, 2 * layout.fat_blocks, 2 * layout.fat_blocks + 1);
+    let mut table = vec![ 0u8; VOLUME_TABLE_SECTORS as usize * SECTOR_SIZE ];
+    table[0..16].copy_from_slice(VOLUME_TABLE_SIGNATURE);
+    LittleEndian::write_u32(&mut table[16..], 1);
+    let entry = &mut table[VOLUME_TABLE_HEADER_SIZE..VOLUME_TABLE_HEADER_SIZE + VOLUME_TABLE_ENTRY_SIZE];
+    entry[0] = name.len() as u8;
+    entry[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    // The volume has a single segment, so the last segment and segment
+    // number (16 bits each) are 0, as are the flags and first block
+    let fields = [ 0, signature, layout.volume_blocks, layout.volume_blocks * layout.sectors_per_block, layout.first_sector, 0,
+        layout.sectors_per_block, fat, mirror_fat, directory, mirror_directory, 0 ];
+    for (n, value) in fields.iter().enumerate() {
+        LittleEndian::write_u32(&mut entry[16 + n * 4..], *value);
+    }
+    table
+}
+
+// FAT entries for the FAT, the directory and their mirror copies; all other
+// blocks are free, which is an entry of zeroes
+fn fat(layout: &Layout, block_size: usize) -> Vec<u8> {
+    let chain = |first: u32, count: u32| (first..first + count).map(move |b| (b, b - first, if b + 1 < first + count { b + 1 } else { FAT_CHAIN_END }));
+    let mut fat = vec![ FAT_FREE as u8; layout.fat_blocks as usize * block_size ];
+    let used = chain(0, layout.fat_blocks).chain(chain(layout.fat_blocks, layout.fat_blocks))
+        .chain(chain(2 * layout.fat_blocks, 1)).chain(chain(2 * layout.fat_blocks + 1, 1));
+    for (block, index, next) in used {
+        let offset = block as usize * FAT_ENTRY_SIZE;
+        LittleEndian::write_u32(&mut fat[offset..], index);
+        LittleEndian::write_u32(&mut fat[offset + 4..], next);
+    }
+    fat
+}
+
+// The root directory is the first entry and carries the volume name
+fn directory(name: &str, block_size: usize, timestamp: u32) -> Vec<u8> {
+    let mut directory = vec![ 0u8; block_size ];
+    for chunk in directory.chunks_exact_mut(DIR_ENTRY_SIZE) {
+        LittleEndian::write_u32(chunk, DIR_FREE);
+    }
+    let root = &mut directory[0..DIR_ENTRY_SIZE];
+    LittleEndian::write_u32(&mut root[0x00..], DIR_ROOT);
+    LittleEndian::write_u32(&mut root[0x04..], ATTR_DIRECTORY);
+    root[0x0b] = name.len() as u8;
+    root[0x0c..0x0c + name.len()].copy_from_slice(name.as_bytes());
+    LittleEndian::write_u32(&mut root[0x18..], timestamp);
+    LittleEndian::write_u32(&mut root[0x1c..], SUPERVISOR_ID);
+    LittleEndian::write_u32(&mut root[0x28..], timestamp);
+    LittleEndian::write_u32(&mut root[0x2c..], SUPERVISOR_ID);
+    LittleEndian::write_u32(&mut root[0x34..], FAT_CHAIN_END);
+    LittleEndian::write_u16(&mut root[0x40..], RIGHTS_ALL);
+    directory
+}
+
+// Creates an image of size bytes at path, which must not exist yet, holding
+// volume name; timestamp is the creation time of the root directory.
+// Returns the number of blocks of the volume
+pub fn mkfs(path: &Path, size: u64, name: &str, block_size: usize, timestamp: u32) -> Result<u32, NWFSError> {
+    if !is_valid_volume_name(name) {
+        return Err(NWFSError::Unsupported(format!("volume name '{}'", name)));
+    }
+    if !BLOCK_SIZES.contains(&block_size) {
+        return Err(NWFSError::Unsupported(format!("block size {}", block_size)));
+    }
+    let layout = Layout::new(size, block_size)?;
+
+    // Partition and volume signatures only need to differ between disks
+    let seconds = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as u32;
+    let (partition_id, signature) = (seconds, seconds.rotate_left(16) ^ 0x4e574653);
+
+    let mut file = File::options().write(true).create_new(true).open(path)?;
+    file.set_len(size)?;
+    let partition = PARTITION_FIRST_SECTOR as u64 * SECTOR_SIZE as u64;
+    write_at(&mut file, 0, &master_boot_record(&layout))?;
+    write_at(&mut file, partition + HOTFIX_SECTOR * SECTOR_SIZE as u64, &hotfix_header(&layout, partition_id))?;
+    write_at(&mut file, partition + MIRROR_SECTOR * SECTOR_SIZE as u64, &mirror_header(&layout, partition_id))?;
+    let data_area = partition + (REDIRECTION_SECTOR + layout.redirection_sectors as u64) * SECTOR_SIZE as u64;
+    write_at(&mut file, data_area, &volume_table(&layout, name, signature))?;
+    let fat = fat(&layout, block_size);
+    write_at(&mut file, layout.block_offset(0), &fat)?;
+    write_at(&mut file, layout.block_offset(layout.fat_blocks), &fat)?;
+    let directory = directory(name, block_size, timestamp);
+    write_at(&mut file, layout.block_offset(2 * layout.fat_blocks), &directory)?;
+    write_at(&mut file, layout.block_offset(2 * layout.fat_blocks + 1), &directory)?;
+    Ok(layout.volume_blocks)
+}
@@ -0,0 +1,184 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// NetWare 386 file system, as used by NetWare 3.x and 4.x.
+//
+// A NetWare partition (type 0x65) starts with the hotfix header at sector
+// 0x20, followed by the mirror header. The hotfix redirection area follows
+// at sector 0x40, after which the data area starts. Blocks of the data area
+// which went bad are replaced by a block of the redirection area, so all
+// reads from the data area go through the redirection table. The volume table is
+// located at the start of the data area and describes the volume segments
+// present on this partition.
+//
+// Volumes consist of blocks. The FAT contains an (index, next) pair for every
+// block: index is the position of the block within the file and next the
+// next block in the chain. Both the FAT itself and the directory are chained
+// through the FAT as well. The directory is a flat table of 128 byte entries
+// which refer to their parent directory by entry number.
+//
+// NetWare 2.x uses a simpler variant of this layout, see nwfs286.
+pub mod cache;
+pub mod carve;
+pub mod check;
+pub mod hotfix;
+pub mod image;
+pub mod mirror;
+pub mod mkfs;
+#[cfg(feature = "fuse")]
+pub mod mount;
+pub mod nwfs286;
+pub mod partition;
+pub mod search;
+pub mod tts;
+pub mod volume;
+pub mod vtable;
+pub mod write;
+
+use std::fmt;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum NWFSError {
+    IoError(std::io::Error),
+    InvalidHeader(String),
+    VolumeNotFound(String),
+    FileNotFound(String),
+    Corrupt(String),
+    Unsupported(String),
+    NoSpace(String),
+}
+
+impl From<std::io::Error> for NWFSError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl fmt::Display for NWFSError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NWFSError::IoError(e) => write!(f, "{}", e),
+            NWFSError::InvalidHeader(s) => write!(f, "invalid header: {}", s),
+            NWFSError::VolumeNotFound(s) => write!(f, "volume '{}' not found", s),
+            NWFSError::FileNotFound(s) => write!(f, "file '{}' not found", s),
+            NWFSError::Corrupt(s) => write!(f, "corrupt file system: {}", s),
+            NWFSError::Unsupported(s) => write!(f, "not supported: {}", s),
+            NWFSError::NoSpace(s) => write!(f, "not enough space: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for NWFSError { }
+
+// Anything the file system can be read from: a raw image file, or a virtual
+// disk image decoded by image
+pub trait Device: std::io::Read + std::io::Seek { }
+impl<T: std::io::Read + std::io::Seek> Device for T { }
+
+pub fn read_at(dev: &mut dyn Device, offset: u64, len: usize) -> Result<Vec<u8>, NWFSError> {
+    let mut buf = vec![ 0u8; len ];
+    dev.seek(std::io::SeekFrom::Start(offset))?;
+    dev.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub fn write_at<D: std::io::Write + std::io::Seek>(dev: &mut D, offset: u64, data: &[u8]) -> Result<(), NWFSError> {
+    dev.seek(std::io::SeekFrom::Start(offset))?;
+    dev.write_all(data)?;
+    Ok(())
+}
+
+// Timestamps are stored as a DOS date (high word) and time (low word)
+fn split_timestamp(value: u32) -> (u32, u32, u32, u32, u32, u32) {
+    let (date, time) = (value >> 16, value & 0xffff);
+    (1980 + (date >> 9), (date >> 5) & 0xf, date & 0x1f, time >> 11, (time >> 5) & 0x3f, (time & 0x1f) * 2)
+}
+
+pub fn format_timestamp(value: u32) -> String {
+    if value == 0 {
+        return "-".to_string();
+    }
+    let (year, month, day, hour, minute, second) = split_timestamp(value);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second)
+}
+
+// Seconds since the Unix epoch, taking the timestamp to be UTC
+pub fn timestamp_to_unix(value: u32) -> Option<u64> {
+    let (year, month, day, hour, minute, second) = split_timestamp(value);
+    if !(1..=12).contains(&month) || day == 0 || hour > 23 || minute > 59 {
+        return None;
+    }
+    // Days since 1970-01-01 using a year starting in March, so the leap day
+    // is at its end
+    let y = if month <= 2 { year - 1 } else { year } as u64;
+    let day_of_year = (153 * ((month as u64 + 9) % 12) + 2) / 5 + day as u64 - 1;
+    let days = y * 365 + y / 4 - y / 100 + y / 400 + day_of_year - 719468;
+    Some(days * 86400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64)
+}
+
+// Offset of a time zone in seconds east of UTC, given as UTC, +HH, +HHMM or
+// +HH:MM
+pub fn parse_timezone(s: &str) -> Option<i32> {
+    if s.eq_ignore_ascii_case("UTC") || s == "Z" {
+        return Some(0);
+    }
+    let (sign, rest) = match s.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some(parts) => parts,
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60) as i32)
+}
+
+// Year, month, day, hour, minute and second of seconds since the Unix epoch
+fn split_unix_time(seconds: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Civil date from the days since 1970-01-01, using a year starting in
+    // March as timestamp_to_unix() does
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as u64, month as u64, day as u64, time / 3600, (time / 60) % 60, time % 60)
+}
+
+// Seconds since the Unix epoch as an ISO 8601 date and time in UTC
+pub fn format_unix_time(seconds: u64) -> String {
+    let (year, month, day, hour, minute, second) = split_unix_time(seconds);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+// Timestamp of seconds since the Unix epoch, taken to be in the time zone
+// the timestamp is to be in; times before 1980 cannot be stored and become
+// 1980-01-01
+pub fn unix_to_timestamp(seconds: u64) -> u32 {
+    let (year, month, day, hour, minute, second) = split_unix_time(seconds);
+    if year < 1980 {
+        return (1 << 21) | (1 << 16);
+    }
+    let date = ((year - 1980).min(127) << 9) | (month << 5) | day;
+    let time = (hour << 11) | (minute << 5) | (second / 2);
+    ((date << 16) | time) as u32
+}
@@ -0,0 +1,152 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Read-only FUSE file system presenting a volume. Inode numbers are the
+// directory entry numbers plus 2, except for the root directory which must
+// be inode 1. Only the blocks of a file which are read are accessed; the
+// location of the blocks of the most recently read file is kept, so its chain
+// is not followed for every read.
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request, FUSE_ROOT_ID};
+
+use crate::nwfs::{self, NWFSError};
+use crate::nwfs::volume::{DirEntry, FileBlocks, Volume};
+
+const TTL: Duration = Duration::from_secs(60);
+
+pub struct VolumeFs {
+    volume: Volume,
+    root: u32,
+    uid: u32,
+    gid: u32,
+    file: Option<(u32, FileBlocks)>,
+}
+
+impl VolumeFs {
+    pub fn new(volume: Volume) -> Self {
+        let root = volume.root();
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Self{ volume, root, uid, gid, file: None }
+    }
+
+    fn ino(&self, entry: u32) -> u64 {
+        if entry == self.root { FUSE_ROOT_ID } else { entry as u64 + 2 }
+    }
+
+    fn entry(&self, ino: u64) -> Option<&DirEntry> {
+        let entry = if ino == FUSE_ROOT_ID { self.root } else { ino.checked_sub(2)? as u32 };
+        self.volume.entries.get(entry as usize).filter(|e| e.is_in_use() && !e.is_deleted())
+    }
+
+    fn attr(&self, e: &DirEntry) -> FileAttr {
+        let time = |t: u32| self.volume.unix_time(t).map(|s| SystemTime::UNIX_EPOCH + Duration::from_secs(s)).unwrap_or(SystemTime::UNIX_EPOCH);
+        let (kind, perm, size) = if e.is_directory() { (FileType::Directory, 0o555, 0) } else { (FileType::RegularFile, 0o444, e.size as u64) };
+        FileAttr{
+            ino: self.ino(e.entry),
+            size,
+            blocks: size.div_ceil(nwfs::SECTOR_SIZE as u64),
+            atime: time(e.modified),
+            mtime: time(e.modified),
+            ctime: time(e.modified),
+            crtime: time(e.created),
+            kind,
+            perm,
+            nlink: if e.is_directory() { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: self.volume.block_size() as u32,
+            flags: 0,
+        }
+    }
+}
+
+impl Filesystem for VolumeFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let parent = match self.entry(parent) {
+            Some(parent) => parent.entry,
+            None => return reply.error(libc::ENOENT),
+        };
+        let name = name.to_string_lossy();
+        match self.volume.children(parent).find(|e| self.volume.display_name(e) == name) {
+            Some(e) => reply.entry(&TTL, &self.attr(e), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.entry(ino) {
+            Some(e) => reply.attr(&TTL, &self.attr(e)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        let e = match self.entry(ino) {
+            Some(e) if !e.is_directory() => e.clone(),
+            Some(_) => return reply.error(libc::EISDIR),
+            None => return reply.error(libc::ENOENT),
+        };
+        if self.file.as_ref().map(|(entry, _)| *entry) != Some(e.entry) {
+            match self.volume.file_blocks(&e) {
+                Ok(blocks) => self.file = Some((e.entry, blocks)),
+                Err(err) => {
+                    println!("cannot read {}: {}", self.volume.path(&e), err);
+                    return reply.error(libc::EIO);
+                }
+            }
+        }
+        let Some((_, blocks)) = &self.file else { return reply.error(libc::EIO) };
+        let start = (offset.max(0) as usize).min(blocks.size);
+        let end = (start + size as usize).min(blocks.size);
+        let mut data = Vec::with_capacity(end - start);
+        for index in start / blocks.block_size..end.div_ceil(blocks.block_size) {
+            match self.volume.read_file_part(blocks, index) {
+                Ok(part) => data.extend(part),
+                Err(err) => {
+                    println!("cannot read {}: {}", self.volume.path(&e), err);
+                    return reply.error(libc::EIO);
+                }
+            }
+        }
+        let skip = start % blocks.block_size;
+        reply.data(&data[skip.min(data.len())..(skip + end - start).min(data.len())]);
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let dir = match self.entry(ino) {
+            Some(e) if e.is_directory() => e,
+            Some(_) => return reply.error(libc::ENOTDIR),
+            None => return reply.error(libc::ENOENT),
+        };
+        let parent = if dir.entry == self.root { FUSE_ROOT_ID } else { self.ino(dir.parent) };
+        let mut items: Vec<(u64, FileType, String)> = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (parent, FileType::Directory, "..".to_string()),
+        ];
+        for e in self.volume.children(dir.entry) {
+            let kind = if e.is_directory() { FileType::Directory } else { FileType::RegularFile };
+            items.push((self.ino(e.entry), kind, self.volume.display_name(e).to_string()));
+        }
+        for (n, (ino, kind, name)) in items.iter().enumerate().skip(offset.max(0) as usize) {
+            if reply.add(*ino, (n + 1) as i64, *kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+// Blocks until the file system is unmounted
+pub fn mount(volume: Volume, mountpoint: &Path) -> Result<(), NWFSError> {
+    let name = volume.name().to_string();
+    let options = [ MountOption::RO, MountOption::FSName(format!("nwfs:{}", name)), MountOption::DefaultPermissions ];
+    fuser::mount2(VolumeFs::new(volume), mountpoint, &options)?;
+    Ok(())
+}
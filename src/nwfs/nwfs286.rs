@@ -0,0 +1,99 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// NetWare 286 file system, as used by NetWare 2.x. Partitions (type 0x64)
+// have the same hotfix area and volume table as NetWare 386, but the volume
+// itself differs: the FAT is a contiguous table of 16-bit next pointers,
+// which does not describe its own chain, and directory entries are 32 bytes
+// with 16-bit parent and block numbers and no owner. The position of a block
+// within a file follows from its position in the chain, and there are no
+// name spaces or suballocation.
+use std::io::{Cursor, Read};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::{DirEntry, FatEntry, DIR_FREE, DIR_ROOT, DIR_TRUSTEE, FAT_CHAIN_END, NS_DOS, RIGHTS_ALL};
+
+pub const PARTITION_TYPE_NETWARE_286: u8 = 0x64;
+
+pub const FAT_ENTRY_SIZE: usize = 2;
+pub const FAT_CHAIN_END_286: u16 = 0xffff;
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+pub const DIR_NAME_MAX: usize = 14;
+
+pub const DIR_FREE_286: u16 = 0xffff;
+pub const DIR_TRUSTEE_286: u16 = 0xfffe;
+pub const DIR_ROOT_286: u16 = 0xfffd;
+
+// The root directory is the first directory entry on both file systems; its
+// parent field tells them apart, as on NetWare 386 it is 32 bits wide
+pub fn is_nwfs286_directory(data: &[u8]) -> bool {
+    data.len() >= 4 && LittleEndian::read_u16(data) == DIR_ROOT_286 && LittleEndian::read_u32(data) != DIR_ROOT
+}
+
+// Number of blocks taken by the FAT of a volume of total_blocks blocks
+pub fn fat_blocks(total_blocks: u32, block_size: usize) -> u32 {
+    (total_blocks as usize * FAT_ENTRY_SIZE).div_ceil(block_size) as u32
+}
+
+pub fn parse_fat(data: &[u8], total_blocks: usize) -> Vec<FatEntry> {
+    data.chunks_exact(FAT_ENTRY_SIZE)
+        .take(total_blocks)
+        .map(|e| match LittleEndian::read_u16(e) {
+            FAT_CHAIN_END_286 => FAT_CHAIN_END,
+            next => next as u32,
+        })
+        .map(|next| FatEntry{ index: 0, next })
+        .collect()
+}
+
+pub fn parse_dir_entry(entry: u32, data: &[u8]) -> Result<DirEntry, NWFSError> {
+    let mut cursor = Cursor::new(data);
+    let parent = match cursor.read_u16::<LittleEndian>()? {
+        DIR_FREE_286 => DIR_FREE,
+        DIR_TRUSTEE_286 => DIR_TRUSTEE,
+        DIR_ROOT_286 => DIR_ROOT,
+        parent => parent as u32,
+    };
+    let mut name = [ 0u8; DIR_NAME_MAX ];
+    cursor.read_exact(&mut name)?;
+    let name_len = name.iter().position(|b| *b == 0).unwrap_or(DIR_NAME_MAX);
+    let name = String::from_utf8_lossy(&name[0..name_len]).to_string();
+    let attributes = cursor.read_u8()? as u32;
+    let _extended_attributes = cursor.read_u8()?;
+    let size = cursor.read_u32::<LittleEndian>()?;
+    let created_date = cursor.read_u16::<LittleEndian>()? as u32;
+    let modified_date = cursor.read_u16::<LittleEndian>()? as u32;
+    let modified_time = cursor.read_u16::<LittleEndian>()? as u32;
+    let first_block = match cursor.read_u16::<LittleEndian>()? {
+        FAT_CHAIN_END_286 => FAT_CHAIN_END,
+        block => block as u32,
+    };
+    Ok(DirEntry{
+        entry,
+        parent,
+        attributes,
+        flags: 0,
+        namespace: NS_DOS,
+        primary: entry,
+        name,
+        created: created_date << 16,
+        owner: 0,
+        modified: (modified_date << 16) | modified_time,
+        modifier: 0,
+        archived: 0,
+        archiver: 0,
+        size,
+        first_block,
+        deleted: 0,
+        deleted_by: 0,
+        finder_info: None,
+        inherited_rights: RIGHTS_ALL,
+        ea_handle: 0,
+    })
+}
@@ -0,0 +1,501 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::nwfs::{read_at, write_at, Device, NWFSError, SECTOR_SIZE};
+use crate::nwfs::cache::DEFAULT_CACHE_SIZE;
+use crate::nwfs::nwfs286::PARTITION_TYPE_NETWARE_286;
+
+pub const PARTITION_TYPE_NETWARE: u8 = 0x65;
+// NetWare 5 and later store their volumes in NSS pools, which use a different
+// file system altogether; these partitions are recognized, but not read.
+// Reading them needs a description of the format or sample images
+pub const PARTITION_TYPE_NSS: u8 = 0x69;
+
+pub const HOTFIX_SECTOR: u64 = 0x20;
+pub const MIRROR_SECTOR: u64 = 0x21;
+pub const REDIRECTION_SECTOR: u64 = 0x40;
+pub const HOTFIX_SIGNATURE: &[u8; 8] = b"HOTFIX00";
+pub const MIRROR_SIGNATURE: &[u8; 8] = b"MIRROR00";
+
+pub const VOLUME_TABLE_SIGNATURE: &[u8; 16] = b"NetWare Volumes\0";
+pub const VOLUME_TABLE_HEADER_SIZE: usize = 0x20;
+pub const VOLUME_TABLE_ENTRY_SIZE: usize = 0x40;
+pub const VOLUME_TABLE_SECTORS: u64 = 0x20;
+pub const VOLUME_NAME_MAX: usize = 15;
+// Names of the fields of a volume table entry which follow the volume name,
+// in the order in which they are stored
+pub const SEGMENT_FIELDS: [ &str; 13 ] = [ "last-segment", "segment-number", "signature", "volume-blocks", "segment-sectors", "first-sector",
+    "flags", "sectors-per-block", "first-fat", "mirror-fat", "first-directory", "mirror-directory", "first-block" ];
+
+#[derive(Debug)]
+pub struct Hotfix {
+    pub partition_id: u32,
+    pub flags: u32,
+    pub date: u32,
+    pub data_sectors: u32,
+    pub redirection_sectors: u32,
+}
+
+impl Hotfix {
+    pub fn from(data: &[u8]) -> Result<Self, NWFSError> {
+        if &data[0..8] != HOTFIX_SIGNATURE {
+            return Err(NWFSError::InvalidHeader("hotfix signature mismatch".to_string()));
+        }
+        let mut cursor = Cursor::new(&data[8..]);
+        let partition_id = cursor.read_u32::<LittleEndian>()?;
+        let flags = cursor.read_u32::<LittleEndian>()?;
+        let date = cursor.read_u32::<LittleEndian>()?;
+        let data_sectors = cursor.read_u32::<LittleEndian>()?;
+        let redirection_sectors = cursor.read_u32::<LittleEndian>()?;
+        Ok(Self{ partition_id, flags, date, data_sectors, redirection_sectors })
+    }
+}
+
+// The redirection area is used in blocks of this size; its first blocks hold
+// the redirection table
+pub const HOTFIX_BLOCK_SIZE: usize = 4096;
+pub const REDIRECTION_ENTRY_SIZE: usize = 4;
+
+// Blocks of the data area which went bad are replaced by a block of the
+// redirection area. The table contains the data area block number (in units
+// of HOTFIX_BLOCK_SIZE) replaced by every redirection block, or 0 if the
+// block is unused; the first data block, holding the volume table, cannot be
+// redirected. Entries which do not refer to a block of the data area mark
+// redirection blocks which cannot be used, such as ones which went bad
+// themselves.
+#[derive(Debug, Clone, Default)]
+pub struct Redirection {
+    // Byte offset of the redirection area within the image
+    pub offset: u64,
+    // Number of blocks in the redirection area, including the table
+    pub total_blocks: u32,
+    pub table_blocks: u32,
+    // Data area block to redirection block
+    pub blocks: BTreeMap<u32, u32>,
+    pub unusable: Vec<u32>,
+}
+
+impl Redirection {
+    pub fn read(dev: &mut dyn Device, partition_offset: u64, hotfix: &Hotfix) -> Result<Self, NWFSError> {
+        let offset = partition_offset + REDIRECTION_SECTOR * SECTOR_SIZE as u64;
+        let total_blocks = (hotfix.redirection_sectors as usize * SECTOR_SIZE / HOTFIX_BLOCK_SIZE) as u32;
+        let table_blocks = (total_blocks as usize * REDIRECTION_ENTRY_SIZE).div_ceil(HOTFIX_BLOCK_SIZE) as u32;
+        let mut redirection = Self{ offset, total_blocks, table_blocks, blocks: BTreeMap::new(), unusable: Vec::new() };
+        if total_blocks == 0 {
+            return Ok(redirection);
+        }
+        let data_blocks = (hotfix.data_sectors as usize * SECTOR_SIZE / HOTFIX_BLOCK_SIZE) as u32;
+        let table = read_at(dev, offset, table_blocks as usize * HOTFIX_BLOCK_SIZE)?;
+        let mut cursor = Cursor::new(&table);
+        for block in 0..total_blocks {
+            let data_block = cursor.read_u32::<LittleEndian>()?;
+            if block < table_blocks || data_block == 0 { continue; }
+            if data_block >= data_blocks {
+                redirection.unusable.push(block);
+                continue;
+            }
+            redirection.blocks.insert(data_block, block);
+        }
+        Ok(redirection)
+    }
+
+    // Replaces the parts of buf, read from the data area at byte offset
+    // data_offset, which are redirected
+    pub fn apply(&self, dev: &mut dyn Device, data_offset: u64, buf: &mut [u8]) -> Result<(), NWFSError> {
+        if self.blocks.is_empty() || buf.is_empty() {
+            return Ok(());
+        }
+        let block_size = HOTFIX_BLOCK_SIZE as u64;
+        let first = (data_offset / block_size) as u32;
+        let last = ((data_offset + buf.len() as u64 - 1) / block_size) as u32;
+        for (data_block, block) in self.blocks.range(first..=last) {
+            let data = read_at(dev, self.offset + *block as u64 * block_size, HOTFIX_BLOCK_SIZE)?;
+            let start = (*data_block as u64 * block_size).max(data_offset);
+            let end = ((*data_block as u64 + 1) * block_size).min(data_offset + buf.len() as u64);
+            let src = (start - *data_block as u64 * block_size) as usize;
+            let dst = (start - data_offset) as usize;
+            let len = (end - start) as usize;
+            buf[dst..dst + len].copy_from_slice(&data[src..src + len]);
+        }
+        Ok(())
+    }
+
+    // Writes the parts of data, which is written to the data area at byte
+    // offset data_offset, that are redirected to their redirection block
+    pub fn write<D: Device + Write>(&self, dev: &mut D, data_offset: u64, data: &[u8]) -> Result<(), NWFSError> {
+        if self.blocks.is_empty() || data.is_empty() {
+            return Ok(());
+        }
+        let block_size = HOTFIX_BLOCK_SIZE as u64;
+        let first = (data_offset / block_size) as u32;
+        let last = ((data_offset + data.len() as u64 - 1) / block_size) as u32;
+        for (data_block, block) in self.blocks.range(first..=last) {
+            let offset = self.offset + *block as u64 * block_size;
+            let mut buf = read_at(dev, offset, HOTFIX_BLOCK_SIZE)?;
+            let start = (*data_block as u64 * block_size).max(data_offset);
+            let end = ((*data_block as u64 + 1) * block_size).min(data_offset + data.len() as u64);
+            let dst = (start - *data_block as u64 * block_size) as usize;
+            let src = (start - data_offset) as usize;
+            let len = (end - start) as usize;
+            buf[dst..dst + len].copy_from_slice(&data[src..src + len]);
+            write_at(dev, offset, &buf)?;
+        }
+        Ok(())
+    }
+}
+
+// The mirror header identifies the mirror group the partition belongs to;
+// partitions with the same group ID hold the same data
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    pub flags: u32,
+    pub partition_id: u32,
+    pub group_id: u32,
+    pub data_sectors: u32,
+    pub members: Vec<u32>,
+}
+
+pub const MIRROR_MAX_MEMBERS: usize = 8;
+
+impl Mirror {
+    pub fn from(data: &[u8]) -> Result<Self, NWFSError> {
+        if &data[0..8] != MIRROR_SIGNATURE {
+            return Err(NWFSError::InvalidHeader("mirror signature mismatch".to_string()));
+        }
+        let mut cursor = Cursor::new(&data[8..]);
+        let flags = cursor.read_u32::<LittleEndian>()?;
+        let partition_id = cursor.read_u32::<LittleEndian>()?;
+        let group_id = cursor.read_u32::<LittleEndian>()?;
+        let data_sectors = cursor.read_u32::<LittleEndian>()?;
+        let num_members = (cursor.read_u32::<LittleEndian>()? as usize).min(MIRROR_MAX_MEMBERS);
+        let mut members = Vec::with_capacity(num_members);
+        for _ in 0..num_members {
+            members.push(cursor.read_u32::<LittleEndian>()?);
+        }
+        Ok(Self{ flags, partition_id, group_id, data_sectors, members })
+    }
+}
+
+// A volume table entry describes a single segment of a volume; all block
+// numbers are relative to the volume and sectors to the data area
+#[derive(Debug, Clone)]
+pub struct VolumeSegment {
+    pub name: String,
+    pub last_segment: u16,
+    pub segment_number: u16,
+    pub signature: u32,
+    pub volume_blocks: u32,
+    pub segment_sectors: u32,
+    pub first_sector: u32,
+    pub flags: u32,
+    pub sectors_per_block: u32,
+    pub first_fat: u32,
+    pub mirror_fat: u32,
+    pub first_directory: u32,
+    pub mirror_directory: u32,
+    pub first_block: u32,
+}
+
+impl VolumeSegment {
+    pub fn from(data: &[u8]) -> Result<Self, NWFSError> {
+        let name_len = (data[0] as usize).min(VOLUME_NAME_MAX);
+        let name = String::from_utf8_lossy(&data[1..1 + name_len]).to_string();
+        let mut cursor = Cursor::new(&data[16..]);
+        let last_segment = cursor.read_u16::<LittleEndian>()?;
+        let segment_number = cursor.read_u16::<LittleEndian>()?;
+        let signature = cursor.read_u32::<LittleEndian>()?;
+        let volume_blocks = cursor.read_u32::<LittleEndian>()?;
+        let segment_sectors = cursor.read_u32::<LittleEndian>()?;
+        let first_sector = cursor.read_u32::<LittleEndian>()?;
+        let flags = cursor.read_u32::<LittleEndian>()?;
+        let sectors_per_block = cursor.read_u32::<LittleEndian>()?;
+        let first_fat = cursor.read_u32::<LittleEndian>()?;
+        let mirror_fat = cursor.read_u32::<LittleEndian>()?;
+        let first_directory = cursor.read_u32::<LittleEndian>()?;
+        let mirror_directory = cursor.read_u32::<LittleEndian>()?;
+        let first_block = cursor.read_u32::<LittleEndian>()?;
+        if sectors_per_block == 0 {
+            return Err(NWFSError::InvalidHeader(format!("volume '{}' has no block size", name)));
+        }
+        Ok(Self{ name, last_segment, segment_number, signature, volume_blocks, segment_sectors, first_sector, flags, sectors_per_block, first_fat, mirror_fat, first_directory, mirror_directory, first_block })
+    }
+
+    // Stores the segment in volume table entry entry, which is
+    // VOLUME_TABLE_ENTRY_SIZE bytes
+    pub fn write(&self, entry: &mut [u8]) {
+        let name = &self.name.as_bytes()[..self.name.len().min(VOLUME_NAME_MAX)];
+        entry[0..16].fill(0);
+        entry[0] = name.len() as u8;
+        entry[1..1 + name.len()].copy_from_slice(name);
+        LittleEndian::write_u16(&mut entry[16..], self.last_segment);
+        LittleEndian::write_u16(&mut entry[18..], self.segment_number);
+        for (n, field) in SEGMENT_FIELDS[2..].iter().enumerate() {
+            LittleEndian::write_u32(&mut entry[20 + n * 4..], self.field(field).unwrap());
+        }
+    }
+
+    // Value of the field named as in SEGMENT_FIELDS
+    pub fn field(&self, field: &str) -> Option<u32> {
+        Some(match field {
+            "last-segment" => self.last_segment as u32,
+            "segment-number" => self.segment_number as u32,
+            "signature" => self.signature,
+            "volume-blocks" => self.volume_blocks,
+            "segment-sectors" => self.segment_sectors,
+            "first-sector" => self.first_sector,
+            "flags" => self.flags,
+            "sectors-per-block" => self.sectors_per_block,
+            "first-fat" => self.first_fat,
+            "mirror-fat" => self.mirror_fat,
+            "first-directory" => self.first_directory,
+            "mirror-directory" => self.mirror_directory,
+            "first-block" => self.first_block,
+            _ => return None,
+        })
+    }
+
+    pub fn set_field(&mut self, field: &str, value: u32) -> Result<(), NWFSError> {
+        let short = || u16::try_from(value).map_err(|_| NWFSError::Unsupported(format!("{} values above {}", field, u16::MAX)));
+        match field {
+            "last-segment" => self.last_segment = short()?,
+            "segment-number" => self.segment_number = short()?,
+            "signature" => self.signature = value,
+            "volume-blocks" => self.volume_blocks = value,
+            "segment-sectors" => self.segment_sectors = value,
+            "first-sector" => self.first_sector = value,
+            "flags" => self.flags = value,
+            "sectors-per-block" if value == 0 => return Err(NWFSError::Unsupported("a block size of 0".to_string())),
+            "sectors-per-block" => self.sectors_per_block = value,
+            "first-fat" => self.first_fat = value,
+            "mirror-fat" => self.mirror_fat = value,
+            "first-directory" => self.first_directory = value,
+            "mirror-directory" => self.mirror_directory = value,
+            "first-block" => self.first_block = value,
+            _ => return Err(NWFSError::Unsupported(format!("volume table field '{}'", field))),
+        }
+        Ok(())
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.sectors_per_block as usize * SECTOR_SIZE
+    }
+
+    pub fn segment_blocks(&self) -> u32 {
+        self.segment_sectors / self.sectors_per_block
+    }
+}
+
+pub struct Partition {
+    // Offset of the partition within the image, in bytes
+    pub offset: u64,
+    pub hotfix: Hotfix,
+    // Missing on partitions which were never mirrored by some versions
+    pub mirror: Option<Mirror>,
+    pub redirection: Redirection,
+    pub segments: Vec<VolumeSegment>,
+}
+
+impl Partition {
+    pub fn read(dev: &mut dyn Device, offset: u64) -> Result<Self, NWFSError> {
+        let data = read_at(dev, offset + HOTFIX_SECTOR * SECTOR_SIZE as u64, SECTOR_SIZE)?;
+        let hotfix = Hotfix::from(&data)?;
+
+        let data = read_at(dev, offset + MIRROR_SECTOR * SECTOR_SIZE as u64, SECTOR_SIZE)?;
+        let mirror = Mirror::from(&data).ok();
+
+        let redirection = Redirection::read(dev, offset, &hotfix)?;
+
+        let mut partition = Self{ offset, hotfix, mirror, redirection, segments: Vec::new() };
+        partition.read_volume_table(dev)?;
+        Ok(partition)
+    }
+
+    // Reads a partition whose hotfix header is lost from its data area,
+    // which starts with the volume table. Without the redirection table,
+    // redirected blocks are read from their original location
+    pub fn from_data_area(dev: &mut dyn Device, data_offset: u64) -> Result<Self, NWFSError> {
+        let offset = data_offset.checked_sub(REDIRECTION_SECTOR * SECTOR_SIZE as u64)
+            .ok_or(NWFSError::InvalidHeader("data area cannot start before the redirection area".to_string()))?;
+        let hotfix = Hotfix{ partition_id: 0, flags: 0, date: 0, data_sectors: 0, redirection_sectors: 0 };
+        let mut partition = Self{ offset, hotfix, mirror: None, redirection: Redirection::default(), segments: Vec::new() };
+        partition.read_volume_table(dev)?;
+        Ok(partition)
+    }
+
+    fn read_volume_table(&mut self, dev: &mut dyn Device) -> Result<(), NWFSError> {
+        let data = self.read_data(dev, 0, VOLUME_TABLE_SECTORS as usize * SECTOR_SIZE)?;
+        if &data[0..16] != VOLUME_TABLE_SIGNATURE {
+            return Err(NWFSError::InvalidHeader("volume table signature mismatch".to_string()));
+        }
+        let mut cursor = Cursor::new(&data[16..]);
+        let num_entries = cursor.read_u32::<LittleEndian>()? as usize;
+        let max_entries = (data.len() - VOLUME_TABLE_HEADER_SIZE) / VOLUME_TABLE_ENTRY_SIZE;
+        if num_entries > max_entries {
+            return Err(NWFSError::InvalidHeader(format!("volume table has {} entries", num_entries)));
+        }
+        for n in 0..num_entries {
+            let offset = VOLUME_TABLE_HEADER_SIZE + n * VOLUME_TABLE_ENTRY_SIZE;
+            self.segments.push(VolumeSegment::from(&data[offset..offset + VOLUME_TABLE_ENTRY_SIZE])?);
+        }
+        Ok(())
+    }
+
+    // Byte offset of the data area within the image
+    pub fn data_offset(&self) -> u64 {
+        self.offset + (REDIRECTION_SECTOR + self.hotfix.redirection_sectors as u64) * SECTOR_SIZE as u64
+    }
+
+    // Reads from the data area, at byte offset offset within it, taking
+    // redirected blocks into account
+    pub fn read_data(&self, dev: &mut dyn Device, offset: u64, len: usize) -> Result<Vec<u8>, NWFSError> {
+        let mut data = read_at(dev, self.data_offset() + offset, len)?;
+        self.redirection.apply(dev, offset, &mut data)?;
+        Ok(data)
+    }
+
+    // Writes to the data area at byte offset offset; redirected blocks are
+    // written to both places, so the data is the same whichever is read
+    pub fn write_data<D: Device + Write>(&self, dev: &mut D, offset: u64, data: &[u8]) -> Result<(), NWFSError> {
+        write_at(dev, self.data_offset() + offset, data)?;
+        self.redirection.write(dev, offset, data)
+    }
+
+    // Byte offset of the first block of the segment within the image
+    pub fn segment_offset(&self, segment: &VolumeSegment) -> u64 {
+        self.data_offset() + segment.first_sector as u64 * SECTOR_SIZE as u64
+    }
+}
+
+// How partitions are located on disks which are not as usual: the size of
+// the sectors counted by the partition table, the geometry (cylinders, heads,
+// sectors per track) with which the CHS addresses of the partition table are
+// used instead of the LBA ones, and the byte offset of the NetWare partition,
+// which bypasses the partition table altogether. NetWare addresses its own
+// structures in 512 byte sectors regardless
+#[derive(Debug, Clone, Copy)]
+pub struct DiskLayout {
+    pub sector_size: u64,
+    pub geometry: Option<(u32, u32, u32)>,
+    pub partition_offset: Option<u64>,
+    // Bytes of every image cached while reading volumes, see cache
+    pub cache_size: usize,
+}
+
+impl Default for DiskLayout {
+    fn default() -> Self {
+        Self{ sector_size: SECTOR_SIZE as u64, geometry: None, partition_offset: None, cache_size: DEFAULT_CACHE_SIZE }
+    }
+}
+
+impl DiskLayout {
+    // Parses a geometry given as C/H/S
+    pub fn parse_geometry(s: &str) -> Option<(u32, u32, u32)> {
+        let values: Vec<u32> = s.split('/').map(|v| v.parse::<u32>().ok()).collect::<Option<_>>()?;
+        match values[..] {
+            [ cylinders, heads, sectors ] if cylinders > 0 && (1..=256).contains(&heads) && (1..=63).contains(&sectors) => Some((cylinders, heads, sectors)),
+            _ => None,
+        }
+    }
+}
+
+// Sector number of a CHS address of the partition table, as stored: head,
+// then the sector in bits 0-5 and bits 8-9 of the cylinder in bits 6-7, and
+// then bits 0-7 of the cylinder
+fn chs_to_lba(chs: &[u8], (_, heads, sectors): (u32, u32, u32)) -> u32 {
+    let (head, sector, cylinder) = (chs[0] as u32, (chs[1] & 0x3f) as u32, ((chs[1] as u32 & 0xc0) << 2) | chs[2] as u32);
+    (cylinder * heads + head) * sectors + sector.saturating_sub(1)
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionEntry {
+    // Position within the partition table, 0-3
+    pub index: usize,
+    pub bootable: bool,
+    pub partition_type: u8,
+    pub first_sector: u32,
+    pub sectors: u32,
+    pub sector_size: u64,
+}
+
+impl PartitionEntry {
+    pub fn is_netware(&self) -> bool {
+        self.partition_type == PARTITION_TYPE_NETWARE || self.partition_type == PARTITION_TYPE_NETWARE_286
+    }
+
+    pub fn is_nss(&self) -> bool {
+        self.partition_type == PARTITION_TYPE_NSS
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.first_sector as u64 * self.sector_size
+    }
+}
+
+// Reads the used entries of the partition table in the MBR; images without
+// a valid MBR have none
+pub fn read_partition_table(dev: &mut dyn Device, layout: &DiskLayout) -> Result<Vec<PartitionEntry>, NWFSError> {
+    let mut result = Vec::new();
+    let mbr = match read_at(dev, 0, SECTOR_SIZE) {
+        Ok(mbr) => mbr,
+        Err(_) => return Ok(result)
+    };
+    if mbr[510] != 0x55 || mbr[511] != 0xaa {
+        return Ok(result);
+    }
+    for index in 0..4 {
+        let mut cursor = Cursor::new(&mbr[0x1be + index * 16..]);
+        let mut entry = [ 0u8; 8 ];
+        cursor.read_exact(&mut entry)?;
+        let mut first_sector = cursor.read_u32::<LittleEndian>()?;
+        let mut sectors = cursor.read_u32::<LittleEndian>()?;
+        if let Some(geometry) = layout.geometry {
+            first_sector = chs_to_lba(&entry[1..4], geometry);
+            sectors = (chs_to_lba(&entry[5..8], geometry) + 1).saturating_sub(first_sector);
+        }
+        if entry[4] != 0 {
+            result.push(PartitionEntry{ index, bootable: entry[0] == 0x80, partition_type: entry[4], first_sector, sectors, sector_size: layout.sector_size });
+        }
+    }
+    Ok(result)
+}
+
+// Returns whether the image contains just a NetWare partition
+pub fn is_partition_image(dev: &mut dyn Device) -> bool {
+    read_at(dev, HOTFIX_SECTOR * SECTOR_SIZE as u64, 8).is_ok_and(|h| h == HOTFIX_SIGNATURE)
+}
+
+// Locates all NetWare partitions within the image. Images of a single
+// partition are recognized by the hotfix signature
+pub fn find_partitions(dev: &mut dyn Device, layout: &DiskLayout) -> Result<Vec<u64>, NWFSError> {
+    if let Some(offset) = layout.partition_offset {
+        return Ok(vec![ offset ]);
+    }
+    if is_partition_image(dev) {
+        return Ok(vec![ 0 ]);
+    }
+    Ok(read_partition_table(dev, layout)?.iter().filter(|e| e.is_netware()).map(|e| e.offset()).collect())
+}
+
+// Reads all NetWare partitions within the image
+pub fn read_partitions(dev: &mut dyn Device, layout: &DiskLayout) -> Result<Vec<Partition>, NWFSError> {
+    let offsets = find_partitions(dev, layout)?;
+    if offsets.is_empty() && read_partition_table(dev, layout)?.iter().any(|e| e.is_nss()) {
+        return Err(NWFSError::Unsupported("NSS partitions, as used by NetWare 5 and later".to_string()));
+    }
+    if offsets.is_empty() {
+        return Err(NWFSError::InvalidHeader("no NetWare partition found".to_string()));
+    }
+    // A partition offset given may also point at the data area, if the
+    // hotfix header is lost
+    offsets.into_iter().map(|offset| match Partition::read(dev, offset) {
+        Err(e) if layout.partition_offset.is_some() => Partition::from_data_area(dev, offset).map_err(|_| e),
+        result => result,
+    }).collect()
+}
@@ -0,0 +1,90 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Searches the contents of files for a sequence of bytes. Files are read a
+// block at a time; the bytes at the end of a block are kept, so matches
+// which span blocks are found and can be shown along with what surrounds
+// them.
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::{DirEntry, Volume};
+
+// Bytes shown before and after a match
+pub const CONTEXT_SIZE: usize = 16;
+
+pub struct Pattern {
+    bytes: Vec<u8>,
+    ignore_case: bool,
+}
+
+pub struct Match {
+    // Byte offset within the file
+    pub offset: usize,
+    // The match along with up to CONTEXT_SIZE bytes before and after it
+    pub context: Vec<u8>,
+    pub context_offset: usize,
+}
+
+impl Pattern {
+    // Case is only ignored for ASCII letters
+    pub fn new(bytes: &[u8], ignore_case: bool) -> Self {
+        let bytes = if ignore_case { bytes.to_ascii_lowercase() } else { bytes.to_vec() };
+        Self{ bytes, ignore_case }
+    }
+
+    // Parses hex digits, which may be separated by spaces, such as
+    // '4e 65 74' or '4e6574'
+    pub fn from_hex(s: &str, ignore_case: bool) -> Option<Self> {
+        let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes: Option<Vec<u8>> = digits.chunks(2).map(|d| u8::from_str_radix(std::str::from_utf8(d).ok()?, 16).ok()).collect();
+        Some(Self::new(&bytes?, ignore_case))
+    }
+
+    fn matches_at(&self, data: &[u8], pos: usize) -> bool {
+        let candidate = &data[pos..pos + self.bytes.len()];
+        if self.ignore_case {
+            candidate.iter().zip(&self.bytes).all(|(a, b)| a.to_ascii_lowercase() == *b)
+        } else {
+            candidate == self.bytes.as_slice()
+        }
+    }
+}
+
+// Finds all matches within the file. Matches may overlap
+pub fn search_file(volume: &mut Volume, entry: &DirEntry, pattern: &Pattern) -> Result<Vec<Match>, NWFSError> {
+    let len = pattern.bytes.len();
+    let mut result = Vec::new();
+    // The bytes kept start at base, and matches are searched for from next
+    let mut buffer: Vec<u8> = Vec::new();
+    let (mut base, mut next) = (0, 0);
+    let mut scan = |buffer: &[u8], base: usize, next: &mut usize, last: bool| {
+        // Matches are only taken once their context is complete, unless the
+        // end of the file is reached
+        let end = if last { buffer.len() } else { buffer.len().saturating_sub(CONTEXT_SIZE) };
+        let mut pos = *next - base;
+        while pos + len <= end {
+            if pattern.matches_at(buffer, pos) {
+                let start = pos.saturating_sub(CONTEXT_SIZE);
+                let context = buffer[start..(pos + len + CONTEXT_SIZE).min(buffer.len())].to_vec();
+                result.push(Match{ offset: base + pos, context, context_offset: base + start });
+            }
+            pos += 1;
+        }
+        *next = base + pos;
+    };
+    volume.read_file_blocks(entry, |_, data| {
+        buffer.extend_from_slice(data);
+        scan(&buffer, base, &mut next, false);
+        let drop = (next - base).saturating_sub(CONTEXT_SIZE);
+        buffer.drain(..drop);
+        base += drop;
+    })?;
+    scan(&buffer, base, &mut next, true);
+    Ok(result)
+}
@@ -0,0 +1,50 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Transaction Tracking System. Writes to files with the transactional
+// attribute are grouped into transactions; before a write, the old data is
+// saved to the backout file in the root of the TTS volume (SYS, unless
+// configured otherwise), so that transactions which were not complete when
+// the server went down are backed out when the volume is mounted again.
+// Problems doing so are logged to TTS$LOG.ERR.
+//
+// The format of the backout file is not documented, so incomplete
+// transactions cannot be backed out here; the files involved are located so
+// they can be examined, and the backout file can be extracted for analysis.
+// Parsing its records needs a format description or sample backout files of
+// which the transactions are known.
+use crate::nwfs::NWFSError;
+use crate::nwfs::volume::{DirEntry, Volume, ATTR_TRANSACTIONAL};
+
+pub const BACKOUT_FILE: &str = "BACKOUT.TTS";
+pub const LOG_FILE: &str = "TTS$LOG.ERR";
+
+pub struct TtsFiles {
+    pub backout: Option<DirEntry>,
+    pub log: Option<DirEntry>,
+    // Files with the transactional attribute, which may have been changed by
+    // an incomplete transaction
+    pub transactional: Vec<DirEntry>,
+}
+
+pub fn find(volume: &Volume) -> TtsFiles {
+    let backout = volume.lookup(BACKOUT_FILE).filter(|e| !e.is_directory()).cloned();
+    let log = volume.lookup(LOG_FILE).filter(|e| !e.is_directory()).cloned();
+    let transactional = volume.walk(volume.root())
+        .filter(|e| !e.is_directory() && e.attributes & ATTR_TRANSACTIONAL != 0)
+        .cloned()
+        .collect();
+    TtsFiles{ backout, log, transactional }
+}
+
+// Number of bytes of the backout file which are not zero, as an indication
+// of whether it holds any saved data
+pub fn used_bytes(volume: &mut Volume, backout: &DirEntry) -> Result<usize, NWFSError> {
+    let mut result = 0;
+    volume.read_file_blocks(backout, |_, data| result += data.iter().filter(|b| **b != 0).count())?;
+    Ok(result)
+}
@@ -0,0 +1,958 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::nwfs::{self, check, image, nwfs286, Device, NWFSError, SECTOR_SIZE};
+use crate::nwfs::cache::{BlockCache, DEFAULT_CACHE_SIZE};
+use crate::nwfs::partition::{read_partitions, DiskLayout, Partition, VolumeSegment};
+
+pub const FAT_ENTRY_SIZE: usize = 8;
+pub const FAT_CHAIN_END: u32 = 0xffffffff;
+// Free blocks have a next block of 0, which no chain can refer to as the
+// first block of the volume holds the FAT
+pub const FAT_FREE: u32 = 0;
+
+// On NetWare 4.x, the last part of a file may be stored in a suballocation
+// unit instead of a block of its own, so blocks can be shared by the ends of
+// several files. The last FAT entry of such a file has this bit set in its
+// next field; the remaining bits give the first sector (counted from the
+// start of the volume) of the contiguous sectors holding the tail.
+pub const FAT_SUBALLOC: u32 = 0x80000000;
+pub const SUBALLOC_UNIT_SIZE: usize = SECTOR_SIZE;
+
+pub const DIR_ENTRY_SIZE: usize = 128;
+pub const DIR_NAME_MAX: usize = 12;
+
+// Special values of the parent field; anything else is the entry number of
+// the directory containing the entry
+pub const DIR_FREE: u32 = 0xffffffff;
+pub const DIR_TRUSTEE: u32 = 0xfffffffe;
+pub const DIR_ROOT: u32 = 0xfffffffd;
+
+// Every file has an entry in the DOS name space; other name spaces add an
+// entry with the name of the file in that name space, which refers to the
+// DOS entry. Their names are stored at NS_NAME_OFFSET.
+pub const NS_DOS: u8 = 0;
+pub const NS_MAC: u8 = 1;
+pub const NS_NFS: u8 = 2;
+pub const NS_FTAM: u8 = 3;
+// Called OS/2 before NetWare 4.11
+pub const NS_LONG: u8 = 4;
+
+pub const NS_NAME_OFFSET: usize = 0x10;
+pub const NS_NAME_MAX: usize = DIR_ENTRY_SIZE - NS_NAME_OFFSET;
+pub const MAC_NAME_MAX: usize = 31;
+
+// MAC name space entries also describe the resource fork of the file, which
+// is a chain of its own, and hold the Finder info
+pub const MAC_RESOURCE_FORK_OFFSET: usize = 0x30;
+pub const MAC_FINDER_INFO_OFFSET: usize = 0x38;
+pub const FINDER_INFO_SIZE: usize = 32;
+
+pub const NAMESPACES: [ (u8, &str); 5 ] = [
+    (NS_DOS, "DOS"),
+    (NS_MAC, "MAC"),
+    (NS_NFS, "NFS"),
+    (NS_FTAM, "FTAM"),
+    (NS_LONG, "LONG"),
+];
+
+pub fn namespace_name(namespace: u8) -> String {
+    NAMESPACES.iter().find(|(ns, _)| *ns == namespace).map(|(_, name)| name.to_string())
+        .unwrap_or(format!("namespace {}", namespace))
+}
+
+pub fn find_namespace(name: &str) -> Option<u8> {
+    if name.eq_ignore_ascii_case("OS2") {
+        return Some(NS_LONG);
+    }
+    NAMESPACES.iter().find(|(_, n)| n.eq_ignore_ascii_case(name)).map(|(ns, _)| *ns)
+}
+
+// Deleted files are kept in their directory until purged, so they can be
+// salvaged
+pub const FLAG_DELETED: u8 = 0x01;
+
+pub const ATTR_READ_ONLY: u32 = 0x01;
+pub const ATTR_HIDDEN: u32 = 0x02;
+pub const ATTR_SYSTEM: u32 = 0x04;
+pub const ATTR_EXECUTE_ONLY: u32 = 0x08;
+pub const ATTR_DIRECTORY: u32 = 0x10;
+pub const ATTR_ARCHIVE: u32 = 0x20;
+pub const ATTR_SHAREABLE: u32 = 0x80;
+pub const ATTR_TRANSACTIONAL: u32 = 0x1000;
+pub const ATTR_READ_AUDIT: u32 = 0x4000;
+pub const ATTR_WRITE_AUDIT: u32 = 0x8000;
+pub const ATTR_PURGE: u32 = 0x10000;
+pub const ATTR_RENAME_INHIBIT: u32 = 0x20000;
+pub const ATTR_DELETE_INHIBIT: u32 = 0x40000;
+pub const ATTR_COPY_INHIBIT: u32 = 0x80000;
+// NetWare 4.x file compression
+pub const ATTR_IMMEDIATE_COMPRESS: u32 = 0x02000000;
+pub const ATTR_COMPRESSED: u32 = 0x04000000;
+pub const ATTR_DONT_COMPRESS: u32 = 0x08000000;
+
+const ATTRIBUTES: [ (u32, char, &str); 15 ] = [
+    (ATTR_DIRECTORY, 'd', "directory"),
+    (ATTR_READ_ONLY, 'r', "read-only"),
+    (ATTR_HIDDEN, 'h', "hidden"),
+    (ATTR_SYSTEM, 's', "system"),
+    (ATTR_ARCHIVE, 'a', "archive"),
+    (ATTR_EXECUTE_ONLY, 'x', "execute-only"),
+    (ATTR_SHAREABLE, 'S', "shareable"),
+    (ATTR_TRANSACTIONAL, 'T', "transactional"),
+    (ATTR_PURGE, 'P', "purge"),
+    (ATTR_READ_AUDIT, 'R', "read-audit"),
+    (ATTR_WRITE_AUDIT, 'W', "write-audit"),
+    (ATTR_RENAME_INHIBIT, 'N', "rename-inhibit"),
+    (ATTR_DELETE_INHIBIT, 'D', "delete-inhibit"),
+    (ATTR_COPY_INHIBIT, 'C', "copy-inhibit"),
+    (ATTR_COMPRESSED, 'c', "compressed"),
+];
+
+// Attributes as shown by ls, i.e. d-hs----------- for a hidden system
+// directory
+pub fn attribute_string(attributes: u32) -> String {
+    ATTRIBUTES.iter().map(|(bit, c, _)| if attributes & bit != 0 { *c } else { '-' }).collect()
+}
+
+pub fn attribute_names(attributes: u32) -> Vec<&'static str> {
+    ATTRIBUTES.iter().filter(|(bit, _, _)| attributes & bit != 0).map(|(_, _, name)| *name).collect()
+}
+
+// Rights of trustees and the inherited rights mask
+pub const RIGHT_READ: u16 = 0x0001;
+pub const RIGHT_WRITE: u16 = 0x0002;
+pub const RIGHT_CREATE: u16 = 0x0008;
+pub const RIGHT_ERASE: u16 = 0x0010;
+pub const RIGHT_ACCESS_CONTROL: u16 = 0x0020;
+pub const RIGHT_FILE_SCAN: u16 = 0x0040;
+pub const RIGHT_MODIFY: u16 = 0x0080;
+pub const RIGHT_SUPERVISOR: u16 = 0x0100;
+pub const RIGHTS_ALL: u16 = 0x01ff;
+
+const RIGHT_LETTERS: [ (u16, char); 8 ] = [
+    (RIGHT_SUPERVISOR, 'S'),
+    (RIGHT_READ, 'R'),
+    (RIGHT_WRITE, 'W'),
+    (RIGHT_CREATE, 'C'),
+    (RIGHT_ERASE, 'E'),
+    (RIGHT_MODIFY, 'M'),
+    (RIGHT_FILE_SCAN, 'F'),
+    (RIGHT_ACCESS_CONTROL, 'A'),
+];
+
+// Rights as shown by NetWare, i.e. [ RWCEMF ]
+pub fn rights_string(rights: u16) -> String {
+    let letters: String = RIGHT_LETTERS.iter().map(|(bit, c)| if rights & bit != 0 { *c } else { ' ' }).collect();
+    format!("[{}]", letters)
+}
+
+// Trustee entries hold up to TRUSTEES_PER_ENTRY trustees of the file or
+// directory whose entry number is at offset TRUSTEE_FILE_OFFSET; a file can
+// have several of them. Unused slots have object ID 0.
+pub const TRUSTEE_FILE_OFFSET: usize = 0x0c;
+pub const TRUSTEE_OBJECTS_OFFSET: usize = 0x10;
+pub const TRUSTEE_RIGHTS_OFFSET: usize = 0x50;
+pub const TRUSTEES_PER_ENTRY: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Trustee {
+    pub object: u32,
+    pub rights: u16,
+}
+
+// Parses a trustee entry, which lists the trustees of the file or directory
+// returned along with them
+pub fn parse_trustees(data: &[u8]) -> (u32, Vec<Trustee>) {
+    let trustees = (0..TRUSTEES_PER_ENTRY)
+        .map(|n| Trustee{
+            object: LittleEndian::read_u32(&data[TRUSTEE_OBJECTS_OFFSET + n * 4..]),
+            rights: LittleEndian::read_u16(&data[TRUSTEE_RIGHTS_OFFSET + n * 2..]),
+        })
+        .filter(|t| t.object != 0)
+        .collect();
+    (LittleEndian::read_u32(&data[TRUSTEE_FILE_OFFSET..]), trustees)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FatEntry {
+    pub index: u32,
+    pub next: u32,
+}
+
+// Parses the entries of FAT blocks of a NetWare 386 volume, see nwfs286 for
+// NetWare 286 volumes
+pub fn parse_fat(data: &[u8]) -> Vec<FatEntry> {
+    data.chunks_exact(FAT_ENTRY_SIZE)
+        .map(|e| FatEntry{ index: LittleEndian::read_u32(e), next: LittleEndian::read_u32(&e[4..]) })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub entry: u32,
+    pub parent: u32,
+    pub attributes: u32,
+    pub flags: u8,
+    pub namespace: u8,
+    // DOS entry the entry is a name for, or the entry itself in the DOS name
+    // space
+    pub primary: u32,
+    pub name: String,
+    pub created: u32,
+    pub owner: u32,
+    pub modified: u32,
+    pub modifier: u32,
+    pub archived: u32,
+    pub archiver: u32,
+    pub size: u32,
+    pub first_block: u32,
+    // Only set for deleted entries
+    pub deleted: u32,
+    pub deleted_by: u32,
+    // Only set for MAC name space entries, whose first block and size are
+    // those of the resource fork
+    pub finder_info: Option<[ u8; FINDER_INFO_SIZE ]>,
+    // Rights which trustees of the parent directory keep on this entry
+    pub inherited_rights: u16,
+    // Extended attributes are kept outside of the directory; this refers to
+    // them, and is 0 for entries without extended attributes
+    pub ea_handle: u32,
+}
+
+impl DirEntry {
+    pub fn from(entry: u32, data: &[u8]) -> Result<Self, NWFSError> {
+        let mut cursor = Cursor::new(data);
+        let parent = cursor.read_u32::<LittleEndian>()?;
+        let attributes = cursor.read_u32::<LittleEndian>()?;
+        let _unique_id = cursor.read_u8()?;
+        let flags = cursor.read_u8()?;
+        let namespace = cursor.read_u8()?;
+        if namespace != NS_DOS && parent != DIR_FREE && parent != DIR_TRUSTEE {
+            return Self::from_namespace(entry, parent, attributes, flags, namespace, data);
+        }
+        let name_len = (cursor.read_u8()? as usize).min(DIR_NAME_MAX);
+        let mut name = [ 0u8; DIR_NAME_MAX ];
+        cursor.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name[0..name_len]).to_string();
+        let created = cursor.read_u32::<LittleEndian>()?;
+        let owner = cursor.read_u32::<LittleEndian>()?;
+        let archived = cursor.read_u32::<LittleEndian>()?;
+        let archiver = cursor.read_u32::<LittleEndian>()?;
+        let modified = cursor.read_u32::<LittleEndian>()?;
+        let modifier = cursor.read_u32::<LittleEndian>()?;
+        let size = cursor.read_u32::<LittleEndian>()?;
+        let first_block = cursor.read_u32::<LittleEndian>()?;
+        let deleted = cursor.read_u32::<LittleEndian>()?;
+        let deleted_by = cursor.read_u32::<LittleEndian>()?;
+        let inherited_rights = cursor.read_u16::<LittleEndian>()?;
+        let _reserved = cursor.read_u16::<LittleEndian>()?;
+        let ea_handle = cursor.read_u32::<LittleEndian>()?;
+        Ok(Self{ entry, parent, attributes, flags, namespace, primary: entry, name, created, owner, modified, modifier, archived, archiver, size, first_block, deleted, deleted_by, finder_info: None, inherited_rights, ea_handle })
+    }
+
+    fn from_namespace(entry: u32, parent: u32, attributes: u32, flags: u8, namespace: u8, data: &[u8]) -> Result<Self, NWFSError> {
+        let max_len = if namespace == NS_MAC { MAC_NAME_MAX } else { NS_NAME_MAX };
+        let name_len = (data[11] as usize).min(max_len);
+        let primary = LittleEndian::read_u32(&data[12..]);
+        let name = String::from_utf8_lossy(&data[NS_NAME_OFFSET..NS_NAME_OFFSET + name_len]).to_string();
+        let (first_block, size, finder_info) = if namespace == NS_MAC {
+            let mut finder_info = [ 0u8; FINDER_INFO_SIZE ];
+            finder_info.copy_from_slice(&data[MAC_FINDER_INFO_OFFSET..MAC_FINDER_INFO_OFFSET + FINDER_INFO_SIZE]);
+            (LittleEndian::read_u32(&data[MAC_RESOURCE_FORK_OFFSET..]), LittleEndian::read_u32(&data[MAC_RESOURCE_FORK_OFFSET + 4..]), Some(finder_info))
+        } else {
+            (FAT_CHAIN_END, 0, None)
+        };
+        Ok(Self{ entry, parent, attributes, flags, namespace, primary, name, created: 0, owner: 0, modified: 0, modifier: 0, archived: 0, archiver: 0, size, first_block, deleted: 0, deleted_by: 0, finder_info, inherited_rights: RIGHTS_ALL, ea_handle: 0 })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        (self.attributes & ATTR_DIRECTORY) != 0
+    }
+
+    pub fn is_in_use(&self) -> bool {
+        self.parent != DIR_FREE && self.parent != DIR_TRUSTEE
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        (self.attributes & ATTR_COMPRESSED) != 0
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.is_in_use() && (self.flags & FLAG_DELETED) != 0
+    }
+}
+
+// Where the contents of a file are stored: the block holding every
+// block-sized part of the file by its index, and the index and first sector
+// of the suballocated tail. Parts without a block are holes
+pub struct FileBlocks {
+    pub size: usize,
+    pub block_size: usize,
+    pub blocks: HashMap<usize, u32>,
+    pub tail: Option<(usize, u32)>,
+}
+
+impl FileBlocks {
+    pub fn parts(&self) -> usize {
+        self.size.div_ceil(self.block_size)
+    }
+
+    // Parts without a block read as zeroes
+    pub fn is_hole(&self, index: usize) -> bool {
+        !self.blocks.contains_key(&index) && self.tail.is_none_or(|(tail_index, _)| tail_index != index)
+    }
+}
+
+// Reads a file as a stream, reading the blocks needed as it goes, so files
+// can be read partially and out of order without reading them as a whole
+pub struct FileReader<'a> {
+    volume: &'a mut Volume,
+    blocks: FileBlocks,
+    pos: u64,
+    // The part read last
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl std::io::Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.blocks.size as u64 {
+            return Ok(0)
+        }
+        let index = self.pos as usize / self.blocks.block_size;
+        if self.current.as_ref().map(|(n, _)| *n) != Some(index) {
+            let data = self.volume.read_file_part(&self.blocks, index).map_err(std::io::Error::other)?;
+            self.current = Some((index, data));
+        }
+        let data = self.current.as_ref().map(|(_, data)| data.as_slice()).unwrap_or_default();
+        let offset = self.pos as usize - index * self.blocks.block_size;
+        let n = buf.len().min(data.len() - offset);
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl std::io::Seek for FileReader<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            std::io::SeekFrom::Start(n) => Some(n),
+            std::io::SeekFrom::End(n) => (self.blocks.size as u64).checked_add_signed(n),
+            std::io::SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start of the file"))?;
+        Ok(self.pos)
+    }
+}
+
+// Iterates over all entries below a directory, depth first
+pub struct Walk<'a> {
+    volume: &'a Volume,
+    children: HashMap<u32, Vec<u32>>,
+    stack: Vec<u32>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = &'a DirEntry;
+
+    fn next(&mut self) -> Option<&'a DirEntry> {
+        let entry = &self.volume.entries[self.stack.pop()? as usize];
+        if let Some(children) = self.children.remove(&entry.entry) {
+            self.stack.extend(children.iter().rev());
+        }
+        Some(entry)
+    }
+}
+
+pub struct MacForks {
+    pub finder_info: [ u8; FINDER_INFO_SIZE ],
+    pub resource_fork: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // NetWare 2.x
+    Nwfs286,
+    // NetWare 3.x and 4.x
+    Nwfs386,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nwfs286 => "NetWare 286",
+            Self::Nwfs386 => "NetWare 386",
+        }
+    }
+}
+
+pub struct Volume {
+    devs: Vec<Box<dyn Device>>,
+    pub format: Format,
+    // Volume segments, along with the index of the partition containing the
+    // segment
+    pub segments: Vec<(usize, VolumeSegment)>,
+    // Partitions of all images, along with the index of the image
+    pub partitions: Vec<(usize, Partition)>,
+    pub fat: Vec<FatEntry>,
+    pub entries: Vec<DirEntry>,
+    // Name space used for names; DOS names are used for files without a
+    // name in this name space
+    pub namespace: u8,
+    // Time zone of the server in seconds east of UTC; timestamps are stored
+    // in local time
+    pub timezone: i32,
+    // Copy of the blocks of mirrored partitions which is read, see
+    // block_copies()
+    pub mirror_copy: usize,
+    // Entries of the other name spaces, by DOS entry and name space
+    names: HashMap<(u32, u8), usize>,
+    // Trustees by entry; NetWare 286 trustees are not read
+    trustees: HashMap<u32, Vec<Trustee>>,
+}
+
+impl Volume {
+    pub fn open(dev: Box<dyn Device>, name: &str) -> Result<Self, NWFSError> {
+        Self::open_set(vec![ dev ], name, &DiskLayout::default())
+    }
+
+    // Opens a volume whose segments may be spread over several images. The
+    // segments are matched by name and by the signature in the volume table,
+    // which is the same for all segments of a volume
+    pub fn open_set(mut devs: Vec<Box<dyn Device>>, name: &str, layout: &DiskLayout) -> Result<Self, NWFSError> {
+        // Images without NetWare partitions are skipped when there are others
+        let num_devs = devs.len();
+        let mut partitions: Vec<(usize, Partition)> = Vec::new();
+        for (n, dev) in devs.iter_mut().enumerate() {
+            match read_partitions(dev.as_mut(), layout) {
+                Ok(p) => partitions.extend(p.into_iter().map(|p| (n, p))),
+                Err(NWFSError::InvalidHeader(_)) | Err(NWFSError::Unsupported(_)) if num_devs > 1 => { },
+                Err(e) => return Err(e),
+            }
+        }
+        let candidates: Vec<(usize, VolumeSegment)> = partitions.iter().enumerate()
+            .flat_map(|(n, (_, p))| p.segments.iter().map(move |s| (n, s.clone())))
+            .filter(|(_, s)| s.name.eq_ignore_ascii_case(name))
+            .collect();
+        let signature = match candidates.iter().find(|(_, s)| s.segment_number == 0) {
+            Some((_, s)) => s.signature,
+            None if candidates.is_empty() => return Err(NWFSError::VolumeNotFound(name.to_string())),
+            None => return Err(NWFSError::Corrupt(format!("first segment of volume '{}' not found", name))),
+        };
+
+        // Segments of mirrored partitions are present more than once; their
+        // blocks are read from one of the copies, see block_copies()
+        let mut segments: Vec<(usize, VolumeSegment)> = Vec::new();
+        for (n, s) in candidates.into_iter().filter(|(_, s)| s.signature == signature) {
+            if !segments.iter().any(|(_, other)| other.segment_number == s.segment_number) {
+                segments.push((n, s));
+            }
+        }
+        segments.sort_by_key(|(_, s)| s.segment_number);
+        let expected = segments[0].1.last_segment as usize + 1;
+        if let Some(missing) = (0..expected).find(|n| !segments.iter().any(|(_, s)| s.segment_number as usize == *n)) {
+            return Err(NWFSError::Corrupt(format!("segment {} of {} of volume '{}' not found", missing, expected, name)));
+        }
+
+        let mut volume = Self{ devs, format: Format::Nwfs386, segments, partitions, fat: Vec::new(), entries: Vec::new(), namespace: NS_DOS, timezone: 0, mirror_copy: 0, names: HashMap::new(), trustees: HashMap::new() };
+        // Mirrors which went out of sync disagree; the copy whose FAT and
+        // directory have the fewest issues is used, or the first one if
+        // they are all fine
+        let mut best: Option<(usize, usize)> = None;
+        let mut error = None;
+        for copy in 0..volume.mirror_copies() {
+            match volume.select_mirror_copy(copy) {
+                Ok(_) => {
+                    let issues = if volume.mirror_copies() > 1 { check::check(&volume).len() } else { 0 };
+                    if best.is_none_or(|(_, fewest)| issues < fewest) {
+                        best = Some((copy, issues));
+                    }
+                    if issues == 0 { break; }
+                },
+                Err(e) => error = Some(e),
+            }
+        }
+        match (best, error) {
+            (Some((copy, _)), _) => volume.select_mirror_copy(copy)?,
+            (None, Some(e)) => return Err(e),
+            (None, None) => unreachable!(),
+        }
+        if volume.namespaces().contains(&NS_LONG) {
+            volume.namespace = NS_LONG;
+        }
+        Ok(volume)
+    }
+
+    // Number of copies of the volume kept by mirrored partitions; segments
+    // which are not mirrored have a single copy, which is used for all
+    pub fn mirror_copies(&self) -> usize {
+        self.segments.iter().map(|(_, s)| self.block_copies(s.first_block).map(|c| c.len()).unwrap_or(1)).max().unwrap_or(1)
+    }
+
+    // Reads the FAT and directory from another copy, which is used for
+    // reading from then on
+    pub fn select_mirror_copy(&mut self, copy: usize) -> Result<(), NWFSError> {
+        self.mirror_copy = copy;
+        self.fat.clear();
+        self.entries.clear();
+        self.names.clear();
+        self.trustees.clear();
+        let first_directory = self.read_block(self.segments[0].1.first_directory)?;
+        self.format = if nwfs286::is_nwfs286_directory(&first_directory) { Format::Nwfs286 } else { Format::Nwfs386 };
+        self.read_fat()?;
+        self.read_directory()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.segments[0].1.name
+    }
+
+    // Seconds since the Unix epoch of a timestamp, taking the time zone of
+    // the server into account
+    pub fn unix_time(&self, timestamp: u32) -> Option<u64> {
+        nwfs::timestamp_to_unix(timestamp)?.checked_add_signed(-self.timezone as i64)
+    }
+
+    // Timestamp in local time of the server of seconds since the Unix epoch
+    pub fn timestamp(&self, seconds: u64) -> u32 {
+        nwfs::unix_to_timestamp(seconds.saturating_add_signed(self.timezone as i64))
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.segments[0].1.block_size()
+    }
+
+    pub fn total_blocks(&self) -> u32 {
+        self.segments[0].1.volume_blocks
+    }
+
+    pub fn read_block(&mut self, block: u32) -> Result<Vec<u8>, NWFSError> {
+        self.read_block_copy(block, self.mirror_copy)
+    }
+
+    // Reads a copy of the block; blocks with fewer copies are read from
+    // their last one
+    pub fn read_block_copy(&mut self, block: u32, copy: usize) -> Result<Vec<u8>, NWFSError> {
+        let block_size = self.block_size();
+        let copies = self.block_copies(block)?;
+        let (partition, offset) = copies[copy.min(copies.len() - 1)];
+        let (dev, partition) = &self.partitions[partition];
+        partition.read_data(self.devs[*dev].as_mut(), offset, block_size)
+    }
+
+    // Locations of a block as partition index and byte offset within the
+    // data area of that partition; mirrored partitions hold a copy each, of
+    // which mirror_copy is used for reading
+    pub fn block_copies(&self, block: u32) -> Result<Vec<(usize, u64)>, NWFSError> {
+        let (first, segment) = self.segments.iter()
+            .find(|(_, s)| block >= s.first_block && block - s.first_block < s.segment_blocks())
+            .ok_or(NWFSError::Corrupt(format!("block {} is outside of the volume", block)))?;
+        let mut copies: Vec<(usize, &VolumeSegment)> = vec![ (*first, segment) ];
+        for (n, (_, p)) in self.partitions.iter().enumerate().filter(|(n, _)| n != first) {
+            copies.extend(p.segments.iter().filter(|s| s.signature == segment.signature && s.segment_number == segment.segment_number).map(|s| (n, s)));
+        }
+        Ok(copies.into_iter().map(|(n, s)| {
+            let sector = s.first_sector as u64 + (block - s.first_block) as u64 * s.sectors_per_block as u64;
+            (n, sector * SECTOR_SIZE as u64)
+        }).collect())
+    }
+
+    // The FAT describes its own chain; this works as long as each FAT block
+    // is described by an entry that has already been read
+    fn read_fat(&mut self) -> Result<(), NWFSError> {
+        let total_blocks = self.total_blocks() as usize;
+        if self.format == Format::Nwfs286 {
+            let first_fat = self.segments[0].1.first_fat;
+            let mut data = Vec::new();
+            for block in first_fat..first_fat + nwfs286::fat_blocks(self.total_blocks(), self.block_size()) {
+                data.extend(self.read_block(block)?);
+            }
+            self.fat = nwfs286::parse_fat(&data, total_blocks);
+            return Ok(())
+        }
+        let mut block = self.segments[0].1.first_fat;
+        let mut visited = 0;
+        while self.fat.len() < total_blocks {
+            let data = self.read_block(block)?;
+            let remaining = total_blocks - self.fat.len();
+            self.fat.extend(parse_fat(&data).into_iter().take(remaining));
+            visited += 1;
+            if self.fat.len() >= total_blocks { break; }
+            let entry = self.fat.get(block as usize)
+                .ok_or(NWFSError::Corrupt(format!("FAT block {} is not described by the FAT read so far", block)))?;
+            if entry.next == FAT_CHAIN_END || visited > total_blocks {
+                return Err(NWFSError::Corrupt(format!("FAT ends after {} of {} entries", self.fat.len(), total_blocks)));
+            }
+            block = entry.next;
+        }
+        Ok(())
+    }
+
+    pub fn chain(&self, first_block: u32) -> Result<Vec<u32>, NWFSError> {
+        self.chain_with_tail(first_block).map(|(chain, _)| chain)
+    }
+
+    // Returns the blocks of the chain along with the first sector of the
+    // suballocated tail, if any
+    pub fn chain_with_tail(&self, first_block: u32) -> Result<(Vec<u32>, Option<u32>), NWFSError> {
+        let mut result = Vec::new();
+        let mut block = first_block;
+        while block != FAT_CHAIN_END {
+            if self.format == Format::Nwfs386 && (block & FAT_SUBALLOC) != 0 {
+                return Ok((result, Some(block & !FAT_SUBALLOC)));
+            }
+            let entry = self.fat.get(block as usize)
+                .ok_or(NWFSError::Corrupt(format!("chain starting at block {} refers to block {}", first_block, block)))?;
+            if result.len() >= self.fat.len() {
+                return Err(NWFSError::Corrupt(format!("chain starting at block {} loops", first_block)));
+            }
+            result.push(block);
+            block = entry.next;
+        }
+        Ok((result, None))
+    }
+
+    // Reads len bytes starting at the given sector of the volume, which may
+    // cross into the next block
+    pub fn read_sectors(&mut self, sector: u32, len: usize) -> Result<Vec<u8>, NWFSError> {
+        let sectors_per_block = self.segments[0].1.sectors_per_block;
+        let mut block = sector / sectors_per_block;
+        let mut offset = (sector % sectors_per_block) as usize * SECTOR_SIZE;
+        let mut result = Vec::with_capacity(len);
+        while result.len() < len {
+            let data = self.read_block(block)?;
+            let n = (len - result.len()).min(data.len() - offset);
+            result.extend_from_slice(&data[offset..offset + n]);
+            block += 1;
+            offset = 0;
+        }
+        Ok(result)
+    }
+
+    // Position of every block within the file, in blocks
+    pub fn block_indices(&self, chain: &[u32]) -> Vec<usize> {
+        chain.iter().enumerate()
+            .map(|(n, block)| if self.format == Format::Nwfs286 { n } else { self.fat[*block as usize].index as usize })
+            .collect()
+    }
+
+    fn read_directory(&mut self) -> Result<(), NWFSError> {
+        let blocks = self.chain(self.segments[0].1.first_directory)?;
+        for block in blocks {
+            let data = self.read_block(block)?;
+            let entry_size = if self.format == Format::Nwfs286 { nwfs286::DIR_ENTRY_SIZE } else { DIR_ENTRY_SIZE };
+            for chunk in data.chunks(entry_size) {
+                let entry = match self.format {
+                    Format::Nwfs286 => nwfs286::parse_dir_entry(self.entries.len() as u32, chunk)?,
+                    Format::Nwfs386 => DirEntry::from(self.entries.len() as u32, chunk)?,
+                };
+                if entry.namespace != NS_DOS && entry.is_in_use() {
+                    self.names.insert((entry.primary, entry.namespace), self.entries.len());
+                }
+                if entry.parent == DIR_TRUSTEE && self.format == Format::Nwfs386 {
+                    let (file, trustees) = parse_trustees(chunk);
+                    self.trustees.entry(file).or_default().extend(trustees);
+                }
+                self.entries.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces (or adds, if it is just past the end) a directory entry by the
+    // one in data, after it has been written to the volume
+    pub(crate) fn set_entry(&mut self, entry: u32, data: &[u8]) -> Result<(), NWFSError> {
+        let e = DirEntry::from(entry, data)?;
+        if let Some(old) = self.entries.get(entry as usize) {
+            self.names.remove(&(old.primary, old.namespace));
+        }
+        if e.namespace != NS_DOS && e.is_in_use() {
+            self.names.insert((e.primary, e.namespace), entry as usize);
+        }
+        match self.entries.get_mut(entry as usize) {
+            Some(old) => *old = e,
+            None => self.entries.push(e),
+        }
+        Ok(())
+    }
+
+    // Name spaces present on the volume
+    pub fn namespaces(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = vec![ NS_DOS ];
+        for (_, ns) in self.names.keys() {
+            if !result.contains(ns) { result.push(*ns); }
+        }
+        result.sort();
+        result
+    }
+
+    // Entry of the given DOS entry in another name space
+    pub fn namespace_entry(&self, entry: &DirEntry, namespace: u8) -> Option<&DirEntry> {
+        self.names.get(&(entry.entry, namespace)).map(|n| &self.entries[*n])
+    }
+
+    // Name of the entry in the selected name space
+    pub fn display_name<'a>(&'a self, entry: &'a DirEntry) -> &'a str {
+        match self.namespace_entry(entry, self.namespace) {
+            Some(e) if !e.name.is_empty() => &e.name,
+            _ => &entry.name,
+        }
+    }
+
+    pub fn trustees(&self, entry: &DirEntry) -> &[Trustee] {
+        self.trustees.get(&entry.entry).map(|t| t.as_slice()).unwrap_or_default()
+    }
+
+    pub fn children(&self, dir: u32) -> impl Iterator<Item = &DirEntry> {
+        self.entries.iter().filter(move |e| e.parent == dir && e.namespace == NS_DOS && e.entry != dir && !e.is_deleted())
+    }
+
+    // All files and directories below dir, excluding dir itself; every
+    // directory is followed by its contents, in the order of the directory
+    pub fn walk(&self, dir: u32) -> Walk<'_> {
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for e in self.entries.iter().filter(|e| e.is_in_use() && e.namespace == NS_DOS && e.entry != e.parent && !e.is_deleted()) {
+            children.entry(e.parent).or_default().push(e.entry);
+        }
+        // Entries are visited once, even if the parents loop
+        let stack = children.remove(&dir).unwrap_or_default().into_iter().rev().collect();
+        Walk{ volume: self, children, stack }
+    }
+
+    // Entries of the DOS entry in all other name spaces
+    pub fn names(&self, entry: &DirEntry) -> impl Iterator<Item = &DirEntry> {
+        let entry = entry.entry;
+        self.names.iter().filter(move |((primary, _), _)| *primary == entry).map(|(_, n)| &self.entries[*n])
+    }
+
+    pub fn root(&self) -> u32 {
+        self.entries.iter().find(|e| e.parent == DIR_ROOT).map(|e| e.entry).unwrap_or(0)
+    }
+
+    // Looks up a path such as SYS:SYSTEM/NET$OBJ.SYS; both / and \ are
+    // accepted as separator and the volume name is optional. Names match in
+    // the DOS name space as well as the one selected
+    pub fn lookup(&self, path: &str) -> Option<&DirEntry> {
+        let path = match path.split_once(':') {
+            Some((_, path)) => path,
+            None => path
+        };
+        let mut current = self.entries.get(self.root() as usize)?;
+        for component in path.split(['/', '\\']).filter(|c| !c.is_empty()) {
+            if !current.is_directory() { return None; }
+            current = self.children(current.entry)
+                .find(|e| e.name.eq_ignore_ascii_case(component) || self.display_name(e).eq_ignore_ascii_case(component))?;
+        }
+        Some(current)
+    }
+
+    // Deleted files and directories below dir, including those in deleted
+    // directories
+    pub fn deleted_entries(&self, dir: u32) -> Vec<&DirEntry> {
+        self.entries.iter()
+            .filter(|e| e.is_deleted() && e.namespace == NS_DOS && self.is_below(e, dir))
+            .collect()
+    }
+
+    fn is_below(&self, entry: &DirEntry, dir: u32) -> bool {
+        let mut current = entry;
+        for _ in 0..self.entries.len() {
+            if current.parent == dir { return true; }
+            current = match self.entries.get(current.parent as usize) {
+                Some(parent) if current.parent != DIR_ROOT => parent,
+                _ => return false,
+            };
+        }
+        false
+    }
+
+    // First blocks of the mirror copies of the FAT and the directory, which
+    // NetWare 386 volumes may keep
+    pub fn mirror_fat(&self) -> Option<u32> {
+        let segment = &self.segments[0].1;
+        let mirrored = self.format == Format::Nwfs386 && segment.mirror_fat != segment.first_fat && segment.mirror_fat != 0;
+        mirrored.then_some(segment.mirror_fat)
+    }
+
+    pub fn mirror_directory(&self) -> Option<u32> {
+        let segment = &self.segments[0].1;
+        let mirrored = self.format == Format::Nwfs386 && segment.mirror_directory != segment.first_directory && segment.mirror_directory != 0;
+        mirrored.then_some(segment.mirror_directory)
+    }
+
+    // Blocks used by the FAT, the directory, their mirror copies and all
+    // files which are not deleted
+    pub fn used_blocks(&self) -> HashSet<u32> {
+        let mut result: HashSet<u32> = HashSet::new();
+        let first_fat = self.segments[0].1.first_fat;
+        match self.format {
+            Format::Nwfs286 => result.extend(first_fat..first_fat + nwfs286::fat_blocks(self.total_blocks(), self.block_size())),
+            Format::Nwfs386 => result.extend(self.chain(first_fat).unwrap_or_default()),
+        }
+        result.extend(self.chain(self.segments[0].1.first_directory).unwrap_or_default());
+        for first in self.mirror_fat().into_iter().chain(self.mirror_directory()) {
+            result.extend(self.chain(first).unwrap_or_default());
+        }
+        let sectors_per_block = self.segments[0].1.sectors_per_block;
+        for e in self.entries.iter().filter(|e| e.is_in_use() && !e.is_deleted() && !e.is_directory()) {
+            if let Ok((chain, tail)) = self.chain_with_tail(e.first_block) {
+                result.extend(chain);
+                result.extend(tail.map(|sector| sector / sectors_per_block));
+            }
+        }
+        result
+    }
+
+    // Full path of the entry, such as SYS:SYSTEM/NET$OBJ.SYS
+    pub fn path(&self, entry: &DirEntry) -> String {
+        let mut components: Vec<&str> = Vec::new();
+        let mut current = entry;
+        while current.parent != DIR_ROOT && components.len() < self.entries.len() {
+            components.push(self.display_name(current));
+            current = match self.entries.get(current.parent as usize) {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+        components.reverse();
+        format!("{}:{}", self.name(), components.join("/"))
+    }
+
+    // The format used by NetWare 4.x to compress files is not known, so these
+    // cannot be read; use read_stored() to obtain their compressed data.
+    // Decompression needs a format description or sample volumes holding
+    // compressed files of which the original contents are known
+    pub fn read_file(&mut self, entry: &DirEntry) -> Result<Vec<u8>, NWFSError> {
+        if entry.is_compressed() {
+            return Err(NWFSError::Unsupported(format!("{} is compressed", self.path(entry))));
+        }
+        let mut result = Vec::with_capacity(entry.size as usize);
+        self.read_file_blocks(entry, |_, data| result.extend_from_slice(data))?;
+        Ok(result)
+    }
+
+    // Reads the file a block at a time, passing the offset and contents of
+    // every block to f in order, so the file is never in memory as a whole.
+    // Holes read as zeroes
+    pub fn read_file_blocks(&mut self, entry: &DirEntry, mut f: impl FnMut(usize, &[u8])) -> Result<(), NWFSError> {
+        let blocks = self.file_blocks(entry)?;
+        for index in 0..blocks.parts() {
+            let data = self.read_file_part(&blocks, index)?;
+            f(index * blocks.block_size, &data);
+        }
+        Ok(())
+    }
+
+    pub fn file_blocks(&self, entry: &DirEntry) -> Result<FileBlocks, NWFSError> {
+        if entry.is_compressed() {
+            return Err(NWFSError::Unsupported(format!("{} is compressed", self.path(entry))));
+        }
+        let (chain, tail) = self.chain_with_tail(entry.first_block)?;
+        let indices = self.block_indices(&chain);
+        // The tail follows the last block
+        let tail_index = indices.iter().max().map(|n| n + 1).unwrap_or(0);
+        let blocks = indices.into_iter().zip(chain).collect();
+        Ok(FileBlocks{ size: entry.size as usize, block_size: self.block_size(), blocks, tail: tail.map(|sector| (tail_index, sector)) })
+    }
+
+    // Contents of the block-sized part of the file at index; the last part
+    // may be shorter
+    pub fn read_file_part(&mut self, blocks: &FileBlocks, index: usize) -> Result<Vec<u8>, NWFSError> {
+        let offset = index * blocks.block_size;
+        let len = blocks.block_size.min(blocks.size.saturating_sub(offset));
+        let mut data = match (blocks.blocks.get(&index), blocks.tail) {
+            (Some(block), _) => self.read_block(*block)?,
+            (None, Some((tail_index, sector))) if index == tail_index => self.read_sectors(sector, len)?,
+            _ => Vec::new(),
+        };
+        data.resize(len, 0);
+        Ok(data)
+    }
+
+    pub fn open_file(&mut self, entry: &DirEntry) -> Result<FileReader<'_>, NWFSError> {
+        let blocks = self.file_blocks(entry)?;
+        Ok(FileReader{ volume: self, blocks, pos: 0, current: None })
+    }
+
+    // Byte ranges (offset, length) of a file which have no block allocated,
+    // and read as zeroes. NetWare 386 files are sparse when their block
+    // indices have gaps; a chain which is too short leaves a hole at the end
+    pub fn holes(&self, entry: &DirEntry) -> Result<Vec<(usize, usize)>, NWFSError> {
+        let block_size = self.block_size();
+        let (chain, tail) = self.chain_with_tail(entry.first_block)?;
+        let mut allocated: HashSet<usize> = self.block_indices(&chain).into_iter().collect();
+        if tail.is_some() {
+            allocated.insert(allocated.iter().max().map(|n| n + 1).unwrap_or(0));
+        }
+        let size = entry.size as usize;
+        let mut result: Vec<(usize, usize)> = Vec::new();
+        for index in (0..size.div_ceil(block_size)).filter(|n| !allocated.contains(n)) {
+            let offset = index * block_size;
+            let len = block_size.min(size - offset);
+            match result.last_mut() {
+                Some((start, hole_len)) if *start + *hole_len == offset => *hole_len += len,
+                _ => result.push((offset, len)),
+            }
+        }
+        Ok(result)
+    }
+
+    // Contents of all blocks of the file, in order; for compressed files this
+    // is the compressed data, padded to a block (or suballocation unit).
+    // Fails if a block is missing
+    pub fn read_stored(&mut self, entry: &DirEntry) -> Result<Vec<u8>, NWFSError> {
+        let block_size = self.block_size();
+        let (chain, tail) = self.chain_with_tail(entry.first_block)?;
+        let mut blocks: Vec<(usize, u32)> = self.block_indices(&chain).into_iter().zip(chain).collect();
+        blocks.sort();
+        if blocks.iter().enumerate().any(|(n, (index, _))| *index != n) {
+            return Err(NWFSError::Corrupt(format!("{} has missing or duplicate blocks", self.path(entry))));
+        }
+        let mut result = Vec::with_capacity(blocks.len() * block_size);
+        for (_, block) in blocks {
+            result.extend(self.read_block(block)?);
+        }
+        if let Some(sector) = tail {
+            let remaining = (entry.size as usize).saturating_sub(result.len()).min(block_size);
+            result.extend(self.read_sectors(sector, remaining.div_ceil(SUBALLOC_UNIT_SIZE) * SUBALLOC_UNIT_SIZE)?);
+        }
+        Ok(result)
+    }
+
+    // Finder info and resource fork of a file stored by a Mac, if it has a
+    // MAC name space entry
+    pub fn mac_forks(&mut self, entry: &DirEntry) -> Result<Option<MacForks>, NWFSError> {
+        let mac = match self.namespace_entry(entry, NS_MAC) {
+            Some(mac) => mac.clone(),
+            None => return Ok(None),
+        };
+        let mut resource_fork = if mac.size > 0 { self.read_stored(&mac)? } else { Vec::new() };
+        resource_fork.truncate(mac.size as usize);
+        Ok(Some(MacForks{ finder_info: mac.finder_info.unwrap_or_default(), resource_fork }))
+    }
+}
+
+pub fn open_volume(path: &str, name: &str) -> Result<Volume, NWFSError> {
+    Volume::open(Box::new(BlockCache::new(image::open_image(path)?, DEFAULT_CACHE_SIZE)), name)
+}
+
+pub fn open_volume_set(paths: &[String], name: &str, layout: &DiskLayout) -> Result<Volume, NWFSError> {
+    let mut devs: Vec<Box<dyn Device>> = Vec::new();
+    for path in paths {
+        devs.push(Box::new(BlockCache::new(image::open_image(path)?, layout.cache_size)));
+    }
+    Volume::open_set(devs, name, layout)
+}
+
+// Volume segments of all NetWare partitions within the image
+pub fn list_segments(path: &str, layout: &DiskLayout) -> Result<Vec<VolumeSegment>, NWFSError> {
+    let mut dev = BlockCache::new(image::open_image(path)?, layout.cache_size);
+    Ok(read_partitions(&mut dev, layout)?.into_iter().flat_map(|p| p.segments).collect())
+}
@@ -0,0 +1,238 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Editing of the volume tables of NetWare 386 partitions, to repair images
+// whose volumes will not mount because of minor damage to them. Every
+// partition holding a segment of a volume has an entry for it in its volume
+// table, and mirrored partitions hold a copy of those; all copies are
+// changed alike. Only raw images are changed.
+//
+// The name of a volume is kept in its entries, and in the root directory
+// entry of the volume and of its mirror copy, which are renamed as well.
+//
+// The entries of a volume can be made consistent with one another. Fields
+// which describe the volume as a whole, such as the signature which ties
+// the segments together and where the FAT and directory are, are set to
+// the value most entries agree on. Segments are numbered by their position
+// in the volume, given by their first block; if those overlap or leave
+// gaps, the segment numbers are used instead and the first blocks are
+// derived from them. The number of segments and blocks of the volume
+// follow from the segments found.
+use std::fs::File;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::nwfs::{NWFSError, SECTOR_SIZE};
+use crate::nwfs::image::{self, ImageFormat};
+use crate::nwfs::mkfs::is_valid_volume_name;
+use crate::nwfs::partition::{read_partitions, DiskLayout, Partition, VolumeSegment, SEGMENT_FIELDS, VOLUME_NAME_MAX, VOLUME_TABLE_ENTRY_SIZE, VOLUME_TABLE_HEADER_SIZE};
+use crate::nwfs::volume::{ATTR_DIRECTORY, DIR_ENTRY_SIZE, DIR_ROOT};
+
+// Fields which hold the same value in every entry of a volume
+const VOLUME_FIELDS: [ &str; 6 ] = [ "signature", "sectors-per-block", "first-fat", "mirror-fat", "first-directory", "mirror-directory" ];
+
+// Offsets of the name within the root directory entry
+const ROOT_NAME_LEN: usize = 0x0b;
+const ROOT_NAME: usize = 0x0c;
+
+#[derive(Clone)]
+pub struct Entry {
+    // Index in VolumeTables::partitions
+    pub partition: usize,
+    // Index within the volume table of the partition
+    pub index: usize,
+    pub segment: VolumeSegment,
+}
+
+pub struct Change {
+    pub field: &'static str,
+    pub old: u32,
+    pub new: u32,
+}
+
+pub struct VolumeTables {
+    // The images, in the order given, opened for writing
+    files: Vec<File>,
+    // Partitions of all images, with the index of their image
+    pub partitions: Vec<(usize, Partition)>,
+}
+
+// The value which occurs most often; the first one if there is a tie
+fn most_common(values: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut counts: Vec<(u32, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts.first().map(|(value, _)| *value)
+}
+
+impl VolumeTables {
+    pub fn open(paths: &[String], layout: &DiskLayout) -> Result<Self, NWFSError> {
+        let mut files = Vec::new();
+        let mut partitions = Vec::new();
+        for (n, path) in paths.iter().enumerate() {
+            let format = image::image_format(path)?;
+            if format != ImageFormat::Raw {
+                return Err(NWFSError::Unsupported(format!("writing to {} images", format.name())));
+            }
+            let mut file = File::options().read(true).write(true).open(path)?;
+            // Images without NetWare partitions are skipped when there are others
+            match read_partitions(&mut file, layout) {
+                Ok(p) => partitions.extend(p.into_iter().map(|p| (n, p))),
+                Err(NWFSError::InvalidHeader(_)) | Err(NWFSError::Unsupported(_)) if paths.len() > 1 => { },
+                Err(e) => return Err(e),
+            }
+            files.push(file);
+        }
+        Ok(Self{ files, partitions })
+    }
+
+    // All entries of volume name, in the order of the partitions
+    pub fn entries(&self, name: &str) -> Vec<Entry> {
+        self.partitions.iter().enumerate()
+            .flat_map(|(n, (_, p))| p.segments.iter().enumerate().map(move |(index, s)| Entry{ partition: n, index, segment: s.clone() }))
+            .filter(|e| e.segment.name.eq_ignore_ascii_case(name))
+            .collect()
+    }
+
+    // Writes the entry to the volume table of its partition
+    pub fn write_entry(&mut self, entry: &Entry) -> Result<(), NWFSError> {
+        let (image, partition) = &mut self.partitions[entry.partition];
+        let file = &mut self.files[*image];
+        let offset = (VOLUME_TABLE_HEADER_SIZE + entry.index * VOLUME_TABLE_ENTRY_SIZE) as u64;
+        let mut data = partition.read_data(file, offset, VOLUME_TABLE_ENTRY_SIZE)?;
+        entry.segment.write(&mut data);
+        partition.write_data(file, offset, &data)?;
+        partition.segments[entry.index] = entry.segment.clone();
+        Ok(())
+    }
+
+    // Renames the root directory entry at the start of directory block
+    // block in every copy of it; returns the number of copies renamed
+    fn rename_root(&mut self, entries: &[Entry], block: u32, name: &str, new_name: &str) -> Result<usize, NWFSError> {
+        let mut result = 0;
+        for e in entries.iter().filter(|e| block >= e.segment.first_block && block - e.segment.first_block < e.segment.segment_blocks()) {
+            let (image, partition) = &self.partitions[e.partition];
+            let file = &mut self.files[*image];
+            let offset = e.segment.first_sector as u64 * SECTOR_SIZE as u64 + (block - e.segment.first_block) as u64 * e.segment.block_size() as u64;
+            let mut root = partition.read_data(file, offset, DIR_ENTRY_SIZE)?;
+            let name_len = (root[ROOT_NAME_LEN] as usize).min(VOLUME_NAME_MAX);
+            if LittleEndian::read_u32(&root[0x00..]) != DIR_ROOT || LittleEndian::read_u32(&root[0x04..]) & ATTR_DIRECTORY == 0
+                || !root[ROOT_NAME..ROOT_NAME + name_len].eq_ignore_ascii_case(name.as_bytes()) {
+                continue;
+            }
+            root[ROOT_NAME..ROOT_NAME + name_len].fill(0);
+            root[ROOT_NAME_LEN] = new_name.len() as u8;
+            root[ROOT_NAME..ROOT_NAME + new_name.len()].copy_from_slice(new_name.as_bytes());
+            partition.write_data(file, offset, &root)?;
+            result += 1;
+        }
+        Ok(result)
+    }
+
+    // Renames volume name to new_name; returns the number of volume table
+    // entries and root directory entries renamed
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(usize, usize), NWFSError> {
+        if !is_valid_volume_name(new_name) {
+            return Err(NWFSError::Unsupported(format!("volume name '{}'", new_name)));
+        }
+        let entries = self.entries(name);
+        if entries.is_empty() {
+            return Err(NWFSError::VolumeNotFound(name.to_string()));
+        }
+        if !name.eq_ignore_ascii_case(new_name) && !self.entries(new_name).is_empty() {
+            return Err(NWFSError::Unsupported(format!("renaming to existing volume '{}'", new_name)));
+        }
+
+        let mut blocks: Vec<u32> = entries.iter()
+            .filter(|e| e.segment.segment_number == 0)
+            .flat_map(|e| [ e.segment.first_directory, e.segment.mirror_directory ])
+            .collect();
+        blocks.sort();
+        blocks.dedup();
+        let mut directories = 0;
+        for block in blocks {
+            directories += self.rename_root(&entries, block, name, new_name)?;
+        }
+        for mut entry in entries.iter().cloned() {
+            entry.segment.name = new_name.to_string();
+            self.write_entry(&entry)?;
+        }
+        Ok((entries.len(), directories))
+    }
+
+    // Entries of volume name which change to make them consistent, as they
+    // would be afterwards, with the changes made to them
+    pub fn plan_fix(&self, name: &str) -> Result<Vec<(Entry, Vec<Change>)>, NWFSError> {
+        let original = self.entries(name);
+        if original.is_empty() {
+            return Err(NWFSError::VolumeNotFound(name.to_string()));
+        }
+        // Entries of the first segment break ties
+        let mut entries = original.clone();
+        entries.sort_by_key(|e| e.segment.segment_number != 0);
+        for field in VOLUME_FIELDS {
+            let value = most_common(entries.iter().map(|e| e.segment.field(field).unwrap())).unwrap();
+            for e in entries.iter_mut() {
+                e.segment.set_field(field, value)?;
+            }
+        }
+
+        // Copies of a segment on mirrored partitions share their first block
+        let mut segments: Vec<(u32, u32)> = entries.iter().map(|e| (e.segment.first_block, e.segment.segment_blocks())).collect();
+        segments.sort();
+        segments.dedup();
+        let mut next = 0;
+        let consecutive = segments.iter().all(|(first, blocks)| {
+            let result = *first == next;
+            next = first.saturating_add(*blocks);
+            result
+        });
+        if consecutive {
+            for e in entries.iter_mut() {
+                let number = segments.iter().position(|(first, _)| *first == e.segment.first_block).unwrap();
+                e.segment.set_field("segment-number", number as u32)?;
+            }
+        } else {
+            let mut numbers: Vec<(u16, u32)> = entries.iter().map(|e| (e.segment.segment_number, e.segment.segment_blocks())).collect();
+            numbers.sort();
+            numbers.dedup();
+            if numbers.iter().enumerate().any(|(n, (number, _))| *number as usize != n) {
+                return Err(NWFSError::Corrupt(format!("the segments of volume '{}' overlap and their segment numbers are not consecutive, so their order is unknown", name)));
+            }
+            segments = numbers.iter().scan(0u32, |first, (_, blocks)| {
+                let segment = (*first, *blocks);
+                *first = first.saturating_add(*blocks);
+                Some(segment)
+            }).collect();
+            for e in entries.iter_mut() {
+                e.segment.first_block = segments[e.segment.segment_number as usize].0;
+            }
+        }
+        let volume_blocks = segments.iter().fold(0u32, |total, (_, blocks)| total.saturating_add(*blocks));
+        for e in entries.iter_mut() {
+            e.segment.set_field("last-segment", segments.len() as u32 - 1)?;
+            e.segment.volume_blocks = volume_blocks;
+        }
+
+        let mut result = Vec::new();
+        for old in original {
+            let new = entries.iter().find(|e| e.partition == old.partition && e.index == old.index).unwrap();
+            let changes: Vec<Change> = SEGMENT_FIELDS.iter()
+                .map(|field| Change{ field, old: old.segment.field(field).unwrap(), new: new.segment.field(field).unwrap() })
+                .filter(|c| c.old != c.new)
+                .collect();
+            if !changes.is_empty() {
+                result.push((new.clone(), changes));
+            }
+        }
+        Ok(result)
+    }
+}
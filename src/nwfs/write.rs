@@ -0,0 +1,291 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Write support for NetWare 386 volumes, so files can be added to a volume
+// or replaced. Data is stored in whole blocks taken from the free blocks of
+// the FAT, which is all NetWare needs to know: free space is counted when
+// the volume is mounted. The blocks of a replaced file are freed, except for
+// a suballocated tail, as the suballocation tables are not updated; this
+// only leaks the sectors of the tail. The data is written before the FAT and
+// the directory refer to it. Volumes keep a mirror copy of the FAT and the
+// directory, which is written as well, as are all copies on mirrored
+// partitions.
+use std::collections::BTreeSet;
+use std::fs::File;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::nwfs::NWFSError;
+use crate::nwfs::image::{self, ImageFormat};
+use crate::nwfs::partition::DiskLayout;
+use crate::nwfs::check::INVALID_NAME_CHARS;
+use crate::nwfs::volume::{self, DirEntry, FatEntry, Format, Volume, ATTR_ARCHIVE, ATTR_COMPRESSED, DIR_ENTRY_SIZE, DIR_FREE, DIR_NAME_MAX, FAT_CHAIN_END, FAT_ENTRY_SIZE, FAT_FREE, MAC_RESOURCE_FORK_OFFSET, NS_DOS, NS_NAME_OFFSET, RIGHTS_ALL};
+
+// Offsets of the fields of directory entries which are written
+const ENTRY_PARENT: usize = 0x00;
+const ENTRY_ATTRIBUTES: usize = 0x04;
+const ENTRY_NAMESPACE: usize = 0x0a;
+const ENTRY_NAME_LEN: usize = 0x0b;
+const ENTRY_NAME: usize = 0x0c;
+const ENTRY_CREATED: usize = 0x18;
+const ENTRY_OWNER: usize = 0x1c;
+const ENTRY_MODIFIED: usize = 0x28;
+const ENTRY_MODIFIER: usize = 0x2c;
+const ENTRY_SIZE: usize = 0x30;
+const ENTRY_FIRST_BLOCK: usize = 0x34;
+const ENTRY_INHERITED_RIGHTS: usize = 0x40;
+// Name space entries refer to the DOS entry instead of holding a DOS name
+const ENTRY_PRIMARY: usize = 0x0c;
+
+pub struct VolumeWriter {
+    pub volume: Volume,
+    // The images, in the order given, opened for writing
+    files: Vec<File>,
+    // Chains of the FAT and directory, followed by those of their mirror
+    // copy if present
+    fat_chains: Vec<Vec<u32>>,
+    directory_chains: Vec<Vec<u32>>,
+}
+
+// Names of new files must be valid 8.3 DOS names, as every file has an entry
+// in the DOS name space; they are stored in upper case
+fn dos_name(name: &str) -> Option<String> {
+    let name = name.to_ascii_uppercase();
+    let (base, ext) = name.split_once('.').unwrap_or((&name, ""));
+    let valid = |s: &str, max: usize| s.len() <= max && s.chars().all(|c| c.is_ascii_graphic() && c != '.' && !INVALID_NAME_CHARS.contains(c));
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+    Some(if ext.is_empty() { base.to_string() } else { name.clone() })
+}
+
+impl VolumeWriter {
+    pub fn open(paths: &[String], name: &str, layout: &DiskLayout) -> Result<Self, NWFSError> {
+        // Blocks are read back after they are written, which the cache
+        // would not notice
+        let volume = volume::open_volume_set(paths, name, &DiskLayout{ cache_size: 0, ..*layout })?;
+        if volume.format == Format::Nwfs286 {
+            return Err(NWFSError::Unsupported("writing to NetWare 286 volumes".to_string()));
+        }
+        let mut files = Vec::new();
+        for path in paths {
+            let format = image::image_format(path)?;
+            if format != ImageFormat::Raw {
+                return Err(NWFSError::Unsupported(format!("writing to {} images", format.name())));
+            }
+            files.push(File::options().read(true).write(true).open(path)?);
+        }
+        let segment = &volume.segments[0].1;
+        let mut fat_chains = vec![ volume.chain(segment.first_fat)? ];
+        let mut directory_chains = vec![ volume.chain(segment.first_directory)? ];
+        if let Some(first) = volume.mirror_fat() {
+            fat_chains.push(volume.chain(first)?);
+        }
+        if let Some(first) = volume.mirror_directory() {
+            directory_chains.push(volume.chain(first)?);
+        }
+        if fat_chains.iter().chain(&directory_chains).any(|c| c.is_empty()) || fat_chains.iter().any(|c| c.len() != fat_chains[0].len())
+            || directory_chains.iter().any(|c| c.len() != directory_chains[0].len()) {
+            return Err(NWFSError::Corrupt("the FAT or directory does not match its mirror copy".to_string()));
+        }
+        Ok(Self{ volume, files, fat_chains, directory_chains })
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8]) -> Result<(), NWFSError> {
+        let mut data = data.to_vec();
+        data.resize(self.volume.block_size(), 0);
+        for (partition, offset) in self.volume.block_copies(block)? {
+            let (dev, partition) = &self.volume.partitions[partition];
+            partition.write_data(&mut self.files[*dev], offset, &data)?;
+        }
+        Ok(())
+    }
+
+    // Writes the FAT blocks holding the entries of the given blocks, as they
+    // are in memory
+    fn write_fat(&mut self, blocks: &[u32]) -> Result<(), NWFSError> {
+        let per_block = self.volume.block_size() / FAT_ENTRY_SIZE;
+        let positions: BTreeSet<usize> = blocks.iter().map(|b| *b as usize / per_block).collect();
+        for position in positions {
+            for n in 0..self.fat_chains.len() {
+                let fat_block = self.fat_chains[n][position];
+                let mut data = self.volume.read_block(fat_block)?;
+                for (chunk, e) in data.chunks_exact_mut(FAT_ENTRY_SIZE).zip(self.volume.fat.iter().skip(position * per_block)) {
+                    LittleEndian::write_u32(&mut chunk[0..], e.index);
+                    LittleEndian::write_u32(&mut chunk[4..], e.next);
+                }
+                self.write_block(fat_block, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Free blocks, lowest first; blocks of deleted files are not reused, so
+    // they can still be salvaged
+    fn allocate(&self, count: usize) -> Result<Vec<u32>, NWFSError> {
+        let used = self.volume.used_blocks();
+        let blocks: Vec<u32> = self.volume.fat.iter().enumerate()
+            .filter(|(block, e)| *block != 0 && e.next == FAT_FREE && !used.contains(&(*block as u32)))
+            .map(|(block, _)| block as u32)
+            .take(count)
+            .collect();
+        if blocks.len() < count {
+            return Err(NWFSError::NoSpace(format!("{} blocks needed, {} free", count, blocks.len())));
+        }
+        Ok(blocks)
+    }
+
+    // Links the blocks together, in order, in the FAT
+    fn link(&mut self, blocks: &[u32]) {
+        for (index, block) in blocks.iter().enumerate() {
+            let next = blocks.get(index + 1).copied().unwrap_or(FAT_CHAIN_END);
+            self.volume.fat[*block as usize] = FatEntry{ index: index as u32, next };
+        }
+    }
+
+    fn free_chain(&mut self, first_block: u32) -> Result<(), NWFSError> {
+        let chain = self.volume.chain(first_block)?;
+        for block in &chain {
+            self.volume.fat[*block as usize] = FatEntry{ index: 0, next: FAT_FREE };
+        }
+        self.write_fat(&chain)
+    }
+
+    fn read_entry(&mut self, entry: u32) -> Result<Vec<u8>, NWFSError> {
+        let per_block = self.volume.block_size() / DIR_ENTRY_SIZE;
+        let data = self.volume.read_block(self.directory_chains[0][entry as usize / per_block])?;
+        let offset = (entry as usize % per_block) * DIR_ENTRY_SIZE;
+        Ok(data[offset..offset + DIR_ENTRY_SIZE].to_vec())
+    }
+
+    fn write_entry(&mut self, entry: u32, data: &[u8]) -> Result<(), NWFSError> {
+        let per_block = self.volume.block_size() / DIR_ENTRY_SIZE;
+        let offset = (entry as usize % per_block) * DIR_ENTRY_SIZE;
+        for n in 0..self.directory_chains.len() {
+            let block = self.directory_chains[n][entry as usize / per_block];
+            let mut block_data = self.volume.read_block(block)?;
+            block_data[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(data);
+            self.write_block(block, &block_data)?;
+        }
+        self.volume.set_entry(entry, data)
+    }
+
+    // Free directory entries; the directory grows by a block (in every copy)
+    // if there are not enough of them
+    fn free_entries(&mut self, count: usize) -> Result<Vec<u32>, NWFSError> {
+        loop {
+            let free: Vec<u32> = self.volume.entries.iter().filter(|e| e.parent == DIR_FREE).map(|e| e.entry).take(count).collect();
+            if free.len() == count {
+                return Ok(free);
+            }
+            let mut data = vec![ 0u8; self.volume.block_size() ];
+            for chunk in data.chunks_exact_mut(DIR_ENTRY_SIZE) {
+                LittleEndian::write_u32(&mut chunk[ENTRY_PARENT..], DIR_FREE);
+            }
+            let blocks = self.allocate(self.directory_chains.len())?;
+            let mut changed = Vec::new();
+            for (n, block) in blocks.iter().enumerate() {
+                self.write_block(*block, &data)?;
+                let last = *self.directory_chains[n].last().unwrap_or(&FAT_CHAIN_END);
+                self.volume.fat[last as usize].next = *block;
+                self.volume.fat[*block as usize] = FatEntry{ index: self.directory_chains[n].len() as u32, next: FAT_CHAIN_END };
+                self.directory_chains[n].push(*block);
+                changed.extend([ last, *block ]);
+            }
+            self.write_fat(&changed)?;
+            for chunk in data.chunks_exact(DIR_ENTRY_SIZE) {
+                self.volume.set_entry(self.volume.entries.len() as u32, chunk)?;
+            }
+        }
+    }
+
+    // Adds a file with the given contents and modification time (in seconds
+    // since the Unix epoch) as path, or replaces the contents of the file
+    // if it exists. Returns the DOS entry of the file and whether it was
+    // replaced
+    pub fn put_file(&mut self, path: &str, data: &[u8], mtime: u64) -> Result<(DirEntry, bool), NWFSError> {
+        if data.len() > u32::MAX as usize {
+            return Err(NWFSError::Unsupported("files of 4 GB or more".to_string()));
+        }
+        let path = path.split_once(':').map(|(_, p)| p).unwrap_or(path).trim_end_matches(['/', '\\']);
+        let (dir_path, name) = path.rsplit_once(['/', '\\']).unwrap_or(("", path));
+        let dir = match self.volume.lookup(dir_path) {
+            Some(dir) if dir.is_directory() => dir.clone(),
+            _ => return Err(NWFSError::FileNotFound(dir_path.to_string())),
+        };
+        let existing = self.volume.children(dir.entry)
+            .find(|e| e.name.eq_ignore_ascii_case(name) || self.volume.display_name(e).eq_ignore_ascii_case(name))
+            .cloned();
+        if existing.as_ref().is_some_and(|e| e.is_directory()) {
+            return Err(NWFSError::Unsupported(format!("replacing directory {} by a file", path)));
+        }
+        let timestamp = self.volume.timestamp(mtime);
+
+        // Directory entries are claimed first, as the directory may have to
+        // grow; there is one for every name space
+        let namespaces = self.volume.namespaces();
+        let entries = match &existing {
+            Some(_) => Vec::new(),
+            None => {
+                if dos_name(name).is_none() {
+                    return Err(NWFSError::Unsupported(format!("'{}' is not a valid DOS 8.3 name", name)));
+                }
+                self.free_entries(namespaces.len())?
+            }
+        };
+
+        let blocks = self.allocate(data.len().div_ceil(self.volume.block_size()))?;
+        for (block, chunk) in blocks.iter().zip(data.chunks(self.volume.block_size())) {
+            self.write_block(*block, chunk)?;
+        }
+        self.link(&blocks);
+        self.write_fat(&blocks)?;
+        let first_block = blocks.first().copied().unwrap_or(FAT_CHAIN_END);
+
+        if let Some(e) = existing {
+            let mut raw = self.read_entry(e.entry)?;
+            let attributes = (e.attributes & !ATTR_COMPRESSED) | ATTR_ARCHIVE;
+            LittleEndian::write_u32(&mut raw[ENTRY_ATTRIBUTES..], attributes);
+            LittleEndian::write_u32(&mut raw[ENTRY_MODIFIED..], timestamp);
+            LittleEndian::write_u32(&mut raw[ENTRY_SIZE..], data.len() as u32);
+            LittleEndian::write_u32(&mut raw[ENTRY_FIRST_BLOCK..], first_block);
+            self.write_entry(e.entry, &raw)?;
+            self.free_chain(e.first_block)?;
+            return Ok((self.volume.entries[e.entry as usize].clone(), true));
+        }
+
+        // New files are owned by the owner of their directory
+        let dos_entry = entries[0];
+        for (entry, namespace) in entries.iter().zip(&namespaces) {
+            let mut raw = [ 0u8; DIR_ENTRY_SIZE ];
+            LittleEndian::write_u32(&mut raw[ENTRY_PARENT..], dir.entry);
+            LittleEndian::write_u32(&mut raw[ENTRY_ATTRIBUTES..], ATTR_ARCHIVE);
+            raw[ENTRY_NAMESPACE] = *namespace;
+            if *namespace == NS_DOS {
+                let name = dos_name(name).unwrap_or_default();
+                let len = name.len().min(DIR_NAME_MAX);
+                raw[ENTRY_NAME_LEN] = len as u8;
+                raw[ENTRY_NAME..ENTRY_NAME + len].copy_from_slice(&name.as_bytes()[0..len]);
+                LittleEndian::write_u32(&mut raw[ENTRY_CREATED..], timestamp);
+                LittleEndian::write_u32(&mut raw[ENTRY_OWNER..], dir.owner);
+                LittleEndian::write_u32(&mut raw[ENTRY_MODIFIED..], timestamp);
+                LittleEndian::write_u32(&mut raw[ENTRY_MODIFIER..], dir.owner);
+                LittleEndian::write_u32(&mut raw[ENTRY_SIZE..], data.len() as u32);
+                LittleEndian::write_u32(&mut raw[ENTRY_FIRST_BLOCK..], first_block);
+                LittleEndian::write_u16(&mut raw[ENTRY_INHERITED_RIGHTS..], RIGHTS_ALL);
+            } else {
+                raw[ENTRY_NAME_LEN] = name.len() as u8;
+                LittleEndian::write_u32(&mut raw[ENTRY_PRIMARY..], dos_entry);
+                raw[NS_NAME_OFFSET..NS_NAME_OFFSET + name.len()].copy_from_slice(name.as_bytes());
+                if *namespace == volume::NS_MAC {
+                    LittleEndian::write_u32(&mut raw[MAC_RESOURCE_FORK_OFFSET..], FAT_CHAIN_END);
+                }
+            }
+            self.write_entry(*entry, &raw)?;
+        }
+        Ok((self.volume.entries[dos_entry as usize].clone(), false))
+    }
+}
@@ -0,0 +1,232 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Packet captures, as written by tcpdump and Wireshark. Both the classic
+// pcap format and pcapng are read, in either byte order; the format is
+// recognized by the magic number at the start of the file.
+//
+// A pcap file has a single link type, given in its header, and timestamps in
+// micro- or nanoseconds depending on its magic. A pcapng file consists of
+// blocks: every section starts with a section header block, which gives the
+// byte order, and interface description blocks give the link type and
+// timestamp resolution of the interfaces whose packets follow in enhanced
+// (or simple) packet blocks. Other blocks are skipped.
+use std::io::Read;
+
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const PCAP_MAGIC_US: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+// Blocks and packets larger than this are taken to be corrupt
+const MAX_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub link_type: u32,
+    // Nanoseconds since 1970-01-01 UTC
+    pub timestamp: u64,
+    // Length of the packet on the wire, which is more than data holds if it
+    // was truncated by the snapshot length
+    pub length: u32,
+    pub data: Vec<u8>,
+}
+
+struct Interface {
+    link_type: u32,
+    // Timestamp units per second
+    units: u64,
+}
+
+enum Format {
+    Pcap{ link_type: u32, nanoseconds: bool },
+    Pcapng{ interfaces: Vec<Interface> },
+}
+
+pub struct Capture<R: Read> {
+    reader: R,
+    big_endian: bool,
+    format: Format,
+}
+
+fn u16_from(data: &[u8], big_endian: bool) -> u16 {
+    let bytes = [ data[0], data[1] ];
+    if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+}
+
+fn u32_from(data: &[u8], big_endian: bool) -> u32 {
+    let bytes = [ data[0], data[1], data[2], data[3] ];
+    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+}
+
+// Timestamp units per second given by the if_tsresol option: a power of ten,
+// or of two if the top bit is set
+fn tsresol_units(value: u8) -> u64 {
+    let exponent = (value & 0x7f) as u32;
+    let units = if value & 0x80 != 0 { 2u64.checked_pow(exponent) } else { 10u64.checked_pow(exponent) };
+    units.unwrap_or(u64::MAX)
+}
+
+fn to_nanoseconds(units: u64, value: u64) -> u64 {
+    ((value as u128 * 1_000_000_000) / units as u128) as u64
+}
+
+impl<R: Read> Capture<R> {
+    pub fn open(mut reader: R) -> Result<Self, std::io::Error> {
+        let mut magic = [ 0u8; 4 ];
+        reader.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut length = [ 0u8; 4 ];
+            reader.read_exact(&mut length)?;
+            let mut capture = Self{ reader, big_endian: false, format: Format::Pcapng{ interfaces: Vec::new() } };
+            capture.read_section_header(length)?;
+            return Ok(capture)
+        }
+        let (big_endian, nanoseconds) = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAP_MAGIC_US, _) => (true, false),
+            (PCAP_MAGIC_NS, _) => (true, true),
+            (_, PCAP_MAGIC_US) => (false, false),
+            (_, PCAP_MAGIC_NS) => (false, true),
+            _ => return Err(invalid("not a pcap or pcapng file".to_string())),
+        };
+        let mut header = [ 0u8; 20 ];
+        reader.read_exact(&mut header)?;
+        let link_type = u32_from(&header[16..], big_endian) & 0xffff;
+        Ok(Self{ reader, big_endian, format: Format::Pcap{ link_type, nanoseconds } })
+    }
+
+    // Reads the rest of a section header block, whose type and length were
+    // already read; this sets the byte order of the blocks that follow
+    fn read_section_header(&mut self, length: [ u8; 4 ]) -> Result<(), std::io::Error> {
+        let mut magic = [ 0u8; 4 ];
+        self.reader.read_exact(&mut magic)?;
+        self.big_endian = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAPNG_BYTE_ORDER_MAGIC, _) => true,
+            (_, PCAPNG_BYTE_ORDER_MAGIC) => false,
+            _ => return Err(invalid("invalid byte order in pcapng section header".to_string())),
+        };
+        let length = u32_from(&length, self.big_endian);
+        if !(12..=MAX_BLOCK_SIZE).contains(&length) {
+            return Err(invalid(format!("invalid pcapng block length {}", length)));
+        }
+        self.read_data(length as usize - 12)?;
+        self.format = Format::Pcapng{ interfaces: Vec::new() };
+        Ok(())
+    }
+
+    fn read_data(&mut self, len: usize) -> Result<Vec<u8>, std::io::Error> {
+        let mut data = vec![ 0u8; len ];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    // Reads the next header, or returns None at the end of the file
+    fn read_header<const N: usize>(&mut self) -> Result<Option<[ u8; N ]>, std::io::Error> {
+        let mut header = [ 0u8; N ];
+        let mut len = 0;
+        while len < N {
+            match self.reader.read(&mut header[len..])? {
+                0 if len == 0 => return Ok(None),
+                0 => return Err(invalid("capture file is truncated".to_string())),
+                n => len += n,
+            }
+        }
+        Ok(Some(header))
+    }
+
+    // Returns the next packet, or None at the end of the capture
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, std::io::Error> {
+        match self.format {
+            Format::Pcap{ link_type, nanoseconds } => {
+                let Some(header) = self.read_header::<16>()? else { return Ok(None) };
+                let seconds = u32_from(&header[0..], self.big_endian) as u64;
+                let fraction = u32_from(&header[4..], self.big_endian) as u64;
+                let captured = u32_from(&header[8..], self.big_endian);
+                let length = u32_from(&header[12..], self.big_endian);
+                if captured > MAX_BLOCK_SIZE {
+                    return Err(invalid(format!("invalid captured length {}", captured)));
+                }
+                let data = self.read_data(captured as usize)?;
+                let timestamp = seconds * 1_000_000_000 + if nanoseconds { fraction } else { fraction * 1000 };
+                Ok(Some(Packet{ link_type, timestamp, length, data }))
+            },
+            Format::Pcapng{ .. } => self.next_pcapng_packet(),
+        }
+    }
+
+    fn next_pcapng_packet(&mut self) -> Result<Option<Packet>, std::io::Error> {
+        loop {
+            let Some(header) = self.read_header::<8>()? else { return Ok(None) };
+            if u32::from_le_bytes(header[0..4].try_into().unwrap()) == PCAPNG_SECTION_HEADER {
+                self.read_section_header(header[4..8].try_into().unwrap())?;
+                continue;
+            }
+            let block_type = u32_from(&header[0..], self.big_endian);
+            let length = u32_from(&header[4..], self.big_endian);
+            if !(12..=MAX_BLOCK_SIZE).contains(&length) || !length.is_multiple_of(4) {
+                return Err(invalid(format!("invalid pcapng block length {}", length)));
+            }
+            // The body, followed by the length again
+            let body = self.read_data(length as usize - 8)?;
+            let body = &body[..body.len() - 4];
+            let big_endian = self.big_endian;
+            let Format::Pcapng{ interfaces } = &mut self.format else { unreachable!() };
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                    let link_type = u16_from(body, big_endian) as u32;
+                    let mut units = 1_000_000;
+                    let mut options = &body[8..];
+                    while options.len() >= 4 {
+                        let code = u16_from(options, big_endian);
+                        let len = u16_from(&options[2..], big_endian) as usize;
+                        let Some(value) = options.get(4..4 + len) else { break };
+                        if code == PCAPNG_OPTION_TSRESOL && len == 1 {
+                            units = tsresol_units(value[0]);
+                        }
+                        options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or_default();
+                    }
+                    interfaces.push(Interface{ link_type, units });
+                },
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = u32_from(body, big_endian) as usize;
+                    let time = ((u32_from(&body[4..], big_endian) as u64) << 32) | u32_from(&body[8..], big_endian) as u64;
+                    let captured = u32_from(&body[12..], big_endian) as usize;
+                    let length = u32_from(&body[16..], big_endian);
+                    let iface = interfaces.get(interface).ok_or(invalid(format!("packet of unknown interface {}", interface)))?;
+                    let data = body.get(20..20 + captured).ok_or(invalid(format!("invalid captured length {}", captured)))?.to_vec();
+                    return Ok(Some(Packet{ link_type: iface.link_type, timestamp: to_nanoseconds(iface.units, time), length, data }))
+                },
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let iface = interfaces.first().ok_or(invalid("packet of unknown interface 0".to_string()))?;
+                    let length = u32_from(body, big_endian);
+                    let data = body[4..body.len().min(4 + length as usize)].to_vec();
+                    return Ok(Some(Packet{ link_type: iface.link_type, timestamp: 0, length, data }))
+                },
+                _ => { },
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Capture<R> {
+    type Item = Result<Packet, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
@@ -0,0 +1,170 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Resolving addresses to module!symbol+offset, using the symbol files written
+// by the other tools and a map of the addresses at which modules were loaded.
+// Symbols of a module are either absolute, or relative to the addresses
+// nlm2elf uses, in which case they are moved to where the module was loaded.
+use crate::nlm::{NLM_CODE_VADDR, NLM_DATA_VADDR};
+use crate::symbols::{self, Symbol};
+
+#[derive(Debug, Clone)]
+pub struct ModuleRange {
+    pub name: String,
+    pub code_address: u32,
+    pub code_length: u32,
+    pub data_address: u32,
+    pub data_length: u32,
+}
+
+impl ModuleRange {
+    pub fn is_code(&self, addr: u32) -> bool {
+        addr >= self.code_address && addr - self.code_address < self.code_length
+    }
+
+    pub fn is_data(&self, addr: u32) -> bool {
+        addr >= self.data_address && addr - self.data_address < self.data_length
+    }
+}
+
+// Symbols further away than this from an address outside of any module are
+// not taken to be its location
+pub const MAX_DISTANCE: u32 = 0x10000;
+
+// Module and segment (code or data) an address is in
+type Region = Option<(usize, bool)>;
+
+// Parses 'ADDR+LEN'
+fn parse_range(s: &str) -> Option<(u32, u32)> {
+    let (addr, len) = s.split_once('+')?;
+    Some((symbols::parse_address(addr)?, symbols::parse_address(len)?))
+}
+
+// Reads a module map, which has a line per module containing its name
+// followed by 'code ADDR+LEN' and optionally 'data ADDR+LEN', as printed by
+// extract-loaded-modules. Lines without a code range are ignored.
+pub fn parse_module_map(text: &str) -> Vec<ModuleRange> {
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let code_pos = match words.iter().position(|w| *w == "code") {
+            Some(pos) if pos > 0 => pos,
+            _ => continue,
+        };
+        let (code_address, code_length) = match words.get(code_pos + 1).and_then(|w| parse_range(w)) {
+            Some(range) => range,
+            None => continue,
+        };
+        let (data_address, data_length) = words.iter().position(|w| *w == "data")
+            .and_then(|pos| words.get(pos + 1))
+            .and_then(|w| parse_range(w))
+            .unwrap_or((0, 0));
+        result.push(ModuleRange{ name: words[code_pos - 1].to_string(), code_address, code_length, data_address, data_length });
+    }
+    result
+}
+
+// Reads a symbol file in the text ('name 0xaddr'), nm ('addr T name') or
+// ghidra ('name 0xaddr f') format; lines that cannot be parsed are ignored
+pub fn parse_symbol_file(text: &str) -> Vec<Symbol> {
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let symbol = match words.as_slice() {
+            [ addr, kind, name ] if kind.len() == 1 && !addr.starts_with("0x") => {
+                symbols::parse_address(addr).map(|address| Symbol{ name: name.to_string(), address })
+            },
+            [ name, addr, .. ] if addr.starts_with("0x") => {
+                symbols::parse_address(addr).map(|address| Symbol{ name: name.to_string(), address })
+            },
+            _ => None,
+        };
+        result.extend(symbol);
+    }
+    result
+}
+
+pub struct Resolver {
+    pub modules: Vec<ModuleRange>,
+    // Symbols, along with the region they are in
+    symbols: Vec<(Region, Symbol)>,
+}
+
+impl Resolver {
+    pub fn new(modules: Vec<ModuleRange>) -> Self {
+        Self{ modules, symbols: Vec::new() }
+    }
+
+    fn region(&self, addr: u32) -> Region {
+        self.modules.iter().enumerate().find_map(|(n, m)| {
+            if m.is_code(addr) { return Some((n, true)); }
+            if m.is_data(addr) { return Some((n, false)); }
+            None
+        })
+    }
+
+    // Adds the symbols of a module; those at the nlm2elf addresses are moved
+    // to where the module was loaded
+    pub fn add_module_symbols(&mut self, module: &str, symbols: Vec<Symbol>) -> Result<(), String> {
+        let index = self.modules.iter().position(|m| m.name.eq_ignore_ascii_case(module))
+            .ok_or(format!("module '{}' is not in the module map", module))?;
+        let m = &self.modules[index];
+        for mut sym in symbols {
+            if sym.address >= NLM_DATA_VADDR {
+                sym.address = sym.address - NLM_DATA_VADDR + m.data_address;
+            } else if sym.address >= NLM_CODE_VADDR {
+                sym.address = sym.address - NLM_CODE_VADDR + m.code_address;
+            }
+            let region = self.region(sym.address);
+            self.symbols.push((region, sym));
+        }
+        Ok(())
+    }
+
+    // Adds symbols at absolute addresses
+    pub fn add_symbols(&mut self, symbols: Vec<Symbol>) {
+        for sym in symbols {
+            let region = self.region(sym.address);
+            self.symbols.push((region, sym));
+        }
+    }
+
+    // All symbols, at the addresses they were moved to
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().map(|(_, sym)| sym)
+    }
+
+    // Whether addr is likely the address of code: either within the code of
+    // a module or, outside of the modules, close to a symbol
+    pub fn is_code(&self, addr: u32) -> bool {
+        match self.region(addr) {
+            Some((_, is_code)) => is_code,
+            None => self.symbols.iter().any(|(r, s)| r.is_none() && s.address <= addr && addr - s.address < MAX_DISTANCE),
+        }
+    }
+
+    // Returns 'module!symbol+offset', leaving out what is not known; None if
+    // nothing is known about addr. Only symbols in the same segment of the
+    // same module are considered, or those outside of any module if there is
+    // none
+    pub fn resolve(&self, addr: u32) -> Option<String> {
+        let region = self.region(addr);
+        let nearest = self.symbols.iter()
+            .filter(|(r, s)| s.address <= addr && *r == region)
+            .max_by_key(|(_, s)| s.address);
+        let offset = |base: u32| if addr == base { String::new() } else { format!("+{:x}", addr - base) };
+        match (region, nearest) {
+            (Some((n, _)), Some((_, sym))) => Some(format!("{}!{}{}", self.modules[n].name, sym.name, offset(sym.address))),
+            (Some((n, is_code)), None) => {
+                let m = &self.modules[n];
+                Some(format!("{}{}", m.name, offset(if is_code { m.code_address } else { m.data_address })))
+            },
+            (None, Some((_, sym))) => Some(format!("{}{}", sym.name, offset(sym.address))),
+            (None, None) => None,
+        }
+    }
+}
@@ -0,0 +1,247 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Service Advertising Protocol. Servers broadcast the services they offer to
+// IPX socket 0x452 every minute, and routers (every NetWare server is one)
+// pass on what they learn to the networks they are attached to, adding a hop
+// each time. Workstations look for a server by sending a nearest server query
+// when the shell loads, and utilities such as SLIST send general queries.
+//
+// A packet starts with the operation. Queries are followed by the service
+// type looked for (0xffff for all of them), responses by up to seven
+// entries, each giving the type and name of a service, the IPX address it is
+// offered at and the number of hops to it. A service which goes away is
+// advertised once more with 16 hops, meaning it is unreachable.
+//
+// An inventory of the services seen in a capture, and of the servers and
+// routers which advertised them, gives an overview of a network of which
+// little else may be known, as most servers advertise their internal
+// network and every router passes on all services it knows about.
+use std::io::{Cursor, Read};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::codepage;
+use crate::ipx::Address;
+
+pub const OP_GENERAL_QUERY: u16 = 1;
+pub const OP_GENERAL_RESPONSE: u16 = 2;
+pub const OP_NEAREST_QUERY: u16 = 3;
+pub const OP_NEAREST_RESPONSE: u16 = 4;
+
+pub const SERVICE_ALL: u16 = 0xffff;
+pub const SERVICE_NAME_SIZE: usize = 48;
+pub const ENTRY_SIZE: usize = 64;
+pub const MAX_ENTRIES: usize = 7;
+pub const HOPS_UNREACHABLE: u16 = 16;
+// Seconds between periodic broadcasts
+pub const BROADCAST_INTERVAL: u64 = 60;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Service {
+    // Bindery object type of the service, such as 0x0004 for a file server
+    pub service_type: u16,
+    pub name: String,
+    pub address: Address,
+    pub hops: u16,
+}
+
+impl Service {
+    pub fn is_unreachable(&self) -> bool {
+        self.hops >= HOPS_UNREACHABLE
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SapPacket {
+    Query{ nearest: bool, service_type: u16 },
+    Response{ nearest: bool, services: Vec<Service> },
+}
+
+impl SapPacket {
+    pub fn parse(data: &[u8]) -> Result<Self, std::io::Error> {
+        let mut cursor = Cursor::new(data);
+        let operation = cursor.read_u16::<BigEndian>()?;
+        match operation {
+            OP_GENERAL_QUERY | OP_NEAREST_QUERY => {
+                let service_type = cursor.read_u16::<BigEndian>()?;
+                Ok(SapPacket::Query{ nearest: operation == OP_NEAREST_QUERY, service_type })
+            },
+            OP_GENERAL_RESPONSE | OP_NEAREST_RESPONSE => {
+                let mut services = Vec::new();
+                // Trailing bytes which do not make up an entry are ignored
+                for _ in 0..(data.len() - 2) / ENTRY_SIZE {
+                    let service_type = cursor.read_u16::<BigEndian>()?;
+                    let mut name = [ 0u8; SERVICE_NAME_SIZE ];
+                    cursor.read_exact(&mut name)?;
+                    let name_len = name.iter().position(|b| *b == 0).unwrap_or(SERVICE_NAME_SIZE);
+                    let address = Address::read(&mut cursor)?;
+                    let hops = cursor.read_u16::<BigEndian>()?;
+                    services.push(Service{ service_type, name: codepage::DEFAULT.decode(&name[..name_len]), address, hops });
+                }
+                Ok(SapPacket::Response{ nearest: operation == OP_NEAREST_RESPONSE, services })
+            },
+            _ => Err(invalid(format!("unknown SAP operation {}", operation))),
+        }
+    }
+
+    pub fn operation(&self) -> u16 {
+        match self {
+            SapPacket::Query{ nearest: false, .. } => OP_GENERAL_QUERY,
+            SapPacket::Response{ nearest: false, .. } => OP_GENERAL_RESPONSE,
+            SapPacket::Query{ nearest: true, .. } => OP_NEAREST_QUERY,
+            SapPacket::Response{ nearest: true, .. } => OP_NEAREST_RESPONSE,
+        }
+    }
+
+    // The packet data; responses should hold at most MAX_ENTRIES services
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        result.write_u16::<BigEndian>(self.operation()).unwrap();
+        match self {
+            SapPacket::Query{ service_type, .. } => {
+                result.write_u16::<BigEndian>(*service_type).unwrap();
+            },
+            SapPacket::Response{ services, .. } => {
+                for s in services {
+                    result.write_u16::<BigEndian>(s.service_type).unwrap();
+                    let mut name = codepage::DEFAULT.encode(&s.name);
+                    name.resize(SERVICE_NAME_SIZE, 0);
+                    // Names are zero-terminated
+                    name[SERVICE_NAME_SIZE - 1] = 0;
+                    result.extend(name);
+                    s.address.write(&mut result);
+                    result.write_u16::<BigEndian>(s.hops).unwrap();
+                }
+            },
+        }
+        result
+    }
+}
+
+pub fn operation_name(operation: u16) -> &'static str {
+    match operation {
+        OP_GENERAL_QUERY => "general query",
+        OP_GENERAL_RESPONSE => "general response",
+        OP_NEAREST_QUERY => "nearest server query",
+        OP_NEAREST_RESPONSE => "nearest server response",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Appeared,
+    // Reached in a different number of hops, such as when a route changed
+    Hops(u16),
+    Moved(Address),
+    Unreachable,
+    Reachable,
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    // Nanoseconds since 1970-01-01 UTC, as in captures
+    pub time: u64,
+    pub service_type: u16,
+    pub name: String,
+    pub change: Change,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServiceRecord {
+    // As reached through the closest route
+    pub service: Service,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    // Number of responses the service was part of
+    pub advertisements: usize,
+    // Senders of those responses, which are the server itself or routers,
+    // and the hops they last gave
+    pub routes: Vec<(Address, u16)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Advertiser {
+    pub address: Address,
+    pub responses: usize,
+    pub services: usize,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+// Services seen in the responses of a capture, along with how they changed
+// over time. Every router on a segment advertises the services it knows
+// about, so a service is reached through the one closest to it
+#[derive(Default)]
+pub struct Inventory {
+    pub services: Vec<ServiceRecord>,
+    pub advertisers: Vec<Advertiser>,
+    pub events: Vec<Event>,
+    // Queries, and the addresses which sent them
+    pub queries: usize,
+    pub queriers: Vec<Address>,
+}
+
+impl Inventory {
+    // Adds a packet sent by source at time; packets are to be added in the
+    // order of their time
+    pub fn add(&mut self, time: u64, source: &Address, packet: &SapPacket) {
+        let services = match packet {
+            SapPacket::Query{ .. } => {
+                self.queries += 1;
+                if !self.queriers.contains(source) {
+                    self.queriers.push(*source);
+                }
+                return
+            },
+            SapPacket::Response{ services, .. } => services,
+        };
+        let advertiser = match self.advertisers.iter_mut().find(|a| a.address == *source) {
+            Some(a) => a,
+            None => {
+                self.advertisers.push(Advertiser{ address: *source, responses: 0, services: 0, first_seen: time, last_seen: time });
+                self.advertisers.last_mut().unwrap()
+            },
+        };
+        advertiser.responses += 1;
+        advertiser.services += services.len();
+        advertiser.last_seen = time;
+
+        for s in services {
+            let mut event = |change| self.events.push(Event{ time, service_type: s.service_type, name: s.name.clone(), change });
+            let Some(r) = self.services.iter_mut().find(|r| r.service.service_type == s.service_type && r.service.name == s.name) else {
+                event(if s.is_unreachable() { Change::Unreachable } else { Change::Appeared });
+                self.services.push(ServiceRecord{ service: s.clone(), first_seen: time, last_seen: time, advertisements: 1, routes: vec![ (*source, s.hops) ] });
+                continue;
+            };
+            r.advertisements += 1;
+            r.last_seen = time;
+            match r.routes.iter_mut().find(|(a, _)| a == source) {
+                Some((_, hops)) => *hops = s.hops,
+                None => r.routes.push((*source, s.hops)),
+            }
+            let hops = r.routes.iter().map(|(_, hops)| *hops).min().unwrap();
+            let old = std::mem::replace(&mut r.service, Service{ hops, ..s.clone() });
+            if r.service.is_unreachable() {
+                r.service.address = old.address;
+                if !old.is_unreachable() {
+                    event(Change::Unreachable);
+                }
+            } else if old.is_unreachable() {
+                event(Change::Reachable);
+            } else if old.address != s.address {
+                event(Change::Moved(s.address));
+            } else if old.hops != hops {
+                event(Change::Hops(hops));
+            }
+        }
+    }
+}
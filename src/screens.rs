@@ -0,0 +1,150 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Console screens of a running server. Every screen (the system console and
+// those created by modules) is described by a screen structure, which are
+// kept in a linked list using their first field. Only the screen currently
+// shown is in video memory; the contents of all others are kept in a buffer
+// of character/attribute pairs. Like the load definition structures, the
+// screen structures are located by scanning memory.
+use std::collections::HashSet;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::codepage::Codepage;
+use crate::symbols::Image;
+
+// Offsets within the screen structure
+const SCR_LINK: u32 = 0x00;
+const SCR_NAME: u32 = 0x08;
+const SCR_BUFFER: u32 = 0x0c;
+const SCR_ROWS: u32 = 0x10;
+const SCR_COLUMNS: u32 = 0x12;
+const SCR_CURSOR_ROW: u32 = 0x14;
+const SCR_CURSOR_COLUMN: u32 = 0x16;
+
+const SCR_MAX_ROWS: u16 = 60;
+const SCR_COLUMN_WIDTHS: [ u16; 3 ] = [ 40, 80, 132 ];
+const SCR_NAME_MAX: usize = 80;
+
+// Colour text mode video memory, at its physical address
+pub const VIDEO_MEMORY: usize = 0xb8000;
+pub const VIDEO_ROWS: u16 = 25;
+pub const VIDEO_COLUMNS: u16 = 80;
+
+#[derive(Debug, Clone)]
+pub struct Screen {
+    // Address of the screen structure, or None for video memory
+    pub address: Option<u32>,
+    pub next: u32,
+    pub name: String,
+    pub buffer: u32,
+    pub rows: u16,
+    pub columns: u16,
+    pub cursor: (u16, u16),
+}
+
+impl Screen {
+    // Returns the screen at addr, if the structure there looks valid
+    pub fn read(image: &Image, address: u32) -> Option<Self> {
+        let field = |offset: u32| image.read_u32(address.checked_add(offset)?);
+        let half = |offset: u32| image.slice(address.checked_add(offset)?, 2).map(LittleEndian::read_u16);
+        let rows = half(SCR_ROWS)?;
+        let columns = half(SCR_COLUMNS)?;
+        if rows == 0 || rows > SCR_MAX_ROWS || !SCR_COLUMN_WIDTHS.contains(&columns) {
+            return None;
+        }
+        let cursor = (half(SCR_CURSOR_ROW)?, half(SCR_CURSOR_COLUMN)?);
+        if cursor.0 >= rows || cursor.1 > columns {
+            return None;
+        }
+        let buffer = field(SCR_BUFFER)?;
+        image.slice(buffer, rows as usize * columns as usize * 2)?;
+        let next = field(SCR_LINK)?;
+        if next != 0 && image.slice(next, 4).is_none() {
+            return None;
+        }
+        // Screen names contain spaces, so read_name() cannot be used
+        let name_ptr = field(SCR_NAME)?;
+        let len = *image.slice(name_ptr, 1)?.first()? as usize;
+        let name = image.slice(name_ptr.checked_add(1)?, len)?;
+        if len == 0 || len > SCR_NAME_MAX || !name.iter().all(|b| *b == b' ' || b.is_ascii_graphic()) {
+            return None;
+        }
+        Some(Self{
+            address: Some(address), next, buffer, rows, columns, cursor,
+            name: String::from_utf8_lossy(name).to_string(),
+        })
+    }
+
+    // The screen currently shown, as found in video memory
+    pub fn video_memory(data: &[u8]) -> Option<Self> {
+        data.get(VIDEO_MEMORY..VIDEO_MEMORY + VIDEO_ROWS as usize * VIDEO_COLUMNS as usize * 2)?;
+        Some(Self{
+            address: None, next: 0,
+            name: "Video memory".to_string(),
+            buffer: VIDEO_MEMORY as u32,
+            rows: VIDEO_ROWS, columns: VIDEO_COLUMNS, cursor: (0, 0),
+        })
+    }
+
+    // Returns the contents as text lines; trailing spaces and empty lines
+    // are removed
+    pub fn text(&self, cells: &[u8], codepage: &Codepage) -> Vec<String> {
+        let chars: Vec<u8> = cells.iter().step_by(2).map(|c| if *c < 0x20 { b' ' } else { *c }).collect();
+        let mut lines: Vec<String> = chars.chunks(self.columns as usize)
+            .take(self.rows as usize)
+            .map(|row| codepage.decode(row).trim_end().to_string())
+            .collect();
+        while lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+
+    pub fn cells<'a>(&self, image: &Image<'a>) -> Option<&'a [u8]> {
+        let length = self.rows as usize * self.columns as usize * 2;
+        // Video memory is at a physical address, which --base does not apply to
+        match self.address {
+            Some(_) => image.slice(self.buffer, length),
+            None => image.data.get(VIDEO_MEMORY..VIDEO_MEMORY + length),
+        }
+    }
+}
+
+// Locates all screen structures, in list order; structures which cannot be
+// reached from the list head are appended
+pub fn find_screens(image: &Image) -> Vec<Screen> {
+    let mut candidates: Vec<Screen> = Vec::new();
+    for offset in (0..image.data.len().saturating_sub(3)).step_by(4) {
+        let addr = match image.base.checked_add(offset as u32) {
+            Some(addr) => addr,
+            None => break,
+        };
+        if let Some(screen) = Screen::read(image, addr) {
+            candidates.push(screen);
+        }
+    }
+
+    let address = |s: &Screen| s.address.unwrap_or(0);
+    let referenced: HashSet<u32> = candidates.iter().map(|s| s.next).collect();
+    let mut result: Vec<Screen> = Vec::new();
+    let mut seen: HashSet<u32> = HashSet::new();
+    for head in candidates.iter().filter(|s| !referenced.contains(&address(s))) {
+        let mut current = Some(head);
+        while let Some(screen) = current {
+            if !seen.insert(address(screen)) { break; }
+            result.push(screen.clone());
+            current = candidates.iter().find(|s| address(s) == screen.next);
+        }
+    }
+    for screen in &candidates {
+        if seen.insert(address(screen)) {
+            result.push(screen.clone());
+        }
+    }
+    result
+}
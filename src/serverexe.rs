@@ -0,0 +1,314 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Layout of SERVER.EXE. The DOS loader is followed by the embedded NLMs; a
+// block starting with the signature HERE near the start of the file describes
+// where SERVER.NLM is stored. This is the same block the loader patching
+// utility LSWAP.EXE looks for. Only some of its fields are understood; the
+// others are kept as-is so they can be inspected.
+use byteorder::{ByteOrder, LittleEndian};
+use serde_json::{json, Value};
+
+use crate::loader::MzHeader;
+use crate::nlm::{NLM, NLM_MAGIC};
+use crate::versions;
+
+pub const HERE_SIGNATURE: &[u8; 4] = b"HERE";
+pub const HERE_SEARCH_LIMIT: usize = 0x1000;
+pub const HERE_BLOCK_SIZE: usize = 0x30;
+
+// Embedded NLMs are padded to a paragraph
+pub const NLM_ALIGNMENT: usize = 16;
+
+pub const HERE_NLM_OFFSET: usize = 0x18;
+const HERE_VERSION: usize = 0x2a;
+
+pub struct HereBlock {
+    // File offset of the block
+    pub offset: usize,
+    // File offset of SERVER.NLM
+    pub nlm_offset: u32,
+    // Words at 0x2a and 0x2c, which differ per version
+    pub version_words: (u16, u16),
+    pub raw: [ u8; HERE_BLOCK_SIZE ],
+}
+
+impl HereBlock {
+    pub fn find(data: &[u8]) -> Result<Self, String> {
+        let search_len = data.len().min(HERE_SEARCH_LIMIT);
+        let offset = data[0..search_len].windows(HERE_SIGNATURE.len()).position(|w| w == HERE_SIGNATURE)
+            .ok_or("HERE signature not found".to_string())?;
+        let block = data.get(offset..offset + HERE_BLOCK_SIZE)
+            .ok_or("HERE block is truncated".to_string())?;
+        Ok(Self::from(offset, block))
+    }
+
+    pub fn from(offset: usize, block: &[u8]) -> Self {
+        let mut raw = [ 0u8; HERE_BLOCK_SIZE ];
+        raw.copy_from_slice(&block[0..HERE_BLOCK_SIZE]);
+        let nlm_offset = LittleEndian::read_u32(&raw[HERE_NLM_OFFSET..]);
+        let version_words = (
+            LittleEndian::read_u16(&raw[HERE_VERSION..]),
+            LittleEndian::read_u16(&raw[HERE_VERSION + 2..])
+        );
+        Self{ offset, nlm_offset, version_words, raw }
+    }
+
+    // Returns the offset and value of the words whose meaning is not known
+    pub fn unknown_words(&self) -> Vec<(usize, u32)> {
+        let mut result = Vec::new();
+        for offset in (HERE_SIGNATURE.len()..HERE_VERSION).step_by(4) {
+            if offset == HERE_NLM_OFFSET { continue; }
+            let len = (HERE_VERSION - offset).min(4);
+            let value = if len == 4 { LittleEndian::read_u32(&self.raw[offset..]) } else { LittleEndian::read_u16(&self.raw[offset..]) as u32 };
+            result.push((offset, value));
+        }
+        result.push((HERE_VERSION + 4, LittleEndian::read_u16(&self.raw[HERE_VERSION + 4..]) as u32));
+        result
+    }
+
+    pub fn to_text(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        result.push(format!("HERE block at offset {:x}", self.offset));
+        result.push(format!("  nlm offset      {:x}", self.nlm_offset));
+        let known = versions::find_by_here_words(self.version_words).map(|v| format!(" ({})", v.description)).unwrap_or_default();
+        result.push(format!("  version words   {:x} {:x}{}", self.version_words.0, self.version_words.1, known));
+        for (offset, value) in self.unknown_words() {
+            result.push(format!("  unknown {:02x}      {:x}", offset, value));
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        let unknown: serde_json::Map<String, Value> = self.unknown_words().iter()
+            .map(|(offset, value)| (format!("{:#04x}", offset), json!(value)))
+            .collect();
+        json!({
+            "offset": self.offset,
+            "nlm_offset": self.nlm_offset,
+            "version_words": [ self.version_words.0, self.version_words.1 ],
+            "version": versions::find_by_here_words(self.version_words).map(|v| v.version),
+            "unknown": unknown,
+        })
+    }
+}
+
+// SFT III servers consist of an IOEngine and an MSEngine, each with their own
+// OS NLM; SMP servers add the multiprocessing kernel (MPK). Both have more
+// symbol lists than the regular server, and their HERE block may not point
+// at the first NLM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerVariant {
+    Standard,
+    SftIII,
+    Smp,
+}
+
+const SFT3_MARKERS: [ &[u8]; 3 ] = [ b"SFT III", b"IOEngine", b"MSEngine" ];
+const SMP_MARKERS: [ &[u8]; 2 ] = [ b"NetWare SMP", b"MPK" ];
+
+impl ServerVariant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::SftIII => "SFT III",
+            Self::Smp => "SMP",
+        }
+    }
+
+    // Detected from the NLM names and the texts within data
+    pub fn detect(data: &[u8], nlms: &[EmbeddedNLM]) -> Self {
+        let contains = |marker: &[u8]| data.windows(marker.len()).any(|w| w == marker);
+        let named = |prefix: &str| nlms.iter().any(|n| n.name.as_ref().is_some_and(|name| name.starts_with(prefix)));
+        if named("IOENGINE") || named("MSENGINE") || SFT3_MARKERS.iter().any(|m| contains(m)) {
+            return Self::SftIII;
+        }
+        if named("SMP") || named("MPK") || SMP_MARKERS.iter().any(|m| contains(m)) {
+            return Self::Smp;
+        }
+        Self::Standard
+    }
+}
+
+// How a pointer in the HERE block relates to the file. DOS loads the image
+// described by the MZ header, which starts after the header, at the load
+// segment; the loader refers to the data following it either by file offset,
+// by offset relative to the start of the load image, or by a segment:offset
+// pair relative to the load segment. Which one is used differs per build.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerKind {
+    FileOffset,
+    ImageOffset,
+    FarPointer,
+}
+
+pub const POINTER_KINDS: [ PointerKind; 3 ] = [ PointerKind::FileOffset, PointerKind::ImageOffset, PointerKind::FarPointer ];
+
+impl PointerKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FileOffset => "file offset",
+            Self::ImageOffset => "load image offset",
+            Self::FarPointer => "segment:offset",
+        }
+    }
+}
+
+// Translates between file offsets and the addresses used by the loader
+#[derive(Debug, Clone, Copy)]
+pub struct AddressMap {
+    // Length of the MZ header, which is not loaded
+    pub header_length: usize,
+}
+
+impl AddressMap {
+    // Files without an MZ header, such as a bare loader image, are mapped 1:1
+    pub fn new(data: &[u8]) -> Self {
+        let header_length = MzHeader::parse(data).map(|h| h.header_length()).unwrap_or(0);
+        Self{ header_length }
+    }
+
+    pub fn to_file(&self, value: u32, kind: PointerKind) -> Option<usize> {
+        match kind {
+            PointerKind::FileOffset => Some(value as usize),
+            PointerKind::ImageOffset => (value as usize).checked_add(self.header_length),
+            PointerKind::FarPointer => {
+                let linear = (value >> 16) as usize * 16 + (value & 0xffff) as usize;
+                linear.checked_add(self.header_length)
+            }
+        }
+    }
+
+    // Far pointers are normalized, so the offset is below 16
+    pub fn from_file(&self, offset: usize, kind: PointerKind) -> Option<u32> {
+        let value = match kind {
+            PointerKind::FileOffset => offset,
+            PointerKind::ImageOffset => offset.checked_sub(self.header_length)?,
+            PointerKind::FarPointer => {
+                let linear = offset.checked_sub(self.header_length)?;
+                let segment = u16::try_from(linear / 16).ok()?;
+                ((segment as usize) << 16) | (linear % 16)
+            }
+        };
+        u32::try_from(value).ok()
+    }
+
+    // Linear address of the byte at file offset when the load image is placed
+    // at load_segment, i.e. the segment following the PSP
+    pub fn linear(&self, offset: usize, load_segment: u16) -> Option<u32> {
+        let image_offset = offset.checked_sub(self.header_length)?;
+        u32::try_from(load_segment as usize * 16 + image_offset).ok()
+    }
+}
+
+// File offset of the NLM the HERE block refers to, along with how the block
+// refers to it; None if no interpretation of the pointer leads to an NLM
+pub fn here_nlm(data: &[u8], here: &HereBlock) -> Option<(usize, PointerKind)> {
+    let map = AddressMap::new(data);
+    POINTER_KINDS.iter()
+        .filter_map(|kind| map.to_file(here.nlm_offset, *kind).map(|offset| (offset, *kind)))
+        .find(|(offset, _)| data.get(*offset..offset.saturating_add(NLM_MAGIC.len())) == Some(NLM_MAGIC))
+}
+
+// File offset of the first embedded NLM: the one the HERE block refers to,
+// or the first NLM signature if there is no NLM at that offset
+pub fn first_nlm(data: &[u8], here: &HereBlock) -> Option<usize> {
+    if let Some((offset, _)) = here_nlm(data, here) {
+        return Some(offset);
+    }
+    data.windows(NLM_MAGIC.len()).position(|w| w == NLM_MAGIC)
+}
+
+pub struct EmbeddedNLM {
+    pub offset: usize,
+    pub length: usize,
+    pub name: Option<String>,
+    // Why the NLM header could not be parsed, if so
+    pub error: Option<String>,
+}
+
+// The NLM referred to by the HERE block (SERVER.NLM) is followed by the other
+// embedded NLMs; these are located by their signature. The length is
+// determined using the NLM header, or is the remainder up to the next NLM if
+// the header cannot be parsed.
+pub fn find_nlms(data: &[u8], first: usize) -> Vec<EmbeddedNLM> {
+    let mut offsets = vec![ first ];
+    let mut pos = first + NLM_MAGIC.len();
+    while let Some(n) = data.get(pos..).and_then(|d| d.windows(NLM_MAGIC.len()).position(|w| w == NLM_MAGIC)) {
+        offsets.push(pos + n);
+        pos += n + NLM_MAGIC.len();
+    }
+
+    let mut result = Vec::new();
+    for (n, offset) in offsets.iter().enumerate() {
+        let limit = offsets.get(n + 1).copied().unwrap_or(data.len());
+        let nlm = match NLM::new(&data[*offset..limit]) {
+            Ok(nlm) => EmbeddedNLM{ offset: *offset, length: nlm.file_length, name: Some(nlm.header.name()), error: None },
+            Err(e) => EmbeddedNLM{ offset: *offset, length: limit - offset, name: None, error: Some(e.to_string()) },
+        };
+        result.push(nlm);
+    }
+    result
+}
+
+impl EmbeddedNLM {
+    pub fn data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..self.offset + self.length]
+    }
+
+    // Name to use when writing the NLM; index is its position in SERVER.EXE,
+    // for NLMs lacking a (unique) name
+    pub fn file_name(&self, index: usize, taken: &[String]) -> String {
+        match &self.name {
+            Some(name) if !name.is_empty() && !taken.contains(name) => name.clone(),
+            _ => format!("MODULE{}.NLM", index),
+        }
+    }
+}
+
+// A field of the HERE block changed by replace_nlm()
+pub struct HereUpdate {
+    pub offset: usize,
+    pub old: u32,
+    pub new: u32,
+}
+
+// Replaces the embedded NLM at index by nlm, padding it so the NLMs that follow
+// stay aligned. As the meaning of most of the HERE block is not known, fields
+// holding the old length of the file or of the NLM are taken to be lengths and
+// updated as well.
+pub fn replace_nlm(data: &[u8], here: &HereBlock, nlms: &[EmbeddedNLM], index: usize, nlm: &[u8]) -> Result<(Vec<u8>, Vec<HereUpdate>), String> {
+    let old = nlms.get(index).ok_or(format!("there is no embedded NLM {}", index))?;
+    NLM::new(nlm).map_err(|e| format!("replacement is not a valid NLM: {}", e))?;
+    let old_end = nlms.get(index + 1).map(|n| n.offset).unwrap_or(old.offset + old.length);
+    let mut new_length = nlm.len();
+    if index + 1 < nlms.len() {
+        new_length = new_length.div_ceil(NLM_ALIGNMENT) * NLM_ALIGNMENT;
+    }
+
+    let mut result = Vec::with_capacity(data.len() - (old_end - old.offset) + new_length);
+    result.extend_from_slice(&data[0..old.offset]);
+    result.extend_from_slice(nlm);
+    result.resize(old.offset + new_length, 0);
+    result.extend_from_slice(&data[old_end..]);
+
+    let lengths = [
+        (data.len(), result.len()),
+        (old.length, nlm.len()),
+        (old_end - old.offset, new_length),
+    ];
+    let mut updates = Vec::new();
+    for (offset, value) in here.unknown_words() {
+        if offset + 4 > HERE_VERSION { continue; }
+        if let Some((_, new)) = lengths.iter().find(|(old, _)| *old as u32 == value) {
+            let new = u32::try_from(*new).map_err(|_| "SERVER.EXE would become too large".to_string())?;
+            LittleEndian::write_u32(&mut result[here.offset + offset..], new);
+            updates.push(HereUpdate{ offset, old: value, new });
+        }
+    }
+    Ok((result, updates))
+}
@@ -0,0 +1,138 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Identification of a NetWare release, as found in the texts of SERVER.EXE,
+// SERVER.NLM or a memory dump: the version string shown at startup, such as
+// 'Novell NetWare v3.12 (250 user) 07/16/93', the copyright notices and the
+// serial number as shown on the console.
+use serde_json::{json, Value};
+
+use crate::versions;
+
+const MIN_TEXT: usize = 4;
+const MAX_TEXT: usize = 160;
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerInfo {
+    pub version_string: Option<String>,
+    pub version: Option<String>,
+    pub date: Option<String>,
+    pub users: Option<u32>,
+    pub serial: Option<String>,
+    pub copyrights: Vec<String>,
+}
+
+// Returns the printable strings in data, along with their offset
+pub fn texts(data: &[u8]) -> Vec<(usize, String)> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    for (n, b) in data.iter().chain(std::iter::once(&0)).enumerate() {
+        if *b == b' ' || b.is_ascii_graphic() { continue; }
+        if n - start >= MIN_TEXT && n - start <= MAX_TEXT {
+            result.push((start, String::from_utf8_lossy(&data[start..n]).to_string()));
+        }
+        start = n + 1;
+    }
+    result
+}
+
+// Finds a date such as 07/16/93 or 7-16-1993
+fn find_date(s: &str) -> Option<String> {
+    s.split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ',').find(|word| {
+        let parts: Vec<&str> = word.split(['/', '-']).collect();
+        parts.len() == 3 && parts.iter().all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())) &&
+            parts[0].len() <= 2 && parts[1].len() <= 2 && (parts[2].len() == 2 || parts[2].len() == 4)
+    }).map(|s| s.to_string())
+}
+
+// Finds the user count, as in '(250 user)' or '5-user'
+fn find_users(s: &str) -> Option<u32> {
+    let lower = s.to_ascii_lowercase();
+    let pos = lower.find("user")?;
+    let digits: String = lower[0..pos].trim_end_matches([' ', '-']).chars().rev()
+        .take_while(|c| c.is_ascii_digit()).collect::<Vec<char>>().into_iter().rev().collect();
+    digits.parse().ok()
+}
+
+fn find_serial(s: &str) -> Option<String> {
+    let lower = s.to_ascii_lowercase();
+    let pos = lower.find("serial number")? + "serial number".len();
+    let value = s[pos..].trim_start_matches([':', ' ', '=', '#']).split_whitespace().next()?;
+    if value.chars().any(|c| c.is_ascii_digit()) && value.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Some(value.to_string());
+    }
+    None
+}
+
+impl ServerInfo {
+    pub fn scan(data: &[u8]) -> Self {
+        let mut info = Self{ version: versions::detect(data), ..Default::default() };
+        for (_, text) in texts(data) {
+            if info.version_string.is_none() && versions::detect(text.as_bytes()).is_some() {
+                // Strings may be preceded by other data that is printable
+                let netware = text.find("NetWare").unwrap_or(0);
+                let start = text[0..netware].rfind("Novell").unwrap_or(netware);
+                let version_string = text[start..].trim();
+                info.date = find_date(version_string);
+                info.users = find_users(version_string);
+                info.version_string = Some(version_string.to_string());
+            }
+            if info.serial.is_none() {
+                info.serial = find_serial(&text);
+            }
+            let lower = text.to_ascii_lowercase();
+            let text = text.trim().to_string();
+            if (lower.contains("copyright") || lower.contains("(c)")) && !info.copyrights.contains(&text) {
+                info.copyrights.push(text);
+            }
+        }
+        info
+    }
+
+    // Fills in what is missing using the information of other, such as that
+    // of an embedded NLM
+    pub fn merge(&mut self, other: ServerInfo) {
+        if self.version_string.is_none() {
+            self.version_string = other.version_string;
+            self.date = other.date;
+            self.users = other.users;
+        }
+        self.version = self.version.take().or(other.version);
+        self.serial = self.serial.take().or(other.serial);
+        for c in other.copyrights {
+            if !self.copyrights.contains(&c) {
+                self.copyrights.push(c);
+            }
+        }
+    }
+
+    pub fn to_text(&self) -> Vec<String> {
+        let unknown = || "unknown".to_string();
+        let mut result = Vec::new();
+        result.push(format!("version string  {}", self.version_string.clone().unwrap_or_else(unknown)));
+        let known = self.version.as_deref().and_then(versions::find).map(|v| format!(" ({})", v.description)).unwrap_or_default();
+        result.push(format!("version         {}{}", self.version.clone().unwrap_or_else(unknown), known));
+        result.push(format!("build date      {}", self.date.clone().unwrap_or_else(unknown)));
+        result.push(format!("licensed users  {}", self.users.map(|u| u.to_string()).unwrap_or_else(unknown)));
+        result.push(format!("serial number   {}", self.serial.clone().unwrap_or_else(unknown)));
+        for c in &self.copyrights {
+            result.push(format!("copyright       {}", c));
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version_string": self.version_string,
+            "version": self.version,
+            "date": self.date,
+            "users": self.users,
+            "serial": self.serial,
+            "copyrights": self.copyrights,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// SHA-256 as specified by FIPS 180-4, used to record the hashes of extracted
+// files so archives can be verified and deduplicated later on
+use byteorder::{BigEndian, ByteOrder};
+
+pub const HASH_SIZE: usize = 32;
+const BLOCK_SIZE: usize = 64;
+
+const K: [ u32; 64 ] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [ u32; 8 ] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+fn compress(state: &mut [ u32; 8 ], block: &[u8]) {
+    let mut w = [ 0u32; 64 ];
+    for (n, word) in block.chunks_exact(4).enumerate() {
+        w[n] = BigEndian::read_u32(word);
+    }
+    for n in 16..64 {
+        let s0 = w[n - 15].rotate_right(7) ^ w[n - 15].rotate_right(18) ^ (w[n - 15] >> 3);
+        let s1 = w[n - 2].rotate_right(17) ^ w[n - 2].rotate_right(19) ^ (w[n - 2] >> 10);
+        w[n] = w[n - 16].wrapping_add(s0).wrapping_add(w[n - 7]).wrapping_add(s1);
+    }
+    let [ mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h ] = *state;
+    for n in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[n]).wrapping_add(w[n]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (s, v) in state.iter_mut().zip([ a, b, c, d, e, f, g, h ]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+// Hashes data given in any number of parts, so files need not be in memory
+// as a whole
+pub struct Sha256 {
+    state: [ u32; 8 ],
+    // Data which does not fill a block yet
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self{ state: INITIAL_STATE, pending: Vec::with_capacity(BLOCK_SIZE), len: 0 }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        if !self.pending.is_empty() {
+            let len = data.len().min(BLOCK_SIZE - self.pending.len());
+            self.pending.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.pending.len() < BLOCK_SIZE {
+                return;
+            }
+            compress(&mut self.state, &self.pending);
+            self.pending.clear();
+        }
+        let mut blocks = data.chunks_exact(BLOCK_SIZE);
+        for block in &mut blocks {
+            compress(&mut self.state, block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [ u8; HASH_SIZE ] {
+        // The message is padded with a single 1 bit, zeroes and its length
+        // in bits, which may take an extra block
+        let rest = &self.pending;
+        let mut tail = [ 0u8; 2 * BLOCK_SIZE ];
+        tail[..rest.len()].copy_from_slice(rest);
+        tail[rest.len()] = 0x80;
+        let tail_len = if rest.len() < BLOCK_SIZE - 8 { BLOCK_SIZE } else { 2 * BLOCK_SIZE };
+        BigEndian::write_u64(&mut tail[tail_len - 8..], self.len.wrapping_mul(8));
+        for block in tail[..tail_len].chunks_exact(BLOCK_SIZE) {
+            compress(&mut self.state, block);
+        }
+
+        let mut hash = [ 0u8; HASH_SIZE ];
+        for (chunk, s) in hash.chunks_exact_mut(4).zip(self.state) {
+            BigEndian::write_u32(chunk, s);
+        }
+        hash
+    }
+}
+
+pub fn sha256(data: &[u8]) -> [ u8; HASH_SIZE ] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+pub fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples from FIPS 180-4 and the NIST test vectors
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const MESSAGE_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const HASH_448: &str = "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+    const MESSAGE_896: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+    const HASH_896: &str = "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1";
+
+    #[test]
+    fn vectors() {
+        assert_eq!(to_hex(&sha256(b"")), EMPTY);
+        assert_eq!(to_hex(&sha256(b"abc")), ABC);
+        assert_eq!(to_hex(&sha256(MESSAGE_448)), HASH_448);
+        assert_eq!(to_hex(&sha256(MESSAGE_896)), HASH_896);
+    }
+
+    #[test]
+    fn split_update() {
+        // Parts which leave data pending, and then one which completes the
+        // first block at offset 64 and continues in the second
+        let mut hasher = Sha256::new();
+        hasher.update(&MESSAGE_896[..5]);
+        hasher.update(&MESSAGE_896[5..50]);
+        hasher.update(&[]);
+        hasher.update(&MESSAGE_896[50..80]);
+        hasher.update(&MESSAGE_896[80..]);
+        assert_eq!(to_hex(&hasher.finish()), HASH_896);
+
+        // One million times 'a', given as parts of 1000 bytes which do not
+        // line up with the blocks
+        let mut hasher = Sha256::new();
+        for _ in 0..1000 {
+            hasher.update(&[ b'a'; 1000 ]);
+        }
+        assert_eq!(to_hex(&hasher.finish()), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+}
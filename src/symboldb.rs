@@ -0,0 +1,88 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// SQLite database of the symbols of many NetWare builds, so it can be tracked
+// which symbols exist in which release and how their addresses moved. Every
+// symbol is stored along with the version it was imported as (i.e. "3.12" or
+// "4.11 SP9") and whether it comes from SERVER.NLM or the loader. Importing a
+// version again replaces its symbols.
+use rusqlite::{params, Connection};
+
+use crate::symbols::Symbol;
+
+pub const SOURCES: [ &str; 2 ] = [ "server", "loader" ];
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS symbols (
+        version TEXT NOT NULL,
+        source TEXT NOT NULL,
+        name TEXT NOT NULL,
+        address INTEGER NOT NULL,
+        PRIMARY KEY (version, source, name, address)
+    );
+    CREATE INDEX IF NOT EXISTS symbols_name ON symbols (name);
+";
+
+pub struct SymbolDb {
+    conn: Connection,
+}
+
+// Symbol as found in a version
+pub struct SymbolRow {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub address: u32,
+}
+
+fn sql_error(e: rusqlite::Error) -> String {
+    format!("database error: {}", e)
+}
+
+impl SymbolDb {
+    pub fn open(fname: &str) -> Result<Self, String> {
+        let conn = Connection::open(fname).map_err(sql_error)?;
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        Ok(Self{ conn })
+    }
+
+    // Replaces the symbols of version and source; returns the number stored,
+    // which is less than given if there are duplicates
+    pub fn import(&mut self, version: &str, source: &str, symbols: &[Symbol]) -> Result<usize, String> {
+        let tx = self.conn.transaction().map_err(sql_error)?;
+        tx.execute("DELETE FROM symbols WHERE version = ?1 AND source = ?2", params![ version, source ]).map_err(sql_error)?;
+        let mut count = 0;
+        {
+            let mut insert = tx.prepare("INSERT OR IGNORE INTO symbols (version, source, name, address) VALUES (?1, ?2, ?3, ?4)").map_err(sql_error)?;
+            for sym in symbols {
+                count += insert.execute(params![ version, source, sym.name, sym.address ]).map_err(sql_error)?;
+            }
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(count)
+    }
+
+    // Every version and source imported, along with the number of symbols
+    pub fn versions(&self) -> Result<Vec<(String, String, usize)>, String> {
+        let mut stmt = self.conn.prepare("SELECT version, source, COUNT(*) FROM symbols GROUP BY version, source ORDER BY version, source").map_err(sql_error)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as usize))).map_err(sql_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(sql_error)
+    }
+
+    // Symbols matching pattern, which may contain * and ? wildcards, ordered
+    // by name and version
+    pub fn lookup(&self, pattern: &str) -> Result<Vec<SymbolRow>, String> {
+        let mut stmt = self.conn.prepare("SELECT name, version, source, address FROM symbols WHERE name GLOB ?1 ORDER BY name, source, version, address").map_err(sql_error)?;
+        let rows = stmt.query_map(params![ pattern ], |row| Ok(SymbolRow{
+            name: row.get(0)?,
+            version: row.get(1)?,
+            source: row.get(2)?,
+            address: row.get(3)?,
+        })).map_err(sql_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(sql_error)
+    }
+}
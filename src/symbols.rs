@@ -0,0 +1,352 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Internal symbol lists, as kept by both the loader and SERVER.NLM.
+//
+// Every symbol is a node { next, func, name } of three 32-bit pointers, where
+// name points to a length-prefixed string. A global variable points to the
+// first node and the last node has a next pointer of zero. The location of
+// this variable differs per build, so it can be located by scanning for the
+// pointer that leads to the longest chain of valid nodes.
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use byteorder::{ByteOrder, LittleEndian};
+use serde_json::{json, Value};
+
+use crate::codepage::{self, Codepage};
+
+pub const NODE_SIZE: usize = 12;
+
+// Number of warnings about damaged symbol names shown by the tools; only the
+// number of any further ones is reported
+pub const MAX_WARNINGS: usize = 10;
+
+// Chains shorter than this are not considered to be a symbol list
+pub const MIN_SYMBOLS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+}
+
+// A piece of memory, along with the address at which it is loaded
+pub struct Image<'a> {
+    pub data: &'a [u8],
+    pub base: u32,
+    // Used to decode names containing characters outside of ASCII
+    pub codepage: &'static Codepage,
+}
+
+impl<'a> Image<'a> {
+    pub fn new(data: &'a [u8], base: u32) -> Self {
+        Self::with_codepage(data, base, codepage::DEFAULT)
+    }
+
+    pub fn with_codepage(data: &'a [u8], base: u32, codepage: &'static Codepage) -> Self {
+        Self{ data, base, codepage }
+    }
+
+    pub fn slice(&self, addr: u32, len: usize) -> Option<&'a [u8]> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.slice(addr, 4).map(LittleEndian::read_u32)
+    }
+
+    // Names are symbol names, so only printable characters are accepted;
+    // these may include characters of the codepage, but not at the start
+    pub fn read_name(&self, addr: u32) -> Option<String> {
+        let len = *self.slice(addr, 1)?.first()? as usize;
+        let name = self.slice(addr.checked_add(1)?, len)?;
+        if len == 0 || !name[0].is_ascii_graphic() || !name.iter().all(|b| b.is_ascii_graphic() || *b >= 0x80) {
+            return None;
+        }
+        Some(self.codepage.decode(name))
+    }
+
+    // Reads a length-prefixed string without any restrictions on its
+    // contents, other than that it is within the image
+    pub fn read_string(&self, addr: u32) -> Result<String, String> {
+        let len = *self.slice(addr, 1).ok_or(format!("string at {:x} is outside of the image", addr))?.first().unwrap_or(&0) as usize;
+        if len == 0 {
+            return Err(format!("string at {:x} is empty", addr));
+        }
+        let data = addr.checked_add(1).and_then(|a| self.slice(a, len))
+            .ok_or(format!("string at {:x} is truncated by the end of the image", addr))?;
+        Ok(self.codepage.decode(data))
+    }
+
+    // Returns the next pointer of the node at addr, if it looks like a
+    // symbol node
+    fn node_next(&self, addr: u32) -> Option<u32> {
+        if !addr.is_multiple_of(4) { return None; }
+        let next = self.read_u32(addr)?;
+        let func = self.read_u32(addr.checked_add(4)?)?;
+        let name = self.read_u32(addr.checked_add(8)?)?;
+        if func == 0 { return None; }
+        self.read_name(name)?;
+        Some(next)
+    }
+
+    // Number of valid nodes reachable from addr; results are cached in
+    // lengths, as every node is part of the chains of all nodes before it
+    fn chain_length(&self, addr: u32, lengths: &mut HashMap<u32, usize>) -> usize {
+        let mut path: Vec<u32> = Vec::new();
+        let mut current = addr;
+        let mut tail = 0;
+        while current != 0 {
+            if let Some(len) = lengths.get(&current) {
+                tail = *len;
+                break;
+            }
+            if path.contains(&current) { break; }
+            match self.node_next(current) {
+                Some(next) => {
+                    path.push(current);
+                    current = next;
+                },
+                None => break,
+            }
+        }
+        for (n, node) in path.iter().rev().enumerate() {
+            lengths.insert(*node, tail + n + 1);
+        }
+        path.len() + tail
+    }
+
+    // Scans the image for the variable pointing to the symbol list; returns
+    // its address and the number of symbols
+    pub fn find_list_head(&self) -> Option<(u32, usize)> {
+        let mut lengths: HashMap<u32, usize> = HashMap::new();
+        let mut best: Option<(u32, usize)> = None;
+        for offset in (0..self.data.len().saturating_sub(3)).step_by(4) {
+            let addr = self.base.checked_add(offset as u32)?;
+            let ptr = LittleEndian::read_u32(&self.data[offset..offset + 4]);
+            if ptr == 0 || self.slice(ptr, NODE_SIZE).is_none() { continue; }
+            let len = self.chain_length(ptr, &mut lengths);
+            if len >= MIN_SYMBOLS && best.is_none_or(|(_, n)| len > n) {
+                best = Some((addr, len));
+            }
+        }
+        best
+    }
+
+    // Scans the image for all variables pointing to a symbol list, as servers
+    // may have more than one; lists that are part of a longer one are not
+    // included. Returns their address and number of symbols, longest first
+    pub fn find_list_heads(&self) -> Vec<(u32, usize)> {
+        let mut lengths: HashMap<u32, usize> = HashMap::new();
+        let mut heads: Vec<(u32, u32, usize)> = Vec::new();
+        for offset in (0..self.data.len().saturating_sub(3)).step_by(4) {
+            let addr = match self.base.checked_add(offset as u32) {
+                Some(addr) => addr,
+                None => break,
+            };
+            let ptr = LittleEndian::read_u32(&self.data[offset..offset + 4]);
+            if ptr == 0 || self.slice(ptr, NODE_SIZE).is_none() { continue; }
+            let len = self.chain_length(ptr, &mut lengths);
+            if len >= MIN_SYMBOLS {
+                heads.push((addr, ptr, len));
+            }
+        }
+        // Nodes of a list are reachable from its first node, so any pointer to
+        // such a node refers to (part of) the same list
+        let mut result: Vec<(u32, usize)> = Vec::new();
+        let mut covered: HashSet<u32> = HashSet::new();
+        heads.sort_by_key(|(_, _, len)| std::cmp::Reverse(*len));
+        for (addr, ptr, len) in heads {
+            if covered.contains(&ptr) { continue; }
+            let mut node = ptr;
+            while node != 0 && covered.insert(node) {
+                node = self.node_next(node).unwrap_or(0);
+            }
+            result.push((addr, len));
+        }
+        result
+    }
+
+    // Reads the symbol list whose first node is pointed to by head
+    pub fn read_list(&self, head: u32) -> Result<Vec<Symbol>, String> {
+        self.read_list_with_warnings(head).map(|(symbols, _)| symbols)
+    }
+
+    // As read_list(), but also returns a warning for every damaged name.
+    // Names which cannot be read are replaced by unnamed_ADDRESS, and
+    // unprintable characters by '?', so the list can still be used
+    pub fn read_list_with_warnings(&self, head: u32) -> Result<(Vec<Symbol>, Vec<String>), String> {
+        let mut result = Vec::new();
+        let mut warnings = Vec::new();
+        let mut ptr = self.read_u32(head).ok_or(format!("list head {:x} is outside of the image", head))?;
+        while ptr != 0 {
+            let node = self.slice(ptr, NODE_SIZE).ok_or(format!("symbol node {:x} is outside of the image", ptr))?;
+            let next = LittleEndian::read_u32(&node[0..4]);
+            let address = LittleEndian::read_u32(&node[4..8]);
+            let name_ptr = LittleEndian::read_u32(&node[8..12]);
+            let name = match self.read_string(name_ptr) {
+                Ok(name) if name.chars().all(|c| !c.is_control() && !c.is_whitespace()) => name,
+                Ok(name) => {
+                    warnings.push(format!("name of symbol node {:x} contains unprintable characters", ptr));
+                    name.chars().map(|c| if c.is_control() || c.is_whitespace() { '?' } else { c }).collect()
+                },
+                Err(e) => {
+                    warnings.push(format!("name of symbol node {:x}: {}", ptr, e));
+                    format!("unnamed_{:08x}", address)
+                }
+            };
+            result.push(Symbol{ name, address });
+            if result.len() > self.data.len() / NODE_SIZE {
+                return Err(format!("symbol list starting at {:x} loops", head));
+            }
+            ptr = next;
+        }
+        Ok((result, warnings))
+    }
+}
+
+pub fn parse_address(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => u32::from_str_radix(s, 16).ok(),
+    }
+}
+
+// Segment of the image the symbols refer to, used to classify symbols and to
+// express their address relative to the segment
+pub struct Segment {
+    pub name: String,
+    pub address: u32,
+    pub length: u32,
+    pub is_code: bool,
+}
+
+impl Segment {
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.address && addr - self.address < self.length
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SymbolFormat {
+    Text,
+    Nm,
+    Map,
+    Ghidra,
+    GhidraScript,
+    Json,
+}
+
+pub const SYMBOL_FORMATS: [ (&str, SymbolFormat); 6 ] = [
+    ("text", SymbolFormat::Text),
+    ("nm", SymbolFormat::Nm),
+    ("map", SymbolFormat::Map),
+    ("ghidra", SymbolFormat::Ghidra),
+    ("ghidra-script", SymbolFormat::GhidraScript),
+    ("json", SymbolFormat::Json),
+];
+
+impl SymbolFormat {
+    pub fn find(name: &str) -> Option<Self> {
+        SYMBOL_FORMATS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, f)| *f)
+    }
+}
+
+// Writes a Ghidra Python script that creates a label at every address, and a
+// function as well for those marked as code. It can be run from the Script
+// Manager without any further input.
+pub fn write_ghidra_script<W: Write>(w: &mut W, labels: &[(&str, u32, bool)]) -> Result<(), std::io::Error> {
+    writeln!(w, "# Creates the labels and functions extracted by nlm-tools")?;
+    writeln!(w, "# @category NetWare")?;
+    writeln!(w, "from ghidra.program.model.symbol import SourceType")?;
+    writeln!(w)?;
+    writeln!(w, "labels = [")?;
+    for (name, address, is_code) in labels {
+        let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(w, "    (\"{}\", 0x{:08x}, {}),", name, address, if *is_code { "True" } else { "False" })?;
+    }
+    writeln!(w, "]")?;
+    writeln!(w)?;
+    writeln!(w, "created = 0")?;
+    writeln!(w, "for name, address, is_code in labels:")?;
+    writeln!(w, "    addr = toAddr(address)")?;
+    writeln!(w, "    if currentProgram.getMemory().getBlock(addr) is None:")?;
+    writeln!(w, "        print(\"skipping %s: %s is not in memory\" % (name, addr))")?;
+    writeln!(w, "        continue")?;
+    writeln!(w, "    createLabel(addr, name, True, SourceType.IMPORTED)")?;
+    writeln!(w, "    if is_code and getFunctionAt(addr) is None:")?;
+    writeln!(w, "        disassemble(addr)")?;
+    writeln!(w, "        createFunction(addr, name)")?;
+    writeln!(w, "    created += 1")?;
+    writeln!(w, "print(\"created %d of %d labels\" % (created, len(labels)))")?;
+    Ok(())
+}
+
+pub fn write_symbols<W: Write>(w: &mut W, symbols: &[Symbol], segments: &[Segment], format: SymbolFormat) -> Result<(), std::io::Error> {
+    let segment_of = |addr: u32| segments.iter().position(|s| s.contains(addr));
+    let is_code = |addr: u32| segment_of(addr).is_none_or(|n| segments[n].is_code);
+    match format {
+        SymbolFormat::Text => {
+            for sym in symbols {
+                writeln!(w, "{} 0x{:x}", sym.name, sym.address)?;
+            }
+        },
+        SymbolFormat::Nm => {
+            let mut sorted: Vec<&Symbol> = symbols.iter().collect();
+            sorted.sort_by_key(|s| s.address);
+            for sym in sorted {
+                let kind = match segment_of(sym.address) {
+                    Some(n) if segments[n].is_code => 'T',
+                    Some(_) => 'D',
+                    None => 'A',
+                };
+                writeln!(w, "{:08x} {} {}", sym.address, kind, sym.name)?;
+            }
+        },
+        SymbolFormat::Map => {
+            // Microsoft LINK style, as accepted by IDA; segments are numbered
+            // from 1 and symbols outside of them are placed in segment 0
+            writeln!(w)?;
+            writeln!(w, " Start         Length     Name                   Class")?;
+            for (n, s) in segments.iter().enumerate() {
+                writeln!(w, " {:04X}:{:08X} {:08X}H {:<23} {}", n + 1, 0, s.length, s.name, if s.is_code { "CODE" } else { "DATA" })?;
+            }
+            writeln!(w)?;
+            writeln!(w, "  Address         Publics by Value")?;
+            writeln!(w)?;
+            let mut sorted: Vec<&Symbol> = symbols.iter().collect();
+            sorted.sort_by_key(|s| (segment_of(s.address).map(|n| n + 1).unwrap_or(0), s.address));
+            for sym in sorted {
+                let (segment, offset) = match segment_of(sym.address) {
+                    Some(n) => (n + 1, sym.address - segments[n].address),
+                    None => (0, sym.address),
+                };
+                writeln!(w, " {:04X}:{:08X}       {}", segment, offset, sym.name)?;
+            }
+        },
+        SymbolFormat::Ghidra => {
+            // ImportSymbolsScript.py expects 'name address [f|l]' per line
+            for sym in symbols {
+                writeln!(w, "{} 0x{:08x} {}", sym.name, sym.address, if is_code(sym.address) { "f" } else { "l" })?;
+            }
+        },
+        SymbolFormat::GhidraScript => {
+            let labels: Vec<(&str, u32, bool)> = symbols.iter().map(|sym| (sym.name.as_str(), sym.address, is_code(sym.address))).collect();
+            write_ghidra_script(w, &labels)?;
+        },
+        SymbolFormat::Json => {
+            let list: Vec<Value> = symbols.iter().map(|sym| json!({
+                "name": sym.name,
+                "address": sym.address,
+                "segment": segment_of(sym.address).map(|n| segments[n].name.clone()),
+            })).collect();
+            writeln!(w, "{}", serde_json::to_string_pretty(&list).unwrap())?;
+        },
+    }
+    Ok(())
+}
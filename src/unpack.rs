@@ -0,0 +1,319 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// DOS executable packers. Some distributions shipped a SERVER.EXE processed
+// by EXEPACK or LZEXE, which compress the load image and append a small
+// unpacker to it; the HERE block cannot be found until the image has been
+// unpacked. Anything past the load image, such as the embedded NLMs, is not
+// compressed and is kept as-is. PKLITE is recognized, but its compression is
+// not supported.
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::loader::{MzHeader, MZ_HEADER_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Packer {
+    Exepack,
+    Lzexe090,
+    Lzexe091,
+    Pklite,
+}
+
+// EXEPACK stores its variables at CS:0, ending with this signature; the
+// relocation table follows the error message of the unpacker
+const EXEPACK_SIGNATURE: &[u8; 2] = b"RB";
+const EXEPACK_ERROR: &[u8] = b"Packed file is corrupt";
+
+// LZEXE stores its signature after the MZ header, and its variables at CS:0
+const LZEXE_SIGNATURE_OFFSET: usize = MZ_HEADER_SIZE;
+const LZEXE_SIGNATURES: [ (&[u8; 4], Packer); 2 ] = [
+    (b"LZ09", Packer::Lzexe090),
+    (b"LZ91", Packer::Lzexe091),
+];
+const LZEXE090_RELOCATIONS: usize = 0x19d;
+const LZEXE091_RELOCATIONS: usize = 0x158;
+
+// PKLITE stores its copyright message after the MZ header
+const PKLITE_SIGNATURE: &[u8] = b"PKLITE";
+const PKLITE_SEARCH_LIMIT: usize = 0x60;
+
+impl Packer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Exepack => "EXEPACK",
+            Self::Lzexe090 => "LZEXE 0.90",
+            Self::Lzexe091 => "LZEXE 0.91",
+            Self::Pklite => "PKLITE",
+        }
+    }
+
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let header = MzHeader::parse(data).ok()?;
+        if let Some(signature) = data.get(LZEXE_SIGNATURE_OFFSET..LZEXE_SIGNATURE_OFFSET + 4) {
+            if let Some((_, packer)) = LZEXE_SIGNATURES.iter().find(|(s, _)| s.as_slice() == signature) {
+                return Some(*packer);
+            }
+        }
+        // The code of the unpacker directly follows the variables
+        let entry = header.header_length() + header.cs as usize * 16 + header.ip as usize;
+        if entry >= 2 && data.get(entry - 2..entry) == Some(EXEPACK_SIGNATURE) {
+            return Some(Self::Exepack);
+        }
+        let limit = data.len().min(PKLITE_SEARCH_LIMIT);
+        if data[MZ_HEADER_SIZE..limit].windows(PKLITE_SIGNATURE.len()).any(|w| w.eq_ignore_ascii_case(PKLITE_SIGNATURE)) {
+            return Some(Self::Pklite);
+        }
+        None
+    }
+}
+
+// Load image and header fields as they were before packing
+struct Unpacked {
+    image: Vec<u8>,
+    relocations: Vec<(u16, u16)>,
+    cs: u16,
+    ip: u16,
+    ss: u16,
+    sp: u16,
+}
+
+impl Unpacked {
+    // Writes a DOS executable, requiring as much memory as the packed one
+    fn to_exe(&self, packed: &MzHeader, packed_image_length: usize) -> Vec<u8> {
+        let header_length = (MZ_HEADER_SIZE + self.relocations.len() * 4).div_ceil(16) * 16;
+        let length = header_length + self.image.len();
+        let packed_paragraphs = packed_image_length.div_ceil(16) + packed.min_alloc as usize;
+        let min_alloc = packed_paragraphs.saturating_sub(self.image.len().div_ceil(16)).min(0xffff) as u16;
+
+        let mut result = vec![ 0u8; header_length ];
+        result[0..2].copy_from_slice(b"MZ");
+        for (offset, value) in [
+            (0x02, (length % 512) as u16),
+            (0x04, length.div_ceil(512) as u16),
+            (0x06, self.relocations.len() as u16),
+            (0x08, (header_length / 16) as u16),
+            (0x0a, min_alloc),
+            (0x0c, packed.max_alloc.max(min_alloc)),
+            (0x0e, self.ss),
+            (0x10, self.sp),
+            (0x14, self.ip),
+            (0x16, self.cs),
+            (0x18, MZ_HEADER_SIZE as u16),
+        ] {
+            LittleEndian::write_u16(&mut result[offset..], value);
+        }
+        for (n, (segment, offset)) in self.relocations.iter().enumerate() {
+            LittleEndian::write_u16(&mut result[MZ_HEADER_SIZE + n * 4..], *offset);
+            LittleEndian::write_u16(&mut result[MZ_HEADER_SIZE + n * 4 + 2..], *segment);
+        }
+        result.extend_from_slice(&self.image);
+        result
+    }
+}
+
+fn read_word(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2).map(LittleEndian::read_u16).ok_or("packed executable is truncated".to_string())
+}
+
+// Relocations stored as a count and a list of offsets for each of the 16
+// segments 0000, 1000, ..., f000
+fn read_segment_relocations(data: &[u8], mut offset: usize) -> Result<Vec<(u16, u16)>, String> {
+    let mut result = Vec::new();
+    for segment in 0..16 {
+        let count = read_word(data, offset)?;
+        offset += 2;
+        for _ in 0..count {
+            result.push((segment * 0x1000, read_word(data, offset)?));
+            offset += 2;
+        }
+    }
+    Ok(result)
+}
+
+// The compressed data is processed from the end, copying and filling blocks
+// towards the end of the unpacked image; what remains at the start is not
+// compressed
+fn unpack_exepack(header: &MzHeader, image: &[u8]) -> Result<Unpacked, String> {
+    let vars = header.cs as usize * 16;
+    let var = |n: usize| read_word(image, vars + n * 2);
+    let exepack_size = var(3)? as usize;
+    let unpacked_length = var(6)? as usize * 16;
+    let skip_paragraphs = if header.ip >= 0x12 { (var(7)? as usize).saturating_sub(1) } else { 0 };
+    let block = image.get(vars..vars + exepack_size).ok_or("EXEPACK block is truncated".to_string())?;
+    let table = block.windows(EXEPACK_ERROR.len()).position(|w| w == EXEPACK_ERROR)
+        .ok_or("EXEPACK relocation table not found".to_string())? + EXEPACK_ERROR.len();
+    let relocations = read_segment_relocations(block, table)?;
+
+    let mut src = vars.checked_sub(skip_paragraphs * 16).ok_or("invalid EXEPACK skip length".to_string())?;
+    let mut buf = image[0..src].to_vec();
+    buf.resize(buf.len().max(unpacked_length), 0);
+    let mut dst = unpacked_length;
+    while src > 0 && buf[src - 1] == 0xff {
+        src -= 1;
+    }
+    let corrupt = || "EXEPACK data is corrupt".to_string();
+    loop {
+        if src < 3 { return Err(corrupt()); }
+        let command = buf[src - 1];
+        let length = LittleEndian::read_u16(&buf[src - 3..]) as usize;
+        src -= 3;
+        dst = dst.checked_sub(length).ok_or_else(corrupt)?;
+        match command & 0xfe {
+            0xb0 => {
+                if src < 1 { return Err(corrupt()); }
+                let fill = buf[src - 1];
+                src -= 1;
+                buf[dst..dst + length].fill(fill);
+            },
+            0xb2 => {
+                src = src.checked_sub(length).ok_or_else(corrupt)?;
+                buf.copy_within(src..src + length, dst);
+            },
+            _ => return Err(format!("invalid EXEPACK command {:02x}", command)),
+        }
+        if command & 1 != 0 { break; }
+    }
+    buf.truncate(unpacked_length);
+    Ok(Unpacked{ image: buf, relocations, ip: var(0)?, cs: var(1)?, sp: var(4)?, ss: var(5)? })
+}
+
+// Bits are read from 16-bit words, starting at the least significant bit,
+// interleaved with the bytes of the compressed data
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u16,
+    count: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, String> {
+        let bits = read_word(data, 0)?;
+        Ok(Self{ data, pos: 2, bits, count: 16 })
+    }
+
+    fn bit(&mut self) -> Result<bool, String> {
+        let bit = self.bits & 1 != 0;
+        self.count -= 1;
+        if self.count == 0 {
+            self.bits = read_word(self.data, self.pos)?;
+            self.pos += 2;
+            self.count = 16;
+        } else {
+            self.bits >>= 1;
+        }
+        Ok(bit)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or("LZEXE data is truncated".to_string())?;
+        self.pos += 1;
+        Ok(byte)
+    }
+}
+
+fn lzexe_decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(data)?;
+    let mut result: Vec<u8> = Vec::new();
+    loop {
+        if reader.bit()? {
+            result.push(reader.byte()?);
+            continue;
+        }
+        let (distance, length) = if !reader.bit()? {
+            let length = ((reader.bit()? as usize) << 1 | reader.bit()? as usize) + 2;
+            (0x100 - reader.byte()? as usize, length)
+        } else {
+            let low = reader.byte()? as usize;
+            let high = reader.byte()? as usize;
+            let distance = 0x2000 - ((high & !7) << 5 | low);
+            let length = match (high & 7) + 2 {
+                2 => match reader.byte()? {
+                    0 => break,
+                    1 => continue,
+                    n => n as usize + 1,
+                },
+                n => n,
+            };
+            (distance, length)
+        };
+        let start = result.len().checked_sub(distance).ok_or("LZEXE data is corrupt".to_string())?;
+        for n in 0..length {
+            result.push(result[start + n]);
+        }
+    }
+    Ok(result)
+}
+
+// Relocations stored as the distance to the previous one, normalized to
+// segment:offset with an offset below 16
+fn read_lzexe091_relocations(data: &[u8], mut offset: usize) -> Result<Vec<(u16, u16)>, String> {
+    let mut result = Vec::new();
+    let (mut segment, mut rel_offset) = (0u16, 0u16);
+    loop {
+        let mut span = *data.get(offset).ok_or("LZEXE relocation table is truncated".to_string())? as u16;
+        offset += 1;
+        if span == 0 {
+            span = read_word(data, offset)?;
+            offset += 2;
+            match span {
+                0 => {
+                    segment = segment.wrapping_add(0x0fff);
+                    continue;
+                },
+                1 => break,
+                _ => {},
+            }
+        }
+        rel_offset = rel_offset.wrapping_add(span);
+        segment = segment.wrapping_add((rel_offset & !0x0f) >> 4);
+        rel_offset &= 0x0f;
+        result.push((segment, rel_offset));
+    }
+    Ok(result)
+}
+
+fn unpack_lzexe(header: &MzHeader, image: &[u8], packer: Packer) -> Result<Unpacked, String> {
+    let vars = header.cs as usize * 16;
+    let var = |n: usize| read_word(image, vars + n * 2);
+    let compressed_paragraphs = var(4)? as usize;
+    let start = vars.checked_sub(compressed_paragraphs * 16).ok_or("invalid LZEXE compressed length".to_string())?;
+    let relocations = match packer {
+        Packer::Lzexe090 => read_segment_relocations(image, vars + LZEXE090_RELOCATIONS)?,
+        _ => read_lzexe091_relocations(image, vars + LZEXE091_RELOCATIONS)?,
+    };
+    let image_data = lzexe_decompress(&image[start..])?;
+    Ok(Unpacked{ image: image_data, relocations, ip: var(0)?, cs: var(1)?, sp: var(2)?, ss: var(3)? })
+}
+
+// Returns the unpacked executable along with the packer used, or None if
+// data is not packed
+pub fn unpack(data: &[u8]) -> Result<Option<(Packer, Vec<u8>)>, String> {
+    let packer = match Packer::detect(data) {
+        Some(packer) => packer,
+        None => return Ok(None),
+    };
+    let header = MzHeader::parse(data)?;
+    let image = data.get(header.header_length()..header.image_end())
+        .ok_or("load image is truncated".to_string())?;
+    let unpacked = match packer {
+        Packer::Exepack => unpack_exepack(&header, image)?,
+        Packer::Lzexe090 | Packer::Lzexe091 => unpack_lzexe(&header, image, packer)?,
+        Packer::Pklite => return Err("PKLITE compressed executables are not supported, unpack it using i.e. UNP first".to_string()),
+    };
+    let mut result = unpacked.to_exe(&header, image.len());
+    result.extend_from_slice(&data[header.image_end()..]);
+    Ok(Some((packer, result)))
+}
+
+// As unpack(), but returns data as-is if it is not packed
+pub fn unpack_exe(data: Vec<u8>) -> Result<(Vec<u8>, Option<Packer>), String> {
+    Ok(match unpack(&data)? {
+        Some((packer, unpacked)) => (unpacked, Some(packer)),
+        None => (data, None),
+    })
+}
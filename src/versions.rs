@@ -0,0 +1,77 @@
+/*-
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ *
+ * Copyright (c) 2023 Rink Springer <rink@rink.nu>
+ * For conditions of distribution and use, see LICENSE file
+ */
+
+// Offsets that differ per NetWare build. Entries are keyed by the version as
+// reported by the server (i.e. "3.12"), which can be detected from the
+// version strings in SERVER.EXE, SERVER.NLM or a memory dump. Values that are
+// not known are None; tools fall back to their heuristics in that case.
+
+pub struct KnownVersion {
+    pub version: &'static str,
+    pub description: &'static str,
+    // Address of the variable pointing to the SERVER.NLM symbol list
+    pub server_sym_ptr: Option<u32>,
+    // Offset of the variable pointing to the loader symbol list in memory
+    pub loader_sym_ptr: Option<u32>,
+    // Words at offset 0x2a and 0x2c of the HERE block of SERVER.EXE
+    pub here_words: Option<(u16, u16)>,
+}
+
+pub const KNOWN_VERSIONS: [ KnownVersion; 1 ] = [
+    KnownVersion{
+        version: "3.12",
+        description: "NetWare 3.12 (Y2K patched loader)",
+        server_sym_ptr: Some(0x40021628),
+        loader_sym_ptr: Some(0x1c564),
+        here_words: Some((0, 0x110)),
+    },
+];
+
+pub fn find(version: &str) -> Option<&'static KnownVersion> {
+    let version = version.trim_start_matches(['v', 'V']);
+    KNOWN_VERSIONS.iter().find(|v| v.version == version)
+}
+
+pub fn find_by_here_words(words: (u16, u16)) -> Option<&'static KnownVersion> {
+    KNOWN_VERSIONS.iter().find(|v| v.here_words == Some(words))
+}
+
+// Looks for a version number such as 3.12 shortly after the text NetWare, as
+// in 'Novell NetWare v3.12' or 'NetWare 386 v3.11'
+pub fn detect(data: &[u8]) -> Option<String> {
+    const WINDOW: usize = 24;
+    let mut offset = 0;
+    while let Some(pos) = data[offset..].windows(7).position(|w| w == b"NetWare") {
+        let start = offset + pos + 7;
+        let window = &data[start..data.len().min(start + WINDOW)];
+        for n in 0..window.len().saturating_sub(3) {
+            let w = &window[n..];
+            let preceded_ok = n == 0 || !window[n - 1].is_ascii_digit();
+            if preceded_ok && w[0].is_ascii_digit() && w[1] == b'.' && w[2].is_ascii_digit() && w[3].is_ascii_digit() {
+                return Some(String::from_utf8_lossy(&w[0..4]).to_string());
+            }
+        }
+        offset = start;
+    }
+    None
+}
+
+// Selects the version given by the user, or the one detected from data
+pub fn select(version: Option<&str>, data: &[u8]) -> Result<Option<&'static KnownVersion>, String> {
+    match version {
+        Some(version) => {
+            match find(version) {
+                Some(v) => Ok(Some(v)),
+                None => {
+                    let known: Vec<&str> = KNOWN_VERSIONS.iter().map(|v| v.version).collect();
+                    Err(format!("unknown NetWare version '{}', known versions are: {}", version, known.join(", ")))
+                }
+            }
+        },
+        None => Ok(detect(data).and_then(|v| find(&v)))
+    }
+}